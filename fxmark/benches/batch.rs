@@ -0,0 +1,58 @@
+/*
+    Round trips saved by DRPC batches: a burst of small pwrites sent one by one
+    vs. in a single batch. Starts its own DRPC server on a private socket.
+*/
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use fxmark_grpc::fxrpc::{init_client, run_server, BatchOp, RPCEndpoint, RPCType};
+use libc::{O_CREAT, O_RDWR, S_IRWXU};
+use std::time::Duration;
+
+// Small writes, so the time goes to round trips rather than copying data
+const OPS: usize = 16;
+const WRITE_SIZE: usize = 64;
+
+fn batch(c: &mut Criterion) {
+    let path = "/dev/shm/fxmark_batch_bench/fx.sock";
+    let endpoint: RPCEndpoint = format!("unix://{}", path).parse().unwrap();
+    let server_endpoint = endpoint.clone();
+    std::thread::spawn(move || run_server(&server_endpoint, RPCType::DRPC));
+    while !std::path::Path::new(path).exists() {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let mut client = init_client(&endpoint, RPCType::DRPC);
+    let filename = "batch_bench.txt";
+    let fd = client
+        .rpc_open(filename, O_CREAT | O_RDWR, S_IRWXU)
+        .unwrap();
+    let page = vec![0xb; WRITE_SIZE];
+    let ops: Vec<BatchOp> = (0..OPS)
+        .map(|n| BatchOp::PWrite {
+            fd: fd,
+            page: page.clone(),
+            offset: (n * WRITE_SIZE) as i64,
+        })
+        .collect();
+
+    let mut group = c.benchmark_group(format!("pwrite x{}", OPS));
+    group.bench_function("separate", |b| {
+        b.iter(|| {
+            for op in &ops {
+                if let BatchOp::PWrite { fd, page, offset } = op {
+                    client.rpc_pwrite(*fd, page, page.len(), *offset).unwrap();
+                }
+            }
+        })
+    });
+    group.bench_function("batched", |b| {
+        b.iter(|| client.rpc_batch(ops.clone()).unwrap())
+    });
+    group.finish();
+
+    client.rpc_close(fd).unwrap();
+    client.rpc_remove(filename).unwrap();
+}
+
+criterion_group!(benches, batch);
+criterion_main!(benches);
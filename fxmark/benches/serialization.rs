@@ -0,0 +1,77 @@
+/*
+    Encode+decode cost of the DRPC messages on the hot path, per codec.
+    Run a single codec with e.g. `cargo bench --bench serialization -- abomonation`.
+*/
+
+use abomonation::{decode, Abomonation};
+use criterion::{criterion_group, criterion_main, Criterion};
+use fxmark_grpc::fxrpc::drpc::fileops::*;
+use serde::{de::DeserializeOwned, Serialize};
+use std::hint::black_box;
+
+const PAGE_SIZE: usize = 4096;
+
+// What DRPC sends today, including the byte order mark
+fn abomonation_roundtrip<T: Abomonation>(msg: &T, bytes: &mut Vec<u8>) {
+    bytes.clear();
+    encode_msg(msg, bytes);
+    let decoded = unsafe { decode::<T>(strip_byte_order_mark(bytes)) };
+    black_box(decoded.expect("Cannot decode message").0);
+}
+
+fn bincode_roundtrip<T: Serialize + DeserializeOwned>(msg: &T, bytes: &mut Vec<u8>) {
+    bytes.clear();
+    bincode::serialize_into(&mut *bytes, msg).expect("Failed to encode message");
+    let decoded: T = bincode::deserialize(bytes).expect("Cannot decode message");
+    black_box(decoded);
+}
+
+// Benchmark ids are <message>/<codec>, so every codec sees the same message
+fn bench_message<T>(c: &mut Criterion, name: &str, msg: T)
+where
+    T: Abomonation + Serialize + DeserializeOwned,
+{
+    let mut group = c.benchmark_group(name);
+    let mut bytes = Vec::with_capacity(2 * PAGE_SIZE);
+    group.bench_function("abomonation", |b| {
+        b.iter(|| abomonation_roundtrip(black_box(&msg), &mut bytes))
+    });
+    group.bench_function("bincode", |b| {
+        b.iter(|| bincode_roundtrip(black_box(&msg), &mut bytes))
+    });
+    group.finish();
+}
+
+fn serialization(c: &mut Criterion) {
+    bench_message(
+        c,
+        "OpenReq",
+        OpenReq {
+            path: b"file0.txt".to_vec(),
+            flags: libc::O_RDWR | libc::O_CREAT,
+            mode: libc::S_IRWXU,
+        },
+    );
+    bench_message(
+        c,
+        "WriteReq",
+        WriteReq {
+            fd: 3,
+            page: vec![0xb; PAGE_SIZE],
+            size: PAGE_SIZE,
+            offset: 0,
+        },
+    );
+    bench_message(
+        c,
+        "Response",
+        Response {
+            result: PAGE_SIZE as i32,
+            size: PAGE_SIZE,
+            page: vec![0xb; PAGE_SIZE],
+        },
+    );
+}
+
+criterion_group!(benches, serialization);
+criterion_main!(benches);
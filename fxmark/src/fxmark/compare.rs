@@ -0,0 +1,235 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Compares the throughput of two result CSVs, e.g. to gate CI on regressions.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// The parameters a result row was measured with.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ConfigKey {
+    pub benchmark: String,
+    pub cores: usize,
+    pub write_ratio: usize,
+    pub open_files: usize,
+    /// Empty for results written before the column existed.
+    pub thread_mapping: String,
+}
+
+impl core::fmt::Display for ConfigKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "{} cores={} write_ratio={} open_files={}",
+            self.benchmark, self.cores, self.write_ratio, self.open_files
+        )?;
+        if !self.thread_mapping.is_empty() {
+            write!(f, " {}", self.thread_mapping)?;
+        }
+        Ok(())
+    }
+}
+
+/// Throughput of one configuration in both runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigDelta {
+    pub key: ConfigKey,
+    /// Operations per second, summed over all threads.
+    pub baseline: f64,
+    pub candidate: f64,
+    /// Change from baseline to candidate in percent, negative is slower.
+    pub delta_pct: f64,
+    pub regressed: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompareReport {
+    pub deltas: Vec<ConfigDelta>,
+    /// Configurations that only one of the two files has.
+    pub unmatched: Vec<ConfigKey>,
+}
+
+impl CompareReport {
+    pub fn regressions(&self) -> impl Iterator<Item = &ConfigDelta> {
+        self.deltas.iter().filter(|d| d.regressed)
+    }
+}
+
+impl core::fmt::Display for CompareReport {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for d in &self.deltas {
+            writeln!(
+                f,
+                "{}: {:.1} -> {:.1} ops/s ({:+.1}%){}",
+                d.key,
+                d.baseline,
+                d.candidate,
+                d.delta_pct,
+                if d.regressed { " REGRESSED" } else { "" }
+            )?;
+        }
+        for key in &self.unmatched {
+            writeln!(f, "{}: only in one file", key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Aligns the rows of two result CSVs by configuration and flags every
+/// configuration whose throughput dropped by more than `threshold` percent.
+pub fn compare(
+    baseline: &Path,
+    candidate: &Path,
+    threshold: f64,
+) -> Result<CompareReport, Box<dyn std::error::Error>> {
+    let baseline = throughput(&fs::read_to_string(baseline)?)?;
+    let mut candidate = throughput(&fs::read_to_string(candidate)?)?;
+
+    let mut report = CompareReport::default();
+    for (key, base) in baseline {
+        match candidate.remove(&key) {
+            Some(cand) => {
+                let delta_pct = if base > 0.0 {
+                    (cand - base) / base * 100.0
+                } else {
+                    0.0
+                };
+                report.deltas.push(ConfigDelta {
+                    key,
+                    baseline: base,
+                    candidate: cand,
+                    delta_pct,
+                    regressed: delta_pct < -threshold,
+                });
+            }
+            None => report.unmatched.push(key),
+        }
+    }
+    report.unmatched.extend(candidate.into_keys());
+    Ok(report)
+}
+
+// Operations per second of each configuration: all operations over the number
+// of distinct seconds they were measured in
+fn throughput(csv: &str) -> Result<BTreeMap<ConfigKey, f64>, String> {
+    let mut lines = csv.lines();
+    let header: Vec<&str> = lines.next().ok_or("empty CSV")?.split(',').collect();
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|c| *c == name)
+            .ok_or(format!("CSV has no '{}' column", name))
+    };
+    let benchmark = column("benchmark")?;
+    let ncores = column("ncores")?;
+    let write_ratio = column("write_ratio")?;
+    let open_files = column("open_files")?;
+    let duration = column("duration")?;
+    let operations = column("operations")?;
+    let thread_mapping = column("thread_mapping").ok();
+
+    let mut totals: BTreeMap<ConfigKey, (u64, Vec<usize>)> = BTreeMap::new();
+    for line in lines.filter(|l| !l.trim().is_empty()) {
+        let fields: Vec<&str> = line.split(',').collect();
+        let field = |i: usize| {
+            fields
+                .get(i)
+                .map(|f| f.trim())
+                .ok_or(format!("short row '{}'", line))
+        };
+        let number = |i: usize| -> Result<usize, String> {
+            field(i)?
+                .parse()
+                .map_err(|e| format!("bad number in row '{}': {}", line, e))
+        };
+
+        let key = ConfigKey {
+            // Written with {:?}, so quoted
+            benchmark: field(benchmark)?.trim_matches('"').to_string(),
+            cores: number(ncores)?,
+            write_ratio: number(write_ratio)?,
+            open_files: number(open_files)?,
+            thread_mapping: match thread_mapping {
+                Some(i) => field(i)?.to_string(),
+                None => String::new(),
+            },
+        };
+        let (ops, seconds) = totals.entry(key).or_default();
+        *ops += number(operations)? as u64;
+        let second = number(duration)?;
+        if !seconds.contains(&second) {
+            seconds.push(second);
+        }
+    }
+
+    Ok(totals
+        .into_iter()
+        .map(|(key, (ops, seconds))| (key, ops as f64 / seconds.len() as f64))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fxmark::CSV_HEADER;
+
+    // Two threads, two seconds, `ops` operations per thread and second
+    fn rows(write_ratio: usize, ops: usize) -> String {
+        let mut rows = String::new();
+        for thread in 0..2 {
+            for second in 1..3 {
+                rows += &format!(
+                    "{},\"mix\",2,{},1,2,{},{},0,2,1,GRPC,0,4096,0,0,0,50.0,TM=Sequential,,0,,,,,\n",
+                    thread, write_ratio, second, ops
+                );
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn compare_flags_regressions() {
+        let dir = std::env::temp_dir().join("fxmark_compare_test");
+        fs::create_dir_all(&dir).unwrap();
+        let baseline = dir.join("baseline.csv");
+        let candidate = dir.join("candidate.csv");
+        fs::write(
+            &baseline,
+            CSV_HEADER.to_string() + &rows(0, 1000) + &rows(50, 1000),
+        )
+        .unwrap();
+        // 20% slower reads, 3% faster writes, one new configuration
+        fs::write(
+            &candidate,
+            CSV_HEADER.to_string() + &rows(0, 800) + &rows(50, 1030) + &rows(100, 500),
+        )
+        .unwrap();
+
+        let report = compare(&baseline, &candidate, 5.0).unwrap();
+        assert_eq!(report.deltas.len(), 2);
+
+        let reads = &report.deltas[0];
+        assert_eq!(reads.key.write_ratio, 0);
+        assert_eq!(reads.baseline, 2000.0);
+        assert_eq!(reads.candidate, 1600.0);
+        assert!((reads.delta_pct + 20.0).abs() < 1e-9);
+        assert!(reads.regressed);
+
+        let writes = &report.deltas[1];
+        assert!((writes.delta_pct - 3.0).abs() < 1e-9);
+        assert!(!writes.regressed);
+
+        assert_eq!(report.regressions().count(), 1);
+        assert_eq!(report.unmatched.len(), 1);
+        assert_eq!(report.unmatched[0].write_ratio, 100);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
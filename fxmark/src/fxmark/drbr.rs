@@ -0,0 +1,379 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+extern crate alloc;
+
+use crate::fxmark::{
+    align_samples, check_op, fill_file, measure_bucket, op_failed, open_target, paced, size_dist,
+    warm_up, Bench, PoorMansBarrier, Samples, SeededRng, PAGE_SIZE,
+};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+
+use crate::fxmark::fill::{PageVerifier, VerifyMode};
+use crate::fxmark::sizes::SizeDistribution;
+use crate::fxmark::utils::{lock_buffer, unlock_buffer};
+use crate::fxrpc::grpc::*;
+use crate::fxrpc::pipeline::{Completion, Pipeline};
+use crate::fxrpc::{init_client_for, BatchOp};
+
+/// Random-offset reads from a per-core file of `ClientParams::file_size` bytes.
+/// Unlike reads at offset 0 this defeats readahead and most caching above the
+/// page cache. With a `ClientParams::device` all cores read the first
+/// `file_size` bytes of the device instead, as far as it reaches. With a
+/// `ClientParams::queue_depth` above 1 each core keeps that many reads in
+/// flight, and an op is one read completing. Reads are a page each unless
+/// `ClientParams::io_size` says otherwise, at offsets aligned to their size.
+/// With a `ClientParams::size_dist` each read draws its size, and offsets
+/// are aligned to the largest size it draws.
+#[derive(Clone)]
+pub struct DRBR {
+    min_core: usize,
+    pages: usize,
+}
+
+impl Default for DRBR {
+    fn default() -> DRBR {
+        DRBR {
+            min_core: 0,
+            pages: 0,
+        }
+    }
+}
+
+impl DRBR {
+    /// Offset of the next read of `size` bytes, aligned to `size` and within
+    /// the first `blocks` of them.
+    fn next_offset(blocks: usize, size: usize, rng: &mut SeededRng) -> i64 {
+        ((rng.next_u64() as usize % blocks) * size) as i64
+    }
+
+    /// Reads a size drawn from `sizes` into `page`, at an offset aligned to
+    /// the largest size and within the first `blocks` of it, and returns the
+    /// offset. Fails like `check_op()` unless the whole size was read.
+    fn read_drawn(
+        client: &mut Box<dyn FxRPC>,
+        fd: i32,
+        sizes: &SizeDistribution,
+        blocks: usize,
+        rng: &mut SeededRng,
+        page: &mut Vec<u8>,
+    ) -> Result<i64, Option<i32>> {
+        let size = sizes.draw(rng);
+        let offset = DRBR::next_offset(blocks, sizes.max(), rng);
+        check_op(client.rpc_pread(fd, page, size, offset), size as i32)?;
+        Ok(offset)
+    }
+}
+
+// Whether a pipelined read completed with as many bytes as `sizes` can draw,
+// like `check_completion()` for one size
+fn check_completion_within(
+    completion: Completion,
+    sizes: &SizeDistribution,
+) -> Result<(), Option<i32>> {
+    match completion {
+        Ok(res) if res.result < 0 => Err(Some(-res.result)),
+        Ok(res) if (sizes.min()..=sizes.max()).contains(&(res.result as usize)) => Ok(()),
+        Ok(_) => Err(None),
+        Err(errno) => Err(errno),
+    }
+}
+
+impl Bench for DRBR {
+    fn init(&mut self, cores: Vec<u64>, _open_files: usize, client_params: &ClientParams) {
+        self.min_core = *cores.iter().min().unwrap() as usize;
+        self.pages = client_params.file_size / PAGE_SIZE;
+    }
+
+    fn run(
+        &self,
+        poor_mans_barrier: &PoorMansBarrier,
+        duration: u64,
+        core: usize,
+        _write_ratio: usize,
+        client_params: &ClientParams,
+    ) -> Samples {
+        let mut client = init_bench_client(client_params);
+
+        let mut iops_per_second = Vec::with_capacity(duration as usize);
+        let mut errors_per_second = Vec::with_capacity(duration as usize);
+        let mut errnos_per_second = Vec::with_capacity(duration as usize);
+        let mut unreliable_per_second = Vec::with_capacity(duration as usize);
+        let mut cpu_util_per_second = Vec::with_capacity(duration as usize);
+        let mut latency_per_second = Vec::with_capacity(duration as usize);
+
+        // Every clone of the bench gets init() for all cores, so each core
+        // writes its own file here, before the timed phase starts
+        let filename = format!("drbr{}.txt", core);
+        let (fd, device_size) = open_target(
+            &mut client,
+            client_params,
+            &filename,
+            client_params.open_flags,
+        );
+        let pages = match device_size {
+            // A device is read as it is
+            Some(size) => self.pages.min(size / PAGE_SIZE),
+            None => {
+                fill_file(&mut client, client_params, fd, core, 0, self.pages);
+                self.pages
+            }
+        };
+
+        // Reads of 0 bytes can start anywhere, larger ones don't run past
+        // the end of the file
+        let sizes = size_dist("drbr", client_params);
+        let max_size = sizes.max();
+        let blocks = (pages * PAGE_SIZE / max_size.max(1)).max(1);
+        let mut page: Vec<u8> = vec![0; max_size];
+        let locked = lock_buffer(client_params.lock_buffers, &page);
+        let mut rng = SeededRng::new(client_params.seed, core);
+        // A device holds what it holds, and pipelined reads complete out of
+        // order, so only page reads of the file one at a time are checked
+        let verify = if device_size.is_none()
+            && client_params.queue_depth <= 1
+            && sizes == SizeDistribution::Fixed(PAGE_SIZE)
+        {
+            client_params.verify
+        } else {
+            VerifyMode::Off
+        };
+        let mut verifier = PageVerifier::new(
+            verify,
+            &client_params.fill_pattern,
+            client_params.seed,
+            core,
+            pages,
+        );
+        let read = |size, offset| BatchOp::PRead {
+            fd: fd,
+            size: size,
+            offset: offset,
+        };
+        let mut pipeline = if client_params.queue_depth > 1 {
+            let params = client_params.clone();
+            let connect = move || init_client_for(&params.endpoint, &params);
+            let mut pipeline = Pipeline::new(client_params.queue_depth, connect);
+            // Fill the queue up to one read short, so every op sends one read
+            // and gets another one's completion
+            while pipeline.in_flight() + 1 < pipeline.depth() {
+                let size = sizes.draw(&mut rng);
+                pipeline.submit(read(size, DRBR::next_offset(blocks, max_size, &mut rng)));
+            }
+            Some(pipeline)
+        } else {
+            None
+        };
+        let mut next_op = paced(client_params, || {
+            let res = match &mut pipeline {
+                // The completion may be of an earlier read of another size,
+                // so drawn sizes only check it reads as much as one can be
+                Some(pipeline) => {
+                    let size = sizes.draw(&mut rng);
+                    let offset = DRBR::next_offset(blocks, max_size, &mut rng);
+                    check_completion_within(
+                        pipeline
+                            .submit(read(size, offset))
+                            .expect("Pipeline isn't full"),
+                        &sizes,
+                    )
+                    .map(|()| offset)
+                }
+                None => DRBR::read_drawn(&mut client, fd, &sizes, blocks, &mut rng, &mut page),
+            };
+            match res {
+                Ok(offset) if verifier.check(&page, offset as usize).is_err() => {
+                    op_failed(client_params, None, "DRBR: read_at() returned other data");
+                    false
+                }
+                Ok(_) => true,
+                Err(errno) => {
+                    op_failed(client_params, errno, "DRBR: read_at() failed");
+                    false
+                }
+            }
+        });
+        warm_up(client_params.warmup_ops, &mut next_op);
+
+        // Synchronize with all cores
+        poor_mans_barrier.wait_start();
+        let first_second = align_samples(client_params.align_samples_ms);
+
+        let mut iterations = 0;
+        while iterations <= duration {
+            let (iops, errors, unreliable, cpu_util, errnos, latency) =
+                measure_bucket(client_params.min_samples, client_params.rerun_short, || {
+                    let mut iops = 0;
+                    let mut errors = 0;
+                    let start = std::time::Instant::now();
+                    while start.elapsed().as_secs() < 1 {
+                        if next_op() {
+                            iops += 1;
+                        } else {
+                            errors += 1;
+                        }
+                    }
+                    (iops, errors)
+                });
+
+            iops_per_second.push(iops);
+            errors_per_second.push(errors);
+            errnos_per_second.push(errnos);
+            unreliable_per_second.push(unreliable);
+            cpu_util_per_second.push(cpu_util);
+            latency_per_second.push(latency);
+            iterations += 1;
+        }
+
+        poor_mans_barrier.wait_end();
+
+        // The reads still in flight don't count, but must end before the close
+        if let Some(mut pipeline) = pipeline {
+            pipeline.drain();
+        }
+        client.rpc_close(fd).expect("FileClose syscall failed");
+        if !client_params.keep_files && !client_params.target_is_device() {
+            client
+                .rpc_remove(&filename)
+                .expect("FileRemove syscall failed");
+        }
+
+        if core == self.min_core {
+            let start = std::time::Instant::now();
+            while start.elapsed().as_secs() < 1 {}
+        }
+        unlock_buffer(locked, &page);
+        Samples {
+            iops: iops_per_second,
+            errors: errors_per_second,
+            errnos: errnos_per_second,
+            unreliable: unreliable_per_second,
+            cpu_util: cpu_util_per_second,
+            latency: latency_per_second,
+            start: Some(first_second),
+            ..Default::default()
+        }
+    }
+}
+
+register_bench!("drbr", DRBR, PAGE_SIZE);
+
+#[cfg(test)]
+mod tests {
+    use super::{SeededRng, SizeDistribution, DRBR, PAGE_SIZE};
+    use crate::fxrpc::FxRPC;
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+    use std::rc::Rc;
+
+    // A server that keeps the size and offset of every read and reads one
+    // byte short if `short`
+    struct Reads {
+        reads: Rc<RefCell<Vec<(usize, i64)>>>,
+        short: bool,
+    }
+
+    impl FxRPC for Reads {
+        fn rpc_pread(
+            &mut self,
+            _fd: i32,
+            _page: &mut Vec<u8>,
+            size: usize,
+            offset: i64,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            self.reads.borrow_mut().push((size, offset));
+            Ok(size as i32 - self.short as i32)
+        }
+
+        unimplemented_ops!(
+            rpc_open,
+            rpc_read,
+            rpc_write,
+            rpc_pwrite,
+            rpc_close,
+            rpc_fsync,
+            rpc_ftruncate,
+            rpc_remove,
+            rpc_rename,
+            rpc_mkdir,
+            rpc_rmdir,
+            rpc_fadvise,
+            rpc_drop_caches,
+            rpc_fstatat,
+            rpc_sendfile,
+            rpc_lseek,
+            rpc_batch,
+            rpc_dump_server_log,
+            rpc_server_stats,
+            rpc_capabilities,
+            rpc_reset
+        );
+    }
+
+    #[test]
+    fn reads_spread_over_file() {
+        let mut rng = SeededRng::new(42, 3);
+        let offsets: Vec<i64> = (0..1000)
+            .map(|_| DRBR::next_offset(4096, PAGE_SIZE, &mut rng))
+            .collect();
+        assert!(offsets
+            .iter()
+            .all(|o| *o % PAGE_SIZE as i64 == 0 && *o < (4096 * PAGE_SIZE) as i64));
+        let distinct: HashSet<&i64> = offsets.iter().collect();
+        assert!(
+            distinct.len() > 800,
+            "only {} distinct offsets",
+            distinct.len()
+        );
+
+        // Same seed and core, same reads
+        let mut rng = SeededRng::new(42, 3);
+        assert!(offsets
+            .iter()
+            .all(|o| *o == DRBR::next_offset(4096, PAGE_SIZE, &mut rng)));
+    }
+
+    #[test]
+    fn reads_draw_their_sizes() {
+        let sizes = SizeDistribution::Uniform(512, 4096);
+        let file_size = 64 * PAGE_SIZE;
+        let reads = Rc::new(RefCell::new(Vec::new()));
+        let mut client: Box<dyn FxRPC> = Box::new(Reads {
+            reads: Rc::clone(&reads),
+            short: false,
+        });
+        let mut rng = SeededRng::new(42, 0);
+        let mut page = vec![0; sizes.max()];
+        let blocks = file_size / sizes.max();
+        for _ in 0..1000 {
+            let offset = DRBR::read_drawn(&mut client, 3, &sizes, blocks, &mut rng, &mut page);
+            assert_eq!(offset, Ok(reads.borrow().last().unwrap().1));
+        }
+
+        // Offsets are aligned to the largest size, so no read runs past the end
+        for (size, offset) in reads.borrow().iter() {
+            assert!((512..=4096).contains(size), "{}", size);
+            assert_eq!(offset % 4096, 0);
+            assert!(
+                *offset as usize + size <= file_size,
+                "{} at {}",
+                size,
+                offset
+            );
+        }
+        let distinct: HashSet<usize> = reads.borrow().iter().map(|(size, _)| *size).collect();
+        assert!(distinct.len() > 500, "only {} sizes", distinct.len());
+
+        // A read that returns other than its drawn size fails
+        let mut client: Box<dyn FxRPC> = Box::new(Reads {
+            reads: Rc::new(RefCell::new(Vec::new())),
+            short: true,
+        });
+        assert_eq!(
+            DRBR::read_drawn(&mut client, 3, &sizes, blocks, &mut rng, &mut page),
+            Err(None)
+        );
+    }
+}
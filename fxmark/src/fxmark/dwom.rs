@@ -0,0 +1,212 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+extern crate alloc;
+
+use crate::fxmark::{
+    align_samples, check_op, fill_file, measure_bucket, op_failed, open_target, paced, warm_up,
+    write_synced, Bench, PoorMansBarrier, Samples, SeededRng, PAGE_SIZE,
+};
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use libc::c_int;
+
+use crate::fxmark::utils::{lock_buffer, unlock_buffer};
+use crate::fxrpc::grpc::*;
+
+/// Overwrites of a random one of `open_files` files per core, all in the
+/// shared server directory. Jumping between files defeats per-file cache
+/// locality. `ClientParams::write_mode` turns the overwrites into appends
+/// or sequential writes. With a `ClientParams::device` every file is the
+/// device, written like a preallocated file of `file_size` bytes at most.
+#[derive(Clone)]
+pub struct DWOM {
+    min_core: usize,
+    open_files: usize,
+}
+
+impl Default for DWOM {
+    fn default() -> DWOM {
+        DWOM {
+            min_core: 0,
+            open_files: 0,
+        }
+    }
+}
+
+impl DWOM {
+    /// Index of the file the next overwrite goes to.
+    fn next_file(&self, rng: &mut SeededRng) -> usize {
+        rng.next_u64() as usize % self.open_files
+    }
+}
+
+impl Bench for DWOM {
+    fn init(&mut self, cores: Vec<u64>, open_files: usize, _client_params: &ClientParams) {
+        self.min_core = *cores.iter().min().unwrap() as usize;
+        self.open_files = open_files;
+    }
+
+    fn run(
+        &self,
+        poor_mans_barrier: &PoorMansBarrier,
+        duration: u64,
+        core: usize,
+        _write_ratio: usize,
+        client_params: &ClientParams,
+    ) -> Samples {
+        let mut client = init_bench_client(client_params);
+
+        let mut iops_per_second = Vec::with_capacity(duration as usize);
+        let mut errors_per_second = Vec::with_capacity(duration as usize);
+        let mut errnos_per_second = Vec::with_capacity(duration as usize);
+        let mut unreliable_per_second = Vec::with_capacity(duration as usize);
+        let mut cpu_util_per_second = Vec::with_capacity(duration as usize);
+        let mut latency_per_second = Vec::with_capacity(duration as usize);
+
+        // Every clone of the bench gets init() for all cores, so each core
+        // creates its own files here, before the timed phase starts
+        let flags = client_params.open_flags
+            | client_params.sync_policy.open_flags()
+            | client_params.write_mode.open_flags();
+        let mut files: Vec<(String, c_int, i64)> = Vec::with_capacity(self.open_files);
+        let mut limit = client_params.file_size as i64;
+        for n in 0..self.open_files {
+            let filename = format!("dwom{}_{}.txt", core, n);
+            let (fd, device_size) = open_target(&mut client, client_params, &filename, flags);
+            let size = match device_size {
+                // Full already, and it can't grow
+                Some(size) => {
+                    limit = limit.min((size / PAGE_SIZE * PAGE_SIZE) as i64);
+                    limit
+                }
+                None => {
+                    let pages = if client_params.preallocate {
+                        client_params.file_size / PAGE_SIZE
+                    } else {
+                        1
+                    };
+                    fill_file(
+                        &mut client,
+                        client_params,
+                        fd,
+                        core * self.open_files + n,
+                        0,
+                        pages,
+                    );
+                    (pages * PAGE_SIZE) as i64
+                }
+            };
+            files.push((filename, fd, size));
+        }
+
+        let page: Vec<u8> = vec![0xc; PAGE_SIZE as usize];
+        let locked = lock_buffer(client_params.lock_buffers, &page);
+        let mut rng = SeededRng::new(client_params.seed, core);
+        let mut writes = 0;
+        let mut next_op = paced(client_params, || {
+            let (_, fd, size) = &mut files[self.next_file(&mut rng)];
+            let (offset, truncate) = client_params.write_mode.next_write(*size, limit);
+            let mut res = Ok(());
+            // A preallocated file or a device keeps its size, the writes
+            // just start over
+            if truncate {
+                if !client_params.preallocate && !client_params.target_is_device() {
+                    res = check_op(client.rpc_ftruncate(*fd, 0), 0);
+                }
+                if res.is_ok() {
+                    *size = 0;
+                }
+            }
+            if res.is_ok() {
+                res = write_synced(&mut client, client_params, &mut writes, *fd, &page, offset);
+            }
+            match res {
+                Ok(()) => *size = (*size).max(offset + PAGE_SIZE as i64),
+                Err(errno) => op_failed(client_params, errno, "DWOM: write_at() failed"),
+            }
+            res.is_ok()
+        });
+        warm_up(client_params.warmup_ops, &mut next_op);
+
+        // Synchronize with all cores
+        poor_mans_barrier.wait_start();
+        let first_second = align_samples(client_params.align_samples_ms);
+
+        let mut iterations = 0;
+        while iterations <= duration {
+            let (iops, errors, unreliable, cpu_util, errnos, latency) =
+                measure_bucket(client_params.min_samples, client_params.rerun_short, || {
+                    let mut iops = 0;
+                    let mut errors = 0;
+                    let start = std::time::Instant::now();
+                    while start.elapsed().as_secs() < 1 {
+                        if next_op() {
+                            iops += 1;
+                        } else {
+                            errors += 1;
+                        }
+                    }
+                    (iops, errors)
+                });
+
+            iops_per_second.push(iops);
+            errors_per_second.push(errors);
+            errnos_per_second.push(errnos);
+            unreliable_per_second.push(unreliable);
+            cpu_util_per_second.push(cpu_util);
+            latency_per_second.push(latency);
+            iterations += 1;
+        }
+
+        poor_mans_barrier.wait_end();
+
+        for (filename, fd, _) in &files {
+            client.rpc_close(*fd).expect("FileClose syscall failed");
+            if !client_params.keep_files && !client_params.target_is_device() {
+                client
+                    .rpc_remove(filename)
+                    .expect("FileRemove syscall failed");
+            }
+        }
+
+        if core == self.min_core {
+            let start = std::time::Instant::now();
+            while start.elapsed().as_secs() < 1 {}
+        }
+        unlock_buffer(locked, &page);
+        Samples {
+            iops: iops_per_second,
+            errors: errors_per_second,
+            errnos: errnos_per_second,
+            unreliable: unreliable_per_second,
+            cpu_util: cpu_util_per_second,
+            latency: latency_per_second,
+            start: Some(first_second),
+            ..Default::default()
+        }
+    }
+}
+
+register_bench!("dwom", DWOM, PAGE_SIZE);
+
+#[cfg(test)]
+mod tests {
+    use super::{SeededRng, DWOM};
+
+    #[test]
+    fn overwrites_hit_every_file() {
+        let dwom = DWOM {
+            open_files: 16,
+            ..Default::default()
+        };
+
+        let mut rng = SeededRng::new(7, 0);
+        let mut hits = vec![0; 16];
+        for _ in 0..1600 {
+            hits[dwom.next_file(&mut rng)] += 1;
+        }
+        assert!(hits.iter().all(|h| *h > 50), "{:?}", hits);
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+extern crate alloc;
+
+use crate::fxmark::{
+    align_samples, check_op, fill_file, measure_bucket, op_failed, paced, warm_up, Bench,
+    PoorMansBarrier, Samples, PAGE_SIZE,
+};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use libc::S_IRWXU;
+
+use crate::fxmark::utils::{lock_buffer, unlock_buffer};
+use crate::fxrpc::grpc::*;
+
+/// Overwrites the first page of `fd` and fsyncs it. Fails like `check_op()`
+/// if either the write or the sync did.
+pub(crate) fn write_then_sync(
+    client: &mut Box<dyn FxRPC>,
+    fd: i32,
+    page: &[u8],
+) -> Result<(), Option<i32>> {
+    check_op(
+        client.rpc_pwrite(fd, page, page.len(), 0),
+        page.len() as i32,
+    )?;
+    check_op(client.rpc_fsync(fd), 0)
+}
+
+/// Overwrites of the first page of a private per-core file, each followed by
+/// an fsync. Every op is one write and sync pair, whatever
+/// `ClientParams::sync_policy` says, so the throughput shows what the
+/// server's file system takes to make a page durable.
+#[derive(Clone)]
+pub struct DWSL {
+    min_core: usize,
+}
+
+impl Default for DWSL {
+    fn default() -> DWSL {
+        DWSL { min_core: 0 }
+    }
+}
+
+impl Bench for DWSL {
+    fn init(&mut self, cores: Vec<u64>, _open_files: usize, _client_params: &ClientParams) {
+        self.min_core = *cores.iter().min().unwrap() as usize;
+    }
+
+    fn run(
+        &self,
+        poor_mans_barrier: &PoorMansBarrier,
+        duration: u64,
+        core: usize,
+        _write_ratio: usize,
+        client_params: &ClientParams,
+    ) -> Samples {
+        let mut client = init_bench_client(client_params);
+
+        let mut iops_per_second = Vec::with_capacity(duration as usize);
+        let mut errors_per_second = Vec::with_capacity(duration as usize);
+        let mut errnos_per_second = Vec::with_capacity(duration as usize);
+        let mut unreliable_per_second = Vec::with_capacity(duration as usize);
+        let mut cpu_util_per_second = Vec::with_capacity(duration as usize);
+        let mut latency_per_second = Vec::with_capacity(duration as usize);
+
+        // Every clone of the bench gets init() for all cores, so each core
+        // creates its own file here, before the timed phase starts
+        let filename = format!("dwsl{}.txt", core);
+        let fd = client
+            .rpc_open(&filename, client_params.open_flags, S_IRWXU.into())
+            .expect("FileOpen syscall failed");
+        assert!(fd != -1, "Unable to create a file");
+        fill_file(&mut client, client_params, fd, core, 0, 1);
+        client.rpc_fsync(fd).expect("FileSync syscall failed");
+
+        let page: Vec<u8> = vec![0xc; PAGE_SIZE as usize];
+        let locked = lock_buffer(client_params.lock_buffers, &page);
+        let mut next_op = paced(client_params, || {
+            let res = write_then_sync(&mut client, fd, &page);
+            if let Err(errno) = res {
+                op_failed(client_params, errno, "DWSL: write_at() or fsync() failed");
+            }
+            res.is_ok()
+        });
+        warm_up(client_params.warmup_ops, &mut next_op);
+
+        // Synchronize with all cores
+        poor_mans_barrier.wait_start();
+        let first_second = align_samples(client_params.align_samples_ms);
+
+        let mut iterations = 0;
+        while iterations <= duration {
+            let (iops, errors, unreliable, cpu_util, errnos, latency) =
+                measure_bucket(client_params.min_samples, client_params.rerun_short, || {
+                    let mut iops = 0;
+                    let mut errors = 0;
+                    let start = std::time::Instant::now();
+                    while start.elapsed().as_secs() < 1 {
+                        if next_op() {
+                            iops += 1;
+                        } else {
+                            errors += 1;
+                        }
+                    }
+                    (iops, errors)
+                });
+
+            iops_per_second.push(iops);
+            errors_per_second.push(errors);
+            errnos_per_second.push(errnos);
+            unreliable_per_second.push(unreliable);
+            cpu_util_per_second.push(cpu_util);
+            latency_per_second.push(latency);
+            iterations += 1;
+        }
+
+        poor_mans_barrier.wait_end();
+
+        client.rpc_close(fd).expect("FileClose syscall failed");
+        if !client_params.keep_files {
+            client
+                .rpc_remove(&filename)
+                .expect("FileRemove syscall failed");
+        }
+
+        if core == self.min_core {
+            let start = std::time::Instant::now();
+            while start.elapsed().as_secs() < 1 {}
+        }
+        unlock_buffer(locked, &page);
+        Samples {
+            iops: iops_per_second,
+            errors: errors_per_second,
+            errnos: errnos_per_second,
+            unreliable: unreliable_per_second,
+            cpu_util: cpu_util_per_second,
+            latency: latency_per_second,
+            start: Some(first_second),
+            ..Default::default()
+        }
+    }
+}
+
+register_bench!("dwsl", DWSL, PAGE_SIZE);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fxmark::write_synced;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    const WRITE_NS: u64 = 2_000;
+    const SYNC_NS: u64 = 500_000;
+
+    // A server on storage where a write only reaches the page cache and a
+    // sync goes to the disk, keeping the time its calls would have taken
+    // instead of spending it
+    #[derive(Default)]
+    struct Disk {
+        busy_ns: Rc<Cell<u64>>,
+        writes: Rc<Cell<u64>>,
+        syncs: Rc<Cell<u64>>,
+    }
+
+    impl FxRPC for Disk {
+        fn rpc_pwrite(
+            &mut self,
+            _fd: i32,
+            _page: &[u8],
+            size: usize,
+            _offset: i64,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            self.busy_ns.set(self.busy_ns.get() + WRITE_NS);
+            self.writes.set(self.writes.get() + 1);
+            Ok(size as i32)
+        }
+
+        fn rpc_fsync(&mut self, _fd: i32) -> Result<i32, Box<dyn std::error::Error>> {
+            self.busy_ns.set(self.busy_ns.get() + SYNC_NS);
+            self.syncs.set(self.syncs.get() + 1);
+            Ok(0)
+        }
+
+        unimplemented_ops!(
+            rpc_open,
+            rpc_read,
+            rpc_pread,
+            rpc_write,
+            rpc_close,
+            rpc_ftruncate,
+            rpc_remove,
+            rpc_rename,
+            rpc_mkdir,
+            rpc_rmdir,
+            rpc_fadvise,
+            rpc_drop_caches,
+            rpc_fstatat,
+            rpc_sendfile,
+            rpc_lseek,
+            rpc_batch,
+            rpc_dump_server_log,
+            rpc_server_stats,
+            rpc_capabilities,
+            rpc_reset
+        );
+    }
+
+    #[test]
+    fn sync_dominates_the_op() {
+        let ops = 1_000;
+        let page = vec![0; PAGE_SIZE];
+
+        // DWSL's ops
+        let (busy_ns, writes, syncs) = (
+            Rc::new(Cell::new(0)),
+            Rc::new(Cell::new(0)),
+            Rc::new(Cell::new(0)),
+        );
+        let mut client: Box<dyn FxRPC> = Box::new(Disk {
+            busy_ns: Rc::clone(&busy_ns),
+            writes: Rc::clone(&writes),
+            syncs: Rc::clone(&syncs),
+        });
+        for _ in 0..ops {
+            assert!(write_then_sync(&mut client, 3, &page).is_ok());
+        }
+        // One op is one write and sync pair
+        assert_eq!((writes.get(), syncs.get()), (ops, ops));
+        let synced_iops = ops * 1_000_000_000 / busy_ns.get();
+
+        // The same writes as DWOM makes them without a sync policy
+        let (busy_ns, syncs) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let mut client: Box<dyn FxRPC> = Box::new(Disk {
+            busy_ns: Rc::clone(&busy_ns),
+            syncs: Rc::clone(&syncs),
+            ..Default::default()
+        });
+        let client_params = crate::fxmark::tests::client_params_for("/");
+        let mut writes = 0;
+        for _ in 0..ops {
+            assert!(write_synced(&mut client, &client_params, &mut writes, 3, &page, 0).is_ok());
+        }
+        assert_eq!(syncs.get(), 0);
+        let unsynced_iops = ops * 1_000_000_000 / busy_ns.get();
+
+        assert!(
+            unsynced_iops > 100 * synced_iops,
+            "{} vs {} IOPS",
+            unsynced_iops,
+            synced_iops
+        );
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! What benches fill their files with. File systems with transparent
+//! compression or deduplication store (and read) very different amounts of
+//! data depending on it.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::str::FromStr;
+use serde::Serialize;
+
+use crate::fxmark::{SeededRng, PAGE_SIZE};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum FillPattern {
+    Zero,
+    /// 0xff bytes.
+    Ones,
+    /// Incompressible bytes, reproducible from `--seed`. No two files or
+    /// pages are alike.
+    Random,
+    /// A byte sequence repeated over the whole file.
+    Repeat(Vec<u8>),
+}
+
+impl Default for FillPattern {
+    /// The 0xb bytes benches always wrote.
+    fn default() -> FillPattern {
+        FillPattern::Repeat(vec![0xb])
+    }
+}
+
+impl FromStr for FillPattern {
+    type Err = String;
+
+    /// `zero`, `ones`, `random` or `repeat=HEX`, e.g. `repeat=deadbeef`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => return Ok(FillPattern::Zero),
+            "ones" => return Ok(FillPattern::Ones),
+            "random" => return Ok(FillPattern::Random),
+            _ => {}
+        }
+        let hex = s.strip_prefix("repeat=").ok_or(format!(
+            "Unknown fill pattern '{}', expected zero, ones, random or repeat=HEX",
+            s
+        ))?;
+        if hex.is_empty() || hex.len() % 2 != 0 {
+            return Err(format!(
+                "Repeated sequence '{}' must be a whole number of hex bytes",
+                hex
+            ));
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|e| format!("Invalid hex in '{}': {}", hex, e))?;
+        Ok(FillPattern::Repeat(bytes))
+    }
+}
+
+impl FillPattern {
+    /// Fills `buf` with the bytes at `offset` of file `file`. `seed` and
+    /// `file` pick the random bytes.
+    pub fn fill(&self, buf: &mut [u8], offset: usize, seed: u64, file: usize) {
+        match self {
+            FillPattern::Zero => buf.fill(0),
+            FillPattern::Ones => buf.fill(0xff),
+            FillPattern::Random => {
+                let mut rng = SeededRng::new(seed.wrapping_add(offset as u64), file);
+                for chunk in buf.chunks_mut(8) {
+                    let bytes = rng.next_u64().to_le_bytes();
+                    chunk.copy_from_slice(&bytes[..chunk.len()]);
+                }
+            }
+            FillPattern::Repeat(seq) => {
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = seq[(offset + i) % seq.len()];
+                }
+            }
+        }
+    }
+}
+
+/// How benches that read back what they filled their files with check it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum VerifyMode {
+    /// Reads aren't checked.
+    Off,
+    /// The CRC32 of every page read is compared to that of the page filled
+    /// there, computed before the timed phase.
+    Checksum,
+    /// Every page read is compared byte by byte to the page filled there,
+    /// which is made again for each read.
+    Full,
+}
+
+impl Default for VerifyMode {
+    fn default() -> VerifyMode {
+        VerifyMode::Off
+    }
+}
+
+impl FromStr for VerifyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(VerifyMode::Off),
+            "checksum" => Ok(VerifyMode::Checksum),
+            "full" => Ok(VerifyMode::Full),
+            _ => Err(format!(
+                "Unknown verify mode '{}', expected off, checksum or full",
+                s
+            )),
+        }
+    }
+}
+
+/// Checks pages read back from a file that `fill_file()` filled with a
+/// `FillPattern`.
+pub struct PageVerifier {
+    mode: VerifyMode,
+    pattern: FillPattern,
+    seed: u64,
+    file: usize,
+    /// CRC32 of each page of the file, in `Checksum` mode.
+    checksums: Vec<u32>,
+    /// The page expected at the offset being checked, in `Full` mode.
+    expected: Vec<u8>,
+}
+
+impl PageVerifier {
+    /// A verifier for the first `pages` pages of file `file`, filled with
+    /// `pattern` from `seed`.
+    pub fn new(
+        mode: VerifyMode,
+        pattern: &FillPattern,
+        seed: u64,
+        file: usize,
+        pages: usize,
+    ) -> PageVerifier {
+        let mut expected = vec![0; PAGE_SIZE];
+        let checksums = match mode {
+            VerifyMode::Checksum => (0..pages)
+                .map(|n| {
+                    pattern.fill(&mut expected, n * PAGE_SIZE, seed, file);
+                    crc32fast::hash(&expected)
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        PageVerifier {
+            mode,
+            pattern: pattern.clone(),
+            seed,
+            file,
+            checksums,
+            expected,
+        }
+    }
+
+    /// Whether `page`, read at the page-aligned `offset`, is what was filled
+    /// there. Fails like `check_op()`, without an errno.
+    pub fn check(&mut self, page: &[u8], offset: usize) -> Result<(), Option<i32>> {
+        let intact = match self.mode {
+            VerifyMode::Off => true,
+            VerifyMode::Checksum => {
+                Some(&crc32fast::hash(page)) == self.checksums.get(offset / PAGE_SIZE)
+            }
+            VerifyMode::Full => {
+                self.pattern
+                    .fill(&mut self.expected, offset, self.seed, self.file);
+                page == &self.expected[..]
+            }
+        };
+        if intact {
+            Ok(())
+        } else {
+            Err(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fill_patterns() {
+        assert_eq!("zero".parse(), Ok(FillPattern::Zero));
+        assert_eq!("random".parse(), Ok(FillPattern::Random));
+        assert_eq!(
+            "repeat=dead0b".parse(),
+            Ok(FillPattern::Repeat(vec![0xde, 0xad, 0x0b]))
+        );
+        assert!("repeat=".parse::<FillPattern>().is_err());
+        assert!("repeat=abc".parse::<FillPattern>().is_err());
+        assert!("repeat=zz".parse::<FillPattern>().is_err());
+        assert!("compressible".parse::<FillPattern>().is_err());
+    }
+
+    #[test]
+    fn repeat_continues_across_pages() {
+        let pattern = FillPattern::Repeat(vec![1, 2, 3]);
+        let mut page = [0; 4];
+        pattern.fill(&mut page, 4, 0, 0);
+        assert_eq!(page, [2, 3, 1, 2]);
+    }
+
+    #[test]
+    fn checksum_catches_a_flipped_byte() {
+        let pattern = FillPattern::Random;
+        let mut verifier = PageVerifier::new(VerifyMode::Checksum, &pattern, 7, 2, 16);
+        let mut page = vec![0; PAGE_SIZE];
+        pattern.fill(&mut page, 5 * PAGE_SIZE, 7, 2);
+        assert_eq!(verifier.check(&page, 5 * PAGE_SIZE), Ok(()));
+        // Nor does it pass as the page of another offset
+        assert_eq!(verifier.check(&page, 6 * PAGE_SIZE), Err(None));
+
+        page[PAGE_SIZE / 3] ^= 0x10;
+        assert_eq!(verifier.check(&page, 5 * PAGE_SIZE), Err(None));
+        let mut full = PageVerifier::new(VerifyMode::Full, &pattern, 7, 2, 16);
+        assert_eq!(full.check(&page, 5 * PAGE_SIZE), Err(None));
+        let mut off = PageVerifier::new(VerifyMode::Off, &pattern, 7, 2, 16);
+        assert_eq!(off.check(&page, 5 * PAGE_SIZE), Ok(()));
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Client-side op latencies, and the pacing that holds bench threads to a
+//! target rate instead of running ops back to back.
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use std::time::{Duration, Instant};
+
+// 16 buckets per power of two, so a bucket is within 6.25% of its values
+const SUB_BUCKET_BITS: u32 = 4;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+/// Log-linear histogram of latencies in ns.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LatencyHistogram {
+    buckets: Vec<u64>,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> LatencyHistogram {
+        LatencyHistogram {
+            buckets: vec![0; BUCKETS],
+        }
+    }
+}
+
+// Values below SUB_BUCKETS get a bucket each, larger ones share a bucket with
+// the values that agree in their top SUB_BUCKET_BITS + 1 bits
+fn bucket_of(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS as u64 {
+        return nanos as usize;
+    }
+    let exp = 63 - nanos.leading_zeros();
+    let sub = (nanos >> (exp - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+    (exp - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS + sub
+}
+
+// The largest value that falls in `bucket`
+fn bucket_max(bucket: usize) -> u64 {
+    if bucket < SUB_BUCKETS {
+        return bucket as u64;
+    }
+    let shift = (bucket / SUB_BUCKETS) as u32 - 1;
+    let min = ((SUB_BUCKETS + bucket % SUB_BUCKETS) as u64) << shift;
+    min + ((1u64 << shift) - 1)
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let nanos = latency.as_nanos().min(u64::MAX as u128) as u64;
+        self.buckets[bucket_of(nanos)] += 1;
+    }
+
+    /// Adds the latencies of `other`.
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (b, o) in self.buckets.iter_mut().zip(&other.buckets) {
+            *b += o;
+        }
+    }
+
+    /// Number of latencies recorded.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// The latency in ns that `quantile` (0.0 to 1.0) of the recorded ones
+    /// don't exceed, rounded up to its bucket. 0 if nothing was recorded.
+    pub fn quantile(&self, quantile: f64) -> u64 {
+        let count = self.count();
+        if count == 0 {
+            return 0;
+        }
+        let rank = ((count as f64 * quantile).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+        for (bucket, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return bucket_max(bucket);
+            }
+        }
+        unreachable!()
+    }
+}
+
+/// The schedule and latencies of a bench thread's ops in the second being
+/// measured.
+#[derive(Default)]
+pub struct OpClock {
+    start: Option<Instant>,
+    started: u64,
+    pub latency: LatencyHistogram,
+}
+
+impl OpClock {
+    /// Starts the schedule over and forgets the latencies.
+    pub fn reset(&mut self) {
+        *self = OpClock::default();
+    }
+
+    /// Waits until the next op is due, with ops evenly spaced at `target_iops`
+    /// per second from the first one on, and returns when it was due.
+    /// Without a target every op is due right away.
+    pub fn wait(&mut self, target_iops: Option<u64>) -> Instant {
+        let now = Instant::now();
+        let target_iops = match target_iops {
+            Some(target_iops) => target_iops,
+            None => return now,
+        };
+        let start = *self.start.get_or_insert(now);
+        let due = start + Duration::from_nanos(self.started * 1_000_000_000 / target_iops);
+        self.started += 1;
+        if due > now {
+            std::thread::sleep(due - now);
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_cover_all_latencies() {
+        for nanos in [0, 1, 15, 16, 17, 31, 32, 1000, 123_456_789, u64::MAX] {
+            let bucket = bucket_of(nanos);
+            assert!(bucket < BUCKETS);
+            assert!(nanos <= bucket_max(bucket), "{}", nanos);
+            if bucket > 0 {
+                assert!(nanos > bucket_max(bucket - 1), "{}", nanos);
+            }
+        }
+        assert_eq!(bucket_max(BUCKETS - 1), u64::MAX);
+    }
+
+    #[test]
+    fn quantiles_of_recorded_latencies() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.quantile(0.99), 0);
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros));
+        }
+
+        let p50 = histogram.quantile(0.5);
+        assert!((50_000..50_000 * 17 / 16).contains(&p50), "{}", p50);
+        let p99 = histogram.quantile(0.99);
+        assert!((99_000..99_000 * 17 / 16).contains(&p99), "{}", p99);
+
+        let mut merged = histogram.clone();
+        merged.merge(&histogram);
+        assert_eq!(merged.count(), 200);
+        assert_eq!(merged.quantile(0.99), p99);
+    }
+
+    #[test]
+    fn ops_are_spaced_at_the_target_rate() {
+        let mut clock = OpClock::default();
+        let first = clock.wait(Some(100));
+        for _ in 0..4 {
+            clock.wait(Some(100));
+        }
+        // The fifth op was due 40 ms after the first
+        assert!(first.elapsed() >= Duration::from_millis(40));
+
+        clock.reset();
+        let start = Instant::now();
+        for _ in 0..100 {
+            clock.wait(None);
+        }
+        assert!(start.elapsed() < Duration::from_millis(40));
+    }
+}
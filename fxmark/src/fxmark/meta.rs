@@ -0,0 +1,127 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Describes the environment a run was measured in, so archived results can
+//! be reproduced.
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use serde::Serialize;
+use std::path::Path;
+
+use crate::fxrpc::ClientParams;
+
+/// Name of the metadata file written next to the results.
+pub const RUN_META_FILE: &str = "run_meta.json";
+
+/// Everything that went into a run besides the results themselves.
+#[derive(Clone, Serialize)]
+pub struct RunMetadata {
+    pub benchmark: String,
+    /// Kernel release, as in `uname -r`.
+    pub kernel: String,
+    /// File system type of the benchmark directory, "remote" if the server
+    /// runs on another host.
+    pub fs_type: String,
+    pub cpu_model: String,
+    /// Commit the client was built from.
+    pub git_hash: String,
+    pub client_params: ClientParams,
+}
+
+impl RunMetadata {
+    /// Gathers the metadata of a run of `benchmark` with its files in
+    /// `base_path`, None if that directory is on another host.
+    pub fn gather(
+        benchmark: &str,
+        base_path: Option<&Path>,
+        client_params: &ClientParams,
+    ) -> RunMetadata {
+        RunMetadata {
+            benchmark: benchmark.to_string(),
+            kernel: kernel_version(),
+            fs_type: match base_path {
+                Some(path) => fs_type(path),
+                None => "remote".to_string(),
+            },
+            cpu_model: cpu_model(),
+            git_hash: env!("FXMARK_GIT_HASH").to_string(),
+            client_params: client_params.clone(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Can't serialize run metadata")
+    }
+
+    /// Writes the metadata to `RUN_META_FILE` in `dir`.
+    pub fn write(&self, dir: &Path) -> std::io::Result<()> {
+        std::fs::write(dir.join(RUN_META_FILE), self.to_json())
+    }
+}
+
+fn kernel_version() -> String {
+    let mut uts = unsafe { std::mem::zeroed::<libc::utsname>() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return "unknown".to_string();
+    }
+    let release = unsafe { std::ffi::CStr::from_ptr(uts.release.as_ptr()) };
+    release.to_string_lossy().into_owned()
+}
+
+// The type of the longest mount point in /proc/mounts that contains `path`
+fn fs_type(path: &Path) -> String {
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        Err(_) => return "unknown".to_string(),
+    };
+    let mounts = match std::fs::read_to_string("/proc/mounts") {
+        Ok(mounts) => mounts,
+        Err(_) => return "unknown".to_string(),
+    };
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            Some((fields.next()?, fields.next()?))
+        })
+        .filter(|(mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.len())
+        .map(|(_, fs_type)| fs_type.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn cpu_model() -> String {
+    std::fs::read_to_string("/proc/cpuinfo")
+        .ok()
+        .and_then(|cpuinfo| {
+            cpuinfo
+                .lines()
+                .find(|l| l.starts_with("model name"))
+                .and_then(|l| l.split(':').nth(1))
+                .map(|model| model.trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RunMetadata;
+    use crate::fxmark::tests::test_client_params;
+    use std::path::Path;
+
+    #[test]
+    fn metadata_describes_the_run() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_run_meta_test/fx.sock");
+        client_params.ccores = 4;
+        let meta = RunMetadata::gather("mix", Some(Path::new("/dev/shm")), &client_params);
+
+        let json: serde_json::Value = serde_json::from_str(&meta.to_json()).unwrap();
+        assert_eq!(json["benchmark"], "mix");
+        assert_eq!(json["client_params"]["ccores"], 4);
+        assert_eq!(json["client_params"]["rpc_type"], "GRPC");
+        #[cfg(target_os = "linux")]
+        assert_eq!(json["fs_type"], "tmpfs");
+    }
+}
@@ -3,157 +3,317 @@
 
 extern crate alloc;
 
-use crate::fxmark::{Bench, MAX_OPEN_FILES, PAGE_SIZE};
+use crate::fxmark::{
+    align_samples, check_op, cleanup_prefill, fill_file, measure_bucket, op_failed, paced,
+    prefill_dir, warm_up, write_synced, Bench, PoorMansBarrier, Samples, MAX_OPEN_FILES, PAGE_SIZE,
+};
 use alloc::vec::Vec;
 use alloc::{format, vec};
-use core::cell::RefCell;
-use core::sync::atomic::{AtomicUsize, Ordering};
-use libc::{O_CREAT, O_RDWR, S_IRWXU};
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::sync::atomic::Ordering;
+use libc::{c_int, S_IRWXU};
 use x86::random::rdrand16;
 
+use crate::fxmark::utils::{lock_buffer, unlock_buffer};
 use crate::fxrpc::grpc::*;
 
 #[derive(Clone)]
 pub struct MIX {
-    page: Vec<u8>,
     size: i64,
-    cores: RefCell<usize>,
-    min_core: RefCell<usize>,
+    min_core: usize,
     max_open_files: usize,
-    open_files: RefCell<usize>,
-    fds: RefCell<Vec<u64>>,
+    open_files: usize,
+    spread: bool,
+    // Each bench thread owns its copy, none is shared between threads
+    not_sync: PhantomData<Cell<()>>,
 }
 
 impl Default for MIX {
     fn default() -> MIX {
-        // Allocate a buffer and write data into it, which is later written to the file.
-        let page = alloc::vec![0xb; PAGE_SIZE as usize];
-        let fd = vec![u64::MAX; 512];
-
         MIX {
-            page,
             size: 256 * 1024 * 1024,
-            cores: RefCell::new(0),
-            min_core: RefCell::new(0),
+            min_core: 0,
             max_open_files: MAX_OPEN_FILES.load(Ordering::Acquire),
-            open_files: RefCell::new(0),
-            fds: RefCell::new(fd),
+            open_files: 0,
+            spread: false,
+            not_sync: PhantomData,
         }
     }
 }
 
+impl MIX {
+    /// File the `op`-th operation of `core` goes to, of the `fds` from
+    /// `open_shared_files()`: the core's own file, or with `spread_files` the next of
+    /// all open files in turn.
+    fn fd_for(&self, fds: &[c_int], core: usize, op: usize) -> c_int {
+        if self.spread {
+            fds[(core + op) % fds.len()]
+        } else {
+            fds[(core % self.max_open_files) % self.open_files]
+        }
+    }
+
+    /// Opens the files all cores share for `client`. Only the client that
+    /// opened a descriptor may close it, so each thread opens its own.
+    fn open_shared_files(
+        &self,
+        client: &mut Box<dyn FxRPC>,
+        client_params: &ClientParams,
+    ) -> Vec<c_int> {
+        let flags = client_params.open_flags | client_params.sync_policy.open_flags();
+        (0..self.open_files)
+            .map(|file_num| {
+                let filename = format!("file{}.txt", file_num);
+                let fd = { client.rpc_open(&filename, flags, S_IRWXU.into()) }
+                    .expect("FileOpen syscall failed");
+                assert!(fd != -1, "Unable to create a file");
+                fd
+            })
+            .collect()
+    }
+}
+
 impl Bench for MIX {
-    fn init(&self, cores: Vec<u64>, open_files: usize, conn_type: ConnType, rpc_type: RPCType) {
-        let mut client = init_client(conn_type, rpc_type);
-
-        *self.cores.borrow_mut() = cores.len();
-        *self.min_core.borrow_mut() = *cores.iter().min().unwrap() as usize;
-        *self.open_files.borrow_mut() = open_files;
-        for file_num in 0..open_files {
-            let filename = format!("file{}.txt", file_num);
-            let fd = { client.rpc_open(&filename, O_RDWR | O_CREAT, S_IRWXU.into()) }
-                .expect("FileOpen syscall failed");
-
-            let ret = {
-                client
-                    .rpc_pwrite(fd, &self.page, PAGE_SIZE, self.size)
-                    .expect("FileWriteAt syscall failed")
+    fn init(&mut self, cores: Vec<u64>, open_files: usize, client_params: &ClientParams) {
+        let mut client = init_bench_client(client_params);
+
+        self.min_core = *cores.iter().min().unwrap() as usize;
+        self.open_files = open_files;
+        self.spread = client_params.spread_files;
+        let fds = self.open_shared_files(&mut client, client_params);
+        for (file_num, fd) in fds.into_iter().enumerate() {
+            // The page past the end gives the file its size, preallocating
+            // writes all pages before it too
+            let (offset, pages) = if client_params.preallocate {
+                (0, self.size as usize / PAGE_SIZE + 1)
+            } else {
+                (self.size, 1)
             };
-            assert_eq!(ret, PAGE_SIZE as i32);
-            self.fds.borrow_mut()[file_num] = fd as u64;
+            fill_file(&mut client, client_params, fd, file_num, offset, pages);
+            client.rpc_close(fd).expect("FileClose syscall failed");
         }
+
+        prefill_dir(&mut client, "", client_params.files_per_dir);
     }
 
     fn run(
         &self,
-        poor_mans_barrier: &AtomicUsize,
+        poor_mans_barrier: &PoorMansBarrier,
         duration: u64,
         core: usize,
         write_ratio: usize,
-        conn_type: ConnType,
-        rpc_type: RPCType,
-    ) -> Vec<usize> {
-        let mut client = init_client(conn_type, rpc_type);
+        client_params: &ClientParams,
+    ) -> Samples {
+        let mut client = init_bench_client(client_params);
 
         let mut iops_per_second = Vec::with_capacity(duration as usize);
+        let mut errors_per_second = Vec::with_capacity(duration as usize);
+        let mut errnos_per_second = Vec::with_capacity(duration as usize);
+        let mut unreliable_per_second = Vec::with_capacity(duration as usize);
+        let mut cpu_util_per_second = Vec::with_capacity(duration as usize);
+        let mut latency_per_second = Vec::with_capacity(duration as usize);
 
-        let file_num = (core % self.max_open_files) % *self.open_files.borrow();
-        let fd = self.fds.borrow()[file_num];
-        if fd == u64::MAX {
-            panic!("Unable to open a file");
-        }
+        let fds = self.open_shared_files(&mut client, client_params);
+        let fd = self.fd_for(&fds, core, 0);
         let total_pages: usize = self.size as usize / 4096;
         // let page: &mut [u8; PAGE_SIZE as usize] = &mut [0; PAGE_SIZE as usize];
         let mut page: Vec<u8> = vec![0; PAGE_SIZE as usize];
+        let locked = lock_buffer(client_params.lock_buffers, &page);
 
         {
             client
-                .rpc_pwrite(fd as i32, &page, PAGE_SIZE, self.size)
+                .rpc_pwrite(fd, &page, PAGE_SIZE, self.size)
                 .expect("can't write_at");
         }
 
+        let mut random_num: u16 = 0;
+        let mut writes = 0;
+        let mut ops = 0;
+        let mut next_op = paced(client_params, || {
+            let fd = self.fd_for(&fds, core, ops);
+            ops += 1;
+            unsafe { rdrand16(&mut random_num) };
+            let rand = random_num as usize % total_pages;
+            let offset = rand * 4096;
+
+            if random_num as usize % 100 < write_ratio {
+                let res = write_synced(
+                    &mut client,
+                    client_params,
+                    &mut writes,
+                    fd,
+                    &page,
+                    offset as i64,
+                );
+                if let Err(errno) = res {
+                    op_failed(client_params, errno, "MIX: write_at() failed");
+                }
+                res.is_ok()
+            } else {
+                let res = check_op(
+                    client.rpc_pread(fd, &mut page, PAGE_SIZE, offset as i64),
+                    PAGE_SIZE as i32,
+                );
+                if let Err(errno) = res {
+                    op_failed(client_params, errno, "MIX: read_at() failed");
+                }
+                res.is_ok()
+            }
+        });
+        warm_up(client_params.warmup_ops, &mut next_op);
+
         // Synchronize with all cores
-        poor_mans_barrier.fetch_sub(1, Ordering::Release);
-        while poor_mans_barrier.load(Ordering::Acquire) != 0 {
-            core::hint::spin_loop();
-        }
+        poor_mans_barrier.wait_start();
+        let first_second = align_samples(client_params.align_samples_ms);
 
-        let mut iops = 0;
         let mut iterations = 0;
-        let mut random_num: u16 = 0;
 
         while iterations <= duration {
-            let start = std::time::Instant::now();
-            while start.elapsed().as_secs() < 1 {
-                for _i in 0..4 {
-                    unsafe { rdrand16(&mut random_num) };
-                    let rand = random_num as usize % total_pages;
-                    let offset = rand * 4096;
-
-                    if random_num as usize % 100 < write_ratio {
-                        if client
-                            .rpc_pwrite(fd as i32, &page, PAGE_SIZE, offset as i64)
-                            .expect("FileWriteAt syscall failed")
-                            != PAGE_SIZE as i32
-                        {
-                            panic!("MIX: write_at() failed");
-                        }
-                    } else {
-                        if client
-                            .rpc_pread(fd as i32, &mut page, PAGE_SIZE, offset as i64)
-                            .expect("FileReadAt syscall failed")
-                            != PAGE_SIZE as i32
-                        {
-                            panic!("MIX: read_at() failed");
+            let (iops, errors, unreliable, cpu_util, errnos, latency) =
+                measure_bucket(client_params.min_samples, client_params.rerun_short, || {
+                    let mut iops = 0;
+                    let mut errors = 0;
+                    let start = std::time::Instant::now();
+                    while start.elapsed().as_secs() < 1 {
+                        for _i in 0..4 {
+                            if next_op() {
+                                iops += 1;
+                            } else {
+                                errors += 1;
+                            }
                         }
                     }
-                    iops += 1;
-                }
-            }
+                    (iops, errors)
+                });
 
             iops_per_second.push(iops);
+            errors_per_second.push(errors);
+            errnos_per_second.push(errnos);
+            unreliable_per_second.push(unreliable);
+            cpu_util_per_second.push(cpu_util);
+            latency_per_second.push(latency);
             iterations += 1;
-            iops = 0;
         }
 
-        poor_mans_barrier.fetch_add(1, Ordering::Release);
-        let num_cores = *self.cores.borrow();
-        while poor_mans_barrier.load(Ordering::Acquire) != num_cores {
-            core::hint::spin_loop();
-        }
+        poor_mans_barrier.wait_end();
 
-        if core == *self.min_core.borrow() {
-            let start = std::time::Instant::now();
-            while start.elapsed().as_secs() < 1 {}
-            for i in 0..*self.open_files.borrow() {
-                let fd = self.fds.borrow()[i];
-                client
-                    .rpc_close(fd as i32)
-                    .expect("FileClose syscall failed");
-            }
+        for fd in fds {
+            client.rpc_close(fd).expect("FileClose syscall failed");
+        }
+        if core == self.min_core && !client_params.keep_files {
+            cleanup_prefill(&mut client, "", client_params.files_per_dir);
+        }
+        unlock_buffer(locked, &page);
+        Samples {
+            iops: iops_per_second,
+            errors: errors_per_second,
+            errnos: errnos_per_second,
+            unreliable: unreliable_per_second,
+            cpu_util: cpu_util_per_second,
+            latency: latency_per_second,
+            start: Some(first_second),
+            ..Default::default()
         }
-        iops_per_second.clone()
     }
 }
 
-unsafe impl Sync for MIX {}
+register_bench!("mix", MIX, PAGE_SIZE);
+
+#[cfg(test)]
+mod tests {
+    use super::{c_int, Bench, ClientParams, PoorMansBarrier, MIX};
+    use crate::fxmark::tests::{server_dir, test_client_params};
+
+    #[test]
+    fn spread_reads_cover_all_files() {
+        let mut mix = MIX {
+            open_files: 16,
+            spread: true,
+            ..Default::default()
+        };
+        let fds: Vec<c_int> = (100..116).collect();
+
+        let mut hits = vec![0; 16];
+        for op in 0..160 {
+            hits[(mix.fd_for(&fds, 3, op) - 100) as usize] += 1;
+        }
+        assert!(hits.iter().all(|h| *h == 10), "{:?}", hits);
+
+        // Without spreading a core sticks to its own file
+        mix.spread = false;
+        assert!((0..160).all(|op| mix.fd_for(&fds, 3, op) == mix.fd_for(&fds, 3, 0)));
+    }
+
+    // Compile-time checks, like static_assertions' assert_impl_all! and
+    // assert_not_impl_any!
+    fn assert_send<T: Send>() {}
+
+    trait AmbiguousIfSync<A> {
+        fn some_item() {}
+    }
+    impl<T: ?Sized> AmbiguousIfSync<()> for T {}
+    impl<T: ?Sized + Sync> AmbiguousIfSync<u8> for T {}
+
+    #[test]
+    fn mix_moves_to_its_thread_but_is_never_shared() {
+        assert_send::<MIX>();
+        // Doesn't compile once MIX is Sync, as both impls then apply
+        let _ = <MIX as AmbiguousIfSync<_>>::some_item;
+    }
+
+    // Files of prefill_dir() in the server directory
+    fn prefill_files(client_params: &ClientParams) -> usize {
+        std::fs::read_dir(server_dir(client_params))
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().starts_with("prefill")
+            })
+            .count()
+    }
+
+    #[test]
+    fn prefilled_directory_is_emptied_after_the_run() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_mix_prefill_test/fx.sock");
+        client_params.files_per_dir = 1000;
+
+        let mut mix = MIX::default();
+        mix.init(vec![0], 1, &client_params);
+        assert_eq!(prefill_files(&client_params), 1000);
+
+        // The run works in the populated directory, then removes what init made
+        let samples = mix.run(&PoorMansBarrier::new(1), 1, 0, 0, &client_params);
+        assert!(samples.iops[1] > 0);
+        assert_eq!(prefill_files(&client_params), 0);
+    }
+
+    #[test]
+    fn every_core_closes_the_files_it_opened() {
+        let client_params = test_client_params("/dev/shm/fxmark_mix_close_test/fx.sock");
+        let files: Vec<_> = (0..3)
+            .map(|n| {
+                std::path::PathBuf::from(format!("{}file{}.txt", server_dir(&client_params), n))
+            })
+            .collect();
+        // The server runs in this process, its descriptors are ours
+        let open = || {
+            std::fs::read_dir("/proc/self/fd")
+                .unwrap()
+                .filter_map(|entry| std::fs::read_link(entry.unwrap().path()).ok())
+                .filter(|target| files.contains(target))
+                .count()
+        };
+
+        // Two cores, each with its own copy, as bench threads get them
+        let mut mixes = [MIX::default(), MIX::default()];
+        for mix in &mut mixes {
+            mix.init(vec![0, 1], 3, &client_params);
+        }
+        assert_eq!(open(), 0, "init left files open");
+        for (core, mix) in mixes.iter().enumerate() {
+            mix.run(&PoorMansBarrier::new(1), 1, core, 0, &client_params);
+        }
+        assert_eq!(open(), 0, "a core left files open");
+    }
+}
@@ -5,45 +5,113 @@
 
 extern crate alloc;
 
-use std::convert::TryInto;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
 use std::fs::OpenOptions;
 use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::Mutex;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use core::num::ParseIntError;
-use core::ptr;
 use core::str::FromStr;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 
 use lazy_static::lazy_static;
 
+/// Registers the `Bench` `$bench` as the benchmark `$name`, whose ops read
+/// or write `$io_size` bytes unless `ClientParams::io_size` says otherwise.
+/// `bench()` and `--bench` find it by its name from then on.
+macro_rules! register_bench {
+    ($name:expr, $bench:ty, $io_size:expr) => {
+        inventory::submit! {
+            crate::fxmark::BenchEntry {
+                name: $name,
+                io_size: $io_size,
+                run: crate::fxmark::start::<$bench>,
+                co_start: crate::fxmark::co_start::<$bench>,
+            }
+        }
+    };
+}
+
 pub mod utils;
 use utils::topology::ThreadMapping;
 use utils::topology::*;
 
 mod mix;
-use crate::fxmark::mix::MIX;
 
-use crate::fxrpc::{ClientParams, ConnType, LogMode, RPCType};
+pub mod compare;
 
-pub const PAGE_SIZE: usize = 1024;
+pub mod fill;
+
+pub mod sizes;
+use crate::fxmark::sizes::SizeDistribution;
+
+pub mod latency;
+use crate::fxmark::latency::{LatencyHistogram, OpClock};
+
+pub mod meta;
+
+mod drbr;
+
+mod dwom;
+
+mod dwsl;
+
+mod mrpl;
+
+mod mrpm;
+
+mod mrph;
+
+mod ococ;
+
+mod rmw;
 
-static POOR_MANS_BARRIER: AtomicUsize = AtomicUsize::new(0);
+mod mrename;
+pub use crate::fxmark::mrename::RenameMode;
+
+pub mod names;
+
+pub mod pivot;
+use crate::fxmark::pivot::render_pivot;
+
+mod recipe;
+pub use crate::fxmark::recipe::Recipe;
+
+use crate::fxrpc::fdtable::nofile_limit;
+use crate::fxrpc::pipeline::Completion;
+use crate::fxrpc::{
+    errno_name, init_client_for, op_errno, ClientParams, FxRPC, LogMode, RPCEndpoint,
+    PROTOCOL_VERSION,
+};
+
+pub const PAGE_SIZE: usize = 1024;
 
 lazy_static! {
     pub static ref MAX_OPEN_FILES: AtomicUsize = AtomicUsize::new(max_open_files());
 }
 
-pub fn _calculate_throughput(ops: u64, time: Duration) -> usize {
-    let nano_duration = time.as_nanos();
-    let nano_per_operation = nano_duration / ops as u128;
-    (Duration::from_secs(1).as_nanos() / nano_per_operation)
-        .try_into()
-        .unwrap()
+/// Operations per second of `ops` operations in `time`, 0 without any.
+pub fn calculate_throughput(ops: u64, time: Duration) -> f64 {
+    if ops == 0 {
+        return 0.0;
+    }
+    ops as f64 / time.as_secs_f64()
+}
+
+/// Mean microseconds an operation of `ops` operations in `time` took one
+/// after the other, 0 without any.
+pub fn calculate_latency(ops: u64, time: Duration) -> f64 {
+    if ops == 0 {
+        return 0.0;
+    }
+    time.as_secs_f64() * 1_000_000.0 / ops as f64
 }
 
 /// This struct is used for passing the core and benchmark type from
@@ -56,18 +124,67 @@ pub struct ARGs {
     pub write_ratio: usize,
 }
 
+/// Why a CORExOPENFxBENCHxWRATIO string couldn't be turned into `ARGs`.
+#[derive(Debug, PartialEq)]
+pub enum ArgsParseError {
+    /// Fewer than four fields, names the first missing one.
+    MissingField(&'static str),
+    /// More than four fields, holds how many were given.
+    TooManyFields(usize),
+    InvalidCores(String),
+    InvalidOpenFiles(String),
+    InvalidWriteRatio(String),
+    EmptyBenchmark,
+}
+
+impl core::fmt::Display for ArgsParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            ArgsParseError::MissingField(field) => write!(f, "missing field '{}'", field),
+            ArgsParseError::TooManyFields(n) => write!(f, "expected 4 fields, got {}", n),
+            ArgsParseError::InvalidCores(v) => write!(f, "invalid value '{}' for 'cores'", v),
+            ArgsParseError::InvalidOpenFiles(v) => {
+                write!(f, "invalid value '{}' for 'open_files'", v)
+            }
+            ArgsParseError::InvalidWriteRatio(v) => {
+                write!(f, "invalid value '{}' for 'write_ratio'", v)
+            }
+            ArgsParseError::EmptyBenchmark => write!(f, "field 'benchmark' is empty"),
+        }
+    }
+}
+
+impl std::error::Error for ArgsParseError {}
+
 /// Both command line and integration tests pass CORExBENCH(ex: 10xdhrl). Convert
 /// the string to the struct which can be used in the benchmarks.
 impl FromStr for ARGs {
-    type Err = ParseIntError;
+    type Err = ArgsParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const FIELDS: [&str; 4] = ["cores", "open_files", "benchmark", "write_ratio"];
+
         let coords: Vec<&str> = s.split('X').collect();
+        if coords.len() < FIELDS.len() {
+            return Err(ArgsParseError::MissingField(FIELDS[coords.len()]));
+        }
+        if coords.len() > FIELDS.len() {
+            return Err(ArgsParseError::TooManyFields(coords.len()));
+        }
 
-        let x_fromstr = coords[0].parse::<usize>()?;
-        let open_files = coords[1].parse::<usize>()?;
+        let x_fromstr = coords[0]
+            .parse::<usize>()
+            .map_err(|_| ArgsParseError::InvalidCores(coords[0].to_string()))?;
+        let open_files = coords[1]
+            .parse::<usize>()
+            .map_err(|_| ArgsParseError::InvalidOpenFiles(coords[1].to_string()))?;
         let benchmark = coords[2].to_string();
-        let write_ratio = coords[3].parse::<usize>()?;
+        if benchmark.is_empty() {
+            return Err(ArgsParseError::EmptyBenchmark);
+        }
+        let write_ratio = coords[3]
+            .parse::<usize>()
+            .map_err(|_| ArgsParseError::InvalidWriteRatio(coords[3].to_string()))?;
         Ok(ARGs {
             cores: x_fromstr,
             open_files,
@@ -77,48 +194,708 @@ impl FromStr for ARGs {
     }
 }
 
+/// Per-second measurements of a single bench thread.
+#[derive(Debug, Default, Clone)]
+pub struct Samples {
+    /// Successful operations in each second.
+    pub iops: Vec<usize>,
+    /// Failed operations in each second (only nonzero under `ErrorPolicy::ContinueOnError`
+    /// or for `ClientParams::tolerated_errnos`).
+    pub errors: Vec<usize>,
+    /// The failed operations of each second whose errno is known, by errno.
+    pub errnos: Vec<ErrnoCounts>,
+    /// Seconds that stayed below `ClientParams::min_samples` operations.
+    pub unreliable: Vec<bool>,
+    /// Busy time of the thread's core in each second, in percent.
+    pub cpu_util: Vec<f64>,
+    /// Latencies of the operations of each second, failed ones included.
+    pub latency: Vec<LatencyHistogram>,
+    /// When second 0 started, see `align_samples()`.
+    pub start: Option<SystemTime>,
+    /// When the barrier let the thread into its timed phase. A thread
+    /// released much later than the others started late, and its seconds
+    /// are offset from theirs.
+    pub released: Option<SystemTime>,
+    /// When the thread was done with its timed phase, before it waited for
+    /// the others.
+    pub ended: Option<SystemTime>,
+    /// Successful operations over the whole run, per op type. Only filled in by
+    /// benches that mix several op types.
+    pub op_counts: Vec<(&'static str, usize)>,
+    /// What the thread did, for benches whose threads do different things.
+    pub role: Option<ThreadRole>,
+}
+
+impl Samples {
+    /// Keeps only the first `seconds` seconds.
+    pub fn truncate(&mut self, seconds: usize) {
+        self.iops.truncate(seconds);
+        self.errors.truncate(seconds);
+        self.errnos.truncate(seconds);
+        self.unreliable.truncate(seconds);
+        self.cpu_util.truncate(seconds);
+        self.latency.truncate(seconds);
+    }
+}
+
+/// The part a thread plays in a bench that splits its cores, such as mrph.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThreadRole {
+    Reader,
+    Writer,
+}
+
+/// Failed operations by errno.
+pub type ErrnoCounts = BTreeMap<i32, usize>;
+
+thread_local! {
+    // The calling thread's failed ops in the second being measured
+    static BUCKET_ERRNOS: RefCell<ErrnoCounts> = RefCell::new(BTreeMap::new());
+    // Schedule and latencies of the calling thread's ops in that second
+    static BUCKET_CLOCK: RefCell<OpClock> = RefCell::new(OpClock::default());
+    // When the barrier released the calling thread and when it got back to it
+    static PHASE_TIMES: Cell<(Option<SystemTime>, Option<SystemTime>)> = Cell::new((None, None));
+    // When the second being measured started, None outside of one
+    static BUCKET_START: Cell<Option<Instant>> = Cell::new(None);
+    // Seconds the calling thread measured, and whether it stopped measuring
+    // because its run went over its error budget
+    static MEASURED: Cell<(usize, bool)> = Cell::new((0, false));
+    // The failed ops of the run the calling thread is of
+    static ERROR_BUDGET: RefCell<Arc<ErrorBudget>> = RefCell::new(Arc::new(ErrorBudget::new()));
+}
+
+/// Failed ops of a run, over all its threads, that count against
+/// `ClientParams::error_budget`.
+struct ErrorBudget {
+    failed: AtomicU64,
+}
+
+impl ErrorBudget {
+    const fn new() -> ErrorBudget {
+        ErrorBudget {
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    fn spend(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn exceeded(&self, budget: u64) -> bool {
+        self.failed.load(Ordering::Relaxed) > budget
+    }
+}
+
+// Stops the calling thread's ops once its run went over the error budget:
+// sleeps out the second being measured, and `measure_bucket()` measures no
+// more after it
+fn stop_over_budget() {
+    MEASURED.with(|measured| measured.set((measured.get().0, true)));
+    if let Some(start) = BUCKET_START.with(Cell::get) {
+        thread::sleep(Duration::from_secs(1).saturating_sub(start.elapsed()));
+    }
+}
+
+// Whether more ops of the calling thread's run failed than `budget` allows
+fn over_budget(budget: u64) -> bool {
+    ERROR_BUDGET.with(|run| run.borrow().exceeded(budget))
+}
+
+/// Fails if more ops of the run of `results` failed than
+/// `ClientParams::error_budget` allows, so the run stopped early.
+pub fn check_error_budget(
+    results: &[CoreResult],
+    client_params: &ClientParams,
+) -> Result<(), String> {
+    match client_params.error_budget {
+        Some(budget) if results.iter().any(|r| r.over_budget) => Err(format!(
+            "More than {} operations failed, the run was stopped early",
+            budget
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Wraps `op`, one operation of a bench's hot loop, so that ops start at
+/// `ClientParams::target_iops` per second, if set, and records their
+/// latencies for `measure_bucket()`. An op's latency counts from when it was
+/// due, so ops delayed by a slow predecessor include the delay. After every
+/// op the thread thinks for `ClientParams::think_time_us`, which isn't part
+/// of the op's latency. Once the run went over its
+/// `ClientParams::error_budget`, the thread sits out the rest of the second
+/// before its next op.
+pub fn paced<F, R>(client_params: &ClientParams, op: F) -> impl FnMut() -> R
+where
+    F: FnMut() -> R,
+{
+    paced_with(client_params, op, think)
+}
+
+// `paced()` with the think time spent by `think`, which tests record instead
+fn paced_with<F, T, R>(client_params: &ClientParams, mut op: F, mut think: T) -> impl FnMut() -> R
+where
+    F: FnMut() -> R,
+    T: FnMut(Duration, bool),
+{
+    let target_iops = client_params.target_iops;
+    let think_time = Duration::from_micros(client_params.think_time_us);
+    let busy_think = client_params.busy_think;
+    let error_budget = client_params.error_budget;
+    move || {
+        if error_budget.map_or(false, over_budget) {
+            stop_over_budget();
+        }
+        let due = BUCKET_CLOCK.with(|clock| clock.borrow_mut().wait(target_iops));
+        let res = op();
+        BUCKET_CLOCK.with(|clock| clock.borrow_mut().latency.record(due.elapsed()));
+        think(think_time, busy_think);
+        res
+    }
+}
+
+// Keeps the calling thread from its next op for `think_time`, as a client
+// processing what the last one returned. Spins if `busy`, sleeps otherwise.
+fn think(think_time: Duration, busy: bool) {
+    if think_time.is_zero() {
+        return;
+    }
+    if busy {
+        let start = Instant::now();
+        while start.elapsed() < think_time {}
+    } else {
+        thread::sleep(think_time);
+    }
+}
+
+/// Handles a failed op of a bench's hot loop: counts it under `errno`, if
+/// known, and panics with `msg` under `AbortOnError` unless `errno` is one of
+/// `ClientParams::tolerated_errnos`. With a `ClientParams::error_budget` it
+/// spends the budget instead of panicking.
+pub fn op_failed(client_params: &ClientParams, errno: Option<i32>, msg: &str) {
+    if let Some(errno) = errno {
+        BUCKET_ERRNOS.with(|counts| *counts.borrow_mut().entry(errno).or_insert(0) += 1);
+        if client_params.tolerated_errnos.contains(&errno) {
+            return;
+        }
+    }
+    if client_params.error_budget.is_some() {
+        ERROR_BUDGET.with(|run| run.borrow().spend());
+        return;
+    }
+    let msg = match errno {
+        Some(errno) => format!("{} ({})", msg, errno_name(errno)),
+        None => msg.to_string(),
+    };
+    client_params.error_policy.on_error(&msg);
+}
+
+/// Whether an RPC returned `expected`, or else the errno it failed with, if known.
+pub fn check_op(
+    res: Result<i32, Box<dyn std::error::Error>>,
+    expected: i32,
+) -> Result<(), Option<i32>> {
+    match res {
+        Ok(ret) if ret == expected => Ok(()),
+        res => Err(op_errno(&res)),
+    }
+}
+
+/// Whether a pipelined op completed with `expected`, like `check_op()`. A
+/// failed read of a batch has -errno as its result.
+pub fn check_completion(completion: Completion, expected: i32) -> Result<(), Option<i32>> {
+    match completion {
+        Ok(res) if res.result == expected => Ok(()),
+        Ok(res) if res.result < 0 => Err(Some(-res.result)),
+        Ok(_) => Err(None),
+        Err(errno) => Err(errno),
+    }
+}
+
+/// Runs `measure` for one second and returns its (successful, failed) operation
+/// counts, plus whether the second is unreliable because it saw fewer than
+/// `min_samples` operations. Such a second is measured again, up to `reruns`
+/// times, and the last attempt is kept. Also returns how busy the calling
+/// thread's core was during the kept attempt, in percent, the errnos that
+/// `op_failed()` saw during it and the latencies of its `paced()` ops. Once
+/// the thread stopped over its run's error budget, nothing is measured and
+/// the second is left out of the thread's results.
+pub fn measure_bucket<F>(
+    min_samples: usize,
+    reruns: usize,
+    mut measure: F,
+) -> (usize, usize, bool, f64, ErrnoCounts, LatencyHistogram)
+where
+    F: FnMut() -> (usize, usize),
+{
+    let (measured, stopped) = MEASURED.with(Cell::get);
+    if stopped {
+        return (
+            0,
+            0,
+            true,
+            0.0,
+            ErrnoCounts::new(),
+            LatencyHistogram::default(),
+        );
+    }
+    MEASURED.with(|m| m.set((measured + 1, false)));
+    let mut attempt = || {
+        BUCKET_ERRNOS.with(|counts| counts.borrow_mut().clear());
+        BUCKET_CLOCK.with(|clock| clock.borrow_mut().reset());
+        BUCKET_START.with(|start| start.set(Some(Instant::now())));
+        let cpu = utils::CpuSampler::start();
+        let (iops, errors) = measure();
+        BUCKET_START.with(|start| start.set(None));
+        (iops, errors, cpu.utilization())
+    };
+    let (mut iops, mut errors, mut cpu_util) = attempt();
+    let mut attempts = 0;
+    // A second cut short by the error budget isn't measured again
+    while iops + errors < min_samples && attempts < reruns && !MEASURED.with(Cell::get).1 {
+        let (i, e, c) = attempt();
+        iops = i;
+        errors = e;
+        cpu_util = c;
+        attempts += 1;
+    }
+    let errnos = BUCKET_ERRNOS.with(|counts| std::mem::take(&mut *counts.borrow_mut()));
+    let latency = BUCKET_CLOCK.with(|clock| std::mem::take(&mut clock.borrow_mut().latency));
+    (
+        iops,
+        errors,
+        iops + errors < min_samples,
+        cpu_util,
+        errnos,
+        latency,
+    )
+}
+
+/// Runs `op`, one operation of a bench's hot loop, `warmup_ops` times before
+/// the measured phase, e.g. to fault in the pages it touches. Failed ops count
+/// too. Does nothing if `warmup_ops` isn't set.
+pub fn warm_up<F>(warmup_ops: Option<u64>, mut op: F)
+where
+    F: FnMut() -> bool,
+{
+    for _ in 0..warmup_ops.unwrap_or(0) {
+        op();
+    }
+}
+
+/// Called by bench threads once they're past the barrier. Sleeps until the
+/// next multiple of `align_ms` ms since the epoch, so the per-second buckets
+/// of all threads, and of clients with synchronized clocks, cover the same
+/// wall-clock intervals. Returns when the first bucket starts, right away
+/// without `align_ms`.
+pub fn align_samples(align_ms: Option<u64>) -> SystemTime {
+    let align_ns = match align_ms {
+        Some(ms) if ms > 0 => ms as u128 * 1_000_000,
+        _ => return SystemTime::now(),
+    };
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let wait = align_ns - since_epoch.as_nanos() % align_ns;
+    thread::sleep(Duration::from_nanos(wait as u64));
+    SystemTime::now()
+}
+
+/// xorshift64*, reproducible from `--seed` and plenty to scatter bench operations.
+pub struct SeededRng(u64);
+
+impl SeededRng {
+    /// Mixes `seed` and `core` (splitmix64) so every core draws its own sequence.
+    pub fn new(seed: u64, core: usize) -> SeededRng {
+        let mut z = seed ^ (core as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        // The state must never be 0
+        SeededRng((z ^ (z >> 31)) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+/// Holds the threads of a run together before and after their timed phase,
+/// so they all measure the same seconds. A synthetic barrier lets every
+/// thread through right away, for cores that run one after the other. A
+/// thread that panics poisons the barrier, which then stops holding the
+/// others, as it would never fill up.
+pub struct PoorMansBarrier {
+    waiting: AtomicUsize,
+    threads: usize,
+    synthetic: bool,
+    poisoned: AtomicBool,
+}
+
+impl PoorMansBarrier {
+    /// A barrier for a run of `threads` threads.
+    pub const fn new(threads: usize) -> PoorMansBarrier {
+        PoorMansBarrier {
+            waiting: AtomicUsize::new(threads),
+            threads,
+            synthetic: false,
+            poisoned: AtomicBool::new(false),
+        }
+    }
+
+    pub const fn synthetic() -> PoorMansBarrier {
+        PoorMansBarrier {
+            waiting: AtomicUsize::new(0),
+            threads: 0,
+            synthetic: true,
+            poisoned: AtomicBool::new(false),
+        }
+    }
+
+    /// Lets every thread through from now on, for a run one of whose
+    /// threads won't arrive.
+    pub fn poison(&self) {
+        self.poisoned.store(true, Ordering::Release);
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Acquire)
+    }
+
+    /// Waits until all threads of the run are about to start their timed
+    /// phase.
+    pub fn wait_start(&self) {
+        if !self.synthetic {
+            self.waiting.fetch_sub(1, Ordering::Release);
+            while self.waiting.load(Ordering::Acquire) != 0 && !self.is_poisoned() {
+                core::hint::spin_loop();
+            }
+        }
+        PHASE_TIMES.with(|times| times.set((Some(SystemTime::now()), None)));
+    }
+
+    /// Waits until all threads of the run are done with their timed phase,
+    /// those of every benchmark of a `co_run()`.
+    pub fn wait_end(&self) {
+        PHASE_TIMES.with(|times| times.set((times.get().0, Some(SystemTime::now()))));
+        if self.synthetic {
+            return;
+        }
+        self.waiting.fetch_add(1, Ordering::Release);
+        while self.waiting.load(Ordering::Acquire) != self.threads && !self.is_poisoned() {
+            core::hint::spin_loop();
+        }
+    }
+}
+
 pub trait Bench {
-    fn init(&self, cores: Vec<u64>, open_files: usize, conn_type: ConnType, rpc_type: RPCType);
+    fn init(&mut self, cores: Vec<u64>, open_files: usize, client_params: &ClientParams);
     fn run(
         &self,
-        barrier: &AtomicUsize,
+        barrier: &PoorMansBarrier,
         duration: u64,
         core: usize,
         write_ratio: usize,
-        conn_type: ConnType,
-        rpc_type: RPCType,
-    ) -> Vec<usize>;
+        client_params: &ClientParams,
+    ) -> Samples;
+}
+
+/// What the threads of one run share. Each run gets its own, so runs in
+/// parallel, as the tests start them, don't get in each other's way.
+struct Run {
+    barrier: PoorMansBarrier,
+    error_budget: Arc<ErrorBudget>,
+    /// Cores a bench thread that can't be pinned to its own may take
+    /// instead: those the process may run on that no thread of the run is on.
+    spare_cores: Mutex<Vec<u64>>,
+}
+
+impl Run {
+    // Before the threads of a run on `cores` start
+    fn new(cores: &[u64]) -> Arc<Run> {
+        let spare_cores = utils::allowed_cpus()
+            .into_iter()
+            .filter(|cpu| !cores.contains(cpu))
+            .collect();
+        Arc::new(Run {
+            barrier: PoorMansBarrier::new(cores.len()),
+            error_budget: Arc::new(ErrorBudget::new()),
+            spare_cores: Mutex::new(spare_cores),
+        })
+    }
+
+    // Whether more ops of the run failed than `ClientParams::error_budget`
+    // allows
+    fn over_budget(&self, client_params: &ClientParams) -> bool {
+        client_params
+            .error_budget
+            .map_or(false, |budget| self.error_budget.exceeded(budget))
+    }
 }
 
-unsafe extern "C" fn fxmark_bencher_trampoline<T>(
+unsafe fn fxmark_bencher_trampoline<T>(
     arg: *mut u8,
+    run: &Run,
     cores: usize,
     core_id: usize,
+    thread_mapping: ThreadMapping,
+    unpinned: bool,
     duration: u64,
     client_params: ClientParams,
-    outfile: &String,
-) -> *mut u8
+) -> CoreResult
 where
-    T: Bench + Default + core::marker::Send + core::marker::Sync + 'static + core::clone::Clone,
+    T: Bench + Default + core::marker::Send + 'static + core::clone::Clone,
 {
-    let bench: Arc<MicroBench<T>> = Arc::from_raw(arg as *const MicroBench<_>);
+    let bench: Box<MicroBench<T>> = Box::from_raw(arg as *mut MicroBench<_>);
     bench.fxmark_bencher(
+        &run.barrier,
+        &run.error_budget,
         cores,
         core_id,
+        thread_mapping,
+        unpinned,
         bench.benchmark,
         bench.write_ratio,
         bench.open_files,
         duration,
         client_params,
-        outfile,
-    );
-    ptr::null_mut()
+    )
+}
+
+// Microseconds since the epoch of `time`, empty without one
+fn epoch_us(time: Option<SystemTime>) -> String {
+    match time {
+        Some(time) => time
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_micros()
+            .to_string(),
+        None => String::new(),
+    }
+}
+
+/// What one bench thread measured.
+#[derive(Debug, Clone)]
+pub struct CoreResult {
+    pub core_id: usize,
+    pub benchmark: String,
+    /// Number of bench threads of the run on this client.
+    pub cores: usize,
+    pub write_ratio: usize,
+    pub open_files: usize,
+    pub thread_mapping: ThreadMapping,
+    /// The thread couldn't be pinned to its core and ran wherever the
+    /// scheduler put it.
+    pub unpinned: bool,
+    pub duration: u64,
+    /// The workload of a `co_run()` the thread was of, `<index>:<benchmark>`.
+    pub workload: Option<String>,
+    /// The run went over `ClientParams::error_budget` and was stopped early.
+    pub over_budget: bool,
+    /// Per-second measurements, second 0 is warmup.
+    pub samples: Samples,
+}
+
+impl CoreResult {
+    /// The CSV rows of this result, one per measured second.
+    pub fn csv_rows(&self, client_params: &ClientParams) -> String {
+        let samples = &self.samples;
+        let mut rows = String::new();
+        for iteration in 1..(self.duration + 1) {
+            let iops = samples.iops[iteration as usize];
+            // Split by role, so readers and writers can be told apart
+            let (reader_ops, writer_ops) = match samples.role {
+                Some(ThreadRole::Reader) => (iops.to_string(), String::new()),
+                Some(ThreadRole::Writer) => (String::new(), iops.to_string()),
+                None => (String::new(), String::new()),
+            };
+            rows += &format!(
+                "{},{:?},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{:.*},{:?},{},{},{},{},{},{},{}{}\n",
+                self.core_id + (client_params.ccores * client_params.cid),
+                self.benchmark,
+                self.cores * client_params.nclients,
+                self.write_ratio,
+                self.open_files,
+                self.duration,
+                iteration,
+                iops,
+                client_params.cid,
+                client_params.ccores,
+                client_params.nclients,
+                client_params.rpc_type,
+                samples.errors[iteration as usize],
+                client_params.host_info.page_size,
+                client_params.host_info.sock_sndbuf,
+                client_params.host_info.sock_rcvbuf,
+                samples.unreliable[iteration as usize] as u8,
+                client_params.precision,
+                samples.cpu_util[iteration as usize],
+                self.thread_mapping,
+                errno_column(&samples.errnos[iteration as usize]),
+                self.unpinned as u8,
+                reader_ops,
+                writer_ops,
+                epoch_us(samples.released),
+                epoch_us(samples.ended),
+                self.workload.as_deref().unwrap_or(""),
+                tag_columns(client_params),
+            );
+        }
+        rows
+    }
+
+    /// Operations of all measured seconds, without the warmup second.
+    pub fn total_ops(&self) -> u64 {
+        self.samples.iops[1..(self.duration as usize + 1)]
+            .iter()
+            .map(|iops| *iops as u64)
+            .sum()
+    }
+
+    /// The throughput of the whole run and the mean latency of its ops,
+    /// next to the per-second rows.
+    pub fn summary_row(&self, client_params: &ClientParams) -> String {
+        let ops = self.total_ops();
+        let time = Duration::from_secs(self.duration);
+        format!(
+            "Core {} total: {} ops in {}s, {:.*} ops/s, {:.*} us/op\n",
+            self.core_id + (client_params.ccores * client_params.cid),
+            ops,
+            self.duration,
+            client_params.precision,
+            calculate_throughput(ops, time),
+            client_params.precision,
+            calculate_latency(ops, time)
+        )
+    }
+}
+
+/// Columns of the rows `CoreResult::csv_rows()` writes, before the tags.
+pub const CSV_HEADER: &str = "thread_id,benchmark,ncores,write_ratio,open_files,duration_total,duration,operations,client_id,client_cores,nclients,rpctype,errors,page_size,sndbuf,rcvbuf,unreliable,cpu_util,thread_mapping,errnos,unpinned,reader_ops,writer_ops,released_us,ended_us,workload\n";
+
+/// Parses a `key=value` tag. The key becomes a CSV column, so it must be
+/// made of letters, digits, `_`, `-` and `.`, and the value can't hold
+/// commas, quotes or line breaks.
+pub fn parse_tag(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or(format!("Tag '{}' is not of the form key=value", s))?;
+    let key_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.';
+    if key.is_empty() || !key.chars().all(key_char) {
+        return Err(format!(
+            "Tag key '{}' must be letters, digits, '_', '-' or '.'",
+            key
+        ));
+    }
+    if value.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        return Err(format!(
+            "Value of tag '{}' can't contain commas, quotes or line breaks",
+            key
+        ));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Checks that no two `tags` share a key and that none takes the name of a
+/// column the rows have anyway.
+pub fn check_tags(tags: &[(String, String)]) -> Result<(), String> {
+    for (i, (key, _)) in tags.iter().enumerate() {
+        if tags[..i].iter().any(|(k, _)| k == key) {
+            return Err(format!("Tag '{}' is given twice", key));
+        }
+        let builtin = |header: &str| header.trim_end().split(',').any(|c| c == key);
+        if builtin(CSV_HEADER) || builtin(LATENCY_CURVE_HEADER) {
+            return Err(format!(
+                "Tag '{}' clashes with a column of the results",
+                key
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// `header` followed by a column for each of `ClientParams::tags`.
+pub fn tagged_header(header: &str, client_params: &ClientParams) -> String {
+    let mut header = header.trim_end().to_string();
+    for (key, _) in &client_params.tags {
+        header += &format!(",{}", key);
+    }
+    header + "\n"
+}
+
+// The values of the tag columns at the end of a row, each after a comma
+fn tag_columns(client_params: &ClientParams) -> String {
+    client_params
+        .tags
+        .iter()
+        .map(|(_, value)| format!(",{}", value))
+        .collect()
+}
+
+// EAGAIN=3;EIO=1, no commas so it stays one CSV column
+fn errno_column(errnos: &ErrnoCounts) -> String {
+    let counts: Vec<String> = errnos
+        .iter()
+        .map(|(errno, count)| format!("{}={}", errno_name(*errno), count))
+        .collect();
+    counts.join(";")
+}
+
+/// Writes `results` to `outfile` or stdout, as `client_params.log_mode` asks.
+pub fn log_results(results: &[CoreResult], client_params: &ClientParams, outfile: &str) {
+    let mut csv_file = if client_params.log_mode == LogMode::CSV {
+        Some(
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(outfile)
+                .expect("Cant open output file"),
+        )
+    } else {
+        None
+    };
+
+    for result in results {
+        let rows = result.csv_rows(client_params);
+        match csv_file {
+            Some(ref mut my_file) => {
+                if !result.samples.op_counts.is_empty() {
+                    let counts: Vec<String> = result
+                        .samples
+                        .op_counts
+                        .iter()
+                        .map(|(op, count)| format!("{}={}", op, count))
+                        .collect();
+                    println!("Core {} op counts: {}", result.core_id, counts.join(" "));
+                }
+
+                // One zstd frame per core, frames concatenate to a valid stream
+                let r = if client_params.compress_output {
+                    let frame = zstd::encode_all(rows.as_bytes(), 0).expect("Cant compress rows");
+                    my_file.write_all(&frame)
+                } else {
+                    my_file.write_all(rows.as_bytes())
+                };
+                assert!(r.is_ok());
+                // The rows of a core are out once it's logged, whatever
+                // happens to the process after
+                my_file.flush().expect("Cant flush output file");
+            }
+            None => print!("{}", rows),
+        }
+        print!("{}", result.summary_row(client_params));
+    }
+
+    if let Some(my_file) = csv_file {
+        if client_params.sync_results {
+            my_file.sync_all().expect("Cant sync output file");
+        }
+    }
 }
 
 #[derive(Clone)]
 struct MicroBench<'a, T>
 where
-    T: Bench + Default + core::marker::Send + core::marker::Sync + 'static + core::clone::Clone,
+    T: Bench + Default + core::marker::Send + 'static + core::clone::Clone,
 {
     thread_mappings: Vec<ThreadMapping>,
     threads: Vec<usize>,
@@ -130,7 +907,7 @@ where
 
 impl<'a, T> MicroBench<'a, T>
 where
-    T: Bench + Default + core::marker::Send + core::marker::Sync + 'static + core::clone::Clone,
+    T: Bench + Default + core::marker::Send + 'static + core::clone::Clone,
 {
     pub fn new(
         benchmark: &'static str,
@@ -138,7 +915,7 @@ where
         open_files: usize,
         client_params: &ClientParams,
     ) -> MicroBench<'a, T> {
-        let mapping = ThreadMapping::Sequential;
+        let mapping = client_params.thread_mapping;
         let max_cores = (*client_params).ccores;
 
         let mut threads = Vec::new();
@@ -150,7 +927,7 @@ where
 
         MicroBench {
             thread_mappings: thread_mapping,
-            threads: threads,
+            threads,
             benchmark,
             write_ratio,
             open_files,
@@ -160,163 +937,2733 @@ where
 
     fn fxmark_bencher(
         &self,
+        barrier: &PoorMansBarrier,
+        error_budget: &Arc<ErrorBudget>,
         cores: usize,
         core_id: usize,
+        thread_mapping: ThreadMapping,
+        unpinned: bool,
         benchmark: &str,
         write_ratio: usize,
         open_files: usize,
         duration: u64,
         client_params: ClientParams,
-        outfile: &String,
-    ) {
+    ) -> CoreResult {
         // let bench_duration_secs = if cfg!(feature = "smoke") { 1 } else { 10 };
-        let bench_duration_secs = duration;
-        let iops = self.bench.run(
-            &POOR_MANS_BARRIER,
-            bench_duration_secs,
+        let mut bench_duration_secs = duration;
+        PHASE_TIMES.with(|times| times.set((None, None)));
+        MEASURED.with(|measured| measured.set((0, false)));
+        ERROR_BUDGET.with(|run| *run.borrow_mut() = error_budget.clone());
+        let run = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.bench.run(
+                barrier,
+                bench_duration_secs,
+                core_id,
+                write_ratio,
+                &client_params,
+            )
+        }));
+        let mut samples = match run {
+            Ok(samples) => samples,
+            Err(panicked) => {
+                // The other threads would wait for this one forever
+                barrier.poison();
+                panic::resume_unwind(panicked)
+            }
+        };
+        let (released, ended) = PHASE_TIMES.with(Cell::get);
+        samples.released = released;
+        samples.ended = ended;
+        if let (measured, true) = MEASURED.with(Cell::get) {
+            // The run ends with the second it stopped in, second 0 is warmup
+            samples.truncate(measured);
+            bench_duration_secs = measured.saturating_sub(1) as u64;
+        }
+
+        CoreResult {
             core_id,
+            benchmark: benchmark.to_string(),
+            cores,
             write_ratio,
-            client_params.conn_type,
-            client_params.rpc_type,
-        );
-
-        let mut csv_file = if client_params.log_mode == LogMode::CSV {
-            Some(Box::new(
-                OpenOptions::new()
-                    .append(true)
-                    .create(true)
-                    .open(outfile)
-                    .expect("Cant open output file"),
-            ))
-        } else {
-            None
-        };
+            open_files,
+            thread_mapping,
+            unpinned,
+            duration: bench_duration_secs,
+            workload: None,
+            over_budget: false,
+            samples,
+        }
+    }
+}
 
-        for iteration in 1..(bench_duration_secs + 1) {
-            let row = format!(
-                "{},{:?},{},{},{},{},{},{},{},{},{},{}\n",
-                core_id + (client_params.ccores * client_params.cid),
-                benchmark,
-                cores * client_params.nclients,
-                write_ratio,
-                open_files,
-                bench_duration_secs,
-                iteration,
-                iops[iteration as usize],
-                client_params.cid,
-                client_params.ccores,
-                client_params.nclients,
-                client_params.rpc_type,
-            );
+/// Creates the directory `dir` that a bench keeps its files in. Another core,
+/// or an earlier run that kept its files or crashed, may have created it
+/// already, which is fine. Panics if the mkdir fails for any other reason.
+pub fn setup_dir(client: &mut Box<dyn FxRPC>, dir: &str) {
+    let ret = client
+        .rpc_mkdir(dir, libc::S_IRWXU.into())
+        .expect("MkDir syscall failed");
+    if ret == -1 {
+        // The servers don't return mkdir's errno, so tell EEXIST apart by
+        // looking for the directory
+        let exists = client
+            .rpc_fstatat(libc::AT_FDCWD, dir, 0)
+            .map_or(false, |stat| stat.is_dir());
+        assert!(exists, "Unable to create directory {}", dir);
+    }
+}
 
-            match client_params.log_mode {
-                LogMode::CSV => {
-                    if let Some(ref mut my_file) = csv_file {
-                        let r = my_file.write(row.as_bytes());
-                        assert!(r.is_ok());
-                    } else {
-                        panic!("Should have file in CSV mode");
-                    }
-                }
-                LogMode::STDOUT => {
-                    print!("{}", row);
-                }
-            }
-        }
+/// Populates `dir`, created if needed, with `count` empty files so that
+/// benchmarks run against a directory of a given size. An empty `dir` is the
+/// server directory itself.
+pub fn prefill_dir(client: &mut Box<dyn FxRPC>, dir: &str, count: usize) {
+    if !dir.is_empty() {
+        setup_dir(client, dir);
+    }
+    for n in 0..count {
+        let filename = format!("{}prefill{}.txt", dir, n);
+        let fd = client
+            .rpc_open(
+                &filename,
+                libc::O_RDWR | libc::O_CREAT,
+                libc::S_IRWXU.into(),
+            )
+            .expect("FileOpen syscall failed");
+        assert!(fd != -1, "Unable to create prefill file {}", filename);
+        client.rpc_close(fd).expect("FileClose syscall failed");
     }
 }
 
-pub fn max_open_files() -> usize {
-    let topology = MachineTopology::new();
-    topology.cores()
+/// Opens `filename`, the file a bench core works on, with `flags`. With a
+/// `ClientParams::device` the device is opened in its place, never created
+/// or truncated, and its size in bytes is returned too. Benches keep their
+/// I/O within that size and neither fill nor remove the device.
+pub fn open_target(
+    client: &mut Box<dyn FxRPC>,
+    client_params: &ClientParams,
+    filename: &str,
+    flags: i32,
+) -> (i32, Option<usize>) {
+    match &client_params.device {
+        Some(device) => {
+            let flags = flags & !(libc::O_CREAT | libc::O_EXCL | libc::O_TRUNC);
+            let fd = client
+                .rpc_open(device, flags, 0)
+                .expect("FileOpen syscall failed");
+            assert!(fd != -1, "Unable to open device {}", device);
+            let stat = client
+                .rpc_fstatat(libc::AT_FDCWD, device, 0)
+                .expect("FstatAt syscall failed");
+            assert!(stat.is_block_device(), "{} is not a block device", device);
+            assert!(
+                stat.size >= PAGE_SIZE as i64,
+                "Device {} is smaller than a page",
+                device
+            );
+            (fd, Some(stat.size as usize))
+        }
+        None => {
+            let fd = client
+                .rpc_open(filename, flags, libc::S_IRWXU.into())
+                .expect("FileOpen syscall failed");
+            assert!(fd != -1, "Unable to create a file");
+            (fd, None)
+        }
+    }
 }
 
-pub fn bench(
-    benchmark: String,
-    open_files: usize,
-    write_ratio: usize,
-    duration: u64,
+/// Writes `pages` pages of `client_params.fill_pattern` to `fd`, starting at
+/// `offset`. `file` tells the files of a bench apart for random patterns.
+/// With `reuse_files` the file may be left over from the previous repeat, so
+/// it's truncated to `offset` first. The pages written here then bring it
+/// back to its initial size, also when it's open with O_APPEND.
+pub fn fill_file(
+    client: &mut Box<dyn FxRPC>,
     client_params: &ClientParams,
-    outfile: &String,
+    fd: i32,
+    file: usize,
+    offset: i64,
+    pages: usize,
 ) {
-    fn start<
-        T: Bench + Default + core::marker::Send + core::marker::Sync + 'static + core::clone::Clone,
-    >(
-        microbench: MicroBench<'static, T>,
-        open_files: usize,
-        write_ratio: usize,
-        duration: u64,
-        client_params: &ClientParams,
-        outfile: &String,
-    ) {
-        let thread_mappings = microbench.thread_mappings.clone();
-        let threads = microbench.threads.clone();
-
-        for tm in thread_mappings.iter() {
-            for ts in threads.iter() {
-                let topology = MachineTopology::new();
-                utils::disable_dvfs();
-
-                let cpus = topology.allocate(*tm, *ts, false);
-                let cores: Vec<u64> = cpus.iter().map(|c| c.cpu).collect();
-                let clen = cores.len();
-
-                if matches!(client_params.log_mode, LogMode::CSV) {
-                    println!(
-                        "Run Benchmark={} TM={} Cores={}; Write-Ratio={} Open-Files={}",
-                        microbench.benchmark, *tm, ts, write_ratio, open_files
-                    );
-                }
-
-                // currently we'll run out of 4 KiB frames
-                let mut thandles = Vec::with_capacity(clen);
-                // Set up barrier
-                POOR_MANS_BARRIER.store(clen, Ordering::SeqCst);
-
-                for core_id in cores.clone() {
-                    let mb = Arc::new(microbench.clone());
-                    mb.bench.init(
-                        cores.clone(),
-                        open_files,
-                        client_params.conn_type,
-                        client_params.rpc_type,
-                    );
-
-                    let bench_duration = duration.clone();
-                    let params = (*client_params).clone();
-                    let outfile_cloned = outfile.clone();
-                    thandles.push(thread::spawn(move || {
-                        utils::pin_thread(core_id);
-                        let arg = Arc::into_raw(mb) as *const _ as *mut u8;
-                        unsafe {
-                            fxmark_bencher_trampoline::<T>(
-                                arg,
-                                clen,
-                                core_id as usize,
-                                bench_duration,
-                                params,
-                                &outfile_cloned,
-                            );
-                        }
-                    }));
-                }
+    if client_params.reuse_files {
+        let ret = client
+            .rpc_ftruncate(fd, offset)
+            .expect("FileTruncate syscall failed");
+        assert_eq!(ret, 0);
+    }
+    let mut page = vec![0; PAGE_SIZE];
+    for n in 0..pages {
+        let page_offset = offset as usize + n * PAGE_SIZE;
+        client_params
+            .fill_pattern
+            .fill(&mut page, page_offset, client_params.seed, file);
+        pwrite_all(client, fd, &page, page_offset as i64).expect("FileWriteAt syscall failed");
+    }
+}
 
-                for thandle in thandles {
-                    let _ = thandle.join();
-                }
-            }
+/// Writes all of `buf` at `offset` of `fd`, writing the rest again for as
+/// long as the server writes less than asked, as it may on a nearly full
+/// disk. Fails with the error of a write that failed or wrote nothing.
+pub fn pwrite_all(
+    client: &mut Box<dyn FxRPC>,
+    fd: i32,
+    buf: &[u8],
+    offset: i64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut written = 0;
+    while written < buf.len() {
+        let rest = &buf[written..];
+        let ret = client.rpc_pwrite(fd, rest, rest.len(), offset + written as i64)?;
+        if ret <= 0 {
+            return Err(format!("write_at() returned {} of {} bytes", ret, rest.len()).into());
         }
+        written += ret as usize;
     }
+    Ok(())
+}
+
+/// Writes `page` at `offset` and syncs it as the client's `SyncPolicy` asks.
+/// `writes` counts the calling core's writes so far. Fails like `check_op()`
+/// if either the write or the sync did.
+pub fn write_synced(
+    client: &mut Box<dyn FxRPC>,
+    client_params: &ClientParams,
+    writes: &mut usize,
+    fd: i32,
+    page: &[u8],
+    offset: i64,
+) -> Result<(), Option<i32>> {
+    check_op(
+        client.rpc_pwrite(fd, page, page.len(), offset),
+        page.len() as i32,
+    )?;
+    *writes += 1;
+    if client_params.sync_policy.sync_after(*writes) {
+        check_op(client.rpc_fsync(fd), 0)?;
+    }
+    Ok(())
+}
+
+/// Removes the files created by `prefill_dir`.
+pub fn cleanup_prefill(client: &mut Box<dyn FxRPC>, dir: &str, count: usize) {
+    for n in 0..count {
+        let filename = format!("{}prefill{}.txt", dir, n);
+        client
+            .rpc_remove(&filename)
+            .expect("FileRemove syscall failed");
+    }
+}
+
+// Descriptors the client keeps for stdio, output files and the like
+const FD_HEADROOM: usize = 32;
+
+/// How many files a benchmark may open under a soft `RLIMIT_NOFILE` of
+/// `soft`, once `connections` connections and some headroom for the
+/// client's own files are taken off. Unbounded without a limit.
+pub fn open_files_cap(soft: Option<usize>, connections: usize) -> usize {
+    match soft {
+        Some(soft) => soft.saturating_sub(FD_HEADROOM + connections).max(1),
+        None => usize::MAX,
+    }
+}
+
+/// How many files a benchmark may open under the client's `RLIMIT_NOFILE`.
+pub fn max_open_files() -> usize {
+    open_files_cap(nofile_limit(), 0)
+}
+
+/// `open_files` capped to `cap`, and the warning to print if that's fewer.
+pub fn clamp_open_files(open_files: usize, cap: usize) -> (usize, Option<String>) {
+    if open_files <= cap {
+        return (open_files, None);
+    }
+    let warning = format!(
+        "{} open files exceed RLIMIT_NOFILE, opening {} (raise it with ulimit -n)",
+        open_files, cap
+    );
+    (cap, Some(warning))
+}
+
+/// Warns if more descriptors are open than the `before` count taken ahead of
+/// benchmark `point`, i.e. the point leaked some. Returns how many it leaked.
+pub fn warn_fd_leak(before: usize, point: &str) -> usize {
+    let after = match utils::open_fd_count() {
+        Some(after) => after,
+        None => return 0,
+    };
+    let leaked = after.saturating_sub(before);
+    if leaked > 0 {
+        eprintln!(
+            "WARNING: {} leaked {} file descriptors ({} open before, {} after)",
+            point, leaked, before, after
+        );
+    }
+    leaked
+}
+
+/// Checks that the benchmark files can be created in `path`: it exists, is a
+/// directory and a probe file can be created in it.
+pub fn check_base_path(path: &Path) -> Result<(), String> {
+    let fail = |reason: String| format!("Benchmark directory {}: {}", path.display(), reason);
+
+    let metadata = std::fs::metadata(path).map_err(|e| fail(format!("not accessible ({})", e)))?;
+    if !metadata.is_dir() {
+        return Err(fail("not a directory".to_string()));
+    }
+
+    let probe = path.join(format!(".fxmark_probe{}", std::process::id()));
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .open(&probe)
+        .map_err(|e| fail(format!("not writable ({})", e)))?;
+    std::fs::remove_file(&probe).map_err(|e| fail(format!("can't remove probe file ({})", e)))
+}
+
+// What went wrong with a failed RPC, its errno if the server sent one
+fn rpc_failure(res: &Result<i32, Box<dyn std::error::Error>>) -> String {
+    match (op_errno(res), res) {
+        (Some(errno), _) => errno_name(errno),
+        (None, Err(e)) => e.to_string(),
+        (None, Ok(ret)) => format!("returned {}", ret),
+    }
+}
+
+/// Checks over RPC that every server of `client_params` can create the
+/// benchmark files in its directory: a probe file is created and removed
+/// there. The directory is the server's, which may be on another host or
+/// elsewhere than `FS_PATH`.
+pub fn check_server_dirs(client_params: &ClientParams) -> Result<(), String> {
+    let probe = format!(".fxmark_probe{}", std::process::id());
+    for server in servers(client_params) {
+        let fail = |reason: String| {
+            format!(
+                "Benchmark directory of server {}: probe file {} {}",
+                server, probe, reason
+            )
+        };
+        let mut client = init_client_for(server, client_params);
+        let res = client.rpc_open(&probe, libc::O_RDWR | libc::O_CREAT, libc::S_IRWXU.into());
+        match res {
+            Ok(fd) if fd != -1 => {
+                let _ = client.rpc_close(fd);
+            }
+            res => return Err(fail(format!("can't be created ({})", rpc_failure(&res)))),
+        }
+        let res = client.rpc_remove(&probe);
+        if !matches!(res, Ok(0)) {
+            return Err(fail(format!("can't be removed ({})", rpc_failure(&res))));
+        }
+    }
+    Ok(())
+}
+
+/// Runs `benchmark` `client_params.repeat` times and writes the results of
+/// each run to `outfile` or stdout. Returns the results of all runs. Fails
+/// before starting any threads if a server's directory isn't usable, and
+/// after writing its results if a run went over its error budget.
+pub fn bench(
+    benchmark: String,
+    open_files: usize,
+    write_ratio: usize,
+    duration: u64,
+    client_params: &ClientParams,
+    outfile: &String,
+) -> Result<Vec<CoreResult>, String> {
+    check_server_dirs(client_params)?;
+    // Every bench thread's connections count against the limit too
+    let connections =
+        client_params.ccores * client_params.conns_per_core * client_params.queue_depth;
+    let (open_files, warning) =
+        clamp_open_files(open_files, open_files_cap(nofile_limit(), connections));
+    if let Some(warning) = warning {
+        eprintln!("WARNING: {}", warning);
+    }
+    let topology = MachineTopology::new();
+    let cores: Vec<u64> = topology
+        .allocate(client_params.thread_mapping, client_params.ccores, false)
+        .iter()
+        .map(|c| c.cpu)
+        .collect();
+    for warning in topology.preflight_warnings(&cores, utils::turbo_enabled()) {
+        eprintln!("WARNING: {}", warning);
+    }
+    eprint!(
+        "{}",
+        effective_config(&benchmark, open_files, write_ratio, duration, client_params)
+    );
+
+    let mut all_results = Vec::new();
+    for repeat in 0..client_params.repeat {
+        let params = repeat_params(client_params, repeat);
+        let results = bench_collect(
+            benchmark.clone(),
+            open_files,
+            write_ratio,
+            duration,
+            &params,
+        )?;
+        log_results(&results, &params, outfile);
+        check_error_budget(&results, &params)?;
+        all_results.extend(results);
+    }
+    Ok(all_results)
+}
+
+/// Every parameter a run of `benchmark` resolved to, one `# name = value`
+/// line each, so a misconfigured run shows before its results do.
+pub fn effective_config(
+    benchmark: &str,
+    open_files: usize,
+    write_ratio: usize,
+    duration: u64,
+    client_params: &ClientParams,
+) -> String {
+    let mut config = format!(
+        "# benchmark = {}\n# open_files = {}\n# write_ratio = {}\n# duration = {}\n",
+        benchmark, open_files, write_ratio, duration
+    );
+    let mut client_params = client_params.clone();
+    client_params.io_size = Some(io_size(benchmark, &client_params));
+    let params = serde_json::to_value(client_params).expect("Can't serialize client params");
+    if let serde_json::Value::Object(params) = params {
+        for (name, value) in params {
+            config.push_str(&format!("# {} = {}\n", name, value));
+        }
+    }
+    config
+}
+
+/// The client params of repeat `repeat` of a benchmark point. With
+/// `reuse_files` every repeat but the last keeps its files for the next one.
+pub fn repeat_params(client_params: &ClientParams, repeat: usize) -> ClientParams {
+    let mut params = client_params.clone();
+    if client_params.reuse_files && repeat + 1 < client_params.repeat {
+        params.keep_files = true;
+    }
+    params
+}
+
+// Every server the bench threads of `client_params` connect to
+fn servers(client_params: &ClientParams) -> &[RPCEndpoint] {
+    if client_params.servers.is_empty() {
+        core::slice::from_ref(&client_params.endpoint)
+    } else {
+        &client_params.servers
+    }
+}
+
+/// Asks every server of `client_params` what it supports, before a run.
+/// Warns about servers of another protocol version and servers that don't
+/// answer the probe, which predate it. Returns false if a server doesn't
+/// support `client_params.rpc_type`, the run should be skipped then.
+pub fn probe_servers(client_params: &ClientParams) -> bool {
+    for server in servers(client_params) {
+        let mut client = init_client_for(server, client_params);
+        match client.rpc_capabilities() {
+            Ok(caps) => {
+                if !caps.rpc_types.contains(&client_params.rpc_type) {
+                    eprintln!(
+                        "Skipping run: server {} doesn't support {}",
+                        server, client_params.rpc_type
+                    );
+                    return false;
+                }
+                if caps.protocol_version != PROTOCOL_VERSION {
+                    eprintln!(
+                        "WARNING: server {} speaks protocol version {}, this client {}",
+                        server, caps.protocol_version, PROTOCOL_VERSION
+                    );
+                }
+            }
+            Err(e) => eprintln!(
+                "WARNING: can't probe the capabilities of server {} ({}), it may be older than this client",
+                server, e
+            ),
+        }
+    }
+    true
+}
+
+// Pins a throwaway thread to each of `cores`. Fails a run whose bench threads
+// can't be pinned before any of them starts waiting for the others.
+fn check_pinning(cores: &[u64]) -> Result<(), String> {
+    for core in cores.iter().copied() {
+        thread::spawn(move || utils::pin_thread(core))
+            .join()
+            .expect("Pinning thread panicked")?;
+    }
+    Ok(())
+}
+
+// Pins the calling bench thread to `core_id`, unless `pin` is off and it
+// should float. If it can't be pinned there, it takes one of `spares` so it
+// doesn't migrate or share a core with another bench thread, and floats if
+// none is left. Returns whether the thread runs unpinned, i.e. anywhere but
+// on `core_id`.
+fn place_thread(pin: bool, core_id: u64, spares: &Mutex<Vec<u64>>) -> bool {
+    if !pin {
+        return true;
+    }
+    let e = match utils::pin_thread(core_id) {
+        Ok(()) => return false,
+        Err(e) => e,
+    };
+    loop {
+        // Not locked while pinning, other threads may take spares meanwhile
+        let spare = spares.lock().unwrap().pop();
+        match spare {
+            Some(spare) if utils::pin_thread(spare).is_ok() => {
+                eprintln!(
+                    "WARNING: {}, pinned to spare core {} instead, results are flagged unpinned",
+                    e, spare
+                );
+                return true;
+            }
+            Some(_) => continue,
+            None => {
+                eprintln!("WARNING: {}, results are flagged unpinned", e);
+                return true;
+            }
+        }
+    }
+}
+
+/// A benchmark `bench_collect()` runs by name. Bench modules register
+/// theirs with `register_bench!`.
+pub struct BenchEntry {
+    name: &'static str,
+    /// Bytes each op reads or writes unless `ClientParams::io_size` says
+    /// otherwise. Benches that do metadata ops only default to 0.
+    io_size: usize,
+    run: BenchRun,
+    co_start: BenchCoStart,
+}
+
+// `start()` of a bench
+type BenchRun =
+    fn(&'static str, usize, usize, u64, &ClientParams) -> Result<Vec<CoreResult>, String>;
+
+// `co_start()` of a bench
+type BenchCoStart = fn(
+    &'static str,
+    &[u64],
+    &Arc<Run>,
+    usize,
+    usize,
+    u64,
+    &ClientParams,
+    usize,
+) -> Vec<thread::JoinHandle<CoreResult>>;
+
+inventory::collect!(BenchEntry);
+
+// The benchmark registered as `benchmark`, if any
+fn registered(benchmark: &str) -> Option<&'static BenchEntry> {
+    inventory::iter::<BenchEntry>
+        .into_iter()
+        .find(|b| b.name == benchmark)
+}
+
+/// Names of all registered benchmarks, sorted.
+pub fn bench_names() -> Vec<&'static str> {
+    let mut names: Vec<&'static str> = inventory::iter::<BenchEntry>
+        .into_iter()
+        .map(|b| b.name)
+        .collect();
+    names.sort_unstable();
+    names
+}
+
+/// Bytes each op of `benchmark` reads or writes: `ClientParams::io_size` if
+/// set, the default of the benchmark otherwise. Only drbr and ococ take
+/// other sizes than their default, the rest keep to theirs.
+pub fn io_size(benchmark: &str, client_params: &ClientParams) -> usize {
+    client_params
+        .io_size
+        .unwrap_or_else(|| registered(benchmark).map_or(PAGE_SIZE, |b| b.io_size))
+}
+
+/// What drbr and ococ draw the size of each op from:
+/// `ClientParams::size_dist` if set, `io_size()` for every op otherwise.
+pub fn size_dist(benchmark: &str, client_params: &ClientParams) -> SizeDistribution {
+    client_params
+        .size_dist
+        .unwrap_or_else(|| SizeDistribution::Fixed(io_size(benchmark, client_params)))
+}
+
+// Runs the bench `T` under the name `benchmark` it's registered as
+fn start<T: Bench + Default + core::marker::Send + 'static + core::clone::Clone>(
+    benchmark: &'static str,
+    open_files: usize,
+    write_ratio: usize,
+    duration: u64,
+    client_params: &ClientParams,
+) -> Result<Vec<CoreResult>, String> {
+    let microbench = MicroBench::<T>::new(benchmark, write_ratio, open_files, client_params);
+    let mut results = Vec::new();
+    let thread_mappings = microbench.thread_mappings.clone();
+    let threads = microbench.threads.clone();
+
+    for tm in thread_mappings.iter() {
+        for ts in threads.iter() {
+            let topology = MachineTopology::new();
+            utils::disable_dvfs();
+            let fds_before = if client_params.check_fd_leaks {
+                utils::open_fd_count()
+            } else {
+                None
+            };
+
+            let cpus = topology.allocate(*tm, *ts, false);
+            let cores: Vec<u64> = cpus.iter().map(|c| c.cpu).collect();
+            let clen = cores.len();
+            if client_params.pin && client_params.require_pinning {
+                check_pinning(&cores)?;
+            }
+
+            if matches!(client_params.log_mode, LogMode::CSV) {
+                println!(
+                    "Run Benchmark={} TM={} Cores={}; Write-Ratio={} Open-Files={}",
+                    microbench.benchmark, *tm, ts, write_ratio, open_files
+                );
+            }
+
+            let run = Run::new(&cores);
+            let first_result = results.len();
+
+            let benches = init_benches(&microbench, &cores, open_files, client_params);
+
+            // After init so the files it wrote aren't cached either. The
+            // server's cache is the one its reads hit.
+            if client_params.drop_caches {
+                for server in servers(client_params) {
+                    let mut client = init_client_for(server, client_params);
+                    client.rpc_drop_caches().expect("DropCaches RPC failed");
+                }
+            }
+
+            if client_params.single_threaded {
+                // The cores take turns on this thread, each through its
+                // whole run, unpinned. The lowest goes first, as it sets up
+                // what the cores of some benches share.
+                let barrier = PoorMansBarrier::synthetic();
+                let mut turns: Vec<_> = cores.iter().zip(benches).enumerate().collect();
+                turns.sort_by_key(|(_, (core_id, _))| **core_id);
+                for (thread, (core_id, mb)) in turns {
+                    let mut params = (*client_params).clone();
+                    params.endpoint = client_params.server_of(thread).clone();
+                    let turn = panic::catch_unwind(AssertUnwindSafe(|| {
+                        mb.fxmark_bencher(
+                            &barrier,
+                            &run.error_budget,
+                            clen,
+                            *core_id as usize,
+                            *tm,
+                            true,
+                            mb.benchmark,
+                            mb.write_ratio,
+                            mb.open_files,
+                            duration,
+                            params,
+                        )
+                    }));
+                    results.push(thread_result(benchmark, turn)?);
+                }
+            } else {
+                let thandles =
+                    spawn_benches(benches, &cores, &run, *tm, duration, client_params, 0);
+                results.extend(join_benches(benchmark, thandles)?);
+            }
+            let over_budget = run.over_budget(client_params);
+            for result in &mut results[first_result..] {
+                result.over_budget = over_budget;
+            }
+
+            if let Some(before) = fds_before {
+                let point = format!(
+                    "Benchmark={} TM={} Cores={}; Write-Ratio={} Open-Files={}",
+                    microbench.benchmark, *tm, ts, write_ratio, open_files
+                );
+                warn_fd_leak(before, &point);
+            }
+        }
+    }
+    Ok(results)
+}
 
-    if benchmark == "mix" {
-        let mb = MicroBench::<MIX>::new("mix", write_ratio, open_files, client_params);
-        start::<MIX>(
-            mb,
+// What a bench thread of `benchmark` measured, or the panic that ended it
+fn thread_result(
+    benchmark: &str,
+    joined: thread::Result<CoreResult>,
+) -> Result<CoreResult, String> {
+    joined.map_err(|panicked| {
+        let msg = match panicked.downcast_ref::<String>() {
+            Some(msg) => msg.as_str(),
+            None => panicked.downcast_ref::<&str>().copied().unwrap_or("?"),
+        };
+        format!("A thread of {} panicked: {}", benchmark, msg)
+    })
+}
+
+// Joins every one of `thandles`, also after one of them failed, and returns
+// what they measured or the first failure
+fn join_benches(
+    benchmark: &str,
+    thandles: Vec<thread::JoinHandle<CoreResult>>,
+) -> Result<Vec<CoreResult>, String> {
+    let mut results = Vec::with_capacity(thandles.len());
+    let mut failed = None;
+    for thandle in thandles {
+        match thread_result(benchmark, thandle.join()) {
+            Ok(result) => results.push(result),
+            Err(e) => {
+                failed.get_or_insert(e);
+            }
+        }
+    }
+    failed.map_or(Ok(results), Err)
+}
+
+// Initialized copies of `microbench` for its threads on `cores`, as each
+// thread owns its own
+fn init_benches<T: Bench + Default + core::marker::Send + 'static + core::clone::Clone>(
+    microbench: &MicroBench<'static, T>,
+    cores: &[u64],
+    open_files: usize,
+    client_params: &ClientParams,
+) -> Vec<Box<MicroBench<'static, T>>> {
+    let mut benches = Vec::with_capacity(cores.len());
+    for _ in 0..cores.len() {
+        let mut mb = microbench.clone();
+        mb.bench.init(cores.to_vec(), open_files, client_params);
+        benches.push(Box::new(mb));
+    }
+    benches
+}
+
+// Spawns a thread for each of `benches`, on its core of `cores`, as threads
+// of `run`. Thread `n` connects to the server of thread `first_thread + n`.
+fn spawn_benches<T: Bench + Default + core::marker::Send + 'static + core::clone::Clone>(
+    benches: Vec<Box<MicroBench<'static, T>>>,
+    cores: &[u64],
+    run: &Arc<Run>,
+    thread_mapping: ThreadMapping,
+    duration: u64,
+    client_params: &ClientParams,
+    first_thread: usize,
+) -> Vec<thread::JoinHandle<CoreResult>> {
+    let clen = cores.len();
+    let mut thandles = Vec::with_capacity(clen);
+    for (thread, (core_id, mb)) in cores.iter().copied().zip(benches).enumerate() {
+        let mut params = (*client_params).clone();
+        params.endpoint = client_params.server_of(first_thread + thread).clone();
+        let run = run.clone();
+        thandles.push(thread::spawn(move || {
+            let unpinned = place_thread(params.pin, core_id, &run.spare_cores);
+            if params.numa_local_alloc {
+                utils::bind_memory_to_local_node(core_id);
+            }
+            let arg = Box::into_raw(mb) as *mut u8;
+            unsafe {
+                fxmark_bencher_trampoline::<T>(
+                    arg,
+                    &run,
+                    clen,
+                    core_id as usize,
+                    thread_mapping,
+                    unpinned,
+                    duration,
+                    params,
+                )
+            }
+        }));
+    }
+    thandles
+}
+
+// Initializes the bench `T` as the benchmark `benchmark` on `cores` and
+// spawns its threads, the first of them as thread `first_thread` of the
+// `co_run()` `run`
+fn co_start<T: Bench + Default + core::marker::Send + 'static + core::clone::Clone>(
+    benchmark: &'static str,
+    cores: &[u64],
+    run: &Arc<Run>,
+    open_files: usize,
+    write_ratio: usize,
+    duration: u64,
+    client_params: &ClientParams,
+    first_thread: usize,
+) -> Vec<thread::JoinHandle<CoreResult>> {
+    let microbench = MicroBench::<T>::new(benchmark, write_ratio, open_files, client_params);
+    let benches = init_benches(&microbench, cores, open_files, client_params);
+    spawn_benches(
+        benches,
+        cores,
+        run,
+        client_params.thread_mapping,
+        duration,
+        client_params,
+        first_thread,
+    )
+}
+
+/// Runs the benchmarks of `plan` at the same time, each on its own cores,
+/// and returns what each of their threads measured. The threads of all of
+/// them start and end their timed phase together, so each benchmark's
+/// results show what the others cost it. Each result is tagged with its
+/// workload, `<index>:<benchmark>` by the order of `plan`. Fails if a
+/// benchmark isn't registered, a core is in two workloads or a thread
+/// can't be pinned under `ClientParams::require_pinning`.
+pub fn co_run(
+    plan: Vec<(String, Vec<u64>)>,
+    open_files: usize,
+    write_ratio: usize,
+    duration: u64,
+    client_params: &ClientParams,
+) -> Result<Vec<CoreResult>, String> {
+    if client_params.single_threaded || client_params.drop_caches {
+        return Err("Co-located benchmarks can't run single-threaded or drop caches".to_string());
+    }
+    let mut workloads = Vec::with_capacity(plan.len());
+    let mut all_cores: Vec<u64> = Vec::new();
+    for (benchmark, cores) in &plan {
+        let entry = registered(benchmark).ok_or(format!("No benchmark {}", benchmark))?;
+        if cores.is_empty() {
+            return Err(format!("No cores to run {} on", benchmark));
+        }
+        if let Some(core) = cores.iter().find(|c| all_cores.contains(c)) {
+            return Err(format!("Core {} is in two workloads", core));
+        }
+        all_cores.extend(cores);
+        workloads.push((entry, cores));
+    }
+    if client_params.pin && client_params.require_pinning {
+        check_pinning(&all_cores)?;
+    }
+    utils::disable_dvfs();
+
+    let run = Run::new(&all_cores);
+    let mut thandles = Vec::with_capacity(workloads.len());
+    let mut first_thread = 0;
+    for (entry, cores) in workloads {
+        let mut params = client_params.clone();
+        params.ccores = cores.len();
+        thandles.push((entry.co_start)(
+            entry.name,
+            cores,
+            &run,
+            open_files,
+            write_ratio,
+            duration,
+            &params,
+            first_thread,
+        ));
+        first_thread += cores.len();
+    }
+
+    let mut results = Vec::with_capacity(all_cores.len());
+    let mut failed = None;
+    for (index, (workload, (benchmark, _))) in thandles.into_iter().zip(&plan).enumerate() {
+        match join_benches(benchmark, workload) {
+            Ok(workload) => {
+                for mut result in workload {
+                    result.workload = Some(format!("{}:{}", index, result.benchmark));
+                    results.push(result);
+                }
+            }
+            Err(e) => {
+                failed.get_or_insert(e);
+            }
+        }
+    }
+    if let Some(e) = failed {
+        return Err(e);
+    }
+    let over_budget = run.over_budget(client_params);
+    for result in &mut results {
+        result.over_budget = over_budget;
+    }
+    Ok(results)
+}
+
+/// Parses a `--co-run` workload, `BENCHMARK@CORE` or `BENCHMARK@FIRST-LAST`
+/// for the cores `FIRST` to `LAST`.
+pub fn parse_workload(s: &str) -> Result<(String, Vec<u64>), String> {
+    let invalid = || format!("Invalid workload {}, expected BENCHMARK@FIRST-LAST", s);
+    let (benchmark, cores) = s.split_once('@').ok_or_else(invalid)?;
+    let (first, last) = cores.split_once('-').unwrap_or((cores, cores));
+    let first: u64 = first.parse().map_err(|_| invalid())?;
+    let last: u64 = last.parse().map_err(|_| invalid())?;
+    if benchmark.is_empty() || last < first {
+        return Err(invalid());
+    }
+    Ok((benchmark.to_string(), (first..=last).collect()))
+}
+
+/// Runs `benchmark` and returns what each of its threads measured. Fails if
+/// no benchmark of that name is registered, or a thread can't be pinned to
+/// its core under `ClientParams::require_pinning`.
+pub fn bench_collect(
+    benchmark: String,
+    open_files: usize,
+    write_ratio: usize,
+    duration: u64,
+    client_params: &ClientParams,
+) -> Result<Vec<CoreResult>, String> {
+    match registered(&benchmark) {
+        Some(entry) => (entry.run)(entry.name, open_files, write_ratio, duration, client_params),
+        None => Err(format!("Unknown benchmark {}", benchmark)),
+    }
+}
+
+/// Runs the benchmark `args` describes. `args` decides the core count, the
+/// `ccores` of `client_params` is ignored.
+pub fn bench_from_args(
+    args: &ARGs,
+    duration: u64,
+    client_params: &ClientParams,
+    outfile: &str,
+) -> Result<(), String> {
+    let mut params = client_params.clone();
+    params.ccores = args.cores;
+    bench(
+        args.benchmark.clone(),
+        args.open_files,
+        args.write_ratio,
+        duration,
+        &params,
+        &outfile.to_string(),
+    )
+    .map(|_| ())
+}
+
+/// Core counts of a `--sweep <step>`: 1, then every `step` cores up to
+/// and including `max_cores`.
+pub fn sweep_steps(max_cores: usize, step: usize) -> Vec<usize> {
+    let mut steps: Vec<usize> = (0..(max_cores + 1))
+        .step_by(core::cmp::max(1, step))
+        .map(|t| if t == 0 { 1 } else { t })
+        .collect();
+    steps.push(max_cores);
+    steps.sort_unstable();
+    steps.dedup();
+    steps
+}
+
+/// Runs `bench()` for every combination of open files, write ratio and core
+/// count. Every row records its configuration, so all runs share one output.
+/// Once `deadline` has passed since the sweep started, the remaining
+/// configurations are skipped, and a run is shortened to end by the deadline.
+/// With `summary_table` a table of the runs' throughput is printed at the
+/// end. Returns the skipped configurations.
+pub fn sweep(
+    benchmark: String,
+    open_files: &[usize],
+    write_ratios: &[usize],
+    core_counts: &[usize],
+    duration: u64,
+    client_params: &ClientParams,
+    outfile: &String,
+    deadline: Option<Duration>,
+) -> Result<Vec<String>, String> {
+    let start = Instant::now();
+    let mut skipped = Vec::new();
+    let mut results = Vec::new();
+
+    for of in open_files {
+        for wr in write_ratios {
+            for cores in core_counts {
+                let mut run_duration = duration;
+                if let Some(deadline) = deadline {
+                    let left = deadline.saturating_sub(start.elapsed()).as_secs();
+                    if left == 0 {
+                        let config = format!(
+                            "Benchmark={} Cores={}; Write-Ratio={} Open-Files={}",
+                            benchmark, cores, wr, of
+                        );
+                        println!(
+                            "Skip {}: deadline of {}s reached",
+                            config,
+                            deadline.as_secs()
+                        );
+                        skipped.push(config);
+                        continue;
+                    }
+                    run_duration = core::cmp::min(duration, left);
+                }
+
+                let mut params = client_params.clone();
+                params.ccores = *cores;
+                results.extend(bench(
+                    benchmark.clone(),
+                    *of,
+                    *wr,
+                    run_duration,
+                    &params,
+                    outfile,
+                )?);
+            }
+        }
+    }
+    if client_params.summary_table {
+        print!("{}", render_pivot(&results));
+    }
+    Ok(skipped)
+}
+
+/// Columns of the rows `latency_curve()` writes.
+pub const LATENCY_CURVE_HEADER: &str =
+    "client_id,benchmark,ncores,write_ratio,open_files,offered_iops,achieved_iops,p99_latency_ns\n";
+
+/// One run of a latency curve: the load this client offered, the throughput
+/// it got and the 99th percentile latency of its ops, over all its threads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadPoint {
+    pub offered_iops: u64,
+    pub achieved_iops: f64,
+    pub p99_latency_ns: u64,
+}
+
+/// Runs `benchmark` once for every rate of `target_iops`, in order, with each
+/// thread held to that many ops per second. Writes one row per run to
+/// `outfile` or stdout, as `client_params.log_mode` asks, and returns them.
+/// With increasing rates latency stays flat until the server saturates.
+pub fn latency_curve(
+    benchmark: String,
+    open_files: usize,
+    write_ratio: usize,
+    duration: u64,
+    client_params: &ClientParams,
+    target_iops: &[u64],
+    outfile: &str,
+) -> Result<Vec<LoadPoint>, String> {
+    check_server_dirs(client_params)?;
+
+    let mut points = Vec::with_capacity(target_iops.len());
+    for target in target_iops {
+        let mut params = client_params.clone();
+        params.target_iops = Some(*target);
+        let results = bench_collect(
+            benchmark.clone(),
             open_files,
             write_ratio,
             duration,
-            client_params,
-            outfile,
+            &params,
+        )?;
+        check_error_budget(&results, &params)?;
+
+        // Second 0 is warmup
+        let measured = 1..(duration as usize + 1);
+        let mut latency = LatencyHistogram::default();
+        let mut ops = 0;
+        for result in &results {
+            ops += result.samples.iops[measured.clone()].iter().sum::<usize>();
+            for second in &result.samples.latency[measured.clone()] {
+                latency.merge(second);
+            }
+        }
+        let point = LoadPoint {
+            offered_iops: target * results.len() as u64,
+            achieved_iops: ops as f64 / duration as f64,
+            p99_latency_ns: latency.quantile(0.99),
+        };
+
+        let row = format!(
+            "{},{:?},{},{},{},{},{:.*},{}{}\n",
+            client_params.cid,
+            benchmark,
+            results.len() * client_params.nclients,
+            write_ratio,
+            open_files,
+            point.offered_iops,
+            client_params.precision,
+            point.achieved_iops,
+            point.p99_latency_ns,
+            tag_columns(client_params),
         );
+        if client_params.log_mode == LogMode::CSV {
+            let mut csv_file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(outfile)
+                .expect("Cant open output file");
+            let r = if client_params.compress_output {
+                let frame = zstd::encode_all(row.as_bytes(), 0).expect("Cant compress row");
+                csv_file.write_all(&frame)
+            } else {
+                csv_file.write_all(row.as_bytes())
+            };
+            assert!(r.is_ok());
+        } else {
+            print!("{}", row);
+        }
+        points.push(point);
+    }
+    Ok(points)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{
+        align_samples, bench_collect, bench_from_args, calculate_latency, calculate_throughput,
+        check_base_path, check_server_dirs, effective_config, io_size, latency_curve, log_results,
+        measure_bucket, open_target, paced, paced_with, place_thread, prefill_dir, repeat_params,
+        setup_dir, sweep, sweep_steps, utils, warn_fd_leak, ARGs, ArgsParseError, Bench,
+        CoreResult, PoorMansBarrier, Samples, LATENCY_CURVE_HEADER,
+    };
+    use super::{bench, bench_names, co_run, fill_file, parse_workload, pwrite_all};
+    use super::{
+        check_error_budget, check_tags, clamp_open_files, open_files_cap, parse_tag, tagged_header,
+        think, CSV_HEADER,
+    };
+    use crate::fxrpc::drpc::quic::serve_drpc_quic;
+    use crate::fxrpc::drpc::server::serve_drpc_tcp;
+    use crate::fxrpc::*;
+    use std::cell::{Cell, RefCell};
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::path::Path;
+    use std::rc::Rc;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn aligned_samples_start_together() {
+        // Two threads leaving the barrier 30 ms apart, early in a 200 ms
+        // interval, so both wait for the same boundary
+        align_samples(Some(200));
+        let threads: Vec<_> = [0, 30]
+            .iter()
+            .map(|delay| {
+                let delay = Duration::from_millis(*delay);
+                thread::spawn(move || {
+                    thread::sleep(delay);
+                    align_samples(Some(200))
+                })
+            })
+            .collect();
+        let starts: Vec<Duration> = threads
+            .into_iter()
+            .map(|t| t.join().unwrap().duration_since(UNIX_EPOCH).unwrap())
+            .collect();
+
+        let tolerance = Duration::from_millis(20);
+        for start in &starts {
+            assert!(
+                start.as_nanos() % 200_000_000 < tolerance.as_nanos(),
+                "{:?}",
+                start
+            );
+        }
+        let apart = if starts[0] > starts[1] {
+            starts[0] - starts[1]
+        } else {
+            starts[1] - starts[0]
+        };
+        assert!(apart < tolerance, "{:?}", starts);
+
+        // Without alignment the first second starts right away
+        let now = SystemTime::now();
+        let start = align_samples(None);
+        assert!(start.duration_since(now).unwrap() < tolerance);
+    }
+
+    #[test]
+    fn short_bucket_is_flagged() {
+        let mut stalls = vec![(3, 0)];
+        let (iops, errors, unreliable, _, _, _) =
+            measure_bucket(100, 0, || stalls.pop().unwrap_or((500, 0)));
+        assert_eq!((iops, errors), (3, 0));
+        assert!(unreliable);
+    }
+
+    #[test]
+    fn short_bucket_is_rerun() {
+        // Two stalled seconds, then a normal one
+        let mut buckets = vec![(500, 1), (2, 0), (3, 0)];
+        let (iops, errors, unreliable, _, _, _) = measure_bucket(100, 3, || buckets.pop().unwrap());
+        assert_eq!((iops, errors), (500, 1));
+        assert!(!unreliable);
+        assert!(buckets.is_empty());
+    }
+
+    #[test]
+    fn paced_ops_record_their_latency() {
+        let mut client_params = client_params_for("/");
+        client_params.target_iops = Some(1000);
+        let (iops, _, _, _, _, latency) = measure_bucket(0, 0, || {
+            let mut op = paced(&client_params, || {
+                std::thread::sleep(Duration::from_millis(2))
+            });
+            for _ in 0..5 {
+                op();
+            }
+            (5, 0)
+        });
+        assert_eq!(latency.count(), iops as u64);
+        // Due every ms but taking 2, the last op waited for the ones before
+        assert!(latency.quantile(1.0) >= 6_000_000, "{:?}", latency);
+    }
+
+    #[test]
+    fn think_time_follows_every_op() {
+        let mut client_params = client_params_for("/");
+        client_params.think_time_us = 2000;
+        for busy in [false, true] {
+            client_params.busy_think = busy;
+            // None for an op, the think time and whether to spin for thinking
+            let events = RefCell::new(Vec::new());
+            let mut op = paced_with(
+                &client_params,
+                || events.borrow_mut().push(None),
+                |think_time, spin| events.borrow_mut().push(Some((think_time, spin))),
+            );
+            op();
+            op();
+            let thought = Some((Duration::from_millis(2), busy));
+            assert_eq!(*events.borrow(), vec![None, thought, None, thought]);
+        }
+    }
+
+    #[test]
+    fn thinking_takes_the_think_time() {
+        for busy in [false, true] {
+            let start = Instant::now();
+            think(Duration::from_millis(2), busy);
+            // Neither sleeping nor spinning ends early, how late is up to the scheduler
+            assert!(
+                start.elapsed() >= Duration::from_millis(2),
+                "busy: {}",
+                busy
+            );
+        }
+    }
+
+    #[test]
+    fn parse_args() {
+        let args: ARGs = "8X1XmixX10".parse().unwrap();
+        assert_eq!(
+            args,
+            ARGs {
+                cores: 8,
+                open_files: 1,
+                benchmark: "mix".to_string(),
+                write_ratio: 10,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_args_errors() {
+        let cases = [
+            (
+                "8X1",
+                ArgsParseError::MissingField("benchmark"),
+                "benchmark",
+            ),
+            ("8X1XmixX10X3", ArgsParseError::TooManyFields(5), "5"),
+            (
+                "eightX1XmixX10",
+                ArgsParseError::InvalidCores("eight".to_string()),
+                "cores",
+            ),
+            (
+                "8XoneXmixX10",
+                ArgsParseError::InvalidOpenFiles("one".to_string()),
+                "open_files",
+            ),
+            (
+                "8X1XmixX-1",
+                ArgsParseError::InvalidWriteRatio("-1".to_string()),
+                "write_ratio",
+            ),
+            ("8X1XX10", ArgsParseError::EmptyBenchmark, "benchmark"),
+        ];
+        for (input, expected, field) in cases {
+            let err = input.parse::<ARGs>().unwrap_err();
+            assert!(err.to_string().contains(field), "{}: {}", input, err);
+            assert_eq!(err, expected, "{}", input);
+        }
+    }
+
+    // Starts a server on its own socket and returns client params to reach it,
+    // the server keeps its files next to the socket, see `server_dir()`
+    pub(crate) fn test_client_params(path: &'static str) -> ClientParams {
+        start_test_server(path, RPCType::GRPC);
+        client_params_for(path)
+    }
+
+    // Like `test_client_params()`, for a server that runs `service`
+    pub(crate) fn serve_test_service(
+        path: &'static str,
+        service: grpc::SyscallService,
+    ) -> ClientParams {
+        std::thread::spawn(move || {
+            set_thread_server_root(&socket_dir(path));
+            server_runtime()
+                .block_on(grpc::serve_uds(
+                    path,
+                    service,
+                    None,
+                    &SocketOptions::default(),
+                ))
+                .unwrap();
+        });
+        client_params_for(path)
+    }
+
+    // The directory the socket at `path` is in, which the server makes anew
+    fn socket_dir(path: &str) -> String {
+        let dir = Path::new(path).parent().unwrap().to_str().unwrap();
+        format!("{}/", dir)
+    }
+
+    // Where the test server `client_params` reach keeps its files, ends with
+    // a `/`
+    pub(crate) fn server_dir(client_params: &ClientParams) -> String {
+        match &client_params.endpoint {
+            RPCEndpoint::Unix { path } => socket_dir(path),
+            endpoint => panic!("{} isn't a test server", endpoint),
+        }
+    }
+
+    // Client params to reach the gRPC server at `path`, once it's up
+    pub(crate) fn client_params_for(path: &str) -> ClientParams {
+        while !std::path::Path::new(path).exists() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let endpoint: RPCEndpoint = format!("unix://{}", path).parse().unwrap();
+        ClientParams {
+            cid: 0,
+            nclients: 1,
+            ccores: 1,
+            log_mode: LogMode::CSV,
+            conn_type: ConnType::UDS,
+            endpoint: endpoint.clone(),
+            servers: Vec::new(),
+            rpc_type: RPCType::GRPC,
+            wire_format: WireFormat::Abomonation,
+            conns_per_core: 1,
+            queue_depth: 1,
+            tls: None,
+            sockets: SocketOptions::default(),
+            error_policy: ErrorPolicy::AbortOnError,
+            tolerated_errnos: Vec::new(),
+            error_budget: None,
+            sync_policy: SyncPolicy::None,
+            write_mode: WriteMode::default(),
+            open_flags: libc::O_CREAT | libc::O_RDWR,
+            files_per_dir: 0,
+            spread_files: false,
+            drop_caches: false,
+            keep_files: false,
+            repeat: 1,
+            reuse_files: false,
+            preallocate: false,
+            numa_local_alloc: false,
+            lock_buffers: false,
+            check_fd_leaks: false,
+            thread_mapping: ThreadMapping::Sequential,
+            pin: true,
+            require_pinning: true,
+            single_threaded: false,
+            min_samples: 0,
+            rerun_short: 0,
+            warmup_ops: None,
+            target_iops: None,
+            align_samples_ms: None,
+            name_len: 0,
+            name_charset: Charset::Ascii,
+            file_size: 1024 * 1024,
+            device: None,
+            working_set_bytes: 1024 * 1024,
+            share_factor: 1,
+            reader_fraction: 0.5,
+            rename_mode: RenameMode::Intra,
+            seed: 0,
+            fill_pattern: FillPattern::default(),
+            verify: VerifyMode::Off,
+            io_size: None,
+            size_dist: None,
+            think_time_us: 0,
+            busy_think: false,
+            precision: 1,
+            compress_output: false,
+            sync_results: false,
+            summary_table: false,
+            host_info: HostInfo::new(&endpoint, &SocketOptions::default()),
+            recipe: Recipe::default(),
+            tags: Vec::new(),
+        }
+    }
+
+    // thread_id,benchmark,ncores,write_ratio,open_files,...
+    fn assert_rows(csv: &str, expected: [&str; 4]) {
+        let rows: Vec<Vec<&str>> = csv.lines().map(|l| l.split(',').collect()).collect();
+        assert!(!rows.is_empty());
+        for row in rows {
+            assert_eq!(row[1..5], expected);
+        }
+    }
+
+    #[test]
+    fn bench_runs_parsed_args() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_bench_args_test/fx.sock");
+        // Overridden by the parsed args
+        client_params.ccores = 4;
+
+        let outfile = std::env::temp_dir().join("fxmark_bench_args_test.csv");
+        let _ = std::fs::remove_file(&outfile);
+        let args: ARGs = "1X1XmixX20".parse().unwrap();
+        bench_from_args(&args, 1, &client_params, outfile.to_str().unwrap()).unwrap();
+
+        let csv = std::fs::read_to_string(&outfile).unwrap();
+        assert_rows(&csv, ["\"mix\"", "1", "20", "1"]);
+        std::fs::remove_file(&outfile).unwrap();
+    }
+
+    #[test]
+    fn config_dump_lists_resolved_params() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_config_dump_test/fx.sock");
+        client_params.ccores = 2;
+        let config = effective_config("mix", 1, 20, 5, &client_params);
+
+        let lines: Vec<&str> = config.lines().collect();
+        assert!(lines.iter().all(|l| l.starts_with("# ")));
+        assert!(lines.contains(&"# benchmark = mix"));
+        assert!(lines.contains(&"# write_ratio = 20"));
+        assert!(lines.contains(&format!("# io_size = {}", PAGE_SIZE).as_str()));
+        assert!(lines.contains(&"# ccores = 2"));
+        assert!(lines.contains(&"# rpc_type = \"GRPC\""));
+    }
+
+    #[test]
+    fn benches_default_to_their_io_size() {
+        let mut client_params = client_params_for("/");
+        assert_eq!(io_size("ococ", &client_params), 0);
+        assert_eq!(io_size("drbr", &client_params), PAGE_SIZE);
+
+        client_params.io_size = Some(4096);
+        assert_eq!(io_size("ococ", &client_params), 4096);
+        assert_eq!(io_size("drbr", &client_params), 4096);
+    }
+
+    #[test]
+    fn summary_row_holds_run_throughput() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_summary_test/fx.sock");
+        client_params.ccores = 2;
+        client_params.cid = 1;
+        let result = CoreResult {
+            core_id: 1,
+            benchmark: "mix".to_string(),
+            cores: 2,
+            write_ratio: 0,
+            open_files: 1,
+            thread_mapping: ThreadMapping::Sequential,
+            unpinned: false,
+            duration: 3,
+            workload: None,
+            over_budget: false,
+            samples: Samples {
+                // The warmup second doesn't count
+                iops: vec![1_000_000, 300, 250, 350],
+                ..Default::default()
+            },
+        };
+
+        assert_eq!(result.total_ops(), 900);
+        let throughput = calculate_throughput(900, Duration::from_secs(3));
+        assert_eq!(throughput, 300.0);
+        assert_eq!(
+            result.summary_row(&client_params),
+            "Core 3 total: 900 ops in 3s, 300.0 ops/s, 3333.3 us/op\n"
+        );
+        assert_eq!(calculate_throughput(0, Duration::from_secs(3)), 0.0);
+        assert_eq!(calculate_latency(0, Duration::from_secs(3)), 0.0);
+    }
+
+    #[test]
+    fn slow_rates_keep_their_fraction() {
+        // 1.5 ops per ms
+        let (ops, time) = (3, Duration::from_millis(2));
+        assert_eq!(calculate_throughput(ops, time), 1500.0);
+        let latency = calculate_latency(ops, time);
+        assert!((latency - 2000.0 / 3.0).abs() < 1e-9, "{}", latency);
+
+        let mut client_params = client_params_for("/");
+        client_params.precision = 3;
+        let result = CoreResult {
+            core_id: 0,
+            benchmark: "mix".to_string(),
+            cores: 1,
+            write_ratio: 0,
+            open_files: 1,
+            thread_mapping: ThreadMapping::Sequential,
+            unpinned: false,
+            duration: 3,
+            workload: None,
+            over_budget: false,
+            samples: Samples {
+                iops: vec![0, 1, 0, 1],
+                ..Default::default()
+            },
+        };
+        assert_eq!(
+            result.summary_row(&client_params),
+            "Core 0 total: 2 ops in 3s, 0.667 ops/s, 1500000.000 us/op\n"
+        );
+    }
+
+    #[test]
+    fn tags_become_columns() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_tags_test/fx.sock");
+        client_params.tags = ["kernel=5.15.0-rc1", "mount=noatime"]
+            .iter()
+            .map(|tag| parse_tag(tag).unwrap())
+            .collect();
+        assert!(check_tags(&client_params.tags).is_ok());
+        let result = CoreResult {
+            core_id: 0,
+            benchmark: "mix".to_string(),
+            cores: 1,
+            write_ratio: 0,
+            open_files: 1,
+            thread_mapping: ThreadMapping::Sequential,
+            unpinned: false,
+            duration: 3,
+            workload: None,
+            over_budget: false,
+            samples: Samples {
+                iops: vec![0; 4],
+                errors: vec![0; 4],
+                errnos: vec![Default::default(); 4],
+                unreliable: vec![false; 4],
+                cpu_util: vec![0.0; 4],
+                ..Default::default()
+            },
+        };
+
+        let header = tagged_header(CSV_HEADER, &client_params);
+        let columns: Vec<&str> = header.trim_end().split(',').collect();
+        let base = CSV_HEADER.trim_end().split(',').count();
+        assert_eq!(columns[base..], ["kernel", "mount"]);
+        let rows = result.csv_rows(&client_params);
+        assert_eq!(rows.lines().count(), 3);
+        for row in rows.lines() {
+            let fields: Vec<&str> = row.split(',').collect();
+            assert_eq!(fields.len(), columns.len(), "{}", row);
+            assert_eq!(fields[base..], ["5.15.0-rc1", "noatime"]);
+        }
+
+        for (tag, err) in [
+            ("kernel", "key=value"),
+            ("=x", "Tag key"),
+            ("a,b=x", "Tag key"),
+            ("mount=a,b", "commas"),
+        ] {
+            let e = parse_tag(tag).unwrap_err();
+            assert!(e.contains(err), "{}: {}", tag, e);
+        }
+        client_params.tags.push(parse_tag("kernel=6.1").unwrap());
+        assert!(check_tags(&client_params.tags).is_err());
+        assert!(check_tags(&[parse_tag("ncores=8").unwrap()]).is_err());
+    }
+
+    #[test]
+    fn compressed_output_decompresses_to_rows() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_compress_test/fx.sock");
+        client_params.compress_output = true;
+
+        let outfile = std::env::temp_dir().join("fxmark_compress_test.csv.zst");
+        let _ = std::fs::remove_file(&outfile);
+        let args: ARGs = "1X1XmixX0".parse().unwrap();
+        // Two runs, two zstd frames in the same file
+        bench_from_args(&args, 1, &client_params, outfile.to_str().unwrap()).unwrap();
+        bench_from_args(&args, 1, &client_params, outfile.to_str().unwrap()).unwrap();
+
+        let file = std::fs::File::open(&outfile).unwrap();
+        let csv = String::from_utf8(zstd::decode_all(file).unwrap()).unwrap();
+        // duration 1 writes one row per run and core
+        assert_eq!(csv.lines().count(), 2);
+        assert!(csv.ends_with('\n'));
+        assert_rows(&csv, ["\"mix\"", "1", "0", "1"]);
+        std::fs::remove_file(&outfile).unwrap();
+    }
+
+    #[test]
+    fn dwom_overwrites_and_cleans_up() {
+        let client_params = test_client_params("/dev/shm/fxmark_dwom_test/fx.sock");
+
+        let outfile = std::env::temp_dir().join("fxmark_dwom_test.csv");
+        let _ = std::fs::remove_file(&outfile);
+        let args: ARGs = "1X16XdwomX100".parse().unwrap();
+        bench_from_args(&args, 1, &client_params, outfile.to_str().unwrap()).unwrap();
+
+        let csv = std::fs::read_to_string(&outfile).unwrap();
+        assert_rows(&csv, ["\"dwom\"", "1", "100", "16"]);
+        for row in csv.lines() {
+            let operations: usize = row.split(',').nth(7).unwrap().parse().unwrap();
+            assert!(operations > 0, "no overwrites in {}", row);
+        }
+        std::fs::remove_file(&outfile).unwrap();
+
+        let dir = server_dir(&client_params);
+        let leftover = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter(|e| {
+                let name = e.as_ref().unwrap().file_name();
+                name.to_string_lossy().starts_with("dwom")
+            })
+            .count();
+        assert_eq!(leftover, 0, "dwom files left in {}", dir);
+    }
+
+    #[test]
+    fn collected_results_match_csv() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_collect_test/fx.sock");
+        client_params.ccores = 2;
+        client_params.thread_mapping = ThreadMapping::Compact;
+
+        let results = bench_collect("mix".to_string(), 1, 10, 2, &client_params).unwrap();
+        assert_eq!(results.len(), 2);
+        for result in &results {
+            assert_eq!(result.benchmark, "mix");
+            assert_eq!(
+                (result.cores, result.write_ratio, result.open_files),
+                (2, 10, 1)
+            );
+            assert!(result.thread_mapping == ThreadMapping::Compact);
+            // Plus the warmup second
+            assert_eq!(result.samples.iops.len(), 3);
+            assert_eq!(result.samples.errors.len(), 3);
+            assert!(result.samples.iops[1..].iter().all(|iops| *iops > 0));
+        }
+
+        let outfile = std::env::temp_dir().join("fxmark_collect_test.csv");
+        let _ = std::fs::remove_file(&outfile);
+        log_results(&results, &client_params, outfile.to_str().unwrap());
+        let csv = std::fs::read_to_string(&outfile).unwrap();
+        let rendered: String = results.iter().map(|r| r.csv_rows(&client_params)).collect();
+        assert_eq!(csv, rendered);
+        assert_eq!(csv.lines().count(), 4);
+        assert!(csv.lines().all(|row| row.contains(",TM=Compact,")));
+        std::fs::remove_file(&outfile).unwrap();
+    }
+
+    // A server that writes at most `max` bytes per write into `file`
+    struct ShortWrites {
+        file: Rc<RefCell<Vec<u8>>>,
+        max: usize,
+        writes: Rc<Cell<usize>>,
+    }
+
+    impl FxRPC for ShortWrites {
+        fn rpc_pwrite(
+            &mut self,
+            _fd: i32,
+            page: &[u8],
+            size: usize,
+            offset: i64,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            self.writes.set(self.writes.get() + 1);
+            let size = size.min(self.max);
+            let offset = offset as usize;
+            let mut file = self.file.borrow_mut();
+            if file.len() < offset + size {
+                file.resize(offset + size, 0);
+            }
+            file[offset..offset + size].copy_from_slice(&page[..size]);
+            Ok(size as i32)
+        }
+
+        unimplemented_ops!(
+            rpc_open,
+            rpc_read,
+            rpc_pread,
+            rpc_write,
+            rpc_close,
+            rpc_fsync,
+            rpc_ftruncate,
+            rpc_remove,
+            rpc_rename,
+            rpc_mkdir,
+            rpc_rmdir,
+            rpc_fadvise,
+            rpc_drop_caches,
+            rpc_fstatat,
+            rpc_sendfile,
+            rpc_lseek,
+            rpc_batch,
+            rpc_dump_server_log,
+            rpc_server_stats,
+            rpc_capabilities,
+            rpc_reset
+        );
+    }
+
+    #[test]
+    fn short_writes_are_finished() {
+        let client_params = client_params_for("/");
+        let file = Rc::new(RefCell::new(Vec::new()));
+        let writes = Rc::new(Cell::new(0));
+        let mut client: Box<dyn FxRPC> = Box::new(ShortWrites {
+            file: Rc::clone(&file),
+            max: 300,
+            writes: Rc::clone(&writes),
+        });
+        fill_file(&mut client, &client_params, 3, 0, 0, 3);
+
+        // 300 + 300 + 300 + 124 bytes a page
+        assert_eq!(writes.get(), 3 * 4);
+        let mut expected = vec![0; 3 * PAGE_SIZE];
+        for (n, page) in expected.chunks_mut(PAGE_SIZE).enumerate() {
+            client_params
+                .fill_pattern
+                .fill(page, n * PAGE_SIZE, client_params.seed, 0);
+        }
+        assert_eq!(*file.borrow(), expected);
+
+        // A server that writes nothing fails the write instead of hanging it
+        let mut client: Box<dyn FxRPC> = Box::new(ShortWrites {
+            file: Rc::new(RefCell::new(Vec::new())),
+            max: 0,
+            writes: Rc::new(Cell::new(0)),
+        });
+        assert!(pwrite_all(&mut client, 3, &[0xb; PAGE_SIZE], 0).is_err());
+    }
+
+    // Counts 7 ops a second without doing any
+    #[derive(Clone, Default)]
+    struct Dummy;
+
+    impl Bench for Dummy {
+        fn init(&mut self, _cores: Vec<u64>, _open_files: usize, _client_params: &ClientParams) {}
+
+        fn run(
+            &self,
+            poor_mans_barrier: &PoorMansBarrier,
+            duration: u64,
+            _core: usize,
+            _write_ratio: usize,
+            _client_params: &ClientParams,
+        ) -> Samples {
+            poor_mans_barrier.wait_start();
+            poor_mans_barrier.wait_end();
+            let seconds = duration as usize + 1;
+            Samples {
+                iops: vec![7; seconds],
+                errors: vec![0; seconds],
+                errnos: vec![Default::default(); seconds],
+                unreliable: vec![false; seconds],
+                cpu_util: vec![0.0; seconds],
+                ..Default::default()
+            }
+        }
+    }
+
+    register_bench!("dummy", Dummy, 0);
+
+    // Panics on its lowest core once all cores passed the start barrier
+    #[derive(Clone, Default)]
+    struct Panicky {
+        min_core: usize,
+    }
+
+    impl Bench for Panicky {
+        fn init(&mut self, cores: Vec<u64>, _open_files: usize, _client_params: &ClientParams) {
+            self.min_core = *cores.iter().min().unwrap() as usize;
+        }
+
+        fn run(
+            &self,
+            poor_mans_barrier: &PoorMansBarrier,
+            duration: u64,
+            core: usize,
+            _write_ratio: usize,
+            _client_params: &ClientParams,
+        ) -> Samples {
+            poor_mans_barrier.wait_start();
+            if core == self.min_core {
+                panic!("injected failure");
+            }
+            poor_mans_barrier.wait_end();
+            Samples {
+                iops: vec![7; duration as usize + 1],
+                ..Default::default()
+            }
+        }
+    }
+
+    register_bench!("panicky", Panicky, 0);
+
+    #[test]
+    fn panicked_thread_fails_the_run() {
+        let mut client_params = client_params_for("/");
+        client_params.pin = false;
+        client_params.ccores = 2;
+
+        // The other thread would wait for it at the end barrier forever
+        let err = bench_collect("panicky".to_string(), 1, 0, 1, &client_params).unwrap_err();
+        assert_eq!(err, "A thread of panicky panicked: injected failure");
+
+        client_params.single_threaded = true;
+        let err = bench_collect("panicky".to_string(), 1, 0, 1, &client_params).unwrap_err();
+        assert_eq!(err, "A thread of panicky panicked: injected failure");
+    }
+
+    #[test]
+    fn poisoned_barrier_lets_threads_through() {
+        let barrier = PoorMansBarrier::new(2);
+        barrier.poison();
+        // Without the second thread
+        barrier.wait_start();
+        barrier.wait_end();
+        assert!(barrier.is_poisoned());
+    }
+
+    #[test]
+    fn registered_benches_run_by_name() {
+        assert!(bench_names().contains(&"dummy"));
+        assert!(bench_names().contains(&"mix"));
+
+        // Serves only the check of its directory
+        let client_params = test_client_params("/dev/shm/fxmark_registry_test/fx.sock");
+        let outfile = std::env::temp_dir().join("fxmark_registry_test.csv");
+        let _ = std::fs::remove_file(&outfile);
+        let results = bench(
+            "dummy".to_string(),
+            1,
+            0,
+            2,
+            &client_params,
+            &outfile.to_str().unwrap().to_string(),
+        )
+        .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].benchmark, "dummy");
+        assert_eq!(results[0].total_ops(), 14);
+        assert_eq!(
+            std::fs::read_to_string(&outfile).unwrap().lines().count(),
+            2
+        );
+        std::fs::remove_file(&outfile).unwrap();
+    }
+
+    #[test]
+    fn unknown_benchmark_is_an_error() {
+        let client_params = client_params_for("/");
+        let res = bench_collect("nosuchbench".to_string(), 1, 0, 1, &client_params);
+        assert_eq!(res.unwrap_err(), "Unknown benchmark nosuchbench");
+    }
+
+    #[test]
+    fn parallel_runs_keep_their_barriers() {
+        // As the test harness runs them, each run with its own barrier
+        let mut client_params = client_params_for("/");
+        client_params.pin = false;
+        let runs: Vec<_> = (0..8)
+            .map(|_| {
+                let client_params = client_params.clone();
+                thread::spawn(move || {
+                    bench_collect("dummy".to_string(), 1, 0, 1, &client_params).unwrap()
+                })
+            })
+            .collect();
+        for run in runs {
+            let results = run.join().unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].total_ops(), 7);
+        }
+    }
+
+    #[test]
+    fn co_run_tags_each_workload() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_co_run_test/fx.sock");
+        client_params.pin = false;
+        let plan = vec![("mix".to_string(), vec![0]), ("drbr".to_string(), vec![1])];
+        let results = co_run(plan, 1, 0, 1, &client_params).unwrap();
+        assert_eq!(results.len(), 2);
+        for (result, (workload, core)) in results.iter().zip([("0:mix", 0), ("1:drbr", 1)]) {
+            assert_eq!(result.workload.as_deref(), Some(workload));
+            assert_eq!((result.core_id, result.cores), (core, 1));
+            assert!(result.samples.iops[1] > 0);
+            let rows = result.csv_rows(&client_params);
+            let thread = format!("{},", core);
+            let column = format!(",{}", workload);
+            assert!(rows
+                .lines()
+                .all(|row| row.starts_with(&thread) && row.ends_with(&column)));
+        }
+
+        let twice = vec![("mix".to_string(), vec![0]), ("drbr".to_string(), vec![0])];
+        assert!(co_run(twice, 1, 0, 1, &client_params).is_err());
+        let unknown = vec![("dwol".to_string(), vec![0])];
+        assert!(co_run(unknown, 1, 0, 1, &client_params).is_err());
+    }
+
+    #[test]
+    fn parse_workloads() {
+        assert_eq!(
+            parse_workload("mix@0-3"),
+            Ok(("mix".to_string(), vec![0, 1, 2, 3]))
+        );
+        assert_eq!(parse_workload("drbr@4"), Ok(("drbr".to_string(), vec![4])));
+        assert!(parse_workload("mix").is_err());
+        assert!(parse_workload("@0-3").is_err());
+        assert!(parse_workload("mix@3-1").is_err());
+        assert!(parse_workload("mix@a-b").is_err());
+    }
+
+    #[test]
+    fn logged_rows_are_on_file() {
+        use std::io::Read;
+
+        let mut client_params = client_params_for("/");
+        client_params.sync_results = true;
+        let results: Vec<CoreResult> = (0..3)
+            .map(|core_id| CoreResult {
+                core_id: core_id,
+                benchmark: "mix".to_string(),
+                cores: 3,
+                write_ratio: 0,
+                open_files: 1,
+                thread_mapping: ThreadMapping::Sequential,
+                unpinned: false,
+                duration: 2,
+                workload: None,
+                over_budget: false,
+                samples: Samples {
+                    iops: vec![10, 20, 30],
+                    errors: vec![0; 3],
+                    errnos: vec![Default::default(); 3],
+                    unreliable: vec![false; 3],
+                    cpu_util: vec![0.0; 3],
+                    ..Default::default()
+                },
+            })
+            .collect();
+
+        let outfile = std::env::temp_dir().join("fxmark_flush_test.csv");
+        let _ = std::fs::remove_file(&outfile);
+        log_results(&results, &client_params, outfile.to_str().unwrap());
+
+        // Another handle sees every row, as another process would
+        let mut csv = String::new();
+        std::fs::File::open(&outfile)
+            .unwrap()
+            .read_to_string(&mut csv)
+            .unwrap();
+        let rendered: String = results.iter().map(|r| r.csv_rows(&client_params)).collect();
+        assert_eq!(csv, rendered);
+        assert_eq!(csv.lines().count(), 6);
+        std::fs::remove_file(&outfile).unwrap();
+    }
+
+    #[test]
+    fn threads_are_released_together() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_skew_test/fx.sock");
+        client_params.ccores = 3;
+        client_params.pin = false;
+
+        let results = bench_collect("mix".to_string(), 1, 10, 1, &client_params).unwrap();
+        assert_eq!(results.len(), 3);
+        let mut released = Vec::new();
+        for result in &results {
+            let start = result.samples.released.unwrap();
+            let end = result.samples.ended.unwrap();
+            // Second 0 and the measured second
+            assert!(end.duration_since(start).unwrap() >= Duration::from_secs(2));
+            released.push(start);
+        }
+        // Spinning on the barrier, no thread lags the others by much
+        let first = *released.iter().min().unwrap();
+        let last = *released.iter().max().unwrap();
+        assert!(last.duration_since(first).unwrap() < Duration::from_millis(100));
+
+        for row in results[0].csv_rows(&client_params).lines() {
+            let fields: Vec<&str> = row.split(',').collect();
+            let released_us: u128 = fields[23].parse().unwrap();
+            let ended_us: u128 = fields[24].parse().unwrap();
+            assert!(released_us < ended_us);
+        }
+    }
+
+    #[test]
+    fn unusable_base_path_is_reported() {
+        let err = check_base_path(Path::new("/dev/shm/fxmark_no_such_dir")).unwrap_err();
+        assert!(err.starts_with("Benchmark directory /dev/shm/fxmark_no_such_dir: not accessible"));
+
+        let file = std::env::temp_dir().join("fxmark_base_path_test");
+        std::fs::write(&file, b"").unwrap();
+        let err = check_base_path(&file).unwrap_err();
+        assert!(err.ends_with("not a directory"), "{}", err);
+        std::fs::remove_file(&file).unwrap();
+
+        assert!(check_base_path(&std::env::temp_dir()).is_ok());
+    }
+
+    // sysfs refuses new files even to root, for whom a chmod'ed directory
+    // would still be writable
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn read_only_base_path_is_reported() {
+        let err = check_base_path(Path::new("/sys")).unwrap_err();
+        assert!(
+            err.starts_with("Benchmark directory /sys: not writable"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn unusable_server_dir_is_reported() {
+        let client_params = test_client_params("/dev/shm/fxmark_server_dir_test/fx.sock");
+        let probe = format!(
+            "{}.fxmark_probe{}",
+            server_dir(&client_params),
+            std::process::id()
+        );
+
+        assert!(check_server_dirs(&client_params).is_ok());
+        assert!(!Path::new(&probe).exists());
+
+        // A directory in the probe file's place can't be opened for writing
+        std::fs::create_dir(&probe).unwrap();
+        let err = check_server_dirs(&client_params).unwrap_err();
+        std::fs::remove_dir(&probe).unwrap();
+        assert!(
+            err.starts_with("Benchmark directory of server unix:///dev/shm/fxmark_server_dir_test/fx.sock: probe file .fxmark_probe"),
+            "{}",
+            err
+        );
+        assert!(err.contains(" can't be created ("), "{}", err);
+    }
+
+    #[test]
+    fn setup_dir_is_idempotent() {
+        let client_params = test_client_params("/dev/shm/fxmark_setup_dir_test/fx.sock");
+        let mut client = init_client_for(&client_params.endpoint, &client_params);
+        let dir = "fxmark_setup_dir_test_dir";
+        let path = format!("{}{}", server_dir(&client_params), dir);
+
+        setup_dir(&mut client, dir);
+        assert!(Path::new(&path).is_dir());
+        // Left over from the first call, like from an aborted run
+        setup_dir(&mut client, dir);
+        prefill_dir(&mut client, &format!("{}/", dir), 2);
+        assert!(Path::new(&path).join("prefill1.txt").exists());
+        std::fs::remove_dir_all(&path).unwrap();
+
+        // A file in its place is still an error
+        std::fs::write(&path, b"").unwrap();
+        let res =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| setup_dir(&mut client, dir)));
+        std::fs::remove_file(&path).unwrap();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn open_files_fit_the_rlimit() {
+        // A soft limit of 256 with 4 cores of 2 connections each
+        let cap = open_files_cap(Some(256), 8);
+        assert!(cap < 256 - 8);
+        let (open_files, warning) = clamp_open_files(1000, cap);
+        assert_eq!(open_files, cap);
+        assert!(warning.unwrap().contains("1000 open files"));
+
+        let (open_files, warning) = clamp_open_files(16, cap);
+        assert_eq!(open_files, 16);
+        assert!(warning.is_none());
+
+        assert_eq!(open_files_cap(Some(4), 8), 1);
+        assert_eq!(open_files_cap(None, 8), usize::MAX);
+    }
+
+    #[test]
+    fn single_threaded_run_has_the_same_shape() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_single_thread_test/fx.sock");
+        client_params.ccores = 3;
+        client_params.pin = false;
+        let shape = |results: &[CoreResult]| {
+            let mut rows: Vec<(usize, usize, usize)> = results
+                .iter()
+                .map(|r| {
+                    let csv = r.csv_rows(&client_params);
+                    let columns = csv.lines().map(|l| l.split(',').count()).max();
+                    (r.core_id, csv.lines().count(), columns.unwrap())
+                })
+                .collect();
+            rows.sort_unstable();
+            rows
+        };
+
+        let threaded = bench_collect("mix".to_string(), 1, 50, 2, &client_params).unwrap();
+        client_params.single_threaded = true;
+        let single = bench_collect("mix".to_string(), 1, 50, 2, &client_params).unwrap();
+        assert_eq!(single.len(), 3);
+        assert_eq!(shape(&single), shape(&threaded));
+        assert!(single.iter().all(|r| r.cores == 3 && r.samples.iops[1] > 0));
+    }
+
+    #[test]
+    fn kept_files_survive_the_run() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_keep_files_test/fx.sock");
+        client_params.keep_files = true;
+        client_params.file_size = 4 * PAGE_SIZE;
+
+        let results = bench_collect("drbr".to_string(), 1, 0, 1, &client_params).unwrap();
+        assert_eq!(results.len(), 1);
+
+        let kept = format!(
+            "{}drbr{}.txt",
+            server_dir(&client_params),
+            results[0].core_id
+        );
+        let metadata = std::fs::metadata(&kept).expect("drbr file was removed");
+        assert_eq!(metadata.len(), 4 * PAGE_SIZE as u64);
+        std::fs::remove_file(&kept).unwrap();
+    }
+
+    #[test]
+    fn fill_pattern_shapes_file_contents() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_fill_test/fx.sock");
+        client_params.keep_files = true;
+        client_params.file_size = 4 * PAGE_SIZE;
+        let dir = server_dir(&client_params);
+        let kept = |core_id| {
+            let path = format!("{}drbr{}.txt", dir, core_id);
+            let contents = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            contents
+        };
+
+        client_params.ccores = 2;
+        client_params.fill_pattern = FillPattern::Random;
+        let results = bench_collect("drbr".to_string(), 1, 0, 1, &client_params).unwrap();
+        let files: Vec<Vec<u8>> = results.iter().map(|r| kept(r.core_id)).collect();
+        assert_eq!(files.len(), 2);
+        assert_ne!(files[0], files[1]);
+        // Not one random page repeated either
+        assert_ne!(files[0][..PAGE_SIZE], files[0][PAGE_SIZE..2 * PAGE_SIZE]);
+
+        client_params.ccores = 1;
+        client_params.fill_pattern = FillPattern::Zero;
+        let results = bench_collect("drbr".to_string(), 1, 0, 1, &client_params).unwrap();
+        let file = kept(results[0].core_id);
+        assert_eq!(file.len(), 4 * PAGE_SIZE);
+        assert!(file.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn reused_files_keep_their_inode() {
+        use std::os::unix::fs::MetadataExt;
+
+        let mut client_params = test_client_params("/dev/shm/fxmark_reuse_files_test/fx.sock");
+        client_params.file_size = 4 * PAGE_SIZE;
+        client_params.repeat = 3;
+        let run = |client_params: &ClientParams, repeat| {
+            let params = repeat_params(client_params, repeat);
+            let results = bench_collect("drbr".to_string(), 1, 0, 1, &params).unwrap();
+            format!(
+                "{}drbr{}.txt",
+                server_dir(client_params),
+                results[0].core_id
+            )
+        };
+
+        // Recreated every repeat
+        let path = run(&client_params, 0);
+        assert!(!Path::new(&path).exists());
+
+        client_params.reuse_files = true;
+        let path = run(&client_params, 0);
+        let inode = std::fs::metadata(&path)
+            .expect("drbr file was removed")
+            .ino();
+        // Grown past its initial size, the reset truncates it back
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[0; PAGE_SIZE])
+            .unwrap();
+
+        run(&client_params, 1);
+        let metadata = std::fs::metadata(&path).expect("drbr file was removed");
+        assert_eq!(metadata.ino(), inode);
+        assert_eq!(metadata.len(), 4 * PAGE_SIZE as u64);
+
+        // The last repeat cleans up
+        run(&client_params, 2);
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[test]
+    fn write_modes_shape_file_sizes() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_write_mode_test/fx.sock");
+        client_params.keep_files = true;
+        client_params.file_size = 4 * PAGE_SIZE;
+        let dir = server_dir(&client_params);
+        let mut run = |write_mode| {
+            client_params.write_mode = write_mode;
+            let results = bench_collect("dwom".to_string(), 1, 100, 1, &client_params).unwrap();
+            let path = format!("{}dwom{}_0.txt", dir, results[0].core_id);
+            let len = std::fs::metadata(&path).unwrap().len() as usize;
+            std::fs::remove_file(&path).unwrap();
+            let writes: usize = results[0].samples.iops.iter().sum();
+            (len, writes)
+        };
+
+        // Constant
+        let (len, _) = run(WriteMode::OverwriteAt(2 * PAGE_SIZE as i64));
+        assert_eq!(len, 3 * PAGE_SIZE);
+        // Growing with every write, on top of the filled page
+        let (len, writes) = run(WriteMode::Append);
+        assert_eq!(len, (writes + 1) * PAGE_SIZE);
+        // Growing, then truncated whenever it reached the limit
+        let (len, writes) = run(WriteMode::SequentialGrow);
+        assert!(writes > 4);
+        assert_eq!(len, (writes % 4 + 1) * PAGE_SIZE);
+    }
+
+    #[test]
+    fn preallocated_files_start_full() {
+        use std::os::unix::fs::MetadataExt;
+
+        let mut client_params = test_client_params("/dev/shm/fxmark_preallocate_test/fx.sock");
+        client_params.keep_files = true;
+        client_params.preallocate = true;
+        client_params.file_size = 8 * PAGE_SIZE;
+        let dir = server_dir(&client_params);
+        let mut run = |write_mode| {
+            client_params.write_mode = write_mode;
+            let results = bench_collect("dwom".to_string(), 1, 100, 1, &client_params).unwrap();
+            let path = format!("{}dwom{}_0.txt", dir, results[0].core_id);
+            let metadata = std::fs::metadata(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let writes: usize = results[0].samples.iops.iter().sum();
+            (metadata, writes)
+        };
+
+        // The timed writes only touch the first page, so init wrote the rest,
+        // and not as holes
+        let (metadata, _) = run(WriteMode::OverwriteAt(0));
+        assert_eq!(metadata.len(), 8 * PAGE_SIZE as u64);
+        assert!(metadata.blocks() * 512 >= 8 * PAGE_SIZE as u64);
+        // Wrapped around many times without ever being truncated
+        let (metadata, writes) = run(WriteMode::SequentialGrow);
+        assert!(writes > 8);
+        assert_eq!(metadata.len(), 8 * PAGE_SIZE as u64);
+    }
+
+    // losetup needs root, elsewhere there's nothing to test against
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn drbr_reads_a_loop_device() {
+        use std::process::Command;
+
+        if unsafe { libc::geteuid() } != 0 {
+            eprintln!("Skipping drbr_reads_a_loop_device, setting up a loop device needs root");
+            return;
+        }
+        let pages = 64;
+        let backing = std::env::temp_dir().join("fxmark_loop_test.img");
+        let data: Vec<u8> = (0..pages * PAGE_SIZE)
+            .map(|i| (i / PAGE_SIZE) as u8)
+            .collect();
+        std::fs::write(&backing, &data).unwrap();
+        let losetup = Command::new("losetup")
+            .args(["--find", "--show"])
+            .arg(&backing)
+            .output()
+            .expect("Can't run losetup");
+        assert!(
+            losetup.status.success(),
+            "{}",
+            String::from_utf8_lossy(&losetup.stderr)
+        );
+        let loop_dev = String::from_utf8(losetup.stdout)
+            .unwrap()
+            .trim()
+            .to_string();
+        let mut client_params = test_client_params("/dev/shm/fxmark_loop_test/fx.sock");
+        // The server only opens paths below its root
+        let link = "fxmark_loop_test_dev";
+        let link_path = format!("{}{}", server_dir(&client_params), link);
+        std::os::unix::fs::symlink(&loop_dev, &link_path).unwrap();
+        client_params.device = Some(link.to_string());
+        // Larger than the device, the reads stay within it anyway
+        client_params.file_size = 2 * pages * PAGE_SIZE;
+
+        let mut client = init_client_for(&client_params.endpoint, &client_params);
+        let flags = libc::O_RDWR | libc::O_CREAT;
+        let (fd, size) = open_target(&mut client, &client_params, "loop_test.txt", flags);
+        assert_eq!(size, Some(pages * PAGE_SIZE));
+        let mut page = vec![0; PAGE_SIZE];
+        for n in [0, 17, pages - 1] {
+            let offset = (n * PAGE_SIZE) as i64;
+            let read = client.rpc_pread(fd, &mut page, PAGE_SIZE, offset).unwrap();
+            assert_eq!(read, PAGE_SIZE as i32);
+            assert!(
+                page.iter().all(|b| *b == n as u8),
+                "wrong data in page {}",
+                n
+            );
+        }
+        client.rpc_close(fd).unwrap();
+
+        let results = bench_collect("drbr".to_string(), 1, 0, 1, &client_params).unwrap();
+        assert!(results[0].samples.iops[1] > 0);
+        assert_eq!(results[0].samples.errors[1], 0);
+        // Neither a file was created nor the device removed
+        let created = format!("{}loop_test.txt", server_dir(&client_params));
+        assert!(!Path::new(&created).exists());
+        assert!(Path::new(&link_path).exists());
+
+        std::fs::remove_file(&link_path).unwrap();
+        let detached = Command::new("losetup").arg("-d").arg(&loop_dev).status();
+        assert!(detached.unwrap().success());
+        std::fs::remove_file(&backing).unwrap();
+    }
+
+    #[test]
+    fn open_close_pairs_leave_the_file_alone() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_ococ_test/fx.sock");
+        client_params.keep_files = true;
+
+        let results = bench_collect("ococ".to_string(), 1, 0, 1, &client_params).unwrap();
+        assert_eq!(results.len(), 1);
+        let samples = &results[0].samples;
+        assert!(samples.iops[1] > 0);
+        assert_eq!(samples.errors[1], 0);
+        // Created with one page before the timed phase, never grown or emptied
+        let path = format!(
+            "{}ococ{}.txt",
+            server_dir(&client_params),
+            results[0].core_id
+        );
+        let len = std::fs::metadata(&path).unwrap().len();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(len, PAGE_SIZE as u64);
+    }
+
+    #[test]
+    fn benches_run_over_every_transport() {
+        // gRPC over a Unix socket as in the other tests, DRPC over every
+        // transport it has, each server on any free port
+        let grpc = test_client_params("/dev/shm/fxmark_transport_grpc_test/fx.sock");
+        let drpc_uds =
+            start_test_server("/dev/shm/fxmark_transport_drpc_test/fx.sock", RPCType::DRPC);
+        let root = "/dev/shm/fxmark_transport_test";
+        let _ = std::fs::remove_dir_all(root);
+        std::fs::create_dir_all(root).unwrap();
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let drpc_tcp: RPCEndpoint = format!("tcp://{}", tcp.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        std::thread::spawn(move || {
+            set_thread_server_root(root);
+            serve_drpc_tcp(tcp, &SocketOptions::default())
+        });
+        let quic = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let drpc_quic: RPCEndpoint = format!("quic://{}", quic.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        std::thread::spawn(move || {
+            set_thread_server_root(root);
+            serve_drpc_quic(quic)
+        });
+
+        for (rpc_type, endpoint) in [
+            (RPCType::GRPC, grpc.endpoint.clone()),
+            (RPCType::DRPC, drpc_uds),
+            (RPCType::DRPC, drpc_tcp),
+            (RPCType::DRPC, drpc_quic),
+        ] {
+            let mut client_params = grpc.clone();
+            client_params.rpc_type = rpc_type;
+            client_params.endpoint = endpoint.clone();
+            let results = bench_collect("mix".to_string(), 1, 50, 1, &client_params).unwrap();
+            assert!(results[0].samples.iops[1] > 0, "{}", endpoint);
+            assert_eq!(results[0].samples.errors[1], 0, "{}", endpoint);
+        }
+    }
+
+    #[test]
+    fn striped_connections_complete_ops() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_striped_bench_test/fx.sock");
+        client_params.conns_per_core = 4;
+
+        let results = bench_collect("mix".to_string(), 1, 50, 1, &client_params).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].samples.iops[1] > 0);
+        assert_eq!(results[0].samples.errors[1], 0);
+    }
+
+    #[test]
+    fn host_columns_describe_the_connection() {
+        let client_params = test_client_params("/dev/shm/fxmark_host_info_test/fx.sock");
+
+        let results = bench_collect("mix".to_string(), 1, 0, 1, &client_params).unwrap();
+        let rows = results[0].csv_rows(&client_params);
+        let row: Vec<&str> = rows.lines().next().unwrap().split(',').collect();
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        assert_eq!(row[13], page_size.to_string());
+        // Those of a connection to the server like the bench's
+        let stream =
+            std::os::unix::net::UnixStream::connect("/dev/shm/fxmark_host_info_test/fx.sock")
+                .unwrap();
+        let buf_size = |opt| {
+            let mut val: libc::c_int = 0;
+            let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+            let fd = std::os::unix::io::AsRawFd::as_raw_fd(&stream);
+            let res = unsafe {
+                libc::getsockopt(
+                    fd,
+                    libc::SOL_SOCKET,
+                    opt,
+                    &mut val as *mut libc::c_int as *mut libc::c_void,
+                    &mut len,
+                )
+            };
+            assert_eq!(res, 0);
+            val.to_string()
+        };
+        assert_eq!(row[14], buf_size(libc::SO_SNDBUF));
+        assert_eq!(row[15], buf_size(libc::SO_RCVBUF));
+    }
+
+    #[test]
+    fn tolerated_errors_are_counted() {
+        let service = grpc::SyscallService::with_faults(grpc::fault::FaultConfig {
+            read_errno_every: Some((10, libc::EAGAIN)),
+            ..Default::default()
+        });
+        let mut client_params =
+            serve_test_service("/dev/shm/fxmark_tolerate_test/fx.sock", service);
+        client_params.tolerated_errnos = vec![libc::EAGAIN];
+        client_params.file_size = 4 * PAGE_SIZE;
+
+        // Every 10th read fails, which would abort the run without the allowlist
+        let results = bench_collect("drbr".to_string(), 1, 0, 1, &client_params).unwrap();
+        assert_eq!(results.len(), 1);
+        let samples = &results[0].samples;
+        assert!(samples.iops[1] > 0);
+        assert!(samples.errors[1] > 0);
+        assert_eq!(
+            samples.errnos[1].get(&libc::EAGAIN),
+            Some(&samples.errors[1])
+        );
+        let rows = results[0].csv_rows(&client_params);
+        assert!(rows.contains(",TM=Sequential,EAGAIN="), "{}", rows);
+    }
+
+    #[test]
+    fn continue_on_error_finishes_the_run() {
+        let service = grpc::SyscallService::with_faults(grpc::fault::FaultConfig {
+            read_errno_every: Some((10, libc::EINTR)),
+            ..Default::default()
+        });
+        let mut client_params =
+            serve_test_service("/dev/shm/fxmark_continue_on_error_test/fx.sock", service);
+        client_params.error_policy = ErrorPolicy::ContinueOnError;
+        client_params.file_size = 4 * PAGE_SIZE;
+
+        // Every 10th read fails, which would panic the thread under
+        // AbortOnError
+        let results = bench_collect("drbr".to_string(), 1, 0, 2, &client_params).unwrap();
+        assert_eq!(results.len(), 1);
+        let samples = &results[0].samples;
+        // Plus the warmup second
+        assert_eq!(samples.iops.len(), 3);
+        assert!(
+            samples.iops.iter().all(|iops| *iops > 0),
+            "{:?}",
+            samples.iops
+        );
+        assert!(samples.errors.iter().sum::<usize>() > 0);
+        let rows = results[0].csv_rows(&client_params);
+        for (row, errors) in rows.lines().zip(&samples.errors[1..]) {
+            let row: Vec<&str> = row.split(',').collect();
+            assert_eq!(row[12], errors.to_string());
+        }
+    }
+
+    #[test]
+    fn error_budget_stops_the_run() {
+        let service = grpc::SyscallService::with_faults(grpc::fault::FaultConfig {
+            read_errno_every: Some((1, libc::EIO)),
+            ..Default::default()
+        });
+        let mut client_params =
+            serve_test_service("/dev/shm/fxmark_error_budget_test/fx.sock", service);
+        client_params.error_budget = Some(5);
+        client_params.file_size = 4 * PAGE_SIZE;
+        // Slow enough for the budget to last past the warmup second
+        client_params.target_iops = Some(3);
+
+        // Every read fails, the run stops in its first measured second
+        // instead of panicking on the first failure
+        let results = bench_collect("drbr".to_string(), 1, 0, 5, &client_params).unwrap();
+        assert!(check_error_budget(&results, &client_params).is_err());
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].duration, 1);
+        let samples = &results[0].samples;
+        assert_eq!(samples.iops, vec![0, 0]);
+        // Over the budget, plus the op the thread was about to start when
+        // it noticed
+        let errors: usize = samples.errors.iter().sum();
+        assert!((6..=7).contains(&errors), "{:?}", samples.errors);
+        assert_eq!(samples.errnos[1].get(&libc::EIO), Some(&samples.errors[1]));
+        let rows = results[0].csv_rows(&client_params);
+        assert_eq!(rows.lines().count(), 1);
+        let row: Vec<&str> = rows.trim_end().split(',').collect();
+        assert_eq!(row[5], "1");
+        assert_eq!(row[12], samples.errors[1].to_string());
+    }
+
+    #[test]
+    fn latency_curve_has_a_row_per_load() {
+        let client_params = test_client_params("/dev/shm/fxmark_latency_curve_test/fx.sock");
+        let outfile = std::env::temp_dir().join("fxmark_latency_curve_test.csv");
+        let _ = std::fs::remove_file(&outfile);
+
+        let points = latency_curve(
+            "drbr".to_string(),
+            1,
+            0,
+            1,
+            &client_params,
+            &[100, 200],
+            outfile.to_str().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(points.len(), 2);
+        assert!(points[0].offered_iops < points[1].offered_iops);
+        for point in &points {
+            assert!(point.p99_latency_ns > 0);
+            // Far below what a local server can take
+            assert!(point.achieved_iops > 0.9 * point.offered_iops as f64);
+        }
+
+        let csv = std::fs::read_to_string(&outfile).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0][5], "100");
+        assert_eq!(rows[1][5], "200");
+        assert_eq!(
+            rows[1].len(),
+            LATENCY_CURVE_HEADER.trim_end().split(',').count()
+        );
+        std::fs::remove_file(&outfile).unwrap();
+    }
+
+    #[test]
+    fn sweep_stops_at_deadline() {
+        let client_params = test_client_params("/dev/shm/fxmark_deadline_test/fx.sock");
+        let outfile = std::env::temp_dir()
+            .join("fxmark_deadline_test.csv")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&outfile);
+
+        // Three runs of 10 seconds each without the deadline
+        let start = std::time::Instant::now();
+        let skipped = sweep(
+            "mix".to_string(),
+            &[1],
+            &[0, 50, 100],
+            &[1],
+            10,
+            &client_params,
+            &outfile,
+            Some(Duration::from_secs(2)),
+        )
+        .unwrap();
+        // The first run is cut to 2 seconds, plus its warmup and cooldown
+        assert!(
+            start.elapsed() < Duration::from_secs(8),
+            "{:?}",
+            start.elapsed()
+        );
+        assert_eq!(
+            skipped,
+            vec![
+                "Benchmark=mix Cores=1; Write-Ratio=50 Open-Files=1",
+                "Benchmark=mix Cores=1; Write-Ratio=100 Open-Files=1"
+            ]
+        );
+
+        let csv = std::fs::read_to_string(&outfile).unwrap();
+        assert_eq!(csv.lines().count(), 2);
+        assert_rows(&csv, ["\"mix\"", "1", "0", "1"]);
+        std::fs::remove_file(&outfile).unwrap();
+    }
+
+    #[test]
+    fn sweep_steps_start_at_one_and_stop_at_max() {
+        assert_eq!(sweep_steps(8, 2), vec![1, 2, 4, 6, 8]);
+        assert_eq!(sweep_steps(8, 4), vec![1, 4, 8]);
+        // The last step is cut short at the client's core count
+        assert_eq!(sweep_steps(7, 2), vec![1, 2, 4, 6, 7]);
+        assert_eq!(sweep_steps(3, 8), vec![1, 3]);
+        assert_eq!(sweep_steps(1, 2), vec![1]);
+        // A step of 0 runs every core count
+        assert_eq!(sweep_steps(3, 0), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn sweep_writes_a_row_per_thread_and_second() {
+        let client_params = test_client_params("/dev/shm/fxmark_sweep_rows_test/fx.sock");
+        let outfile = std::env::temp_dir()
+            .join("fxmark_sweep_rows_test.csv")
+            .to_str()
+            .unwrap()
+            .to_string();
+        let _ = std::fs::remove_file(&outfile);
+
+        let core_counts = sweep_steps(2, 1);
+        assert_eq!(core_counts, vec![1, 2]);
+        let skipped = sweep(
+            "mix".to_string(),
+            &[1],
+            &[0],
+            &core_counts,
+            2,
+            &client_params,
+            &outfile,
+            None,
+        )
+        .unwrap();
+        assert!(skipped.is_empty());
+
+        // 2 seconds of 1 thread, then of 2 threads
+        let csv = std::fs::read_to_string(&outfile).unwrap();
+        let ncores: Vec<&str> = csv.lines().map(|l| l.split(',').nth(2).unwrap()).collect();
+        assert_eq!(ncores.len(), 2 + 2 * 2);
+        assert_eq!(ncores.iter().filter(|n| **n == "1").count(), 2);
+        assert_eq!(ncores.iter().filter(|n| **n == "2").count(), 4);
+        std::fs::remove_file(&outfile).unwrap();
+    }
+
+    // Opens descriptors and never closes them
+    #[derive(Clone, Default)]
+    struct LeakyBench;
+
+    impl Bench for LeakyBench {
+        fn init(&mut self, _cores: Vec<u64>, _open_files: usize, _client_params: &ClientParams) {}
+
+        fn run(
+            &self,
+            _barrier: &PoorMansBarrier,
+            _duration: u64,
+            _core: usize,
+            _write_ratio: usize,
+            _client_params: &ClientParams,
+        ) -> Samples {
+            for _ in 0..64 {
+                std::mem::forget(std::fs::File::open("/dev/null").unwrap());
+            }
+            Samples::default()
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn leaked_fds_are_flagged() {
+        let client_params = test_client_params("/dev/shm/fxmark_fd_leak_test/fx.sock");
+        let mut bench = LeakyBench::default();
+        bench.init(vec![0], 1, &client_params);
+
+        let before = utils::open_fd_count().unwrap();
+        bench.run(&PoorMansBarrier::new(1), 1, 0, 0, &client_params);
+        assert!(warn_fd_leak(before, "Benchmark=leaky") > 0);
+    }
+
+    // The cores the calling thread may run on
+    #[cfg(target_os = "linux")]
+    fn allowed_cpus() -> usize {
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        let res = unsafe { libc::sched_getaffinity(0, std::mem::size_of_val(&set), &mut set) };
+        assert_eq!(res, 0);
+        unsafe { libc::CPU_COUNT(&set) as usize }
+    }
+
+    // The cores the calling thread ran on over 100 ms
+    #[cfg(target_os = "linux")]
+    fn sampled_cpus() -> Vec<i32> {
+        let mut cpus = Vec::new();
+        let start = std::time::Instant::now();
+        while start.elapsed() < Duration::from_millis(100) {
+            let cpu = unsafe { libc::sched_getcpu() };
+            if !cpus.contains(&cpu) {
+                cpus.push(cpu);
+            }
+        }
+        cpus
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn floating_threads_stay_unpinned() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_float_test/fx.sock");
+        client_params.pin = false;
+        let results = bench_collect("mix".to_string(), 1, 0, 1, &client_params).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].unpinned);
+        assert!(results[0].samples.iops[1] > 0);
+
+        let process_cpus = allowed_cpus();
+        let core = unsafe { libc::sched_getcpu() } as u64;
+        let (allowed, ran_on) = thread::spawn(move || {
+            assert!(!place_thread(true, core, &Mutex::new(Vec::new())));
+            (allowed_cpus(), sampled_cpus())
+        })
+        .join()
+        .unwrap();
+        assert_eq!(allowed, 1);
+        assert_eq!(ran_on, [core as i32]);
+
+        // Free to go anywhere the process may, not held to `core`
+        let (allowed, ran_on) = thread::spawn(move || {
+            assert!(place_thread(false, core, &Mutex::new(Vec::new())));
+            (allowed_cpus(), sampled_cpus())
+        })
+        .join()
+        .unwrap();
+        assert_eq!(allowed, process_cpus);
+        assert!(!ran_on.is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn unpinnable_threads_take_a_spare_core() {
+        // No machine here has that core
+        let missing = libc::CPU_SETSIZE as u64 - 1;
+        let spare = unsafe { libc::sched_getcpu() } as u64;
+        let (allowed, ran_on, spares) = thread::spawn(move || {
+            let spares = Mutex::new(vec![spare]);
+            assert!(place_thread(true, missing, &spares));
+            (allowed_cpus(), sampled_cpus(), spares)
+        })
+        .join()
+        .unwrap();
+        assert_eq!(allowed, 1);
+        assert_eq!(ran_on, [spare as i32]);
+        assert!(spares.lock().unwrap().is_empty());
+
+        // With the spares used up, the thread floats
+        let process_cpus = allowed_cpus();
+        let allowed = thread::spawn(move || {
+            assert!(place_thread(true, missing, &spares));
+            allowed_cpus()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(allowed, process_cpus);
     }
 }
@@ -0,0 +1,365 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+extern crate alloc;
+
+use crate::fxmark::{
+    align_samples, measure_bucket, op_failed, paced, setup_dir, warm_up, Bench, PoorMansBarrier,
+    Samples,
+};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use libc::{O_CREAT, O_RDWR, S_IRWXU};
+use serde::Serialize;
+
+use crate::fxrpc::grpc::*;
+
+/// Which parents the files of the mrename benchmark move between.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum RenameMode {
+    /// Renamed within one directory, which only locks that directory.
+    Intra,
+    /// Moved between two directories, which locks both and may take the
+    /// file system's rename lock.
+    Inter,
+}
+
+impl Default for RenameMode {
+    fn default() -> RenameMode {
+        RenameMode::Intra
+    }
+}
+
+impl std::str::FromStr for RenameMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "intra" => Ok(RenameMode::Intra),
+            "inter" => Ok(RenameMode::Inter),
+            _ => Err(format!(
+                "Unknown rename mode '{}', expected intra or inter",
+                s
+            )),
+        }
+    }
+}
+
+// The directories below the per-core directory `dir` that the file moves
+// between, none for `Intra`, and the two paths it takes turns at
+fn rename_paths(dir: &str, mode: RenameMode) -> (Vec<String>, [String; 2]) {
+    match mode {
+        RenameMode::Intra => (
+            Vec::new(),
+            [format!("{}/a.txt", dir), format!("{}/b.txt", dir)],
+        ),
+        RenameMode::Inter => (
+            vec![format!("{}/a", dir), format!("{}/b", dir)],
+            [format!("{}/a/f.txt", dir), format!("{}/b/f.txt", dir)],
+        ),
+    }
+}
+
+// Creates the empty file `path` again, after a rename lost it
+fn recreate(client: &mut Box<dyn FxRPC>, path: &str) {
+    if let Ok(fd) = client.rpc_open(path, O_CREAT | O_RDWR, S_IRWXU.into()) {
+        if fd != -1 {
+            let _ = client.rpc_close(fd);
+        }
+    }
+}
+
+// Creates the per-core directory `dir`, the `parents` below it and the file
+// at the first of `paths`
+fn set_up(client: &mut Box<dyn FxRPC>, dir: &str, parents: &[String], paths: &[String; 2]) {
+    setup_dir(client, dir);
+    for parent in parents {
+        setup_dir(client, parent);
+    }
+    recreate(client, &paths[0]);
+}
+
+// Moves the file from where it is, the path of `paths` at `at`, to the other
+// one. A failed rename recreates the file where it was, for the next one to
+// move.
+fn rename_turn(
+    client: &mut Box<dyn FxRPC>,
+    paths: &[String; 2],
+    at: &mut usize,
+) -> Result<(), Option<i32>> {
+    let (from, to) = (&paths[*at], &paths[1 - *at]);
+    let res = client.rpc_rename(from, to);
+    if matches!(res, Ok(0)) {
+        *at = 1 - *at;
+        Ok(())
+    } else {
+        recreate(client, from);
+        Err(op_errno(&res))
+    }
+}
+
+// Removes what `set_up()` created
+fn clean_up(client: &mut Box<dyn FxRPC>, dir: &str, parents: &[String], paths: &[String; 2]) {
+    // A failed rename may have left the file at both paths
+    for path in paths {
+        let _ = client.rpc_remove(path);
+    }
+    for parent in parents.iter().map(String::as_str).chain([dir]) {
+        let res = client.rpc_rmdir(parent).expect("RmDir syscall failed");
+        assert!(res == 0, "Unable to remove directory {}", parent);
+    }
+}
+
+/// Renames a private per-core file back and forth, within one directory or
+/// between two, as `ClientParams::rename_mode` picks. Every op is one
+/// rename. A failed rename recreates the file where the next one expects
+/// it, so the loop keeps going.
+#[derive(Clone)]
+pub struct MRENAME {
+    min_core: usize,
+    mode: RenameMode,
+}
+
+impl Default for MRENAME {
+    fn default() -> MRENAME {
+        MRENAME {
+            min_core: 0,
+            mode: RenameMode::Intra,
+        }
+    }
+}
+
+impl Bench for MRENAME {
+    fn init(&mut self, cores: Vec<u64>, _open_files: usize, client_params: &ClientParams) {
+        self.min_core = *cores.iter().min().unwrap() as usize;
+        self.mode = client_params.rename_mode;
+    }
+
+    fn run(
+        &self,
+        poor_mans_barrier: &PoorMansBarrier,
+        duration: u64,
+        core: usize,
+        _write_ratio: usize,
+        client_params: &ClientParams,
+    ) -> Samples {
+        let mut client = init_bench_client(client_params);
+
+        let mut iops_per_second = Vec::with_capacity(duration as usize);
+        let mut errors_per_second = Vec::with_capacity(duration as usize);
+        let mut errnos_per_second = Vec::with_capacity(duration as usize);
+        let mut unreliable_per_second = Vec::with_capacity(duration as usize);
+        let mut cpu_util_per_second = Vec::with_capacity(duration as usize);
+        let mut latency_per_second = Vec::with_capacity(duration as usize);
+
+        // Every clone of the bench gets init() for all cores, so each core
+        // sets up its own directory here, before the timed phase starts
+        let dir = format!("mrename{}", core);
+        let (parents, paths) = rename_paths(&dir, self.mode);
+        set_up(&mut client, &dir, &parents, &paths);
+
+        // Where the file is now
+        let mut at = 0;
+        let mut next_op = paced(client_params, || {
+            match rename_turn(&mut client, &paths, &mut at) {
+                Ok(()) => true,
+                Err(errno) => {
+                    op_failed(client_params, errno, "MRENAME: rename() failed");
+                    false
+                }
+            }
+        });
+        warm_up(client_params.warmup_ops, &mut next_op);
+
+        // Synchronize with all cores
+        poor_mans_barrier.wait_start();
+        let first_second = align_samples(client_params.align_samples_ms);
+
+        let mut iterations = 0;
+        while iterations <= duration {
+            let (iops, errors, unreliable, cpu_util, errnos, latency) =
+                measure_bucket(client_params.min_samples, client_params.rerun_short, || {
+                    let mut iops = 0;
+                    let mut errors = 0;
+                    let start = std::time::Instant::now();
+                    while start.elapsed().as_secs() < 1 {
+                        if next_op() {
+                            iops += 1;
+                        } else {
+                            errors += 1;
+                        }
+                    }
+                    (iops, errors)
+                });
+
+            iops_per_second.push(iops);
+            errors_per_second.push(errors);
+            errnos_per_second.push(errnos);
+            unreliable_per_second.push(unreliable);
+            cpu_util_per_second.push(cpu_util);
+            latency_per_second.push(latency);
+            iterations += 1;
+        }
+
+        poor_mans_barrier.wait_end();
+
+        if !client_params.keep_files {
+            clean_up(&mut client, &dir, &parents, &paths);
+        }
+
+        if core == self.min_core {
+            let start = std::time::Instant::now();
+            while start.elapsed().as_secs() < 1 {}
+        }
+        Samples {
+            iops: iops_per_second,
+            errors: errors_per_second,
+            errnos: errnos_per_second,
+            unreliable: unreliable_per_second,
+            cpu_util: cpu_util_per_second,
+            latency: latency_per_second,
+            start: Some(first_second),
+            ..Default::default()
+        }
+    }
+}
+
+register_bench!("mrename", MRENAME, 0);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::BTreeSet;
+    use std::rc::Rc;
+
+    // A file system of the paths in `tree`, directories and files alike. A
+    // rename fails if there's nothing to move, a rmdir if the directory
+    // isn't there or not empty.
+    struct Tree {
+        tree: Rc<RefCell<BTreeSet<String>>>,
+    }
+
+    impl FxRPC for Tree {
+        fn rpc_open(
+            &mut self,
+            path: &str,
+            _flags: i32,
+            _mode: u32,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            self.tree.borrow_mut().insert(path.to_string());
+            Ok(3)
+        }
+
+        fn rpc_close(&mut self, _fd: i32) -> Result<i32, Box<dyn std::error::Error>> {
+            Ok(0)
+        }
+
+        fn rpc_mkdir(&mut self, path: &str, _mode: u32) -> Result<i32, Box<dyn std::error::Error>> {
+            self.tree.borrow_mut().insert(path.to_string());
+            Ok(0)
+        }
+
+        fn rpc_rename(
+            &mut self,
+            old_path: &str,
+            new_path: &str,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            let mut tree = self.tree.borrow_mut();
+            if !tree.remove(old_path) {
+                return Ok(-1);
+            }
+            tree.insert(new_path.to_string());
+            Ok(0)
+        }
+
+        fn rpc_remove(&mut self, path: &str) -> Result<i32, Box<dyn std::error::Error>> {
+            Ok(if self.tree.borrow_mut().remove(path) {
+                0
+            } else {
+                -1
+            })
+        }
+
+        fn rpc_rmdir(&mut self, path: &str) -> Result<i32, Box<dyn std::error::Error>> {
+            let mut tree = self.tree.borrow_mut();
+            let below = format!("{}/", path);
+            if tree.iter().any(|p| p.starts_with(&below)) || !tree.remove(path) {
+                return Ok(-1);
+            }
+            Ok(0)
+        }
+
+        unimplemented_ops!(
+            rpc_read,
+            rpc_pread,
+            rpc_write,
+            rpc_pwrite,
+            rpc_fsync,
+            rpc_ftruncate,
+            rpc_fadvise,
+            rpc_drop_caches,
+            rpc_fstatat,
+            rpc_sendfile,
+            rpc_lseek,
+            rpc_batch,
+            rpc_dump_server_log,
+            rpc_server_stats,
+            rpc_capabilities,
+            rpc_reset
+        );
+    }
+
+    #[test]
+    fn renames_in_both_modes() {
+        for mode in [RenameMode::Intra, RenameMode::Inter] {
+            let tree = Rc::new(RefCell::new(BTreeSet::new()));
+            let mut client: Box<dyn FxRPC> = Box::new(Tree {
+                tree: Rc::clone(&tree),
+            });
+            let (parents, paths) = rename_paths("mrename0", mode);
+            set_up(&mut client, "mrename0", &parents, &paths);
+            let files = |tree: &Rc<RefCell<BTreeSet<String>>>| {
+                let tree = tree.borrow();
+                paths
+                    .iter()
+                    .filter(|p| tree.contains(*p))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(files(&tree), [paths[0].clone()], "{:?}", mode);
+
+            // The file takes turns at both paths
+            let mut at = 0;
+            for turn in 1..=4 {
+                assert_eq!(rename_turn(&mut client, &paths, &mut at), Ok(()));
+                assert_eq!(at, turn % 2, "{:?}", mode);
+                assert_eq!(files(&tree), [paths[at].clone()], "{:?}", mode);
+            }
+
+            // A rename that fails puts the file back for the next one
+            tree.borrow_mut().remove(&paths[0]);
+            assert!(rename_turn(&mut client, &paths, &mut at).is_err());
+            assert_eq!(
+                (at, files(&tree)),
+                (0, vec![paths[0].clone()]),
+                "{:?}",
+                mode
+            );
+            assert_eq!(rename_turn(&mut client, &paths, &mut at), Ok(()));
+
+            // Nothing of the run is left
+            clean_up(&mut client, "mrename0", &parents, &paths);
+            assert!(tree.borrow().is_empty(), "{:?}: {:?}", mode, tree.borrow());
+        }
+    }
+
+    #[test]
+    fn rename_modes_by_name() {
+        assert_eq!("intra".parse(), Ok(RenameMode::Intra));
+        assert_eq!("inter".parse(), Ok(RenameMode::Inter));
+        assert!("across".parse::<RenameMode>().is_err());
+    }
+}
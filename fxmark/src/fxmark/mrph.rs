@@ -0,0 +1,194 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+extern crate alloc;
+
+use crate::fxmark::{
+    align_samples, check_op, measure_bucket, op_failed, paced, warm_up, Bench, PoorMansBarrier,
+    Samples, ThreadRole, PAGE_SIZE,
+};
+use alloc::vec::Vec;
+use libc::{AT_FDCWD, S_IRWXU};
+
+use crate::fxrpc::grpc::*;
+
+/// The role of `core` among `cores`: the lowest `reader_fraction` of the
+/// cores (rounded up) stat the file, the others modify it.
+pub(crate) fn role_of(cores: &[u64], reader_fraction: f64, core: usize) -> ThreadRole {
+    let mut sorted = cores.to_vec();
+    sorted.sort_unstable();
+    let readers = ((sorted.len() as f64 * reader_fraction).ceil() as usize).min(sorted.len());
+    let index = sorted
+        .iter()
+        .position(|c| *c as usize == core)
+        .expect("core not part of the run");
+    if index < readers {
+        ThreadRole::Reader
+    } else {
+        ThreadRole::Writer
+    }
+}
+
+/// Stats of one file shared by all cores while some of them keep changing
+/// its size. The `ClientParams::reader_fraction` of the cores with the lowest
+/// ids read, the rest write, so the reader throughput shows what the writers
+/// cost it.
+#[derive(Clone)]
+pub struct MRPH {
+    cores: Vec<u64>,
+    min_core: usize,
+}
+
+impl Default for MRPH {
+    fn default() -> MRPH {
+        MRPH {
+            cores: Vec::new(),
+            min_core: 0,
+        }
+    }
+}
+
+impl Bench for MRPH {
+    fn init(&mut self, cores: Vec<u64>, _open_files: usize, _client_params: &ClientParams) {
+        self.min_core = *cores.iter().min().unwrap() as usize;
+        self.cores = cores;
+    }
+
+    fn run(
+        &self,
+        poor_mans_barrier: &PoorMansBarrier,
+        duration: u64,
+        core: usize,
+        _write_ratio: usize,
+        client_params: &ClientParams,
+    ) -> Samples {
+        let mut client = init_bench_client(client_params);
+
+        let mut iops_per_second = Vec::with_capacity(duration as usize);
+        let mut errors_per_second = Vec::with_capacity(duration as usize);
+        let mut errnos_per_second = Vec::with_capacity(duration as usize);
+        let mut unreliable_per_second = Vec::with_capacity(duration as usize);
+        let mut cpu_util_per_second = Vec::with_capacity(duration as usize);
+        let mut latency_per_second = Vec::with_capacity(duration as usize);
+
+        // All cores open the file, the lowest one gives it its first page
+        let filename = "mrph.txt";
+        let fd = client
+            .rpc_open(filename, client_params.open_flags, S_IRWXU.into())
+            .expect("FileOpen syscall failed");
+        assert!(fd != -1, "Unable to create a file");
+        if core == self.min_core {
+            client
+                .rpc_ftruncate(fd, PAGE_SIZE as i64)
+                .expect("FileTruncate syscall failed");
+        }
+
+        let role = role_of(&self.cores, client_params.reader_fraction, core);
+        let mut grow = true;
+        let mut next_op = paced(client_params, || match role {
+            ThreadRole::Reader => {
+                let res = client.rpc_fstatat(AT_FDCWD, filename, 0);
+                if res.is_err() {
+                    op_failed(client_params, op_errno(&res), "MRPH: fstatat() failed");
+                }
+                res.is_ok()
+            }
+            ThreadRole::Writer => {
+                // Flip between one and two pages, so every op changes the size
+                let size = if grow { 2 * PAGE_SIZE } else { PAGE_SIZE };
+                grow = !grow;
+                let res = check_op(client.rpc_ftruncate(fd, size as i64), 0);
+                if let Err(errno) = res {
+                    op_failed(client_params, errno, "MRPH: ftruncate() failed");
+                }
+                res.is_ok()
+            }
+        });
+        warm_up(client_params.warmup_ops, &mut next_op);
+
+        // Synchronize with all cores
+        poor_mans_barrier.wait_start();
+        let first_second = align_samples(client_params.align_samples_ms);
+
+        let mut iterations = 0;
+        while iterations <= duration {
+            let (iops, errors, unreliable, cpu_util, errnos, latency) =
+                measure_bucket(client_params.min_samples, client_params.rerun_short, || {
+                    let mut iops = 0;
+                    let mut errors = 0;
+                    let start = std::time::Instant::now();
+                    while start.elapsed().as_secs() < 1 {
+                        if next_op() {
+                            iops += 1;
+                        } else {
+                            errors += 1;
+                        }
+                    }
+                    (iops, errors)
+                });
+
+            iops_per_second.push(iops);
+            errors_per_second.push(errors);
+            errnos_per_second.push(errnos);
+            unreliable_per_second.push(unreliable);
+            cpu_util_per_second.push(cpu_util);
+            latency_per_second.push(latency);
+            iterations += 1;
+        }
+
+        poor_mans_barrier.wait_end();
+
+        client.rpc_close(fd).expect("FileClose syscall failed");
+        if core == self.min_core {
+            if !client_params.keep_files {
+                client
+                    .rpc_remove(filename)
+                    .expect("FileRemove syscall failed");
+            }
+            let start = std::time::Instant::now();
+            while start.elapsed().as_secs() < 1 {}
+        }
+        Samples {
+            iops: iops_per_second,
+            errors: errors_per_second,
+            errnos: errnos_per_second,
+            unreliable: unreliable_per_second,
+            cpu_util: cpu_util_per_second,
+            latency: latency_per_second,
+            start: Some(first_second),
+            role: Some(role),
+            ..Default::default()
+        }
+    }
+}
+
+register_bench!("mrph", MRPH, 0);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_follow_core_ids() {
+        let cores = [6, 2, 4, 0];
+        let roles: Vec<ThreadRole> = [0, 2, 4, 6]
+            .iter()
+            .map(|core| role_of(&cores, 0.5, *core))
+            .collect();
+        assert_eq!(
+            roles,
+            [
+                ThreadRole::Reader,
+                ThreadRole::Reader,
+                ThreadRole::Writer,
+                ThreadRole::Writer
+            ]
+        );
+
+        // Rounded up, so a nonzero fraction always gets a reader
+        assert_eq!(role_of(&cores, 0.1, 0), ThreadRole::Reader);
+        assert_eq!(role_of(&cores, 0.1, 2), ThreadRole::Writer);
+        assert_eq!(role_of(&cores, 0.0, 0), ThreadRole::Writer);
+        assert_eq!(role_of(&cores, 1.0, 6), ThreadRole::Reader);
+    }
+}
@@ -0,0 +1,483 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+extern crate alloc;
+
+use crate::fxmark::{
+    align_samples, check_op, fill_file, measure_bucket, op_failed, paced, warm_up, Bench,
+    PoorMansBarrier, Samples, SeededRng, PAGE_SIZE,
+};
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use libc::S_IRWXU;
+use std::sync::Mutex;
+
+use crate::fxmark::fill::PageVerifier;
+use crate::fxmark::utils::{lock_buffer, unlock_buffer};
+use crate::fxrpc::grpc::*;
+
+/// Reads a random page of the first `pages` pages of `fd` and returns its
+/// offset. Fails like `check_op()` unless the whole page was read.
+pub(crate) fn read_random_page(
+    client: &mut Box<dyn FxRPC>,
+    fd: i32,
+    pages: usize,
+    rng: &mut SeededRng,
+    page: &mut Vec<u8>,
+) -> Result<usize, Option<i32>> {
+    let offset = (rng.next_u64() as usize % pages) * PAGE_SIZE;
+    check_op(
+        client.rpc_pread(fd, page, PAGE_SIZE, offset as i64),
+        PAGE_SIZE as i32,
+    )?;
+    Ok(offset)
+}
+
+/// Random-position reads from a file of `ClientParams::working_set_bytes`.
+/// Sweeping the working set across the size of the server's cache shows where
+/// reads fall off the cache. Each file is shared by
+/// `ClientParams::share_factor` cores, taken in order of their ids, so the
+/// default of 1 gives every core a private file, and the core count gives
+/// all of them one like MRPM.
+#[derive(Clone)]
+pub struct MRPL {
+    min_core: usize,
+    pages: usize,
+    /// The cores of the run, in order of their ids.
+    cores: Vec<u64>,
+    share_factor: usize,
+    /// How many clones of the bench still get init() for the run, shared by
+    /// all of them. The clones of a run get theirs one after the other, the
+    /// first fills the files.
+    inits_left: Arc<Mutex<usize>>,
+}
+
+impl Default for MRPL {
+    fn default() -> MRPL {
+        MRPL {
+            min_core: 0,
+            pages: 0,
+            cores: Vec::new(),
+            share_factor: 1,
+            inits_left: Arc::new(Mutex::new(0)),
+        }
+    }
+}
+
+impl MRPL {
+    /// The lowest core of those reading the same file as `core`. It fills
+    /// and removes the file, which is named after it. A core `init()` wasn't
+    /// given shares with no other and reads a file of its own.
+    fn file_owner(&self, core: u64) -> u64 {
+        match self.cores.iter().position(|c| *c == core) {
+            Some(i) => self.cores[i - i % self.share_factor],
+            None => core,
+        }
+    }
+
+    // Opens the file of `owner` and, if `fill`, writes it
+    fn open_file(
+        &self,
+        client: &mut Box<dyn FxRPC>,
+        client_params: &ClientParams,
+        owner: u64,
+        fill: bool,
+    ) -> i32 {
+        let filename = format!("mrpl{}.txt", owner);
+        let fd = client
+            .rpc_open(&filename, client_params.open_flags, S_IRWXU.into())
+            .expect("FileOpen syscall failed");
+        assert!(fd != -1, "Unable to create a file");
+        if fill {
+            fill_file(client, client_params, fd, owner as usize, 0, self.pages);
+        }
+        fd
+    }
+
+    // Takes the cores of a run and what they read from `client_params`
+    fn set_cores(&mut self, mut cores: Vec<u64>, client_params: &ClientParams) {
+        self.min_core = *cores.iter().min().unwrap() as usize;
+        self.pages = client_params.working_set_bytes / PAGE_SIZE;
+        cores.sort_unstable();
+        self.cores = cores;
+        self.share_factor = client_params.share_factor;
+    }
+
+    // Writes the file of every owner among the cores of the run
+    fn write_files(&self, client: &mut Box<dyn FxRPC>, client_params: &ClientParams) {
+        for owner in self.cores.iter().step_by(self.share_factor) {
+            let fd = self.open_file(client, client_params, *owner, true);
+            client.rpc_close(fd).expect("FileClose syscall failed");
+        }
+    }
+}
+
+impl Bench for MRPL {
+    fn init(&mut self, cores: Vec<u64>, _open_files: usize, client_params: &ClientParams) {
+        self.set_cores(cores, client_params);
+
+        // Before any thread of the run starts, so no core reads, not even
+        // to warm up, before its file is written
+        let mut inits_left = self.inits_left.lock().unwrap();
+        if *inits_left == 0 {
+            *inits_left = self.cores.len();
+            self.write_files(&mut init_bench_client(client_params), client_params);
+        }
+        *inits_left -= 1;
+    }
+
+    fn run(
+        &self,
+        poor_mans_barrier: &PoorMansBarrier,
+        duration: u64,
+        core: usize,
+        _write_ratio: usize,
+        client_params: &ClientParams,
+    ) -> Samples {
+        let mut client = init_bench_client(client_params);
+
+        let mut iops_per_second = Vec::with_capacity(duration as usize);
+        let mut errors_per_second = Vec::with_capacity(duration as usize);
+        let mut errnos_per_second = Vec::with_capacity(duration as usize);
+        let mut unreliable_per_second = Vec::with_capacity(duration as usize);
+        let mut cpu_util_per_second = Vec::with_capacity(duration as usize);
+        let mut latency_per_second = Vec::with_capacity(duration as usize);
+
+        // init() wrote the files of the cores it was given, a core it wasn't
+        // given reads none of them and writes its own here
+        let owner = self.file_owner(core as u64) as usize;
+        let filename = format!("mrpl{}.txt", owner);
+        let outside = !self.cores.contains(&(core as u64));
+        let fd = self.open_file(&mut client, client_params, owner as u64, outside);
+
+        let mut page: Vec<u8> = vec![0; PAGE_SIZE as usize];
+        let locked = lock_buffer(client_params.lock_buffers, &page);
+        let mut rng = SeededRng::new(client_params.seed, core);
+        let mut verifier = PageVerifier::new(
+            client_params.verify,
+            &client_params.fill_pattern,
+            client_params.seed,
+            owner,
+            self.pages,
+        );
+        let mut next_op = paced(client_params, || {
+            match read_random_page(&mut client, fd, self.pages, &mut rng, &mut page) {
+                Ok(offset) if verifier.check(&page, offset).is_err() => {
+                    op_failed(client_params, None, "MRPL: read_at() returned other data");
+                    false
+                }
+                Ok(_) => true,
+                Err(errno) => {
+                    op_failed(client_params, errno, "MRPL: read_at() failed");
+                    false
+                }
+            }
+        });
+        warm_up(client_params.warmup_ops, &mut next_op);
+
+        // Synchronize with all cores
+        poor_mans_barrier.wait_start();
+        let first_second = align_samples(client_params.align_samples_ms);
+
+        let mut iterations = 0;
+        while iterations <= duration {
+            let (iops, errors, unreliable, cpu_util, errnos, latency) =
+                measure_bucket(client_params.min_samples, client_params.rerun_short, || {
+                    let mut iops = 0;
+                    let mut errors = 0;
+                    let start = std::time::Instant::now();
+                    while start.elapsed().as_secs() < 1 {
+                        if next_op() {
+                            iops += 1;
+                        } else {
+                            errors += 1;
+                        }
+                    }
+                    (iops, errors)
+                });
+
+            iops_per_second.push(iops);
+            errors_per_second.push(errors);
+            errnos_per_second.push(errnos);
+            unreliable_per_second.push(unreliable);
+            cpu_util_per_second.push(cpu_util);
+            latency_per_second.push(latency);
+            iterations += 1;
+        }
+
+        poor_mans_barrier.wait_end();
+
+        client.rpc_close(fd).expect("FileClose syscall failed");
+        if core == owner && !client_params.keep_files {
+            client
+                .rpc_remove(&filename)
+                .expect("FileRemove syscall failed");
+        }
+
+        if core == self.min_core {
+            let start = std::time::Instant::now();
+            while start.elapsed().as_secs() < 1 {}
+        }
+        unlock_buffer(locked, &page);
+        Samples {
+            iops: iops_per_second,
+            errors: errors_per_second,
+            errnos: errnos_per_second,
+            unreliable: unreliable_per_second,
+            cpu_util: cpu_util_per_second,
+            latency: latency_per_second,
+            start: Some(first_second),
+            ..Default::default()
+        }
+    }
+}
+
+register_bench!("mrpl", MRPL, PAGE_SIZE);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fxmark::bench_collect;
+    use crate::fxmark::fill::VerifyMode;
+    use crate::fxmark::tests::{client_params_for, test_client_params};
+    use std::cell::{Cell, RefCell};
+    use std::collections::{BTreeMap, VecDeque};
+    use std::rc::Rc;
+
+    const HIT_NS: u64 = 1_000;
+    const MISS_NS: u64 = 100_000;
+
+    // A server with an LRU cache of `capacity` pages, keeping the time its
+    // reads would have taken instead of spending it
+    struct CachedServer {
+        capacity: usize,
+        lru: VecDeque<i64>,
+        busy_ns: Rc<Cell<u64>>,
+        reads: Rc<Cell<u64>>,
+    }
+
+    impl FxRPC for CachedServer {
+        fn rpc_pread(
+            &mut self,
+            _fd: i32,
+            _page: &mut Vec<u8>,
+            size: usize,
+            offset: i64,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            match self.lru.iter().position(|o| *o == offset) {
+                Some(i) => {
+                    self.lru.remove(i);
+                    self.busy_ns.set(self.busy_ns.get() + HIT_NS);
+                }
+                None => {
+                    if self.lru.len() == self.capacity {
+                        self.lru.pop_back();
+                    }
+                    self.busy_ns.set(self.busy_ns.get() + MISS_NS);
+                }
+            }
+            self.lru.push_front(offset);
+            self.reads.set(self.reads.get() + 1);
+            Ok(size as i32)
+        }
+
+        unimplemented_ops!(
+            rpc_open,
+            rpc_read,
+            rpc_write,
+            rpc_pwrite,
+            rpc_close,
+            rpc_fsync,
+            rpc_ftruncate,
+            rpc_remove,
+            rpc_rename,
+            rpc_mkdir,
+            rpc_rmdir,
+            rpc_fadvise,
+            rpc_drop_caches,
+            rpc_fstatat,
+            rpc_sendfile,
+            rpc_lseek,
+            rpc_batch,
+            rpc_dump_server_log,
+            rpc_server_stats,
+            rpc_capabilities,
+            rpc_reset
+        );
+    }
+
+    // A server that keeps how many bytes were written to each file it opened,
+    // by name, and which of them are still open
+    #[derive(Default)]
+    struct Files {
+        names: Vec<String>,
+        written: Rc<RefCell<BTreeMap<String, usize>>>,
+        open: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl FxRPC for Files {
+        fn rpc_open(
+            &mut self,
+            path: &str,
+            _flags: i32,
+            _mode: u32,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            self.names.push(path.to_string());
+            self.written
+                .borrow_mut()
+                .entry(path.to_string())
+                .or_insert(0);
+            self.open.borrow_mut().push(path.to_string());
+            Ok(self.names.len() as i32 - 1)
+        }
+
+        fn rpc_pwrite(
+            &mut self,
+            fd: i32,
+            _page: &[u8],
+            size: usize,
+            _offset: i64,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            *self
+                .written
+                .borrow_mut()
+                .get_mut(&self.names[fd as usize])
+                .unwrap() += size;
+            Ok(size as i32)
+        }
+
+        fn rpc_close(&mut self, fd: i32) -> Result<i32, Box<dyn std::error::Error>> {
+            let name = &self.names[fd as usize];
+            self.open.borrow_mut().retain(|open| open != name);
+            Ok(0)
+        }
+
+        unimplemented_ops!(
+            rpc_read,
+            rpc_pread,
+            rpc_write,
+            rpc_fsync,
+            rpc_ftruncate,
+            rpc_remove,
+            rpc_rename,
+            rpc_mkdir,
+            rpc_rmdir,
+            rpc_fadvise,
+            rpc_drop_caches,
+            rpc_fstatat,
+            rpc_sendfile,
+            rpc_lseek,
+            rpc_batch,
+            rpc_dump_server_log,
+            rpc_server_stats,
+            rpc_capabilities,
+            rpc_reset
+        );
+    }
+
+    // Reads per second against a 256 page cache
+    fn modeled_iops(working_set_pages: usize) -> u64 {
+        let busy_ns = Rc::new(Cell::new(0));
+        let mut client: Box<dyn FxRPC> = Box::new(CachedServer {
+            capacity: 256,
+            lru: VecDeque::new(),
+            busy_ns: busy_ns.clone(),
+            reads: Rc::new(Cell::new(0)),
+        });
+        let mut rng = SeededRng::new(0, 0);
+        let mut page = vec![0; PAGE_SIZE];
+        let reads = 10_000;
+        for _ in 0..reads {
+            assert!(
+                read_random_page(&mut client, 3, working_set_pages, &mut rng, &mut page).is_ok()
+            );
+        }
+        reads * 1_000_000_000 / busy_ns.get()
+    }
+
+    #[test]
+    fn iops_drop_past_the_cache() {
+        let fits = modeled_iops(64);
+        let spills = modeled_iops(4096);
+        // Nearly all hits vs nearly all misses
+        assert!(fits > 20 * spills, "{} vs {} IOPS", fits, spills);
+    }
+
+    #[test]
+    fn warmup_runs_exact_op_count() {
+        let reads = Rc::new(Cell::new(0));
+        let mut client: Box<dyn FxRPC> = Box::new(CachedServer {
+            capacity: 256,
+            lru: VecDeque::new(),
+            busy_ns: Rc::new(Cell::new(0)),
+            reads: reads.clone(),
+        });
+        let mut rng = SeededRng::new(0, 0);
+        let mut page = vec![0; PAGE_SIZE];
+        let mut next_op = || read_random_page(&mut client, 3, 64, &mut rng, &mut page).is_ok();
+
+        warm_up(None, &mut next_op);
+        assert_eq!(reads.get(), 0);
+        warm_up(Some(100), &mut next_op);
+        assert_eq!(reads.get(), 100);
+
+        // The measured phase starts where warmup stopped
+        next_op();
+        assert_eq!(reads.get(), 101);
+    }
+
+    #[test]
+    fn cores_share_files_by_share_factor() {
+        let mut client_params = client_params_for("/");
+        client_params.share_factor = 2;
+        client_params.working_set_bytes = 8 * PAGE_SIZE;
+
+        let mut bench = MRPL::default();
+        bench.set_cores(vec![3, 1, 2, 0], &client_params);
+        let mut readers: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for core in 0..4 {
+            readers
+                .entry(bench.file_owner(core))
+                .or_default()
+                .push(core);
+        }
+        assert_eq!(
+            readers.into_iter().collect::<Vec<_>>(),
+            [(0, vec![0, 1]), (2, vec![2, 3])]
+        );
+        // Not one of the cores of the run
+        assert_eq!(bench.file_owner(7), 7);
+
+        // Only the owners' files are written, in full, and none is left open
+        let files = Files::default();
+        let (written, open) = (Rc::clone(&files.written), Rc::clone(&files.open));
+        let mut client: Box<dyn FxRPC> = Box::new(files);
+        bench.write_files(&mut client, &client_params);
+        assert_eq!(
+            written.borrow().clone().into_iter().collect::<Vec<_>>(),
+            [
+                ("mrpl0.txt".to_string(), 8 * PAGE_SIZE),
+                ("mrpl2.txt".to_string(), 8 * PAGE_SIZE)
+            ]
+        );
+        assert!(open.borrow().is_empty(), "{:?}", open.borrow());
+    }
+
+    #[test]
+    fn shared_files_are_written_before_warmup() {
+        let mut client_params = test_client_params("/dev/shm/fxmark_mrpl_warmup_test/fx.sock");
+        client_params.share_factor = 2;
+        client_params.ccores = 2;
+        // Big enough that the owner is still writing when the other core warms up
+        client_params.working_set_bytes = 64 * 1024 * 1024;
+        client_params.warmup_ops = Some(1000);
+        client_params.verify = VerifyMode::Full;
+
+        // A short or wrong read during warmup fails the run
+        let results = bench_collect("mrpl".to_string(), 1, 0, 1, &client_params).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.samples.errors[1] == 0));
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+extern crate alloc;
+
+use crate::fxmark::mrpl::read_random_page;
+use crate::fxmark::{
+    align_samples, fill_file, measure_bucket, op_failed, paced, warm_up, Bench, PoorMansBarrier,
+    Samples, SeededRng, PAGE_SIZE,
+};
+use alloc::vec;
+use alloc::vec::Vec;
+use libc::S_IRWXU;
+
+use crate::fxmark::fill::PageVerifier;
+use crate::fxmark::utils::{lock_buffer, unlock_buffer};
+use crate::fxrpc::grpc::*;
+
+/// Random-position reads from one file of `ClientParams::working_set_bytes`
+/// shared by all cores, the shared counterpart of MRPL.
+#[derive(Clone)]
+pub struct MRPM {
+    min_core: usize,
+    pages: usize,
+}
+
+impl Default for MRPM {
+    fn default() -> MRPM {
+        MRPM {
+            min_core: 0,
+            pages: 0,
+        }
+    }
+}
+
+impl Bench for MRPM {
+    fn init(&mut self, cores: Vec<u64>, _open_files: usize, client_params: &ClientParams) {
+        self.min_core = *cores.iter().min().unwrap() as usize;
+        self.pages = client_params.working_set_bytes / PAGE_SIZE;
+    }
+
+    fn run(
+        &self,
+        poor_mans_barrier: &PoorMansBarrier,
+        duration: u64,
+        core: usize,
+        _write_ratio: usize,
+        client_params: &ClientParams,
+    ) -> Samples {
+        let mut client = init_bench_client(client_params);
+
+        let mut iops_per_second = Vec::with_capacity(duration as usize);
+        let mut errors_per_second = Vec::with_capacity(duration as usize);
+        let mut errnos_per_second = Vec::with_capacity(duration as usize);
+        let mut unreliable_per_second = Vec::with_capacity(duration as usize);
+        let mut cpu_util_per_second = Vec::with_capacity(duration as usize);
+        let mut latency_per_second = Vec::with_capacity(duration as usize);
+
+        // All cores open the file, the lowest one fills it. Nobody reads
+        // before the barrier, by which time it's written.
+        let filename = "mrpm.txt";
+        let fd = client
+            .rpc_open(filename, client_params.open_flags, S_IRWXU.into())
+            .expect("FileOpen syscall failed");
+        assert!(fd != -1, "Unable to create a file");
+        if core == self.min_core {
+            fill_file(&mut client, client_params, fd, 0, 0, self.pages);
+        }
+
+        let mut page: Vec<u8> = vec![0; PAGE_SIZE as usize];
+        let locked = lock_buffer(client_params.lock_buffers, &page);
+        let mut rng = SeededRng::new(client_params.seed, core);
+        let mut verifier = PageVerifier::new(
+            client_params.verify,
+            &client_params.fill_pattern,
+            client_params.seed,
+            0,
+            self.pages,
+        );
+        let mut next_op = paced(client_params, || {
+            match read_random_page(&mut client, fd, self.pages, &mut rng, &mut page) {
+                Ok(offset) if verifier.check(&page, offset).is_err() => {
+                    op_failed(client_params, None, "MRPM: read_at() returned other data");
+                    false
+                }
+                Ok(_) => true,
+                Err(errno) => {
+                    op_failed(client_params, errno, "MRPM: read_at() failed");
+                    false
+                }
+            }
+        });
+        warm_up(client_params.warmup_ops, &mut next_op);
+
+        // Synchronize with all cores
+        poor_mans_barrier.wait_start();
+        let first_second = align_samples(client_params.align_samples_ms);
+
+        let mut iterations = 0;
+        while iterations <= duration {
+            let (iops, errors, unreliable, cpu_util, errnos, latency) =
+                measure_bucket(client_params.min_samples, client_params.rerun_short, || {
+                    let mut iops = 0;
+                    let mut errors = 0;
+                    let start = std::time::Instant::now();
+                    while start.elapsed().as_secs() < 1 {
+                        if next_op() {
+                            iops += 1;
+                        } else {
+                            errors += 1;
+                        }
+                    }
+                    (iops, errors)
+                });
+
+            iops_per_second.push(iops);
+            errors_per_second.push(errors);
+            errnos_per_second.push(errnos);
+            unreliable_per_second.push(unreliable);
+            cpu_util_per_second.push(cpu_util);
+            latency_per_second.push(latency);
+            iterations += 1;
+        }
+
+        poor_mans_barrier.wait_end();
+
+        client.rpc_close(fd).expect("FileClose syscall failed");
+        if core == self.min_core {
+            if !client_params.keep_files {
+                client
+                    .rpc_remove(filename)
+                    .expect("FileRemove syscall failed");
+            }
+            let start = std::time::Instant::now();
+            while start.elapsed().as_secs() < 1 {}
+        }
+        unlock_buffer(locked, &page);
+        Samples {
+            iops: iops_per_second,
+            errors: errors_per_second,
+            errnos: errnos_per_second,
+            unreliable: unreliable_per_second,
+            cpu_util: cpu_util_per_second,
+            latency: latency_per_second,
+            start: Some(first_second),
+            ..Default::default()
+        }
+    }
+}
+
+register_bench!("mrpm", MRPM, PAGE_SIZE);
@@ -0,0 +1,105 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! File names of a controlled length and alphabet for metadata heavy benches.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use core::str::FromStr;
+use serde::Serialize;
+
+/// Longest file name Linux accepts, in bytes.
+pub const NAME_MAX: usize = 255;
+
+/// Shortest custom name length, leaves room for the unique part of a name.
+pub const MIN_NAME_LEN: usize = 32;
+
+// Two bytes each in UTF-8
+const UTF8_ALPHABET: [char; 8] = ['α', 'β', 'γ', 'δ', 'ε', 'ζ', 'η', 'θ'];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Charset {
+    Ascii,
+    Utf8,
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ascii" => Ok(Charset::Ascii),
+            "utf8" => Ok(Charset::Utf8),
+            _ => Err(format!("Unknown charset '{}', expected ascii or utf8", s)),
+        }
+    }
+}
+
+/// Checks that names of `name_len` bytes can be generated, 0 selects the
+/// default names.
+pub fn validate_name_len(name_len: usize) -> Result<(), String> {
+    if name_len != 0 && !(MIN_NAME_LEN..=NAME_MAX).contains(&name_len) {
+        return Err(format!(
+            "Name length {} must be between {} and {} (NAME_MAX) bytes",
+            name_len, MIN_NAME_LEN, NAME_MAX
+        ));
+    }
+    Ok(())
+}
+
+/// Name of the `n`-th file `core` creates. With `name_len` 0 that's
+/// `{prefix}{core}_{n}.txt`, otherwise a unique prefix padded with `charset`
+/// characters to exactly `name_len` bytes.
+pub fn file_name(prefix: &str, core: usize, n: usize, name_len: usize, charset: Charset) -> String {
+    if name_len == 0 {
+        return format!("{}{}_{}.txt", prefix, core, n);
+    }
+
+    let mut name = format!("{}{:x}.{:x}.", prefix, core, n);
+    let mut i = 0;
+    while name.len() < name_len {
+        let c = match charset {
+            Charset::Ascii => (b'a' + (i % 26) as u8) as char,
+            // Fall back to ASCII for a last odd byte
+            Charset::Utf8 if name_len - name.len() >= 2 => UTF8_ALPHABET[i % UTF8_ALPHABET.len()],
+            Charset::Utf8 => 'a',
+        };
+        name.push(c);
+        i += 1;
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn long_utf8_names() {
+        let names: HashSet<String> = (0..64)
+            .map(|n| file_name("recipe", 3, n, 200, Charset::Utf8))
+            .collect();
+        assert_eq!(names.len(), 64);
+        for name in &names {
+            assert_eq!(name.len(), 200);
+            assert!(name.chars().count() < 200);
+            assert!(!name.contains('/'));
+        }
+    }
+
+    #[test]
+    fn name_len_limits() {
+        assert!(validate_name_len(0).is_ok());
+        assert!(validate_name_len(NAME_MAX).is_ok());
+        assert!(validate_name_len(NAME_MAX + 1).is_err());
+        assert!(validate_name_len(MIN_NAME_LEN - 1).is_err());
+        assert_eq!(
+            file_name("recipe", 1, 2, 0, Charset::Ascii),
+            "recipe1_2.txt"
+        );
+        assert_eq!(file_name("recipe", 1, 2, 33, Charset::Utf8).len(), 33);
+    }
+}
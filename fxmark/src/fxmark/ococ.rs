@@ -0,0 +1,151 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+extern crate alloc;
+
+use crate::fxmark::{
+    align_samples, check_op, fill_file, measure_bucket, op_failed, paced, size_dist, warm_up,
+    Bench, PoorMansBarrier, Samples, SeededRng,
+};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use libc::{O_CREAT, O_EXCL, O_TRUNC, S_IRWXU};
+
+use crate::fxrpc::grpc::*;
+
+/// Opens a private per-core file and closes it again right away, without
+/// any I/O. Every op is one open/close pair, so the throughput shows what
+/// the server's descriptor table and dentry cache cost short-lived files.
+/// With a `ClientParams::io_size` above 0 each open also writes that many
+/// bytes at the start of the file before the close, or as many as it draws
+/// from `ClientParams::size_dist`.
+#[derive(Clone)]
+pub struct OCOC {
+    min_core: usize,
+}
+
+impl Default for OCOC {
+    fn default() -> OCOC {
+        OCOC { min_core: 0 }
+    }
+}
+
+impl Bench for OCOC {
+    fn init(&mut self, cores: Vec<u64>, _open_files: usize, _client_params: &ClientParams) {
+        self.min_core = *cores.iter().min().unwrap() as usize;
+    }
+
+    fn run(
+        &self,
+        poor_mans_barrier: &PoorMansBarrier,
+        duration: u64,
+        core: usize,
+        _write_ratio: usize,
+        client_params: &ClientParams,
+    ) -> Samples {
+        let mut client = init_bench_client(client_params);
+
+        let mut iops_per_second = Vec::with_capacity(duration as usize);
+        let mut errors_per_second = Vec::with_capacity(duration as usize);
+        let mut errnos_per_second = Vec::with_capacity(duration as usize);
+        let mut unreliable_per_second = Vec::with_capacity(duration as usize);
+        let mut cpu_util_per_second = Vec::with_capacity(duration as usize);
+        let mut latency_per_second = Vec::with_capacity(duration as usize);
+
+        // Every clone of the bench gets init() for all cores, so each core
+        // creates its own file here, before the timed phase starts
+        let filename = format!("ococ{}.txt", core);
+        let fd = client
+            .rpc_open(&filename, client_params.open_flags, S_IRWXU.into())
+            .expect("FileOpen syscall failed");
+        assert!(fd != -1, "Unable to create a file");
+        fill_file(&mut client, client_params, fd, core, 0, 1);
+        client.rpc_close(fd).expect("FileClose syscall failed");
+
+        // The timed opens only open the file, they never create or empty it
+        let flags = client_params.open_flags & !(O_CREAT | O_EXCL | O_TRUNC);
+        let sizes = size_dist("ococ", client_params);
+        let data: Vec<u8> = vec![0xc; sizes.max()];
+        let mut rng = SeededRng::new(client_params.seed, core);
+        let mut next_op = paced(client_params, || {
+            let size = sizes.draw(&mut rng);
+            let res = match client.rpc_open(&filename, flags, 0) {
+                Ok(fd) if fd != -1 => {
+                    let written = if size > 0 {
+                        check_op(client.rpc_pwrite(fd, &data, size, 0), size as i32)
+                    } else {
+                        Ok(())
+                    };
+                    // Closed even after a failed write, so it doesn't leak
+                    let closed = check_op(client.rpc_close(fd), 0);
+                    written.and(closed)
+                }
+                res => Err(op_errno(&res)),
+            };
+            if let Err(errno) = res {
+                op_failed(
+                    client_params,
+                    errno,
+                    "OCOC: open(), write_at() or close() failed",
+                );
+            }
+            res.is_ok()
+        });
+        warm_up(client_params.warmup_ops, &mut next_op);
+
+        // Synchronize with all cores
+        poor_mans_barrier.wait_start();
+        let first_second = align_samples(client_params.align_samples_ms);
+
+        let mut iterations = 0;
+        while iterations <= duration {
+            let (iops, errors, unreliable, cpu_util, errnos, latency) =
+                measure_bucket(client_params.min_samples, client_params.rerun_short, || {
+                    let mut iops = 0;
+                    let mut errors = 0;
+                    let start = std::time::Instant::now();
+                    while start.elapsed().as_secs() < 1 {
+                        if next_op() {
+                            iops += 1;
+                        } else {
+                            errors += 1;
+                        }
+                    }
+                    (iops, errors)
+                });
+
+            iops_per_second.push(iops);
+            errors_per_second.push(errors);
+            errnos_per_second.push(errnos);
+            unreliable_per_second.push(unreliable);
+            cpu_util_per_second.push(cpu_util);
+            latency_per_second.push(latency);
+            iterations += 1;
+        }
+
+        poor_mans_barrier.wait_end();
+
+        if !client_params.keep_files {
+            client
+                .rpc_remove(&filename)
+                .expect("FileRemove syscall failed");
+        }
+
+        if core == self.min_core {
+            let start = std::time::Instant::now();
+            while start.elapsed().as_secs() < 1 {}
+        }
+        Samples {
+            iops: iops_per_second,
+            errors: errors_per_second,
+            errnos: errnos_per_second,
+            unreliable: unreliable_per_second,
+            cpu_util: cpu_util_per_second,
+            latency: latency_per_second,
+            start: Some(first_second),
+            ..Default::default()
+        }
+    }
+}
+
+register_bench!("ococ", OCOC, 0);
@@ -0,0 +1,157 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! A summary of a sweep for reading at the terminal: benchmarks as rows,
+//! core counts as columns and the median throughput in the cells.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use std::collections::BTreeMap;
+
+use crate::fxmark::CoreResult;
+
+// Splits `results` into runs. The results of a run are consecutive, as
+// bench_collect() returns them, and each of its threads has its own core.
+fn runs(results: &[CoreResult]) -> Vec<&[CoreResult]> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    for (i, r) in results.iter().enumerate() {
+        let run = &results[start..i];
+        let same_run = run.first().map_or(false, |first| {
+            (
+                &first.benchmark,
+                first.cores,
+                first.write_ratio,
+                first.open_files,
+            ) == (&r.benchmark, r.cores, r.write_ratio, r.open_files)
+                && run.iter().all(|other| other.core_id != r.core_id)
+        });
+        if !same_run && i > start {
+            runs.push(run);
+            start = i;
+        }
+    }
+    if start < results.len() {
+        runs.push(&results[start..]);
+    }
+    runs
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Renders `results` as an aligned table with a row per benchmark and a
+/// column per core count. A cell holds the median over the measured seconds
+/// of the benchmark's runs at that core count of the operations per second,
+/// summed over the threads of a run. Runs with different write ratios or
+/// open files land in the same cell. Benchmarks are in the order they first
+/// appear, cells without runs are `-`.
+pub fn render_pivot(results: &[CoreResult]) -> String {
+    let mut benchmarks: Vec<&str> = Vec::new();
+    let mut per_second: BTreeMap<(&str, usize), Vec<f64>> = BTreeMap::new();
+    for run in runs(results) {
+        let first = &run[0];
+        if !benchmarks.contains(&first.benchmark.as_str()) {
+            benchmarks.push(&first.benchmark);
+        }
+        let seconds = per_second
+            .entry((first.benchmark.as_str(), first.cores))
+            .or_insert_with(Vec::new);
+        // Second 0 is warmup
+        for second in 1..(first.duration as usize + 1) {
+            let iops: usize = run.iter().filter_map(|r| r.samples.iops.get(second)).sum();
+            seconds.push(iops as f64);
+        }
+    }
+    let mut cores: Vec<usize> = per_second.keys().map(|(_, cores)| *cores).collect();
+    cores.sort_unstable();
+    cores.dedup();
+
+    let mut rows: Vec<Vec<String>> = Vec::with_capacity(benchmarks.len() + 1);
+    let mut header = vec!["benchmark".to_string()];
+    header.extend(cores.iter().map(|c| format!("{} cores", c)));
+    rows.push(header);
+    for benchmark in &benchmarks {
+        let mut row = vec![benchmark.to_string()];
+        for c in &cores {
+            row.push(match per_second.get_mut(&(*benchmark, *c)) {
+                Some(seconds) if !seconds.is_empty() => format!("{:.0}", median(seconds)),
+                _ => "-".to_string(),
+            });
+        }
+        rows.push(row);
+    }
+
+    let widths: Vec<usize> = (0..cores.len() + 1)
+        .map(|col| rows.iter().map(|row| row[col].len()).max().unwrap())
+        .collect();
+    let mut table = String::new();
+    for row in &rows {
+        let mut line = format!("{:<1$}", row[0], widths[0]);
+        for (cell, width) in row.iter().zip(&widths).skip(1) {
+            line += &format!("  {:>1$}", cell, width);
+        }
+        table += line.trim_end();
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fxmark::utils::topology::ThreadMapping;
+    use crate::fxmark::Samples;
+
+    fn result(benchmark: &str, cores: usize, core_id: usize, iops: Vec<usize>) -> CoreResult {
+        CoreResult {
+            core_id,
+            benchmark: benchmark.to_string(),
+            cores,
+            write_ratio: 0,
+            open_files: 1,
+            thread_mapping: ThreadMapping::Sequential,
+            unpinned: false,
+            duration: iops.len() as u64 - 1,
+            workload: None,
+            over_budget: false,
+            samples: Samples {
+                iops,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn cells_hold_median_throughput() {
+        let results = vec![
+            result("mix", 1, 0, vec![0, 100, 300, 200]),
+            result("mix", 2, 0, vec![0, 100, 100, 100]),
+            result("mix", 2, 1, vec![0, 150, 250, 50]),
+            result("drbr", 1, 0, vec![999, 10, 20, 30]),
+            // A repeat of the same configuration
+            result("drbr", 1, 0, vec![0, 40, 50, 60]),
+            result("drbr", 2, 0, vec![0, 5, 5, 5]),
+            result("drbr", 2, 1, vec![0, 5, 5, 1000]),
+        ];
+
+        let table = render_pivot(&results);
+        assert_eq!(
+            table,
+            "benchmark  1 cores  2 cores\n\
+             mix            200      250\n\
+             drbr            35       10\n"
+        );
+    }
+}
@@ -0,0 +1,359 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+extern crate alloc;
+
+use crate::fxmark::names::file_name;
+use crate::fxmark::{
+    align_samples, check_op, fill_file, measure_bucket, op_failed, paced, warm_up, write_synced,
+    Bench, PoorMansBarrier, Samples, SeededRng, MAX_OPEN_FILES, PAGE_SIZE,
+};
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::str::FromStr;
+use core::sync::atomic::Ordering;
+use libc::S_IRWXU;
+use serde::Serialize;
+
+use crate::fxmark::utils::{lock_buffer, unlock_buffer};
+use crate::fxrpc::grpc::*;
+
+// Files each core creates up front so unlinks have something to remove
+const UNLINK_POOL: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum RecipeOp {
+    Read,
+    Write,
+    Create,
+    Unlink,
+}
+
+impl RecipeOp {
+    fn name(&self) -> &'static str {
+        match self {
+            RecipeOp::Read => "read",
+            RecipeOp::Write => "write",
+            RecipeOp::Create => "create",
+            RecipeOp::Unlink => "unlink",
+        }
+    }
+}
+
+/// Weighted mix of operations, e.g. "read=70,write=20,create=5,unlink=5".
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Recipe {
+    weights: Vec<(RecipeOp, usize)>,
+    total: usize,
+}
+
+impl Default for Recipe {
+    fn default() -> Recipe {
+        Recipe::from_str("read=50,write=50").unwrap()
+    }
+}
+
+impl FromStr for Recipe {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = Vec::new();
+        for entry in s.split(',') {
+            let (op, weight) = entry
+                .split_once('=')
+                .ok_or(format!("Recipe entry '{}' is not op=weight", entry))?;
+            let op = match op.trim() {
+                "read" => RecipeOp::Read,
+                "write" => RecipeOp::Write,
+                "create" => RecipeOp::Create,
+                "unlink" => RecipeOp::Unlink,
+                other => return Err(format!("Unknown recipe op '{}'", other)),
+            };
+            let weight = weight
+                .trim()
+                .parse::<usize>()
+                .map_err(|e| format!("Invalid weight for '{}': {}", op.name(), e))?;
+            weights.push((op, weight));
+        }
+
+        let total = weights.iter().map(|(_, w)| w).sum();
+        if total == 0 {
+            return Err(String::from("Recipe weights must not all be zero"));
+        }
+        Ok(Recipe { weights, total })
+    }
+}
+
+impl core::fmt::Display for Recipe {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let entries: Vec<String> = self
+            .weights
+            .iter()
+            .map(|(op, w)| format!("{}={}", op.name(), w))
+            .collect();
+        write!(f, "{}", entries.join(","))
+    }
+}
+
+impl Recipe {
+    /// Maps a random number onto an op according to the weights.
+    pub fn pick(&self, random_num: usize) -> RecipeOp {
+        let mut n = random_num % self.total;
+        for (op, weight) in &self.weights {
+            if n < *weight {
+                return *op;
+            }
+            n -= weight;
+        }
+        unreachable!("Recipe weights don't cover {}", random_num)
+    }
+}
+
+#[derive(Clone)]
+pub struct RecipeBench {
+    size: i64,
+    max_open_files: usize,
+    open_files: usize,
+}
+
+impl Default for RecipeBench {
+    fn default() -> RecipeBench {
+        RecipeBench {
+            size: 256 * 1024 * 1024,
+            max_open_files: MAX_OPEN_FILES.load(Ordering::Acquire),
+            open_files: 0,
+        }
+    }
+}
+
+impl Bench for RecipeBench {
+    fn init(&mut self, _cores: Vec<u64>, open_files: usize, client_params: &ClientParams) {
+        let mut client = init_bench_client(client_params);
+
+        self.open_files = open_files;
+        for file_num in 0..open_files {
+            let filename = format!("file{}.txt", file_num);
+            let flags = client_params.open_flags | client_params.sync_policy.open_flags();
+            let fd = { client.rpc_open(&filename, flags, S_IRWXU.into()) }
+                .expect("FileOpen syscall failed");
+            assert!(fd != -1, "Unable to create a file");
+            fill_file(&mut client, client_params, fd, file_num, self.size, 1);
+            client.rpc_close(fd).expect("FileClose syscall failed");
+        }
+    }
+
+    fn run(
+        &self,
+        poor_mans_barrier: &PoorMansBarrier,
+        duration: u64,
+        core: usize,
+        _write_ratio: usize,
+        client_params: &ClientParams,
+    ) -> Samples {
+        let mut client = init_bench_client(client_params);
+        let recipe = &client_params.recipe;
+
+        let mut iops_per_second = Vec::with_capacity(duration as usize);
+        let mut errors_per_second = Vec::with_capacity(duration as usize);
+        let mut errnos_per_second = Vec::with_capacity(duration as usize);
+        let mut unreliable_per_second = Vec::with_capacity(duration as usize);
+        let mut cpu_util_per_second = Vec::with_capacity(duration as usize);
+        let mut latency_per_second = Vec::with_capacity(duration as usize);
+        let mut op_counts = vec![0usize; 4];
+
+        // Only the client that opened a descriptor may close it, so each
+        // core opens its file for itself
+        let file_num = (core % self.max_open_files) % self.open_files;
+        let fd = client
+            .rpc_open(
+                &format!("file{}.txt", file_num),
+                client_params.open_flags | client_params.sync_policy.open_flags(),
+                S_IRWXU.into(),
+            )
+            .expect("FileOpen syscall failed");
+        assert!(fd != -1, "Unable to open file{}.txt", file_num);
+        let total_pages: usize = self.size as usize / 4096;
+        let mut page: Vec<u8> = vec![0; PAGE_SIZE as usize];
+        let locked = lock_buffer(client_params.lock_buffers, &page);
+        let name = |n| {
+            file_name(
+                "recipe",
+                core,
+                n,
+                client_params.name_len,
+                client_params.name_charset,
+            )
+        };
+
+        // Files created by this core that are still around, unlinks remove the oldest
+        let mut live: Vec<String> = Vec::new();
+        let mut next_file = 0;
+        for _ in 0..UNLINK_POOL {
+            let filename = name(next_file);
+            let fd = client
+                .rpc_open(&filename, client_params.open_flags, S_IRWXU.into())
+                .expect("FileOpen syscall failed");
+            client.rpc_close(fd).expect("FileClose syscall failed");
+            live.push(filename);
+            next_file += 1;
+        }
+
+        let mut rng = SeededRng::new(client_params.seed, core);
+        let mut writes = 0;
+        // Returns the op it picked and whether it succeeded
+        let mut next_op = paced(client_params, || {
+            let op = recipe.pick(rng.next_u64() as usize);
+            let offset = (rng.next_u64() as usize % total_pages) * 4096;
+
+            let res = match op {
+                RecipeOp::Read => check_op(
+                    client.rpc_pread(fd, &mut page, PAGE_SIZE, offset as i64),
+                    PAGE_SIZE as i32,
+                ),
+                RecipeOp::Write => write_synced(
+                    &mut client,
+                    client_params,
+                    &mut writes,
+                    fd,
+                    &page,
+                    offset as i64,
+                ),
+                RecipeOp::Create => {
+                    let filename = name(next_file);
+                    next_file += 1;
+                    match client.rpc_open(&filename, client_params.open_flags, S_IRWXU.into()) {
+                        Ok(new_fd) if new_fd != -1 => {
+                            live.push(filename);
+                            check_op(client.rpc_close(new_fd), 0)
+                        }
+                        res => Err(op_errno(&res)),
+                    }
+                }
+                RecipeOp::Unlink => {
+                    if live.is_empty() {
+                        // Nothing left to unlink, create a victim first (not counted)
+                        let filename = name(next_file);
+                        next_file += 1;
+                        if let Ok(new_fd) =
+                            client.rpc_open(&filename, client_params.open_flags, S_IRWXU.into())
+                        {
+                            let _ = client.rpc_close(new_fd);
+                        }
+                        live.push(filename);
+                    }
+                    let filename = live.remove(0);
+                    check_op(client.rpc_remove(&filename), 0)
+                }
+            };
+
+            if let Err(errno) = res {
+                op_failed(
+                    client_params,
+                    errno,
+                    &format!("RECIPE: {}() failed", op.name()),
+                );
+            }
+            (op, res.is_ok())
+        });
+        warm_up(client_params.warmup_ops, || next_op().1);
+
+        // Synchronize with all cores
+        poor_mans_barrier.wait_start();
+        let first_second = align_samples(client_params.align_samples_ms);
+
+        let mut iterations = 0;
+
+        while iterations <= duration {
+            let (iops, errors, unreliable, cpu_util, errnos, latency) =
+                measure_bucket(client_params.min_samples, client_params.rerun_short, || {
+                    let mut iops = 0;
+                    let mut errors = 0;
+                    let start = std::time::Instant::now();
+                    while start.elapsed().as_secs() < 1 {
+                        let (op, ok) = next_op();
+                        if ok {
+                            iops += 1;
+                            op_counts[op as usize] += 1;
+                        } else {
+                            errors += 1;
+                        }
+                    }
+                    (iops, errors)
+                });
+
+            iops_per_second.push(iops);
+            errors_per_second.push(errors);
+            errnos_per_second.push(errnos);
+            unreliable_per_second.push(unreliable);
+            cpu_util_per_second.push(cpu_util);
+            latency_per_second.push(latency);
+            iterations += 1;
+        }
+
+        poor_mans_barrier.wait_end();
+
+        if !client_params.keep_files {
+            for filename in &live {
+                client
+                    .rpc_remove(filename)
+                    .expect("FileRemove syscall failed");
+            }
+        }
+
+        client.rpc_close(fd).expect("FileClose syscall failed");
+
+        unlock_buffer(locked, &page);
+        Samples {
+            iops: iops_per_second,
+            errors: errors_per_second,
+            errnos: errnos_per_second,
+            unreliable: unreliable_per_second,
+            cpu_util: cpu_util_per_second,
+            latency: latency_per_second,
+            start: Some(first_second),
+            op_counts: [
+                RecipeOp::Read,
+                RecipeOp::Write,
+                RecipeOp::Create,
+                RecipeOp::Unlink,
+            ]
+            .iter()
+            .map(|op| (op.name(), op_counts[*op as usize]))
+            .collect(),
+        }
+    }
+}
+
+register_bench!("recipe", RecipeBench, PAGE_SIZE);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ops_follow_the_recipe() {
+        let recipe = Recipe::from_str("read=70,write=20,create=5,unlink=5").unwrap();
+        // Drawn as the bench draws them
+        let mut rng = SeededRng::new(42, 0);
+        let picks: Vec<RecipeOp> = (0..100_000)
+            .map(|_| recipe.pick(rng.next_u64() as usize))
+            .collect();
+        for (op, expected) in [
+            (RecipeOp::Read, 70),
+            (RecipeOp::Write, 20),
+            (RecipeOp::Create, 5),
+            (RecipeOp::Unlink, 5),
+        ] {
+            let n = picks.iter().filter(|pick| **pick == op).count();
+            let percent = n as f64 * 100.0 / picks.len() as f64;
+            assert!(
+                (percent - expected as f64).abs() < 1.0,
+                "{}: {:.1}% of ops, recipe says {}%",
+                op.name(),
+                percent,
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,206 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+extern crate alloc;
+
+use crate::fxmark::{
+    align_samples, check_op, fill_file, measure_bucket, op_failed, paced, warm_up, Bench,
+    PoorMansBarrier, Samples, SeededRng, PAGE_SIZE,
+};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use libc::S_IRWXU;
+
+use crate::fxmark::utils::{lock_buffer, unlock_buffer};
+use crate::fxrpc::grpc::*;
+
+/// Reads the page of `fd` at `offset` into `page`, increments its first
+/// byte and writes it back in place. Fails like `check_op()` if either the
+/// read or the write did, and doesn't write after a failed read.
+pub(crate) fn read_modify_write(
+    client: &mut Box<dyn FxRPC>,
+    fd: i32,
+    page: &mut Vec<u8>,
+    offset: i64,
+) -> Result<(), Option<i32>> {
+    check_op(
+        client.rpc_pread(fd, page, PAGE_SIZE, offset),
+        PAGE_SIZE as i32,
+    )?;
+    page[0] = page[0].wrapping_add(1);
+    check_op(
+        client.rpc_pwrite(fd, page, PAGE_SIZE, offset),
+        PAGE_SIZE as i32,
+    )
+}
+
+/// Read-modify-write cycles on random pages of a per-core file of
+/// `ClientParams::file_size` bytes, as databases update records. Every op
+/// reads a page, changes a byte and writes the page back at the same
+/// offset, so the write waits for the read it depends on.
+#[derive(Clone)]
+pub struct RMW {
+    min_core: usize,
+    pages: usize,
+}
+
+impl Default for RMW {
+    fn default() -> RMW {
+        RMW {
+            min_core: 0,
+            pages: 0,
+        }
+    }
+}
+
+impl Bench for RMW {
+    fn init(&mut self, cores: Vec<u64>, _open_files: usize, client_params: &ClientParams) {
+        self.min_core = *cores.iter().min().unwrap() as usize;
+        self.pages = client_params.file_size / PAGE_SIZE;
+    }
+
+    fn run(
+        &self,
+        poor_mans_barrier: &PoorMansBarrier,
+        duration: u64,
+        core: usize,
+        _write_ratio: usize,
+        client_params: &ClientParams,
+    ) -> Samples {
+        let mut client = init_bench_client(client_params);
+
+        let mut iops_per_second = Vec::with_capacity(duration as usize);
+        let mut errors_per_second = Vec::with_capacity(duration as usize);
+        let mut errnos_per_second = Vec::with_capacity(duration as usize);
+        let mut unreliable_per_second = Vec::with_capacity(duration as usize);
+        let mut cpu_util_per_second = Vec::with_capacity(duration as usize);
+        let mut latency_per_second = Vec::with_capacity(duration as usize);
+
+        // Every clone of the bench gets init() for all cores, so each core
+        // writes its own file here, before the timed phase starts
+        let filename = format!("rmw{}.txt", core);
+        let fd = client
+            .rpc_open(&filename, client_params.open_flags, S_IRWXU.into())
+            .expect("FileOpen syscall failed");
+        assert!(fd != -1, "Unable to create a file");
+        fill_file(&mut client, client_params, fd, core, 0, self.pages);
+
+        let mut page: Vec<u8> = vec![0; PAGE_SIZE as usize];
+        let locked = lock_buffer(client_params.lock_buffers, &page);
+        let mut rng = SeededRng::new(client_params.seed, core);
+        let mut next_op = paced(client_params, || {
+            let offset = ((rng.next_u64() as usize % self.pages) * PAGE_SIZE) as i64;
+            let res = read_modify_write(&mut client, fd, &mut page, offset);
+            if let Err(errno) = res {
+                op_failed(client_params, errno, "RMW: read_at() or write_at() failed");
+            }
+            res.is_ok()
+        });
+        warm_up(client_params.warmup_ops, &mut next_op);
+
+        // Synchronize with all cores
+        poor_mans_barrier.wait_start();
+        let first_second = align_samples(client_params.align_samples_ms);
+
+        let mut iterations = 0;
+        while iterations <= duration {
+            let (iops, errors, unreliable, cpu_util, errnos, latency) =
+                measure_bucket(client_params.min_samples, client_params.rerun_short, || {
+                    let mut iops = 0;
+                    let mut errors = 0;
+                    let start = std::time::Instant::now();
+                    while start.elapsed().as_secs() < 1 {
+                        if next_op() {
+                            iops += 1;
+                        } else {
+                            errors += 1;
+                        }
+                    }
+                    (iops, errors)
+                });
+
+            iops_per_second.push(iops);
+            errors_per_second.push(errors);
+            errnos_per_second.push(errnos);
+            unreliable_per_second.push(unreliable);
+            cpu_util_per_second.push(cpu_util);
+            latency_per_second.push(latency);
+            iterations += 1;
+        }
+
+        poor_mans_barrier.wait_end();
+
+        client.rpc_close(fd).expect("FileClose syscall failed");
+        if !client_params.keep_files {
+            client
+                .rpc_remove(&filename)
+                .expect("FileRemove syscall failed");
+        }
+
+        if core == self.min_core {
+            let start = std::time::Instant::now();
+            while start.elapsed().as_secs() < 1 {}
+        }
+        unlock_buffer(locked, &page);
+        Samples {
+            iops: iops_per_second,
+            errors: errors_per_second,
+            errnos: errnos_per_second,
+            unreliable: unreliable_per_second,
+            cpu_util: cpu_util_per_second,
+            latency: latency_per_second,
+            start: Some(first_second),
+            ..Default::default()
+        }
+    }
+}
+
+register_bench!("rmw", RMW, PAGE_SIZE);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fxmark::tests::test_client_params;
+    use libc::{O_CREAT, O_RDWR};
+
+    #[test]
+    fn updates_persist() {
+        let client_params = test_client_params("/dev/shm/fxmark_rmw_test/fx.sock");
+        let mut client = init_bench_client(&client_params);
+        let fd = client
+            .rpc_open("rmw_test.txt", O_CREAT | O_RDWR, S_IRWXU.into())
+            .unwrap();
+        assert!(fd != -1, "RMWTest: Open Failed");
+        fill_file(&mut client, &client_params, fd, 0, 0, 2);
+
+        let mut first = vec![0; PAGE_SIZE];
+        assert_eq!(
+            client
+                .rpc_pread(fd, &mut first, PAGE_SIZE, PAGE_SIZE as i64)
+                .unwrap(),
+            PAGE_SIZE as i32
+        );
+
+        // Every cycle reads what the one before it wrote
+        let cycles = 300;
+        let mut page = vec![0; PAGE_SIZE];
+        for _ in 0..cycles {
+            assert!(read_modify_write(&mut client, fd, &mut page, PAGE_SIZE as i64).is_ok());
+        }
+
+        let mut last = vec![0; PAGE_SIZE];
+        assert_eq!(
+            client
+                .rpc_pread(fd, &mut last, PAGE_SIZE, PAGE_SIZE as i64)
+                .unwrap(),
+            PAGE_SIZE as i32
+        );
+        assert_eq!(last, page);
+        assert_eq!(last[0], first[0].wrapping_add((cycles % 256) as u8));
+        assert_eq!(last[1..], first[1..]);
+
+        assert_eq!(client.rpc_close(fd).unwrap(), 0);
+        assert_eq!(client.rpc_remove("rmw_test.txt").unwrap(), 0);
+    }
+}
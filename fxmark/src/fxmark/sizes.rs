@@ -0,0 +1,216 @@
+// Copyright © 2021 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! How many bytes each op of a bench transfers. Real workloads mix small and
+//! large requests, which exercises the server's allocations and the
+//! variable-length fields of the wire format more than a fixed size does.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use core::str::FromStr;
+use serde::Serialize;
+
+use crate::fxmark::SeededRng;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum SizeDistribution {
+    /// Every op transfers the same number of bytes.
+    Fixed(usize),
+    /// Any size from the first to the second, both included, alike.
+    Uniform(usize, usize),
+    /// Multiples of `min` up to `max`, the k-th of them drawn with a weight
+    /// of 1/k^`exponent`: mostly small transfers, with a long tail of large
+    /// ones.
+    Zipf {
+        min: usize,
+        max: usize,
+        exponent: f64,
+    },
+}
+
+impl FromStr for SizeDistribution {
+    type Err = String;
+
+    /// `fixed=N`, `uniform=MIN-MAX` or `zipf=MIN-MAX[,EXPONENT]`, the exponent
+    /// defaulting to 1, with sizes in bytes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || {
+            format!(
+                "Unknown size distribution '{}', expected fixed=N, uniform=MIN-MAX or zipf=MIN-MAX[,EXPONENT]",
+                s
+            )
+        };
+        let (kind, args) = s.split_once('=').ok_or_else(unknown)?;
+        let size = |n: &str| {
+            n.parse::<usize>()
+                .map_err(|_| format!("Invalid size '{}' in '{}'", n, s))
+        };
+        let range = |r: &str| {
+            let (min, max) = r
+                .split_once('-')
+                .ok_or(format!("Expected a range MIN-MAX in '{}'", s))?;
+            let (min, max) = (size(min)?, size(max)?);
+            if min > max {
+                return Err(format!("Empty size range '{}' in '{}'", r, s));
+            }
+            Ok((min, max))
+        };
+        match kind {
+            "fixed" => Ok(SizeDistribution::Fixed(size(args)?)),
+            "uniform" => {
+                let (min, max) = range(args)?;
+                Ok(SizeDistribution::Uniform(min, max))
+            }
+            "zipf" => {
+                let (r, exponent) = match args.split_once(',') {
+                    Some((r, exponent)) => (
+                        r,
+                        exponent
+                            .parse::<f64>()
+                            .map_err(|_| format!("Invalid exponent '{}' in '{}'", exponent, s))?,
+                    ),
+                    None => (args, 1.0),
+                };
+                let (min, max) = range(r)?;
+                if min == 0 {
+                    return Err(format!(
+                        "Zipf sizes are multiples of MIN, above 0 in '{}'",
+                        s
+                    ));
+                }
+                if exponent.is_nan() || exponent <= 0.0 {
+                    return Err(format!("Zipf exponent must be above 0 in '{}'", s));
+                }
+                Ok(SizeDistribution::Zipf { min, max, exponent })
+            }
+            _ => Err(unknown()),
+        }
+    }
+}
+
+impl SizeDistribution {
+    /// The smallest size it draws.
+    pub fn min(&self) -> usize {
+        match self {
+            SizeDistribution::Fixed(size) => *size,
+            SizeDistribution::Uniform(min, _) => *min,
+            SizeDistribution::Zipf { min, .. } => *min,
+        }
+    }
+
+    /// The largest size it draws, what buffers must hold.
+    pub fn max(&self) -> usize {
+        match self {
+            SizeDistribution::Fixed(size) => *size,
+            SizeDistribution::Uniform(_, max) => *max,
+            // The largest multiple of `min` up to `max`
+            SizeDistribution::Zipf { min, max, .. } => max / min * min,
+        }
+    }
+
+    /// The size of the next op. A fixed size doesn't draw from `rng`, so
+    /// benches that also draw their offsets from it draw the same ones as
+    /// without a distribution.
+    pub fn draw(&self, rng: &mut SeededRng) -> usize {
+        match *self {
+            SizeDistribution::Fixed(size) => size,
+            SizeDistribution::Uniform(min, max) => {
+                min + (rng.next_u64() % (max - min + 1) as u64) as usize
+            }
+            SizeDistribution::Zipf { min, max, exponent } => {
+                // Inverts the CDF of the continuous power law over [1, n + 1),
+                // which is close enough to the discrete one and needs no table
+                let n = (max / min) as f64;
+                let u = (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+                let x = if (exponent - 1.0).abs() < 1e-9 {
+                    (n + 1.0).powf(u)
+                } else {
+                    let e = 1.0 - exponent;
+                    (u * ((n + 1.0).powf(e) - 1.0) + 1.0).powf(1.0 / e)
+                };
+                let k = (x.floor() as usize).clamp(1, max / min);
+                k * min
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn uniform_sizes_vary_within_range() {
+        let sizes: SizeDistribution = "uniform=512-4096".parse().unwrap();
+        assert_eq!(sizes, SizeDistribution::Uniform(512, 4096));
+        assert_eq!((sizes.min(), sizes.max()), (512, 4096));
+
+        let mut rng = SeededRng::new(7, 0);
+        let drawn: Vec<usize> = (0..1000).map(|_| sizes.draw(&mut rng)).collect();
+        assert!(drawn.iter().all(|s| (512..=4096).contains(s)));
+        let distinct: HashSet<&usize> = drawn.iter().collect();
+        assert!(
+            distinct.len() > 500,
+            "only {} distinct sizes",
+            distinct.len()
+        );
+        // Both halves of the range come up
+        assert!(drawn.iter().any(|s| *s < 1024) && drawn.iter().any(|s| *s > 3584));
+    }
+
+    #[test]
+    fn zipf_favors_small_sizes() {
+        let sizes: SizeDistribution = "zipf=512-65536".parse().unwrap();
+        assert_eq!(sizes.max(), 65536);
+        let mut rng = SeededRng::new(7, 0);
+        let drawn: Vec<usize> = (0..10000).map(|_| sizes.draw(&mut rng)).collect();
+        assert!(drawn
+            .iter()
+            .all(|s| s % 512 == 0 && (512..=65536).contains(s)));
+        let smallest = drawn.iter().filter(|s| **s == 512).count();
+        let second = drawn.iter().filter(|s| **s == 1024).count();
+        assert!(smallest > second && second > 0, "{} {}", smallest, second);
+        assert!(drawn.iter().any(|s| *s > 32768));
+
+        // A steeper exponent draws the smallest size more often
+        let steep: SizeDistribution = "zipf=512-65536,2".parse().unwrap();
+        let steep_smallest = (0..10000).filter(|_| steep.draw(&mut rng) == 512).count();
+        assert!(steep_smallest > smallest);
+    }
+
+    #[test]
+    fn fixed_sizes_leave_the_rng_alone() {
+        let sizes: SizeDistribution = "fixed=1024".parse().unwrap();
+        let (mut rng, mut untouched) = (SeededRng::new(1, 2), SeededRng::new(1, 2));
+        assert_eq!(sizes.draw(&mut rng), 1024);
+        assert_eq!(rng.next_u64(), untouched.next_u64());
+    }
+
+    #[test]
+    fn invalid_distributions() {
+        for s in [
+            "uniform",
+            "uniform=4096-512",
+            "uniform=512",
+            "zipf=0-512",
+            "zipf=512-4096,0",
+            "zipf=512-4096,NaN",
+            "zipf=512-4096,x",
+            "normal=1-2",
+            "fixed=-1",
+        ] {
+            assert!(s.parse::<SizeDistribution>().is_err(), "{}", s);
+        }
+        assert_eq!(
+            "zipf=512-4096,1.5".parse(),
+            Ok(SizeDistribution::Zipf {
+                min: 512,
+                max: 4096,
+                exponent: 1.5
+            })
+        );
+    }
+}
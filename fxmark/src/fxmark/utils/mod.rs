@@ -12,17 +12,94 @@ pub mod topology;
 /// On MacOS this is not guaranteed.
 pub type ThreadId = u64;
 
-// Pin a thread to a core
+/// Pins the calling thread to `core_id`. Fails if the thread may not run
+/// there, e.g. because the core is outside the cpuset of its cgroup.
 #[cfg(target_os = "linux")]
-pub fn pin_thread(core_id: topology::Cpu) {
-    core_affinity::set_for_current(core_affinity::CoreId {
-        id: core_id as usize,
-    });
+pub fn pin_thread(core_id: topology::Cpu) -> Result<(), String> {
+    // sched_setaffinity() itself, core_affinity doesn't tell if it failed
+    let core = core_id as usize;
+    if core >= libc::CPU_SETSIZE as usize {
+        return Err(format!(
+            "Can't pin thread to core {}: no such core",
+            core_id
+        ));
+    }
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    unsafe { libc::CPU_SET(core, &mut set) };
+    let res = unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) };
+    if res != 0 {
+        return Err(format!(
+            "Can't pin thread to core {}: {}",
+            core_id,
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
 }
 
 #[cfg(not(target_os = "linux"))]
-pub fn pin_thread(_core_id: topology::Cpu) {
-    log::warn!("Can't pin threads explicitly for benchmarking.");
+pub fn pin_thread(_core_id: topology::Cpu) -> Result<(), String> {
+    Err("Can't pin threads explicitly for benchmarking on this OS".to_string())
+}
+
+/// The cores the calling thread may run on, in order.
+#[cfg(target_os = "linux")]
+pub fn allowed_cpus() -> Vec<topology::Cpu> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    let res = unsafe { libc::sched_getaffinity(0, std::mem::size_of_val(&set), &mut set) };
+    if res != 0 {
+        return Vec::new();
+    }
+    (0..libc::CPU_SETSIZE as usize)
+        .filter(|cpu| unsafe { libc::CPU_ISSET(*cpu, &set) })
+        .map(|cpu| cpu as topology::Cpu)
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn allowed_cpus() -> Vec<topology::Cpu> {
+    Vec::new()
+}
+
+// From linux/mempolicy.h
+#[cfg(target_os = "linux")]
+const MPOL_BIND: libc::c_int = 2;
+
+/// Restricts the calling thread's future allocations to the NUMA node of
+/// `core_id`, so bench buffers don't end up on a remote node. Call after
+/// `pin_thread`.
+#[cfg(target_os = "linux")]
+pub fn bind_memory_to_local_node(core_id: topology::Cpu) {
+    let node = match topology::MachineTopology::new().node_of(core_id) {
+        Some(node) => node,
+        None => {
+            log::warn!("No NUMA node for core {}, can't bind memory.", core_id);
+            return;
+        }
+    };
+
+    let nodemask: libc::c_ulong = 1 << node;
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_set_mempolicy,
+            MPOL_BIND,
+            &nodemask as *const libc::c_ulong,
+            // Number of bits in nodemask, the kernel ignores the last one
+            (std::mem::size_of::<libc::c_ulong>() * 8 + 1) as libc::c_ulong,
+        )
+    };
+    if res != 0 {
+        log::warn!(
+            "set_mempolicy for node {} failed: {}",
+            node,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn bind_memory_to_local_node(_core_id: topology::Cpu) {
+    log::warn!("Can't bind memory to NUMA nodes, bench buffers may be remote.");
 }
 
 #[cfg(target_os = "linux")]
@@ -43,3 +120,275 @@ pub fn disable_dvfs() {
 pub fn disable_dvfs() {
     log::warn!("Can't disable DVFS, expect non-optimal test results!");
 }
+
+/// Whether the CPUs may clock above their base frequency, from the switch of
+/// intel_pstate or else of cpufreq. None if neither is there, e.g. in a VM.
+#[cfg(target_os = "linux")]
+pub fn turbo_enabled() -> Option<bool> {
+    let read = |path: &str| {
+        std::fs::read_to_string(path)
+            .ok()
+            .map(|s| s.trim().to_string())
+    };
+    if let Some(no_turbo) = read("/sys/devices/system/cpu/intel_pstate/no_turbo") {
+        return Some(no_turbo == "0");
+    }
+    read("/sys/devices/system/cpu/cpufreq/boost").map(|boost| boost == "1")
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn turbo_enabled() -> Option<bool> {
+    None
+}
+
+/// Drops the page cache, dentries and inodes so the next run starts cold.
+/// Needs root, otherwise warns and returns false.
+#[cfg(target_os = "linux")]
+pub fn drop_caches() -> bool {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    // Flush dirty pages first, drop_caches only discards clean ones
+    unsafe { libc::sync() };
+    let res = OpenOptions::new()
+        .write(true)
+        .open("/proc/sys/vm/drop_caches")
+        .and_then(|mut f| f.write_all(b"3"));
+    if let Err(e) = res {
+        eprintln!("Can't drop caches ({}), run as root for cold-cache runs", e);
+        return false;
+    }
+    true
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn drop_caches() -> bool {
+    eprintln!("Can't drop caches on this OS, runs will share a warm page cache");
+    false
+}
+
+/// Locks `buf` into RAM so the bench loop takes no page faults on it. Skipped
+/// when `lock_buffers` is off. If the kernel refuses (e.g. `RLIMIT_MEMLOCK` is
+/// too low) warns and returns false, the run goes on with the buffer unlocked.
+pub fn lock_buffer(lock_buffers: bool, buf: &[u8]) -> bool {
+    if !lock_buffers || buf.is_empty() {
+        return false;
+    }
+    let res = unsafe { libc::mlock(buf.as_ptr() as *const libc::c_void, buf.len()) };
+    if res != 0 {
+        eprintln!(
+            "Can't lock bench buffer ({}), raise RLIMIT_MEMLOCK (ulimit -l) to lock buffers",
+            std::io::Error::last_os_error()
+        );
+        return false;
+    }
+    true
+}
+
+/// Unlocks a buffer if `lock_buffer` locked it.
+pub fn unlock_buffer(locked: bool, buf: &[u8]) {
+    if locked {
+        unsafe { libc::munlock(buf.as_ptr() as *const libc::c_void, buf.len()) };
+    }
+}
+
+/// Number of descriptors this process has open, from /proc/self/fd. None on
+/// systems without it.
+pub fn open_fd_count() -> Option<usize> {
+    // The directory handle read_dir holds is one of the entries
+    std::fs::read_dir("/proc/self/fd")
+        .ok()
+        .map(|entries| entries.count().saturating_sub(1))
+}
+
+/// Busy and total time of `cpu` since boot in jiffies, from /proc/stat.
+#[cfg(target_os = "linux")]
+fn cpu_ticks(cpu: usize) -> Option<(u64, u64)> {
+    let stat = std::fs::read_to_string("/proc/stat").ok()?;
+    let prefix = format!("cpu{} ", cpu);
+    let line = stat.lines().find(|l| l.starts_with(&prefix))?;
+    let ticks: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|t| t.parse().ok())
+        .collect();
+    // user nice system idle iowait irq softirq steal, guest time is part of user
+    let total: u64 = ticks.iter().take(8).sum();
+    let idle = ticks.get(3)? + ticks.get(4).unwrap_or(&0);
+    Some((total - idle, total))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn cpu_ticks(_cpu: usize) -> Option<(u64, u64)> {
+    None
+}
+
+/// Measures how busy the core the calling thread runs on is. Only meaningful
+/// for pinned threads.
+pub struct CpuSampler {
+    cpu: usize,
+    start: Option<(u64, u64)>,
+}
+
+impl CpuSampler {
+    pub fn start() -> CpuSampler {
+        #[cfg(target_os = "linux")]
+        let cpu = unsafe { libc::sched_getcpu() }.max(0) as usize;
+        #[cfg(not(target_os = "linux"))]
+        let cpu = 0;
+
+        CpuSampler {
+            cpu,
+            start: cpu_ticks(cpu),
+        }
+    }
+
+    /// Busy time of the core since `start()`, in percent. 0 if it can't be
+    /// measured on this OS.
+    pub fn utilization(&self) -> f64 {
+        match (self.start, cpu_ticks(self.cpu)) {
+            (Some((busy0, total0)), Some((busy1, total1))) if total1 > total0 => {
+                (busy1 - busy0) as f64 * 100.0 / (total1 - total0) as f64
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::{
+        bind_memory_to_local_node, drop_caches, lock_buffer, pin_thread, topology, unlock_buffer,
+        CpuSampler,
+    };
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::unix::fs::FileExt;
+    use std::time::Instant;
+
+    #[test]
+    fn reads_start_cold_after_drop() {
+        if unsafe { libc::geteuid() } != 0 {
+            eprintln!("Skipping reads_start_cold_after_drop, needs root");
+            return;
+        }
+
+        // /dev/shm is tmpfs, use a disk-backed file
+        let path = "/var/tmp/fxmark_drop_caches_test";
+        let mut file = File::create(path).unwrap();
+        let page = vec![0xb; 4096];
+        for _ in 0..(64 * 1024 / 4) {
+            file.write_all(&page).unwrap();
+        }
+        drop(file);
+
+        // Reads during the first second of two back-to-back runs
+        let mut first_second = Vec::new();
+        for _ in 0..2 {
+            assert!(drop_caches());
+            let file = File::open(path).unwrap();
+            let mut buf = vec![0; 4096];
+            let mut ops = 0u64;
+            let start = Instant::now();
+            while start.elapsed().as_secs() < 1 {
+                let offset = (ops % (64 * 1024 / 4)) * 4096;
+                file.read_at(&mut buf, offset).unwrap();
+                ops += 1;
+            }
+            first_second.push(ops as f64);
+        }
+        std::fs::remove_file(path).unwrap();
+
+        // Without the drop the second run would start warm and be much faster
+        let ratio = first_second[1] / first_second[0];
+        assert!(
+            ratio > 0.5 && ratio < 2.0,
+            "First-second reads differ too much: {:?}",
+            first_second
+        );
+    }
+
+    // Locked memory of this process in kB
+    fn locked_kb() -> u64 {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let line = status.lines().find(|l| l.starts_with("VmLck:")).unwrap();
+        line.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn buffers_are_locked_on_request() {
+        let page = vec![0xbu8; 16 * 4096];
+        let before = locked_kb();
+
+        assert!(!lock_buffer(false, &page));
+        assert_eq!(locked_kb(), before);
+
+        // Best effort, RLIMIT_MEMLOCK may not allow it here
+        if !lock_buffer(true, &page) {
+            eprintln!("Skipping buffers_are_locked_on_request, mlock refused");
+            return;
+        }
+        assert!(locked_kb() >= before + 64);
+        unlock_buffer(true, &page);
+        assert_eq!(locked_kb(), before);
+    }
+
+    #[test]
+    fn buffers_land_on_local_node() {
+        let topology = topology::MachineTopology::new();
+        let cpu = topology.allocate(topology::ThreadMapping::Sequential, 1, false)[0].cpu;
+        let node = match topology.node_of(cpu) {
+            Some(node) => node,
+            None => return,
+        };
+
+        // Fresh thread so the policy doesn't leak into other tests
+        let found = std::thread::spawn(move || {
+            pin_thread(cpu).unwrap();
+            bind_memory_to_local_node(cpu);
+            let page = vec![0xbu8; 4096];
+
+            // get_mempolicy(MPOL_F_NODE | MPOL_F_ADDR) reports the node backing an address
+            let mut found: libc::c_int = -1;
+            let res = unsafe {
+                libc::syscall(
+                    libc::SYS_get_mempolicy,
+                    &mut found as *mut libc::c_int,
+                    std::ptr::null_mut::<libc::c_ulong>(),
+                    0 as libc::c_ulong,
+                    page.as_ptr(),
+                    (1 | 2) as libc::c_ulong,
+                )
+            };
+            assert_eq!(res, 0, "{}", std::io::Error::last_os_error());
+            found
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(found as u64, node);
+    }
+
+    #[test]
+    fn spinning_core_is_busy() {
+        let cpu = unsafe { libc::sched_getcpu() } as topology::Cpu;
+        pin_thread(cpu).unwrap();
+
+        let sampler = CpuSampler::start();
+        let start = Instant::now();
+        while start.elapsed().as_secs() < 1 {
+            core::hint::spin_loop();
+        }
+        let util = sampler.utilization();
+        assert!(util > 90.0 && util <= 100.0, "cpu_util {}", util);
+    }
+
+    #[test]
+    fn failed_pinning_is_reported() {
+        // Not in any cpuset of a machine with fewer cores, the thread stays put
+        let err = pin_thread(libc::CPU_SETSIZE as topology::Cpu - 1).unwrap_err();
+        assert!(err.starts_with("Can't pin thread to core 1023:"), "{}", err);
+        let err = pin_thread(libc::CPU_SETSIZE as topology::Cpu).unwrap_err();
+        assert!(err.contains("no such core"), "{}", err);
+    }
+}
@@ -4,10 +4,49 @@
 //! Allows to query information about the CPU topology.
 
 use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use hwloc2::*;
+use lazy_static::lazy_static;
 use serde::Serialize;
 
+/// How often the hwloc topology was discovered, should stay at one.
+static DISCOVERIES: AtomicUsize = AtomicUsize::new(0);
+
+/// Environment variable with the CPUs benchmarks may use, e.g. `0-3,8`, for
+/// containers that see more CPUs than they are allotted.
+pub const CORES_ENV: &str = "FXMARK_CORES";
+
+lazy_static! {
+    static ref TOPOLOGY: MachineTopology = MachineTopology::discover().restricted_by_env();
+}
+
+/// Parses a CPU list like `0-3,8` into the CPUs it names, in order.
+pub fn parse_cpu_list(list: &str) -> Result<Vec<Cpu>, String> {
+    let parse = |cpu: &str| {
+        cpu.trim()
+            .parse::<Cpu>()
+            .map_err(|_| format!("Invalid CPU '{}' in CPU list '{}'", cpu, list))
+    };
+    let mut cpus = Vec::new();
+    for range in list.split(',') {
+        match range.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse(first)?, parse(last)?);
+                if first > last {
+                    return Err(format!(
+                        "Empty CPU range '{}' in CPU list '{}'",
+                        range, list
+                    ));
+                }
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(parse(range)?),
+        }
+    }
+    Ok(cpus)
+}
+
 pub type Node = u64;
 pub type Socket = u64;
 pub type Core = u64;
@@ -27,6 +66,26 @@ pub enum ThreadMapping {
     /// Spread thread allocation out across sockets (as much as possible).
     #[allow(unused)]
     Interleave,
+    /// Fill all cores of a socket before moving on to the next one.
+    Compact,
+    /// Alternate sockets with every thread.
+    Scatter,
+}
+
+impl std::str::FromStr for ThreadMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sequential" => Ok(ThreadMapping::Sequential),
+            "compact" => Ok(ThreadMapping::Compact),
+            "scatter" => Ok(ThreadMapping::Scatter),
+            _ => Err(format!(
+                "Unknown thread mapping '{}', expected sequential, compact or scatter",
+                s
+            )),
+        }
+    }
 }
 
 impl fmt::Display for ThreadMapping {
@@ -35,6 +94,8 @@ impl fmt::Display for ThreadMapping {
             ThreadMapping::None => write!(f, "None"),
             ThreadMapping::Sequential => write!(f, "Sequential"),
             ThreadMapping::Interleave => write!(f, "Interleave"),
+            ThreadMapping::Compact => write!(f, "Compact"),
+            ThreadMapping::Scatter => write!(f, "Scatter"),
         }
     }
 }
@@ -45,6 +106,8 @@ impl fmt::Debug for ThreadMapping {
             ThreadMapping::None => write!(f, "TM=None"),
             ThreadMapping::Sequential => write!(f, "TM=Sequential"),
             ThreadMapping::Interleave => write!(f, "TM=Interleave"),
+            ThreadMapping::Compact => write!(f, "TM=Compact"),
+            ThreadMapping::Scatter => write!(f, "TM=Scatter"),
         }
     }
 }
@@ -80,13 +143,20 @@ impl std::fmt::Debug for CpuInfo {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MachineTopology {
     data: Vec<CpuInfo>,
 }
 
 impl MachineTopology {
+    /// Returns the machine's topology. It is discovered on first use and
+    /// cached for the rest of the process.
     pub fn new() -> MachineTopology {
+        TOPOLOGY.clone()
+    }
+
+    fn discover() -> MachineTopology {
+        DISCOVERIES.fetch_add(1, Ordering::Relaxed);
         let mut data: Vec<CpuInfo> = Default::default();
 
         let topo = Topology::new().expect("Can't retrieve Topology");
@@ -155,6 +225,34 @@ impl MachineTopology {
         MachineTopology { data }
     }
 
+    // Only the CPUs `CORES_ENV` lists, if it's set
+    fn restricted_by_env(self) -> MachineTopology {
+        let list = match std::env::var(CORES_ENV) {
+            Ok(list) => list,
+            Err(_) => return self,
+        };
+        parse_cpu_list(&list)
+            .and_then(|cpus| self.restricted_to(&cpus))
+            .unwrap_or_else(|e| panic!("{}: {}", CORES_ENV, e))
+    }
+
+    /// Only the CPUs of `cpus`. Fails if one of them isn't on the machine.
+    pub fn restricted_to(&self, cpus: &[Cpu]) -> Result<MachineTopology, String> {
+        if let Some(cpu) = cpus
+            .iter()
+            .find(|cpu| !self.data.iter().any(|t| t.cpu == **cpu))
+        {
+            return Err(format!("CPU {} isn't on this machine", cpu));
+        }
+        let data = self
+            .data
+            .iter()
+            .filter(|t| cpus.contains(&t.cpu))
+            .copied()
+            .collect();
+        Ok(MachineTopology { data })
+    }
+
     /// Return how many processing units that the system has
     pub fn cores(&self) -> usize {
         self.data.len()
@@ -167,10 +265,80 @@ impl MachineTopology {
         sockets
     }
 
+    /// NUMA node of a CPU, if the machine reports one.
+    pub fn node_of(&self, cpu: Cpu) -> Option<Node> {
+        self.data
+            .iter()
+            .find(|t| t.cpu == cpu)
+            .and_then(|t| t.node)
+            .map(|n| n.node)
+    }
+
     pub fn cpus_on_socket(&self, socket: Socket) -> Vec<&CpuInfo> {
         self.data.iter().filter(|t| t.socket == socket).collect()
     }
 
+    // Whether `cpu` is a second hardware thread of its core
+    fn is_hyperthread(&self, cpu: &CpuInfo) -> bool {
+        self.data
+            .iter()
+            .any(|t| t.core == cpu.core && t.cpu < cpu.cpu)
+    }
+
+    /// Pairs of a CPU of `cpus` and another hardware thread of its core,
+    /// ordered as the machine lists its CPUs. A pair of two CPUs of `cpus`
+    /// is listed once.
+    pub fn smt_siblings(&self, cpus: &[Cpu]) -> Vec<(Cpu, Cpu)> {
+        let mut pairs = Vec::new();
+        for cpu in self.data.iter().filter(|t| cpus.contains(&t.cpu)) {
+            for sibling in self
+                .data
+                .iter()
+                .filter(|t| t.core == cpu.core && t.cpu != cpu.cpu)
+            {
+                if !pairs.contains(&(sibling.cpu, cpu.cpu)) {
+                    pairs.push((cpu.cpu, sibling.cpu));
+                }
+            }
+        }
+        pairs
+    }
+
+    /// Warnings to print before running bench threads on `cpus`, about what
+    /// makes their results noisy: cores they share with other hardware
+    /// threads and, if `turbo` says so, turbo boost, which clocks a core
+    /// higher the fewer cores are busy.
+    pub fn preflight_warnings(&self, cpus: &[Cpu], turbo: Option<bool>) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let pairs = self.smt_siblings(cpus);
+        let (shared, idle): (Vec<_>, Vec<_>) = pairs
+            .iter()
+            .partition(|(_, sibling)| cpus.contains(sibling));
+        let list = |pairs: &[&(Cpu, Cpu)]| {
+            let pairs: Vec<String> = pairs.iter().map(|(a, b)| format!("{}/{}", a, b)).collect();
+            pairs.join(", ")
+        };
+        if !shared.is_empty() {
+            warnings.push(format!(
+                "bench threads share cores as SMT siblings (CPUs {}), they compete for the core's execution units",
+                list(&shared)
+            ));
+        }
+        if !idle.is_empty() {
+            warnings.push(format!(
+                "SMT is on, the siblings of bench CPUs may run the server or other work on the same cores (CPUs {})",
+                list(&idle)
+            ));
+        }
+        if turbo == Some(true) {
+            warnings.push(
+                "turbo boost is on, throughput depends on how many cores are busy and how hot they run"
+                    .to_string(),
+            );
+        }
+        warnings
+    }
+
     pub fn allocate(&self, strategy: ThreadMapping, how_many: usize, use_ht: bool) -> Vec<CpuInfo> {
         let v = Vec::with_capacity(how_many);
         let mut cpus = self.data.clone();
@@ -215,6 +383,160 @@ impl MachineTopology {
                 let c = cpus.iter().take(how_many).map(|c| *c).collect();
                 c
             }
+            ThreadMapping::Compact => {
+                // Physical cores of a socket come before its hyper-threads
+                cpus.sort_by_key(|c| (c.socket, self.is_hyperthread(c), c.core));
+                cpus.into_iter().take(how_many).collect()
+            }
+            ThreadMapping::Scatter => {
+                let mut per_socket: Vec<Vec<CpuInfo>> = self
+                    .sockets()
+                    .into_iter()
+                    .map(|s| {
+                        let mut on_socket: Vec<CpuInfo> =
+                            cpus.iter().filter(|c| c.socket == s).copied().collect();
+                        on_socket.sort_by_key(|c| (self.is_hyperthread(c), c.core));
+                        on_socket.reverse();
+                        on_socket
+                    })
+                    .collect();
+
+                // Take one CPU from every socket in turn
+                let mut c = Vec::with_capacity(how_many);
+                while c.len() < how_many && per_socket.iter().any(|s| !s.is_empty()) {
+                    for on_socket in per_socket.iter_mut() {
+                        if c.len() < how_many {
+                            c.extend(on_socket.pop());
+                        }
+                    }
+                }
+                c
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two sockets of four cores, the CPUs of the sockets interleave
+    fn two_sockets() -> MachineTopology {
+        let data = (0..8)
+            .map(|cpu| CpuInfo {
+                node: None,
+                socket: cpu % 2,
+                core: cpu,
+                cpu,
+                l1: cpu,
+                l2: cpu,
+                l3: cpu % 2,
+            })
+            .collect();
+        MachineTopology { data }
+    }
+
+    // One socket of four cores with two hardware threads each, CPU n and
+    // n + 4 are siblings
+    fn smt() -> MachineTopology {
+        let data = (0..8)
+            .map(|cpu| CpuInfo {
+                node: None,
+                socket: 0,
+                core: cpu % 4,
+                cpu,
+                l1: cpu % 4,
+                l2: cpu % 4,
+                l3: 0,
+            })
+            .collect();
+        MachineTopology { data }
+    }
+
+    #[test]
+    fn preflight_warns_about_smt_and_turbo() {
+        let topology = smt();
+        assert_eq!(topology.smt_siblings(&[0, 4]), vec![(0, 4)]);
+        assert_eq!(topology.smt_siblings(&[1, 2]), vec![(1, 5), (2, 6)]);
+
+        let warnings = topology.preflight_warnings(&[0, 4, 1], Some(false));
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(warnings[0].contains("share cores") && warnings[0].contains("0/4"));
+        assert!(warnings[1].contains("SMT is on") && warnings[1].contains("1/5"));
+
+        // Without siblings only turbo boost is worth a warning
+        assert!(two_sockets().preflight_warnings(&[0, 1], None).is_empty());
+        assert!(two_sockets()
+            .preflight_warnings(&[0, 1], Some(false))
+            .is_empty());
+        let warnings = two_sockets().preflight_warnings(&[0, 1], Some(true));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("turbo"));
+    }
+
+    #[test]
+    fn compact_fills_socket_before_scatter_alternates() {
+        let topology = two_sockets();
+
+        let compact = topology.allocate(ThreadMapping::Compact, 4, false);
+        let sockets: Vec<Socket> = compact.iter().map(|c| c.socket).collect();
+        assert_eq!(sockets, vec![0, 0, 0, 0]);
+
+        let scatter = topology.allocate(ThreadMapping::Scatter, 4, false);
+        let sockets: Vec<Socket> = scatter.iter().map(|c| c.socket).collect();
+        assert_eq!(sockets, vec![0, 1, 0, 1]);
+
+        let mut cpus: Vec<Cpu> = scatter.iter().map(|c| c.cpu).collect();
+        cpus.sort();
+        cpus.dedup();
+        assert_eq!(cpus.len(), 4);
+    }
+
+    #[test]
+    fn topology_is_discovered_once() {
+        let first = MachineTopology::new();
+        for _ in 0..10 {
+            let again = MachineTopology::new();
+            assert_eq!(first.data, again.data);
+        }
+        assert_eq!(DISCOVERIES.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn cpu_lists_restrict_the_topology() {
+        assert_eq!(parse_cpu_list("0-3,6"), Ok(vec![0, 1, 2, 3, 6]));
+        assert_eq!(parse_cpu_list("5"), Ok(vec![5]));
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("0,x").is_err());
+
+        let topology = two_sockets().restricted_to(&[0, 2, 4]).unwrap();
+        assert_eq!(topology.cores(), 3);
+        assert_eq!(topology.sockets(), vec![0]);
+        let cpus: Vec<Cpu> = topology
+            .allocate(ThreadMapping::Scatter, 8, false)
+            .iter()
+            .map(|c| c.cpu)
+            .collect();
+        assert_eq!(cpus, vec![0, 2, 4]);
+        assert!(two_sockets().restricted_to(&[9]).is_err());
+    }
+
+    #[test]
+    fn cores_env_overrides_discovery() {
+        // The topology is cached per process, so check it in a fresh one
+        if std::env::var(CORES_ENV).is_ok() {
+            assert_eq!(MachineTopology::new().cores(), 1);
+            return;
         }
+        let cpu = MachineTopology::new().data[0].cpu;
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "fxmark::utils::topology::tests::cores_env_overrides_discovery",
+            ])
+            .env(CORES_ENV, cpu.to_string())
+            .status()
+            .unwrap();
+        assert!(status.success());
     }
 }
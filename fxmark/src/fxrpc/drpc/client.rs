@@ -4,27 +4,207 @@ use rpc::rpc::*;
 use rpc::transport::stdtcp::*;
 use rpc::transport::uds::*;
 use std::net::TcpStream;
+use std::os::unix::io::AsRawFd;
 use std::os::unix::net::UnixStream;
 use std::sync::{Arc, Mutex};
 
-use abomonation::{decode, encode};
-
+use crate::fxrpc::drpc::quic::connect_quic;
 use crate::fxrpc::drpc::*;
-use crate::fxrpc::FxRPC;
+use crate::fxrpc::reqlog::REQUEST_LOG_SIZE;
+use crate::fxrpc::stats::{HISTOGRAM_BUCKETS, HISTOGRAM_OPS};
+use crate::fxrpc::LogRecord;
+use crate::fxrpc::OpHistogram;
 use crate::fxrpc::PAGE_SIZE;
+use crate::fxrpc::{
+    batch_fd_index, copy_read, fstatat_result, lseek_result, read_result, write_len, BatchOp,
+    BatchResult, Capabilities, FileStat, FxRPC, SocketOptions, WireFormat,
+};
 
 ////////////////////////////////// CLIENT //////////////////////////////////
 
 fn decode_response(payload: &mut [u8]) -> (i32, usize, Vec<u8>) {
-    match unsafe { decode::<Response>(payload) } {
-        Some((req, _)) => (req.result, req.size, req.page.clone()),
+    match decode_msg::<Response>(payload) {
+        Some(req) => (req.result, req.size, req.page),
         None => panic!("Cannot decode response!"),
     }
 }
 
+// Decodes a read response directly into the caller's buffer, reusing its allocation
+fn decode_response_into(payload: &mut [u8], page: &mut Vec<u8>) -> (i32, usize) {
+    let decoded = decode_with(payload, |req: &Response| {
+        copy_read(page, &req.page, req.result);
+        (req.result, req.size)
+    });
+    decoded.expect("Cannot decode response!")
+}
+
+// Encodes one op of a batch as the request it would be sent as on its own
+fn batch_entry(format: WireFormat, op: &BatchOp) -> BatchEntry {
+    // The server fills in descriptors that refer to earlier ops
+    let fd_of = |fd: i32| match batch_fd_index(fd) {
+        Some(index) => (-1, index as i64),
+        None => (fd, -1),
+    };
+    let mut payload = Vec::new();
+    let (code, fd_from) = match op {
+        BatchOp::Open { path, flags, mode } => {
+            let request = OpenReq {
+                path: path.as_bytes().to_vec(),
+                flags: *flags,
+                mode: *mode,
+            };
+            encode_as(format, &request, &mut payload);
+            (DRPC::Open, -1)
+        }
+        BatchOp::PRead { fd, size, offset } => {
+            let (fd, fd_from) = fd_of(*fd);
+            let request = ReadReq {
+                fd: fd,
+                size: *size,
+                offset: *offset,
+            };
+            encode_as(format, &request, &mut payload);
+            (DRPC::PRead, fd_from)
+        }
+        BatchOp::PWrite { fd, page, offset } => {
+            let (fd, fd_from) = fd_of(*fd);
+            let request = WriteReq {
+                fd: fd,
+                page: page.clone(),
+                size: page.len(),
+                offset: *offset,
+            };
+            encode_as(format, &request, &mut payload);
+            (DRPC::PWrite, fd_from)
+        }
+        BatchOp::Fsync { fd } => {
+            let (fd, fd_from) = fd_of(*fd);
+            encode_as(format, &FsyncReq { fd: fd }, &mut payload);
+            (DRPC::Fsync, fd_from)
+        }
+        BatchOp::Close { fd } => {
+            let (fd, fd_from) = fd_of(*fd);
+            encode_as(format, &CloseReq { fd: fd }, &mut payload);
+            (DRPC::Close, fd_from)
+        }
+        BatchOp::Remove { path } => {
+            let request = RemoveReq {
+                path: path.as_bytes().to_vec(),
+            };
+            encode_as(format, &request, &mut payload);
+            (DRPC::Remove, -1)
+        }
+    };
+    BatchEntry {
+        op: code as u8,
+        fd_from: fd_from,
+        payload: payload,
+    }
+}
+
+// Where a DrpcClient is connected to, kept to reconnect
+enum DrpcAddr {
+    Tcp(String),
+    Uds(String),
+    Quic(String),
+}
+
+impl DrpcAddr {
+    fn connect(&self, sockets: &SocketOptions) -> std::io::Result<Client> {
+        match self {
+            DrpcAddr::Tcp(addr) => {
+                let stream = TcpStream::connect(addr)?;
+                sockets.apply(stream.as_raw_fd(), true)?;
+                let transport = StdTCP {
+                    stream: Arc::new(Mutex::new(stream)),
+                };
+                Ok(Client::new(Box::new(transport)))
+            }
+            DrpcAddr::Uds(path) => {
+                let stream = UnixStream::connect(path)?;
+                sockets.apply(stream.as_raw_fd(), false)?;
+                let transport = UDS {
+                    stream: Arc::new(Mutex::new(stream)),
+                };
+                Ok(Client::new(Box::new(transport)))
+            }
+            // A new QUIC connection, relayed to a local socket. Its socket
+            // options are quinn's
+            DrpcAddr::Quic(addr) => {
+                let transport = UDS {
+                    stream: Arc::new(Mutex::new(connect_quic(addr)?)),
+                };
+                Ok(Client::new(Box::new(transport)))
+            }
+        }
+    }
+}
+
+/// A DRPC connection that serializes its requests as `format`. A broken
+/// connection is replaced by a new one, see `call_op()`.
+pub struct DrpcClient {
+    client: Client,
+    addr: DrpcAddr,
+    format: WireFormat,
+    sockets: SocketOptions,
+    /// Holds the replies to reads, kept for the next read.
+    reply: Vec<u8>,
+}
+
+impl DrpcClient {
+    fn connect(addr: DrpcAddr, format: WireFormat, sockets: &SocketOptions) -> DrpcClient {
+        DrpcClient {
+            client: addr.connect(sockets).unwrap(),
+            addr: addr,
+            format: format,
+            sockets: *sockets,
+            reply: Vec::new(),
+        }
+    }
+
+    // The buffer for the reply to a read of `size` bytes, grown to fit it.
+    // Give it back to `reply` once the reply is decoded.
+    fn reply_buffer(&mut self, size: usize) -> Vec<u8> {
+        let mut reply = std::mem::take(&mut self.reply);
+        reply.resize(
+            BYTE_ORDER_MARK_LEN + std::mem::size_of::<Response>() + size,
+            0,
+        );
+        reply
+    }
+
+    // Sends `op` and waits for its reply. If that fails, the connection is
+    // replaced and an idempotent op is sent again over the new one. Other ops
+    // may have run before the connection broke, running them again could
+    // fail or do it twice, so their error is returned instead.
+    fn call_op(
+        &mut self,
+        op: DRPC,
+        data_in: &[&[u8]],
+        data_out: &mut [&mut [u8]],
+    ) -> Result<(), RPCError> {
+        let err = match self.client.call(op as RPCType, data_in, data_out) {
+            Ok(_) => return Ok(()),
+            Err(err) => err,
+        };
+        match self.addr.connect(&self.sockets) {
+            Ok(client) => self.client = client,
+            Err(_) => return Err(err),
+        }
+        if !op.is_idempotent() {
+            debug!("Connection broke during {:?}, not sending it again", op);
+            return Err(err);
+        }
+        debug!("Connection broke during {:?}, sending it again", op);
+        self.client
+            .call(op as RPCType, data_in, data_out)
+            .map(|_| ())
+    }
+}
+
 // TODO: ERROR HANDLING
 
-impl FxRPC for Client {
+impl FxRPC for DrpcClient {
     fn rpc_open(
         &mut self,
         path: &str,
@@ -38,10 +218,10 @@ impl FxRPC for Client {
         };
 
         let mut bytes = Vec::new();
-        unsafe { encode(&request, &mut bytes) }.expect("Failed to encode open request");
-        let mut data_out = [0u8; std::mem::size_of::<Response>()];
+        encode_as(self.format, &request, &mut bytes);
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<Response>()];
 
-        match self.call(DRPC::Open as RPCType, &[&bytes], &mut [&mut data_out]) {
+        match self.call_op(DRPC::Open, &[&bytes], &mut [&mut data_out]) {
             Ok(_) => {
                 let (result, size, page) = decode_response(&mut data_out);
                 debug!(
@@ -67,21 +247,21 @@ impl FxRPC for Client {
         };
 
         let mut bytes = Vec::new();
-        unsafe { encode(&request, &mut bytes) }.expect("Failed to encode open request");
+        encode_as(self.format, &request, &mut bytes);
 
-        // probably a bit conservative
-        let mut data_out = [0u8; 2 * PAGE_SIZE];
+        let mut data_out = self.reply_buffer(size);
 
-        match self.call(DRPC::Read as RPCType, &[&bytes], &mut [&mut data_out]) {
-            Ok(_) => {
-                let (result, size, ret_page) = decode_response(&mut data_out);
+        let res = self.call_op(DRPC::Read, &[&bytes], &mut [&mut data_out]);
+        let decoded = res.map(|_| decode_response_into(&mut data_out, page));
+        self.reply = data_out;
+        match decoded {
+            Ok((result, size)) => {
                 debug!(
                     "Received - result: {:?}, size: {:?}, page: {:?}",
-                    result, size, ret_page
+                    result, size, page
                 );
-                *page = ret_page;
 
-                Ok(result)
+                read_result(result)
             }
             Err(_) => Err(Box::from("Read RPC failed")),
         }
@@ -101,21 +281,21 @@ impl FxRPC for Client {
         };
 
         let mut bytes = Vec::new();
-        unsafe { encode(&request, &mut bytes) }.expect("Failed to encode open request");
+        encode_as(self.format, &request, &mut bytes);
 
-        // probably a bit conservative
-        let mut data_out = [0u8; 2 * PAGE_SIZE];
+        let mut data_out = self.reply_buffer(size);
 
-        match self.call(DRPC::PRead as RPCType, &[&bytes], &mut [&mut data_out]) {
-            Ok(_) => {
-                let (result, size, ret_page) = decode_response(&mut data_out);
+        let res = self.call_op(DRPC::PRead, &[&bytes], &mut [&mut data_out]);
+        let decoded = res.map(|_| decode_response_into(&mut data_out, page));
+        self.reply = data_out;
+        match decoded {
+            Ok((result, size)) => {
                 debug!(
                     "Received - result: {:?}, size: {:?}, page: {:?}",
-                    result, size, ret_page
+                    result, size, page
                 );
-                *page = ret_page;
 
-                Ok(result)
+                read_result(result)
             }
             Err(_) => Err(Box::from("PRead RPC failed")),
         }
@@ -124,21 +304,21 @@ impl FxRPC for Client {
     fn rpc_write(
         &mut self,
         fd: i32,
-        page: &Vec<u8>,
+        page: &[u8],
         size: usize,
     ) -> Result<i32, Box<dyn std::error::Error>> {
         let request = WriteReq {
             fd: fd,
-            page: page.to_vec(),
+            page: page[..write_len(page, size)].to_vec(),
             size: size,
             offset: 0,
         };
 
         let mut bytes = Vec::new();
-        unsafe { encode(&request, &mut bytes) }.expect("Failed to encode open request");
-        let mut data_out = [0u8; std::mem::size_of::<Response>()];
+        encode_as(self.format, &request, &mut bytes);
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<Response>()];
 
-        match self.call(DRPC::Write as RPCType, &[&bytes], &mut [&mut data_out]) {
+        match self.call_op(DRPC::Write, &[&bytes], &mut [&mut data_out]) {
             Ok(_) => {
                 let (result, size, page) = decode_response(&mut data_out);
                 debug!(
@@ -155,22 +335,22 @@ impl FxRPC for Client {
     fn rpc_pwrite(
         &mut self,
         fd: i32,
-        page: &Vec<u8>,
+        page: &[u8],
         size: usize,
         offset: i64,
     ) -> Result<i32, Box<dyn std::error::Error>> {
         let request = WriteReq {
             fd: fd,
-            page: page.to_vec(),
+            page: page[..write_len(page, size)].to_vec(),
             size: size,
             offset: offset,
         };
 
         let mut bytes = Vec::new();
-        unsafe { encode(&request, &mut bytes) }.expect("Failed to encode open request");
-        let mut data_out = [0u8; std::mem::size_of::<Response>()];
+        encode_as(self.format, &request, &mut bytes);
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<Response>()];
 
-        match self.call(DRPC::PWrite as RPCType, &[&bytes], &mut [&mut data_out]) {
+        match self.call_op(DRPC::PWrite, &[&bytes], &mut [&mut data_out]) {
             Ok(_) => {
                 let (result, size, page) = decode_response(&mut data_out);
                 debug!(
@@ -188,10 +368,10 @@ impl FxRPC for Client {
         let request = CloseReq { fd: fd };
 
         let mut bytes = Vec::new();
-        unsafe { encode(&request, &mut bytes) }.expect("Failed to encode open request");
-        let mut data_out = [0u8; std::mem::size_of::<Response>()];
+        encode_as(self.format, &request, &mut bytes);
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<Response>()];
 
-        match self.call(DRPC::Close as RPCType, &[&bytes], &mut [&mut data_out]) {
+        match self.call_op(DRPC::Close, &[&bytes], &mut [&mut data_out]) {
             Ok(_) => {
                 let (result, size, page) = decode_response(&mut data_out);
                 debug!(
@@ -205,16 +385,61 @@ impl FxRPC for Client {
         }
     }
 
+    fn rpc_fsync(&mut self, fd: i32) -> Result<i32, Box<dyn std::error::Error>> {
+        let request = FsyncReq { fd: fd };
+
+        let mut bytes = Vec::new();
+        encode_as(self.format, &request, &mut bytes);
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<Response>()];
+
+        match self.call_op(DRPC::Fsync, &[&bytes], &mut [&mut data_out]) {
+            Ok(_) => {
+                let (result, size, page) = decode_response(&mut data_out);
+                debug!(
+                    "Received - result: {:?}, size: {:?}, page: {:?}",
+                    result, size, page
+                );
+
+                Ok(result)
+            }
+            Err(_) => Err(Box::from("Fsync RPC failed")),
+        }
+    }
+
+    fn rpc_ftruncate(&mut self, fd: i32, length: i64) -> Result<i32, Box<dyn std::error::Error>> {
+        let request = FtruncateReq {
+            fd: fd,
+            length: length,
+        };
+
+        let mut bytes = Vec::new();
+        encode_as(self.format, &request, &mut bytes);
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<Response>()];
+
+        match self.call_op(DRPC::Ftruncate, &[&bytes], &mut [&mut data_out]) {
+            Ok(_) => {
+                let (result, size, page) = decode_response(&mut data_out);
+                debug!(
+                    "Received - result: {:?}, size: {:?}, page: {:?}",
+                    result, size, page
+                );
+
+                Ok(result)
+            }
+            Err(_) => Err(Box::from("Ftruncate RPC failed")),
+        }
+    }
+
     fn rpc_remove(&mut self, path: &str) -> Result<i32, Box<dyn std::error::Error>> {
         let request = RemoveReq {
             path: path.as_bytes().to_vec(),
         };
 
         let mut bytes = Vec::new();
-        unsafe { encode(&request, &mut bytes) }.expect("Failed to encode open request");
-        let mut data_out = [0u8; std::mem::size_of::<Response>()];
+        encode_as(self.format, &request, &mut bytes);
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<Response>()];
 
-        match self.call(DRPC::Remove as RPCType, &[&bytes], &mut [&mut data_out]) {
+        match self.call_op(DRPC::Remove, &[&bytes], &mut [&mut data_out]) {
             Ok(_) => {
                 let (result, size, page) = decode_response(&mut data_out);
                 debug!(
@@ -228,6 +453,34 @@ impl FxRPC for Client {
         }
     }
 
+    fn rpc_rename(
+        &mut self,
+        old_path: &str,
+        new_path: &str,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        let request = RenameReq {
+            old_path: old_path.as_bytes().to_vec(),
+            new_path: new_path.as_bytes().to_vec(),
+        };
+
+        let mut bytes = Vec::new();
+        encode_as(self.format, &request, &mut bytes);
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<Response>()];
+
+        match self.call_op(DRPC::Rename, &[&bytes], &mut [&mut data_out]) {
+            Ok(_) => {
+                let (result, size, page) = decode_response(&mut data_out);
+                debug!(
+                    "Received - result: {:?}, size: {:?}, page: {:?}",
+                    result, size, page
+                );
+
+                Ok(result)
+            }
+            Err(_) => Err(Box::from("Rename RPC failed")),
+        }
+    }
+
     fn rpc_mkdir(&mut self, path: &str, mode: u32) -> Result<i32, Box<dyn std::error::Error>> {
         let request = MkdirReq {
             path: path.as_bytes().to_vec(),
@@ -235,10 +488,10 @@ impl FxRPC for Client {
         };
 
         let mut bytes = Vec::new();
-        unsafe { encode(&request, &mut bytes) }.expect("Failed to encode open request");
-        let mut data_out = [0u8; std::mem::size_of::<Response>()];
+        encode_as(self.format, &request, &mut bytes);
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<Response>()];
 
-        match self.call(DRPC::MkDir as RPCType, &[&bytes], &mut [&mut data_out]) {
+        match self.call_op(DRPC::MkDir, &[&bytes], &mut [&mut data_out]) {
             Ok(_) => {
                 let (result, size, page) = decode_response(&mut data_out);
                 debug!(
@@ -258,10 +511,10 @@ impl FxRPC for Client {
         };
 
         let mut bytes = Vec::new();
-        unsafe { encode(&request, &mut bytes) }.expect("Failed to encode open request");
-        let mut data_out = [0u8; std::mem::size_of::<Response>()];
+        encode_as(self.format, &request, &mut bytes);
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<Response>()];
 
-        match self.call(DRPC::RmDir as RPCType, &[&bytes], &mut [&mut data_out]) {
+        match self.call_op(DRPC::RmDir, &[&bytes], &mut [&mut data_out]) {
             Ok(_) => {
                 let (result, size, page) = decode_response(&mut data_out);
                 debug!(
@@ -274,22 +527,483 @@ impl FxRPC for Client {
             Err(_) => Err(Box::from("Rmdir RPC failed")),
         }
     }
+
+    fn rpc_fadvise(
+        &mut self,
+        fd: i32,
+        offset: i64,
+        len: i64,
+        advice: i32,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        let request = FadviseReq {
+            fd: fd,
+            offset: offset,
+            len: len,
+            advice: advice,
+        };
+
+        let mut bytes = Vec::new();
+        encode_as(self.format, &request, &mut bytes);
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<Response>()];
+
+        match self.call_op(DRPC::Fadvise, &[&bytes], &mut [&mut data_out]) {
+            Ok(_) => {
+                let (result, size, page) = decode_response(&mut data_out);
+                debug!(
+                    "Received - result: {:?}, size: {:?}, page: {:?}",
+                    result, size, page
+                );
+
+                Ok(result)
+            }
+            Err(_) => Err(Box::from("Fadvise RPC failed")),
+        }
+    }
+
+    fn rpc_drop_caches(&mut self) -> Result<i32, Box<dyn std::error::Error>> {
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<Response>()];
+
+        let mut bytes = Vec::new();
+        encode_empty(self.format, &mut bytes);
+        match self.call_op(DRPC::DropCaches, &[&bytes], &mut [&mut data_out]) {
+            Ok(_) => {
+                let (result, size, page) = decode_response(&mut data_out);
+                debug!(
+                    "Received - result: {:?}, size: {:?}, page: {:?}",
+                    result, size, page
+                );
+
+                Ok(result)
+            }
+            Err(_) => Err(Box::from("DropCaches RPC failed")),
+        }
+    }
+
+    fn rpc_fstatat(
+        &mut self,
+        dirfd: i32,
+        path: &str,
+        flags: i32,
+    ) -> Result<FileStat, Box<dyn std::error::Error>> {
+        let request = FstatAtReq {
+            dirfd: dirfd,
+            path: path.as_bytes().to_vec(),
+            flags: flags,
+        };
+
+        let mut bytes = Vec::new();
+        encode_as(self.format, &request, &mut bytes);
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<FstatAtResponse>()];
+
+        match self.call_op(DRPC::FstatAt, &[&bytes], &mut [&mut data_out]) {
+            Ok(_) => match decode_msg::<FstatAtResponse>(&mut data_out) {
+                Some(res) => {
+                    debug!(
+                        "Received - result: {:?}, errno: {:?}, size: {:?}, mode: {:?}",
+                        res.result, res.errno, res.size, res.mode
+                    );
+                    fstatat_result(
+                        res.result,
+                        res.errno,
+                        FileStat {
+                            size: res.size,
+                            mode: res.mode,
+                        },
+                    )
+                }
+                None => Err(Box::from("Cannot decode fstatat response!")),
+            },
+            Err(_) => Err(Box::from("FstatAt RPC failed")),
+        }
+    }
+
+    fn rpc_sendfile(
+        &mut self,
+        out_fd: i32,
+        in_fd: i32,
+        offset: i64,
+        count: usize,
+    ) -> Result<isize, Box<dyn std::error::Error>> {
+        let request = SendfileReq {
+            out_fd: out_fd,
+            in_fd: in_fd,
+            offset: offset,
+            count: count as u64,
+        };
+
+        let mut bytes = Vec::new();
+        encode_as(self.format, &request, &mut bytes);
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<SendfileResponse>()];
+
+        match self.call_op(DRPC::Sendfile, &[&bytes], &mut [&mut data_out]) {
+            Ok(_) => match decode_msg::<SendfileResponse>(&mut data_out) {
+                Some(res) => {
+                    debug!("Received - result: {:?}", res.result);
+                    Ok(res.result as isize)
+                }
+                None => Err(Box::from("Cannot decode sendfile response!")),
+            },
+            Err(_) => Err(Box::from("Sendfile RPC failed")),
+        }
+    }
+
+    fn rpc_lseek(
+        &mut self,
+        fd: i32,
+        offset: i64,
+        whence: i32,
+    ) -> Result<i64, Box<dyn std::error::Error>> {
+        let request = LseekReq {
+            fd: fd,
+            offset: offset,
+            whence: whence,
+        };
+
+        let mut bytes = Vec::new();
+        encode_as(self.format, &request, &mut bytes);
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<LseekResponse>()];
+
+        match self.call_op(DRPC::Lseek, &[&bytes], &mut [&mut data_out]) {
+            Ok(_) => match decode_msg::<LseekResponse>(&mut data_out) {
+                Some(res) => {
+                    debug!(
+                        "Received - result: {:?}, errno: {:?}",
+                        res.result, res.errno
+                    );
+                    lseek_result(res.result, res.errno)
+                }
+                None => Err(Box::from("Cannot decode lseek response!")),
+            },
+            Err(_) => Err(Box::from("Lseek RPC failed")),
+        }
+    }
+
+    fn rpc_batch(
+        &mut self,
+        ops: Vec<BatchOp>,
+    ) -> Result<Vec<BatchResult>, Box<dyn std::error::Error>> {
+        // Room for every response and the pages of all reads
+        let read_bytes: usize = ops
+            .iter()
+            .map(|op| match op {
+                BatchOp::PRead { size, .. } => *size,
+                _ => 0,
+            })
+            .sum();
+        let request = BatchReq {
+            ops: ops.iter().map(|op| batch_entry(self.format, op)).collect(),
+        };
+
+        let mut bytes = Vec::new();
+        encode_as(self.format, &request, &mut bytes);
+        let mut data_out = vec![
+            0u8;
+            BYTE_ORDER_MARK_LEN
+                + std::mem::size_of::<BatchResponse>()
+                + ops.len() * std::mem::size_of::<Response>()
+                + read_bytes
+        ];
+
+        match self.call_op(DRPC::Batch, &[&bytes], &mut [&mut data_out]) {
+            Ok(_) => match decode_msg::<BatchResponse>(&mut data_out) {
+                Some(res) => {
+                    debug!("Received - {} responses", res.responses.len());
+                    Ok(res
+                        .responses
+                        .into_iter()
+                        .map(|r| BatchResult {
+                            result: r.result,
+                            page: r.page,
+                        })
+                        .collect())
+                }
+                None => Err(Box::from("Cannot decode batch response!")),
+            },
+            Err(_) => Err(Box::from("Batch RPC failed")),
+        }
+    }
+
+    fn rpc_dump_server_log(&mut self) -> Result<Vec<LogRecord>, Box<dyn std::error::Error>> {
+        let mut data_out = vec![
+            0u8;
+            BYTE_ORDER_MARK_LEN
+                + std::mem::size_of::<LogResponse>()
+                + REQUEST_LOG_SIZE * std::mem::size_of::<LogRecord>()
+        ];
+
+        let mut bytes = Vec::new();
+        encode_empty(self.format, &mut bytes);
+        match self.call_op(DRPC::DumpLog, &[&bytes], &mut [&mut data_out]) {
+            Ok(_) => match decode_msg::<LogResponse>(&mut data_out) {
+                Some(res) => {
+                    debug!("Received - {:?} log records", res.records.len());
+                    Ok(res.records)
+                }
+                None => Err(Box::from("Cannot decode log response!")),
+            },
+            Err(_) => Err(Box::from("DumpLog RPC failed")),
+        }
+    }
+
+    fn rpc_server_stats(&mut self) -> Result<Vec<OpHistogram>, Box<dyn std::error::Error>> {
+        let mut data_out = vec![
+            0u8;
+            BYTE_ORDER_MARK_LEN
+                + std::mem::size_of::<StatsResponse>()
+                + HISTOGRAM_OPS
+                    * (std::mem::size_of::<OpHistogram>()
+                        + HISTOGRAM_BUCKETS * std::mem::size_of::<u64>())
+        ];
+
+        let mut bytes = Vec::new();
+        encode_empty(self.format, &mut bytes);
+        match self.call_op(DRPC::DumpStats, &[&bytes], &mut [&mut data_out]) {
+            Ok(_) => match decode_msg::<StatsResponse>(&mut data_out) {
+                Some(res) => {
+                    debug!("Received - {:?} histograms", res.ops.len());
+                    Ok(res.ops)
+                }
+                None => Err(Box::from("Cannot decode stats response!")),
+            },
+            Err(_) => Err(Box::from("DumpStats RPC failed")),
+        }
+    }
+
+    fn rpc_capabilities(&mut self) -> Result<Capabilities, Box<dyn std::error::Error>> {
+        let mut data_out = [0u8; 2 * PAGE_SIZE];
+
+        let mut bytes = Vec::new();
+        encode_empty(self.format, &mut bytes);
+        match self.call_op(DRPC::Capabilities, &[&bytes], &mut [&mut data_out]) {
+            Ok(_) => match decode_msg::<CapabilitiesResponse>(&mut data_out) {
+                Some(res) => {
+                    debug!("Received - {:?} ops", res.ops.len());
+                    Ok(Capabilities {
+                        protocol_version: res.protocol_version,
+                        rpc_types: res
+                            .rpc_types
+                            .iter()
+                            .map(|t| t.parse())
+                            .collect::<Result<_, String>>()?,
+                        ops: res.ops,
+                    })
+                }
+                None => Err(Box::from("Cannot decode capabilities response!")),
+            },
+            Err(_) => Err(Box::from("Capabilities RPC failed")),
+        }
+    }
+
+    fn rpc_reset(&mut self) -> Result<i32, Box<dyn std::error::Error>> {
+        let mut data_out = [0u8; BYTE_ORDER_MARK_LEN + std::mem::size_of::<Response>()];
+
+        let mut bytes = Vec::new();
+        encode_empty(self.format, &mut bytes);
+        match self.call_op(DRPC::Reset, &[&bytes], &mut [&mut data_out]) {
+            Ok(_) => {
+                let (result, size, page) = decode_response(&mut data_out);
+                debug!(
+                    "Received - result: {:?}, size: {:?}, page: {:?}",
+                    result, size, page
+                );
+
+                Ok(result)
+            }
+            Err(_) => Err(Box::from("Reset RPC failed")),
+        }
+    }
 }
 
-pub fn init_client_drpc_tcp(bind_addr: &str) -> Client {
-    let stream = TcpStream::connect(bind_addr).unwrap();
-    let transport = StdTCP {
-        stream: Arc::new(Mutex::new(stream)),
-    };
-    let client = Client::new(Box::new(transport));
-    client
+pub fn init_client_drpc_tcp(
+    bind_addr: &str,
+    format: WireFormat,
+    sockets: &SocketOptions,
+) -> DrpcClient {
+    DrpcClient::connect(DrpcAddr::Tcp(bind_addr.to_string()), format, sockets)
 }
 
-pub fn init_client_drpc_uds(bind_addr: &str) -> Client {
-    let stream = UnixStream::connect(bind_addr).unwrap();
-    let transport = UDS {
-        stream: Arc::new(Mutex::new(stream)),
-    };
-    let client = Client::new(Box::new(transport));
-    client
+pub fn init_client_drpc_uds(
+    bind_addr: &str,
+    format: WireFormat,
+    sockets: &SocketOptions,
+) -> DrpcClient {
+    DrpcClient::connect(DrpcAddr::Uds(bind_addr.to_string()), format, sockets)
+}
+
+pub fn init_client_drpc_quic(bind_addr: &str, format: WireFormat) -> DrpcClient {
+    DrpcClient::connect(
+        DrpcAddr::Quic(bind_addr.to_string()),
+        format,
+        &SocketOptions::default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_response, decode_response_into, encode_as, Response, WireFormat};
+    use crate::fxrpc::{init_client, start_test_server, RPCEndpoint, RPCType, PAGE_SIZE};
+    use libc::{O_CREAT, O_RDWR, S_IRWXU};
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::io::{Read, Write};
+    use std::net::Shutdown;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    // Forwards connections on `front` to the server on `back`. Once `break_next`
+    // is set, the next reply from the server is dropped along with the connection,
+    // as if it broke after the server ran the request.
+    fn start_breaking_proxy(front: &str, back: &str, break_next: Arc<AtomicBool>) {
+        let _ = std::fs::remove_file(front);
+        let listener = UnixListener::bind(front).unwrap();
+        let back = back.to_string();
+        std::thread::spawn(move || {
+            for client in listener.incoming() {
+                let mut client = client.unwrap();
+                let mut server = UnixStream::connect(&back).unwrap();
+                let mut requests = (client.try_clone().unwrap(), server.try_clone().unwrap());
+                std::thread::spawn(move || std::io::copy(&mut requests.0, &mut requests.1));
+                let break_next = break_next.clone();
+                std::thread::spawn(move || {
+                    let mut buf = vec![0; 4 * PAGE_SIZE];
+                    while let Ok(len) = server.read(&mut buf) {
+                        if len == 0 || break_next.swap(false, Ordering::SeqCst) {
+                            break;
+                        }
+                        if client.write_all(&buf[..len]).is_err() {
+                            break;
+                        }
+                    }
+                    let _ = client.shutdown(Shutdown::Both);
+                    let _ = server.shutdown(Shutdown::Both);
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn reconnect_test() -> Result<(), Box<dyn std::error::Error>> {
+        let path = "/dev/shm/fxmark_reconnect_test/fx.sock";
+        start_test_server(path, RPCType::DRPC);
+        let proxy = "/dev/shm/fxmark_reconnect_test/proxy.sock";
+        let break_next = Arc::new(AtomicBool::new(false));
+        start_breaking_proxy(proxy, path, break_next.clone());
+
+        let endpoint: RPCEndpoint = format!("unix://{}", proxy).parse()?;
+        let mut client = init_client(&endpoint, RPCType::DRPC);
+        let filename = "reconnect_test.txt";
+
+        // The server creates the file, but its reply is lost. Sending the open
+        // again would fail with EEXIST.
+        break_next.store(true, Ordering::SeqCst);
+        let res = client.rpc_open(filename, O_CREAT | libc::O_EXCL | O_RDWR, S_IRWXU);
+        assert!(res.is_err(), "ReconnectTest: Exclusive Open Was Sent Again");
+
+        // Idempotent ops are sent again over the new connection
+        break_next.store(true, Ordering::SeqCst);
+        let stat = client.rpc_fstatat(libc::AT_FDCWD, filename, 0)?;
+        assert!(stat.size == 0, "ReconnectTest: Wrong Size");
+
+        // The new connection serves the ops after them
+        let fd = client.rpc_open(filename, O_RDWR, S_IRWXU)?;
+        assert!(fd != -1, "ReconnectTest: Open After Reconnect Failed");
+        client.rpc_close(fd)?;
+        client.rpc_remove(filename)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn reads_take_any_size() -> Result<(), Box<dyn std::error::Error>> {
+        let endpoint =
+            start_test_server("/dev/shm/fxmark_drpc_read_size_test/fx.sock", RPCType::DRPC);
+        let mut client = init_client(&endpoint, RPCType::DRPC);
+
+        let filename = "read_size_test.txt";
+        let size = 16 * PAGE_SIZE;
+        let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        let fd = client.rpc_open(filename, O_CREAT | O_RDWR, S_IRWXU)?;
+        assert!(fd != -1, "ReadSizeTest: Open Failed");
+        assert_eq!(client.rpc_pwrite(fd, &data, size, 0)?, size as i32);
+
+        let mut page = vec![0; size];
+        assert_eq!(client.rpc_pread(fd, &mut page, size, 0)?, size as i32);
+        assert_eq!(page, data);
+        // A smaller read after it gets its own bytes only
+        let mut page = vec![0; PAGE_SIZE];
+        let offset = PAGE_SIZE as i64;
+        assert_eq!(
+            client.rpc_pread(fd, &mut page, PAGE_SIZE, offset)?,
+            PAGE_SIZE as i32
+        );
+        assert_eq!(page, data[PAGE_SIZE..2 * PAGE_SIZE]);
+
+        client.rpc_close(fd)?;
+        client.rpc_remove(filename)?;
+        Ok(())
+    }
+
+    // Counts the allocations of each thread on its own, so tests running next to
+    // one don't add to its count
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+    // Allocations `f` makes on this thread
+    fn allocations(f: impl FnOnce()) -> usize {
+        let before = ALLOCATIONS.with(Cell::get);
+        f();
+        ALLOCATIONS.with(Cell::get) - before
+    }
+
+    #[test]
+    fn reads_decode_into_the_callers_page() {
+        let response = Response {
+            result: PAGE_SIZE as i32,
+            size: PAGE_SIZE,
+            page: vec![7; PAGE_SIZE],
+        };
+        let mut payload = Vec::new();
+        encode_as(WireFormat::Abomonation, &response, &mut payload);
+        // Decoding fixes up the payload in place, each path gets its own copy
+        let (mut old, mut new) = (payload.clone(), payload);
+        let mut page = vec![0; PAGE_SIZE];
+
+        // The old path took the decoded copy of the server's page in place of
+        // the caller's
+        let old_allocations = allocations(|| {
+            let (_, _, ret_page) = decode_response(&mut old);
+            page = ret_page;
+        });
+        page.fill(0);
+        let new_allocations = allocations(|| {
+            decode_response_into(&mut new, &mut page);
+        });
+
+        assert_eq!(page, response.page);
+        assert_eq!(new_allocations, 0);
+        assert!(
+            new_allocations < old_allocations,
+            "{} allocations per read, {} before",
+            new_allocations,
+            old_allocations
+        );
+    }
 }
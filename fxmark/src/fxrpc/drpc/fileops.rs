@@ -1,7 +1,109 @@
-use abomonation::Abomonation;
+use abomonation::{decode, encode, Abomonation};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+use crate::fxrpc::reqlog::LogRecord;
+use crate::fxrpc::stats::OpHistogram;
+use crate::fxrpc::WireFormat;
 
 ////////////////////////////// FS RPC Hdrs  //////////////////////////////
 
+/// Written in native byte order in front of every abomonated message. Abomonation
+/// copies integers verbatim, so a peer with the other byte order would otherwise
+/// decode garbage. The marker is 8 bytes so the message behind it stays aligned.
+pub const BYTE_ORDER_MARK: u64 = 0x0102_0304_0506_0708;
+pub const BYTE_ORDER_MARK_LEN: usize = std::mem::size_of::<u64>();
+
+/// Encodes `msg` behind the byte order mark.
+pub fn encode_msg<T: Abomonation>(msg: &T, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&BYTE_ORDER_MARK.to_ne_bytes());
+    unsafe { encode(msg, bytes) }.expect("Failed to encode message");
+}
+
+/// Checks the byte order mark and returns the message behind it. Messages from a
+/// peer with a different byte order, or without a mark, are rejected.
+pub fn strip_byte_order_mark(payload: &mut [u8]) -> Result<&mut [u8], &'static str> {
+    let mark = match payload.get(0..BYTE_ORDER_MARK_LEN) {
+        Some(mark) => u64::from_ne_bytes(mark.try_into().unwrap()),
+        None => return Err("DRPC message is too short for a byte order mark"),
+    };
+    if mark == BYTE_ORDER_MARK.swap_bytes() {
+        return Err("Peer uses a different byte order, DRPC requires both ends to match");
+    }
+    if mark != BYTE_ORDER_MARK {
+        return Err("DRPC message has no byte order mark");
+    }
+    Ok(&mut payload[BYTE_ORDER_MARK_LEN..])
+}
+
+/// Written little endian in front of every bincode message, where abomonated
+/// messages have their byte order mark. It matches neither byte order of the
+/// mark, so a receiver tells the formats apart by the first 8 bytes.
+pub const BINCODE_MARK: u64 = 0x6564_6f63_6e69_6221;
+
+/// Encodes `msg` as `format`, behind the mark of that format.
+pub fn encode_as<T: Abomonation + Serialize>(format: WireFormat, msg: &T, bytes: &mut Vec<u8>) {
+    match format {
+        WireFormat::Abomonation => encode_msg(msg, bytes),
+        WireFormat::Bincode => {
+            encode_empty(format, bytes);
+            bincode::serialize_into(&mut *bytes, msg).expect("Failed to encode message");
+        }
+    }
+}
+
+/// Encodes a request without arguments, which is only the mark of `format`.
+pub fn encode_empty(format: WireFormat, bytes: &mut Vec<u8>) {
+    match format {
+        WireFormat::Abomonation => bytes.extend_from_slice(&BYTE_ORDER_MARK.to_ne_bytes()),
+        WireFormat::Bincode => bytes.extend_from_slice(&BINCODE_MARK.to_le_bytes()),
+    }
+}
+
+/// The format of an encoded message. Anything that doesn't start with the
+/// bincode mark is taken to be abomonated.
+pub fn wire_format_of(payload: &[u8]) -> WireFormat {
+    match payload.get(0..BYTE_ORDER_MARK_LEN) {
+        Some(mark) if u64::from_le_bytes(mark.try_into().unwrap()) == BINCODE_MARK => {
+            WireFormat::Bincode
+        }
+        _ => WireFormat::Abomonation,
+    }
+}
+
+/// Decodes a message of either format and hands it to `f`. Abomonated
+/// messages are decoded in place, so `f` sees them without a copy.
+pub fn try_decode_with<T, R>(payload: &mut [u8], f: impl FnOnce(&T) -> R) -> Result<R, &'static str>
+where
+    T: Abomonation + DeserializeOwned,
+{
+    match wire_format_of(payload) {
+        WireFormat::Abomonation => unsafe { decode::<T>(strip_byte_order_mark(payload)?) }
+            .map(|(msg, _)| f(msg))
+            .ok_or("Cannot decode abomonated DRPC message"),
+        WireFormat::Bincode => bincode::deserialize::<T>(&payload[BYTE_ORDER_MARK_LEN..])
+            .map(|msg| f(&msg))
+            .map_err(|_| "Cannot decode bincode DRPC message"),
+    }
+}
+
+/// Like `try_decode_with`, without saying why a message didn't decode.
+pub fn decode_with<T, R>(payload: &mut [u8], f: impl FnOnce(&T) -> R) -> Option<R>
+where
+    T: Abomonation + DeserializeOwned,
+{
+    try_decode_with(payload, f).ok()
+}
+
+/// Decodes a message of either format into an owned copy.
+pub fn decode_msg<T>(payload: &mut [u8]) -> Option<T>
+where
+    T: Abomonation + DeserializeOwned + Clone,
+{
+    decode_with(payload, T::clone)
+}
+
 #[derive(Debug, Eq, PartialEq, PartialOrd, Clone, Copy)]
 #[repr(u8)]
 pub(crate) enum DRPC {
@@ -23,8 +125,68 @@ pub(crate) enum DRPC {
     MkDir = 8,
     /// Remove a directory.
     RmDir = 9,
+    /// Flush a file to disk.
+    Fsync = 10,
+    /// Drop the server's page cache.
+    DropCaches = 11,
+    /// Stat a path relative to a directory.
+    FstatAt = 12,
+    /// Copy a file range to another descriptor on the server.
+    Sendfile = 13,
+    /// Run several ops in order, with one round trip.
+    Batch = 14,
+    /// Truncate or extend a file to a length.
+    Ftruncate = 15,
+    /// Ask the server what it supports.
+    Capabilities = 16,
+    /// Move a file offset, or find the next data or hole of a sparse file.
+    Lseek = 17,
+    /// Rename a file or directory.
+    Rename = 18,
+    /// Dump the server's request log.
+    DumpLog = 32,
+    /// Pass an access hint for a file range.
+    Fadvise = 33,
+    /// Dump the server's per-op service time histograms.
+    DumpStats = 34,
+    /// Close every file the calling client has open.
+    Reset = 35,
 }
 
+impl DRPC {
+    /// Whether running the op twice has the same effect as running it once,
+    /// so it can be sent again when the connection broke before its reply
+    /// arrived. Ops that create, remove or close something, or move a file
+    /// offset, are not.
+    pub(crate) fn is_idempotent(&self) -> bool {
+        match self {
+            DRPC::PRead
+            | DRPC::PWrite
+            | DRPC::Fsync
+            | DRPC::DropCaches
+            | DRPC::FstatAt
+            | DRPC::Ftruncate
+            | DRPC::Capabilities
+            | DRPC::DumpLog
+            | DRPC::Fadvise
+            | DRPC::DumpStats => true,
+            DRPC::Open
+            | DRPC::Read
+            | DRPC::Write
+            | DRPC::Close
+            | DRPC::Remove
+            | DRPC::MkDir
+            | DRPC::RmDir
+            | DRPC::Sendfile
+            | DRPC::Lseek
+            | DRPC::Rename
+            | DRPC::Batch
+            | DRPC::Reset => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct OpenReq {
     pub path: Vec<u8>,
     pub flags: i32,
@@ -33,6 +195,7 @@ pub struct OpenReq {
 
 unsafe_abomonate!(OpenReq : path, flags, mode);
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ReadReq {
     pub fd: i32,
     pub size: usize,
@@ -41,6 +204,7 @@ pub struct ReadReq {
 
 unsafe_abomonate!(ReadReq : fd, size, offset);
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct WriteReq {
     pub fd: i32,
     pub page: Vec<u8>,
@@ -50,18 +214,44 @@ pub struct WriteReq {
 
 unsafe_abomonate!(WriteReq : fd, page, size, offset);
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct CloseReq {
     pub fd: i32,
 }
 
 unsafe_abomonate!(CloseReq : fd);
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FsyncReq {
+    pub fd: i32,
+}
+
+unsafe_abomonate!(FsyncReq : fd);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FtruncateReq {
+    pub fd: i32,
+    pub length: i64,
+}
+
+unsafe_abomonate!(FtruncateReq : fd, length);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct RemoveReq {
     pub path: Vec<u8>,
 }
 
 unsafe_abomonate!(RemoveReq : path);
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RenameReq {
+    pub old_path: Vec<u8>,
+    pub new_path: Vec<u8>,
+}
+
+unsafe_abomonate!(RenameReq : old_path, new_path);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct MkdirReq {
     pub path: Vec<u8>,
     pub mode: u32,
@@ -69,6 +259,70 @@ pub struct MkdirReq {
 
 unsafe_abomonate!(MkdirReq : path, mode);
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FadviseReq {
+    pub fd: i32,
+    pub offset: i64,
+    pub len: i64,
+    pub advice: i32,
+}
+
+unsafe_abomonate!(FadviseReq : fd, offset, len, advice);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FstatAtReq {
+    pub dirfd: i32,
+    pub path: Vec<u8>,
+    pub flags: i32,
+}
+
+unsafe_abomonate!(FstatAtReq : dirfd, path, flags);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FstatAtResponse {
+    pub result: i32,
+    pub errno: i32,
+    pub size: i64,
+    pub mode: u32,
+}
+
+unsafe_abomonate!(FstatAtResponse : result, errno, size, mode);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SendfileReq {
+    pub out_fd: i32,
+    pub in_fd: i32,
+    pub offset: i64,
+    pub count: u64,
+}
+
+unsafe_abomonate!(SendfileReq : out_fd, in_fd, offset, count);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SendfileResponse {
+    pub result: i64,
+}
+
+unsafe_abomonate!(SendfileResponse : result);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LseekReq {
+    pub fd: i32,
+    pub offset: i64,
+    pub whence: i32,
+}
+
+unsafe_abomonate!(LseekReq : fd, offset, whence);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LseekResponse {
+    pub result: i64,
+    pub errno: i32,
+}
+
+unsafe_abomonate!(LseekResponse : result, errno);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Response {
     pub result: i32,
     pub size: usize,
@@ -76,3 +330,210 @@ pub struct Response {
 }
 
 unsafe_abomonate!(Response : result, size, page);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BatchEntry {
+    /// DRPC code of the op.
+    pub op: u8,
+    /// Index of the earlier op whose result is the descriptor to use, -1 for
+    /// the one in `payload`.
+    pub fd_from: i64,
+    /// The op's request, encoded as if it was sent on its own.
+    pub payload: Vec<u8>,
+}
+
+unsafe_abomonate!(BatchEntry : op, fd_from, payload);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BatchReq {
+    pub ops: Vec<BatchEntry>,
+}
+
+unsafe_abomonate!(BatchReq : ops);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BatchResponse {
+    pub responses: Vec<Response>,
+}
+
+unsafe_abomonate!(BatchResponse : responses);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LogResponse {
+    pub records: Vec<LogRecord>,
+}
+
+unsafe_abomonate!(LogResponse : records);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StatsResponse {
+    pub ops: Vec<OpHistogram>,
+}
+
+unsafe_abomonate!(StatsResponse : ops);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CapabilitiesResponse {
+    pub protocol_version: u32,
+    pub rpc_types: Vec<String>,
+    pub ops: Vec<String>,
+}
+
+unsafe_abomonate!(CapabilitiesResponse : protocol_version, rpc_types, ops);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use abomonation::decode;
+
+    #[test]
+    fn byte_order_mark_roundtrip() {
+        let mut bytes = Vec::new();
+        encode_msg(&CloseReq { fd: 42 }, &mut bytes);
+        match unsafe { decode::<CloseReq>(strip_byte_order_mark(&mut bytes).unwrap()) } {
+            Some((req, _)) => assert_eq!(req.fd, 42),
+            None => panic!("Cannot decode close request!"),
+        }
+    }
+
+    #[test]
+    fn byte_swapped_mark_is_rejected() {
+        let mut bytes = Vec::new();
+        encode_msg(&CloseReq { fd: 42 }, &mut bytes);
+        bytes[0..BYTE_ORDER_MARK_LEN].reverse();
+        let err = strip_byte_order_mark(&mut bytes).unwrap_err();
+        assert!(err.contains("different byte order"), "{}", err);
+    }
+
+    #[test]
+    fn short_or_unmarked_messages_are_rejected() {
+        assert!(strip_byte_order_mark(&mut [1, 2, 3]).is_err());
+        assert!(strip_byte_order_mark(&mut [0; 16]).is_err());
+        assert!(decode_msg::<CloseReq>(&mut []).is_none());
+    }
+
+    // Encodes `msg` in both formats and checks that both decode back to it
+    fn roundtrip<T>(msg: T)
+    where
+        T: Abomonation + Serialize + DeserializeOwned + Clone + PartialEq + std::fmt::Debug,
+    {
+        let mut abomonated = Vec::new();
+        encode_as(WireFormat::Abomonation, &msg, &mut abomonated);
+        let mut bincoded = Vec::new();
+        encode_as(WireFormat::Bincode, &msg, &mut bincoded);
+        assert_eq!(wire_format_of(&abomonated), WireFormat::Abomonation);
+        assert_eq!(wire_format_of(&bincoded), WireFormat::Bincode);
+
+        let from_abomonation: T = decode_msg(&mut abomonated).expect("Cannot decode abomonation");
+        let from_bincode: T = decode_msg(&mut bincoded).expect("Cannot decode bincode");
+        assert_eq!(from_bincode, from_abomonation);
+        assert_eq!(from_bincode, msg);
+    }
+
+    #[test]
+    fn wire_formats_agree() {
+        let path = b"dir/file.txt".to_vec();
+        roundtrip(OpenReq {
+            path: path.clone(),
+            flags: 0o102,
+            mode: 0o700,
+        });
+        roundtrip(ReadReq {
+            fd: 3,
+            size: 1024,
+            offset: -1,
+        });
+        roundtrip(WriteReq {
+            fd: 3,
+            page: vec![0xab; 1024],
+            size: 1024,
+            offset: 4096,
+        });
+        roundtrip(CloseReq { fd: 3 });
+        roundtrip(FsyncReq { fd: 3 });
+        roundtrip(FtruncateReq {
+            fd: 3,
+            length: 8192,
+        });
+        roundtrip(RemoveReq { path: path.clone() });
+        roundtrip(RenameReq {
+            old_path: path.clone(),
+            new_path: b"renamed.txt".to_vec(),
+        });
+        roundtrip(MkdirReq {
+            path: path.clone(),
+            mode: 0o755,
+        });
+        roundtrip(FadviseReq {
+            fd: 3,
+            offset: 0,
+            len: 1024,
+            advice: 4,
+        });
+        roundtrip(FstatAtReq {
+            dirfd: -100,
+            path: path.clone(),
+            flags: 0,
+        });
+        roundtrip(FstatAtResponse {
+            result: 0,
+            errno: 0,
+            size: 1024,
+            mode: 0o100644,
+        });
+        roundtrip(SendfileReq {
+            out_fd: 4,
+            in_fd: 3,
+            offset: 0,
+            count: 1024,
+        });
+        roundtrip(SendfileResponse { result: 1024 });
+        roundtrip(LseekReq {
+            fd: 3,
+            offset: 4096,
+            whence: 3,
+        });
+        roundtrip(LseekResponse {
+            result: -1,
+            errno: 6,
+        });
+        let read = Response {
+            result: 4,
+            size: 4,
+            page: b"data".to_vec(),
+        };
+        roundtrip(read.clone());
+        let mut close = Vec::new();
+        encode_as(WireFormat::Bincode, &CloseReq { fd: 3 }, &mut close);
+        roundtrip(BatchReq {
+            ops: vec![BatchEntry {
+                op: DRPC::Close as u8,
+                fd_from: -1,
+                payload: close,
+            }],
+        });
+        roundtrip(BatchResponse {
+            responses: vec![read.clone(), read],
+        });
+        roundtrip(LogResponse {
+            records: vec![LogRecord {
+                op: 6,
+                fd: 3,
+                size: 0,
+                offset: 0,
+                result: 0,
+            }],
+        });
+        roundtrip(StatsResponse {
+            ops: vec![OpHistogram {
+                op: 1,
+                buckets: vec![0, 7, 1],
+            }],
+        });
+        roundtrip(CapabilitiesResponse {
+            protocol_version: 2,
+            rpc_types: vec!["drpc".to_string()],
+            ops: vec!["open".to_string(), "batch".to_string()],
+        });
+    }
+}
@@ -1,4 +1,4 @@
-mod fileops;
+pub mod fileops;
 use crate::fxrpc::drpc::fileops::*;
 
 pub mod server;
@@ -6,3 +6,6 @@ pub use crate::fxrpc::drpc::server::*;
 
 pub mod client;
 pub use crate::fxrpc::drpc::client::*;
+
+pub mod quic;
+pub use crate::fxrpc::drpc::quic::*;
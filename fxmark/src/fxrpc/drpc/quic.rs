@@ -0,0 +1,208 @@
+/*
+    DRPC over QUIC, to measure a server across a lossy link.
+    Each DRPC connection is a bidirectional stream of a QUIC connection,
+    which recovers lost packets and backs off under congestion on its own.
+    Both sides relay the stream to one end of a local socket pair and run
+    the DRPC client or server over the other end as over a Unix domain
+    socket, so messages are serialized as on any other transport. A stream
+    that fails closes its socket, which the DRPC side sees as a broken
+    connection.
+*/
+
+use log::debug;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+
+use quinn::{ClientConfig, Endpoint, EndpointConfig, RecvStream, SendStream, ServerConfig};
+use tokio::runtime::Builder;
+
+use crate::fxrpc::drpc::server::server_from_unix;
+use crate::fxrpc::{server_runtime, spawn_server_thread};
+
+// Certificates are made up by every server and not checked, QUIC needs TLS
+// but benchmarks measure storage, not who they talk to
+const SERVER_NAME: &str = "localhost";
+
+struct AnyServerCert;
+
+impl rustls::client::ServerCertVerifier for AnyServerCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+fn client_config() -> ClientConfig {
+    let crypto = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(AnyServerCert))
+        .with_no_client_auth();
+    ClientConfig::new(Arc::new(crypto))
+}
+
+fn server_config() -> ServerConfig {
+    let cert = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()])
+        .expect("Can't generate a QUIC certificate");
+    let key = rustls::PrivateKey(cert.serialize_private_key_der());
+    let chain = vec![rustls::Certificate(cert.serialize_der().unwrap())];
+    ServerConfig::with_single_cert(chain, key).expect("Invalid QUIC certificate")
+}
+
+fn resolve(addr: &str) -> std::io::Result<SocketAddr> {
+    addr.to_socket_addrs()?.next().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{} doesn't resolve", addr),
+        )
+    })
+}
+
+// Copies between `local` and the QUIC stream until either side closes or
+// fails, then closes both
+async fn relay(local: UnixStream, mut send: SendStream, mut recv: RecvStream) {
+    local.set_nonblocking(true).unwrap();
+    let local = tokio::net::UnixStream::from_std(local).unwrap();
+    let (mut local_recv, mut local_send) = local.into_split();
+    tokio::select! {
+        res = tokio::io::copy(&mut recv, &mut local_send) => {
+            debug!("QUIC stream to local socket ended: {:?}", res);
+        }
+        res = tokio::io::copy(&mut local_recv, &mut send) => {
+            debug!("Local socket to QUIC stream ended: {:?}", res);
+        }
+    }
+    let _ = send.finish().await;
+}
+
+/// Connects to the DRPC server at `addr` (`HOST:PORT`) over QUIC and
+/// returns the socket the DRPC client talks to it through. The connection
+/// is served by a thread of its own until the socket is closed.
+pub fn connect_quic(addr: &str) -> std::io::Result<UnixStream> {
+    let server = resolve(addr)?;
+    let (local, remote) = UnixStream::pair()?;
+    let (connected, outcome) = channel();
+    std::thread::spawn(move || {
+        let rt = Builder::new_current_thread().enable_all().build().unwrap();
+        rt.block_on(async move {
+            let bind: SocketAddr = if server.is_ipv4() {
+                "0.0.0.0:0".parse().unwrap()
+            } else {
+                "[::]:0".parse().unwrap()
+            };
+            let stream = async {
+                let mut endpoint = Endpoint::client(bind)?;
+                endpoint.set_default_client_config(client_config());
+                let conn = endpoint
+                    .connect(server, SERVER_NAME)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+                    .await?;
+                let (send, recv) = conn.open_bi().await?;
+                Ok::<_, std::io::Error>((endpoint, conn, send, recv))
+            };
+            match stream.await {
+                Ok((endpoint, conn, send, recv)) => {
+                    let _ = connected.send(Ok(()));
+                    relay(remote, send, recv).await;
+                    conn.close(0u32.into(), b"done");
+                    endpoint.wait_idle().await;
+                }
+                Err(e) => {
+                    let _ = connected.send(Err(e));
+                }
+            }
+        });
+    });
+    outcome
+        .recv()
+        .unwrap_or_else(|_| Err(std::io::ErrorKind::ConnectionAborted.into()))?;
+    Ok(local)
+}
+
+/// Serves DRPC over QUIC on `bind_addr:port`, each stream of each
+/// connection on a thread of its own, as each connection over TCP.
+pub fn start_drpc_server_quic(bind_addr: &str, port: u16) {
+    let addr = resolve(&format!("{}:{}", bind_addr, port)).expect("Invalid QUIC address");
+    let socket = UdpSocket::bind(addr).expect("Failed to create QUIC endpoint");
+    serve_drpc_quic(socket);
+}
+
+/// Serves DRPC over QUIC on `socket`, which tests bind to any free port.
+pub(crate) fn serve_drpc_quic(socket: UdpSocket) {
+    let rt = server_runtime();
+    rt.block_on(async move {
+        let runtime = quinn::default_runtime().expect("QUIC needs a Tokio runtime");
+        let endpoint = Endpoint::new(
+            EndpointConfig::default(),
+            Some(server_config()),
+            socket,
+            runtime,
+        )
+        .expect("Failed to create QUIC endpoint");
+        while let Some(connecting) = endpoint.accept().await {
+            tokio::spawn(async move {
+                let conn = match connecting.await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        debug!("QUIC handshake failed: {}", e);
+                        return;
+                    }
+                };
+                while let Ok((send, recv)) = conn.accept_bi().await {
+                    let (local, remote) = UnixStream::pair().expect("Can't create a socket pair");
+                    spawn_server_thread(move || server_from_unix(local));
+                    tokio::spawn(relay(remote, send, recv));
+                }
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::serve_drpc_quic;
+    use crate::fxrpc::{init_client, set_thread_server_root, RPCEndpoint, RPCType, PAGE_SIZE};
+    use libc::{O_CREAT, O_RDWR, S_IRWXU};
+
+    #[test]
+    fn write_read_over_quic() -> Result<(), Box<dyn std::error::Error>> {
+        // Any free port, so runs of the test don't wait for each other
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+        let endpoint: RPCEndpoint = format!("quic://{}", socket.local_addr()?).parse()?;
+        // Apart from the files of other tests' servers
+        let root = "/dev/shm/fxmark_quic_test";
+        let _ = std::fs::remove_dir_all(root);
+        std::fs::create_dir_all(root)?;
+        std::thread::spawn(move || {
+            set_thread_server_root(root);
+            serve_drpc_quic(socket)
+        });
+
+        let mut client = init_client(&endpoint, RPCType::DRPC);
+
+        let filename = "quic_test.txt";
+        let fd = client.rpc_open(filename, O_CREAT | O_RDWR, S_IRWXU)?;
+        assert!(fd != -1, "QuicTest: Open Failed");
+
+        let page: Vec<u8> = (0..PAGE_SIZE).map(|n| n as u8).collect();
+        let result = client.rpc_pwrite(fd, &page, PAGE_SIZE, PAGE_SIZE as i64)?;
+        assert_eq!(result, PAGE_SIZE as i32, "QuicTest: Write Failed");
+
+        let mut read: Vec<u8> = vec![0; PAGE_SIZE];
+        let result = client.rpc_pread(fd, &mut read, PAGE_SIZE, PAGE_SIZE as i64)?;
+        assert_eq!(result, PAGE_SIZE as i32, "QuicTest: Read Failed");
+        assert_eq!(read, page, "QuicTest: Read Back Other Data");
+
+        assert_eq!(client.rpc_close(fd)?, 0);
+        assert_eq!(client.rpc_remove(filename)?, 0);
+        Ok(())
+    }
+}
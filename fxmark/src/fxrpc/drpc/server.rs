@@ -4,18 +4,59 @@ use rpc::rpc::*;
 use rpc::server::{RPCHandler, Server};
 use rpc::transport::stdtcp::*;
 use rpc::transport::uds::*;
+use std::cell::Cell;
 use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::AsRawFd;
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-use abomonation::{decode, encode};
+use abomonation::Abomonation;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
 use crate::fxrpc::drpc::fileops::*;
-use crate::fxrpc::FS_PATH;
+use crate::fxrpc::fdtable::{
+    close_all_of, connection_client, reserve_open, track_open, untrack_close,
+};
+use crate::fxrpc::reqlog::{dump_request_log, log_request, RequestOp};
+use crate::fxrpc::stats::{record_service_time, server_stats};
+use crate::fxrpc::{
+    drop_caches, fadvise, fstatat, lseek, read_len, read_reply, rename, retry_eintr, sendfile,
+    server_path, spawn_server_thread, write_len, Capabilities, LogRecord, SocketOptions,
+    WireFormat,
+};
 
 ////////////////////////////////// SERVER //////////////////////////////////
 
+thread_local! {
+    // Format of the request being handled, its reply is sent the same way
+    static REPLY_FORMAT: Cell<WireFormat> = Cell::new(WireFormat::Abomonation);
+}
+
+// Replies to the request in `payload` go out in its format. Requests without
+// arguments are only the mark of their format.
+fn note_format(payload: &[u8]) {
+    REPLY_FORMAT.with(|format| format.set(wire_format_of(payload)));
+}
+
+// A request that doesn't decode ends the connection it came in on, the server
+// keeps serving the others.
+fn decode_req<T: Abomonation + DeserializeOwned + Clone>(
+    payload: &mut [u8],
+) -> Result<T, RPCError> {
+    note_format(payload);
+    try_decode_with(payload, T::clone).map_err(|err| {
+        eprintln!("Dropping DRPC connection: {}", err);
+        RPCError::MalformedRequest
+    })
+}
+
+fn encode_reply<T: Abomonation + Serialize>(msg: &T, bytes: &mut Vec<u8>) {
+    encode_as(REPLY_FORMAT.with(Cell::get), msg, bytes);
+}
+
 fn construct_ret(hdr: &mut RPCHeader, payload: &mut [u8], result: i32, size: usize, page: Vec<u8>) {
     let response = Response {
         result: result,
@@ -24,7 +65,7 @@ fn construct_ret(hdr: &mut RPCHeader, payload: &mut [u8], result: i32, size: usi
     };
 
     let mut bytes = Vec::new();
-    unsafe { encode(&response, &mut bytes) }.expect("Failed to encode response");
+    encode_reply(&response, &mut bytes);
 
     payload[0..bytes.len()].copy_from_slice(&bytes);
 
@@ -34,10 +75,9 @@ fn construct_ret(hdr: &mut RPCHeader, payload: &mut [u8], result: i32, size: usi
 }
 
 fn handle_open(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
-    let (path, flags, modes) = match unsafe { decode::<OpenReq>(payload) } {
-        Some((req, _)) => (req.path.clone(), req.flags, req.mode),
-        None => panic!("Cannot decode open request!"),
-    };
+    let start = Instant::now();
+    let req = decode_req::<OpenReq>(payload)?;
+    let (path, flags, modes) = (req.path, req.flags, req.mode);
 
     let path = std::str::from_utf8(&path).unwrap();
 
@@ -46,21 +86,27 @@ fn handle_open(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError>
         path, flags, modes
     );
 
-    let file_path = format!("{}{}{}", FS_PATH, path, char::from(0));
-    let fd;
-    unsafe {
-        fd = open(file_path.as_ptr() as *const i8, flags, modes);
-    }
+    // Other connections' closes free slots on their own threads
+    reserve_open();
+    let fd = match server_path(path) {
+        Ok(file_path) => unsafe {
+            retry_eintr(|| open(file_path.as_ptr() as *const i8, flags, modes))
+        },
+        Err(_) => -1,
+    };
 
+    // Every connection is served on a thread of its own
+    track_open(fd, connection_client());
+    record_service_time(RequestOp::Open, start.elapsed());
+    log_request(RequestOp::Open, -1, 0, 0, fd as i64);
     construct_ret(hdr, payload, fd, 0, vec![]);
     Ok(())
 }
 
 fn handle_read(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
-    let (fd, size, offset) = match unsafe { decode::<ReadReq>(payload) } {
-        Some((req, _)) => (req.fd, req.size, req.offset),
-        None => panic!("Cannot decode read request!"),
-    };
+    let start = Instant::now();
+    let req = decode_req::<ReadReq>(payload)?;
+    let (fd, size, offset) = (req.fd, req.size, req.offset);
 
     debug!(
         "Read request - fd: {:?}, size: {:?}, offset: {:?}",
@@ -68,20 +114,24 @@ fn handle_read(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError>
     );
 
     let res;
-    let page: Vec<u8> = vec![0; size];
+    let mut page: Vec<u8> = vec![0; size];
     unsafe {
-        res = read(fd, page.as_ptr() as *mut c_void, size);
+        res = retry_eintr(|| read(fd, page.as_mut_ptr() as *mut c_void, size));
     }
+    let result = read_reply(res);
+    // Only what was read goes back
+    page.truncate(read_len(result));
 
-    construct_ret(hdr, payload, res as i32, size, page.to_vec());
+    record_service_time(RequestOp::Read, start.elapsed());
+    log_request(RequestOp::Read, fd, size, 0, res as i64);
+    construct_ret(hdr, payload, result, page.len(), page);
     Ok(())
 }
 
 fn handle_pread(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
-    let (fd, size, offset) = match unsafe { decode::<ReadReq>(payload) } {
-        Some((req, _)) => (req.fd, req.size, req.offset),
-        None => panic!("Cannot decode pread request!"),
-    };
+    let start = Instant::now();
+    let req = decode_req::<ReadReq>(payload)?;
+    let (fd, size, offset) = (req.fd, req.size, req.offset);
 
     debug!(
         "PRead request - fd: {:?}, size: {:?}, offset: {:?}",
@@ -89,115 +139,516 @@ fn handle_pread(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError>
     );
 
     let res;
-    let page: Vec<u8> = vec![0; size];
+    let mut page: Vec<u8> = vec![0; size];
     unsafe {
-        res = pread(fd, page.as_ptr() as *mut c_void, size, offset);
+        res = retry_eintr(|| pread(fd, page.as_mut_ptr() as *mut c_void, size, offset));
     }
+    let result = read_reply(res);
+    page.truncate(read_len(result));
 
-    construct_ret(hdr, payload, res as i32, size, page.to_vec());
+    record_service_time(RequestOp::PRead, start.elapsed());
+    log_request(RequestOp::PRead, fd, size, offset, res as i64);
+    construct_ret(hdr, payload, result, page.len(), page);
     Ok(())
 }
 
 fn handle_write(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
-    let (fd, page, size, offset) = match unsafe { decode::<WriteReq>(payload) } {
-        Some((req, _)) => (req.fd, req.page.clone(), req.size, req.offset),
-        None => panic!("Cannot decode write request!"),
-    };
+    let start = Instant::now();
+    let req = decode_req::<WriteReq>(payload)?;
+    let (fd, page, size, offset) = (req.fd, req.page, req.size, req.offset);
 
     debug!(
         "Write request - fd: {:?}, page: {:?}, size: {:?}, offset: {:?}",
         fd, page, size, offset
     );
 
+    let size = write_len(&page, size);
     let res;
     unsafe {
-        res = write(fd, page.as_ptr() as *const c_void, size);
+        res = retry_eintr(|| write(fd, page.as_ptr() as *const c_void, size));
     }
 
-    construct_ret(hdr, payload, res as i32, 0, vec![]);
+    record_service_time(RequestOp::Write, start.elapsed());
+    log_request(RequestOp::Write, fd, size, 0, res as i64);
+    construct_ret(hdr, payload, res as i32, res.max(0) as usize, vec![]);
     Ok(())
 }
 
 fn handle_pwrite(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
-    let (fd, page, size, offset) = match unsafe { decode::<WriteReq>(payload) } {
-        Some((req, _)) => (req.fd, req.page.clone(), req.size, req.offset),
-        None => panic!("Cannot decode pwrite request!"),
-    };
+    let start = Instant::now();
+    let req = decode_req::<WriteReq>(payload)?;
+    let (fd, page, size, offset) = (req.fd, req.page, req.size, req.offset);
 
     debug!(
         "PWrite request - fd: {:?}, page: {:?}, size: {:?}, offset: {:?}",
         fd, page, size, offset
     );
 
+    let size = write_len(&page, size);
     let res;
     unsafe {
-        res = pwrite(fd, page.as_ptr() as *const c_void, size, offset);
+        res = retry_eintr(|| pwrite(fd, page.as_ptr() as *const c_void, size, offset));
     }
 
-    construct_ret(hdr, payload, res as i32, 0, vec![]);
+    record_service_time(RequestOp::PWrite, start.elapsed());
+    log_request(RequestOp::PWrite, fd, size, offset, res as i64);
+    construct_ret(hdr, payload, res as i32, res.max(0) as usize, vec![]);
     Ok(())
 }
 
 fn handle_close(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
-    let fd = match unsafe { decode::<CloseReq>(payload) } {
-        Some((req, _)) => req.fd,
-        None => panic!("Cannot decode close request!"),
-    };
+    let start = Instant::now();
+    let req = decode_req::<CloseReq>(payload)?;
+    let fd = req.fd;
 
     debug!("Close request - fd: {:?}", fd);
 
     let res;
-    unsafe {
-        res = close(fd);
+    if untrack_close(fd, connection_client()) {
+        unsafe {
+            res = close(fd);
+        }
+    } else {
+        // Not this client's to close, or closed already
+        res = -libc::EBADF;
     }
 
+    record_service_time(RequestOp::Close, start.elapsed());
+    log_request(RequestOp::Close, fd, 0, 0, res as i64);
     construct_ret(hdr, payload, res as i32, 0, vec![]);
     Ok(())
 }
 
+fn handle_fsync(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    let start = Instant::now();
+    let req = decode_req::<FsyncReq>(payload)?;
+    let fd = req.fd;
+
+    debug!("Fsync request - fd: {:?}", fd);
+
+    let res;
+    unsafe {
+        res = retry_eintr(|| fsync(fd));
+    }
+
+    record_service_time(RequestOp::Fsync, start.elapsed());
+    log_request(RequestOp::Fsync, fd, 0, 0, res as i64);
+    construct_ret(hdr, payload, res, 0, vec![]);
+    Ok(())
+}
+
+fn handle_ftruncate(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    let start = Instant::now();
+    let req = decode_req::<FtruncateReq>(payload)?;
+    let (fd, length) = (req.fd, req.length);
+
+    debug!("Ftruncate request - fd: {:?}, length: {:?}", fd, length);
+
+    let res;
+    unsafe {
+        res = retry_eintr(|| ftruncate(fd, length));
+    }
+
+    record_service_time(RequestOp::Ftruncate, start.elapsed());
+    log_request(RequestOp::Ftruncate, fd, 0, length, res as i64);
+    construct_ret(hdr, payload, res, 0, vec![]);
+    Ok(())
+}
+
 fn handle_remove(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
-    let path = match unsafe { decode::<RemoveReq>(payload) } {
-        Some((req, _)) => req.path.clone(),
-        None => panic!("Cannot decode remove request!"),
-    };
+    let start = Instant::now();
+    let req = decode_req::<RemoveReq>(payload)?;
+    let path = req.path;
 
     let path = std::str::from_utf8(&path).unwrap();
 
     debug!("Remove request - path: {:?}", path);
 
-    let file_path = format!("{}{}{}", FS_PATH, path, char::from(0));
-    let fd;
-    unsafe {
-        fd = remove(file_path.as_ptr() as *const i8);
-    }
+    let fd = match server_path(path) {
+        Ok(file_path) => unsafe { remove(file_path.as_ptr() as *const i8) },
+        Err(_) => -1,
+    };
 
+    record_service_time(RequestOp::Remove, start.elapsed());
+    log_request(RequestOp::Remove, -1, 0, 0, fd as i64);
     construct_ret(hdr, payload, fd, 0, vec![]);
     Ok(())
 }
 
+fn handle_rename(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    let start = Instant::now();
+    let req = decode_req::<RenameReq>(payload)?;
+    let (old_path, new_path) = (req.old_path, req.new_path);
+
+    let old_path = std::str::from_utf8(&old_path).unwrap();
+    let new_path = std::str::from_utf8(&new_path).unwrap();
+
+    debug!(
+        "Rename request - old_path: {:?}, new_path: {:?}",
+        old_path, new_path
+    );
+
+    let res = rename(old_path, new_path);
+
+    record_service_time(RequestOp::Rename, start.elapsed());
+    log_request(RequestOp::Rename, -1, 0, 0, res as i64);
+    construct_ret(hdr, payload, res, 0, vec![]);
+    Ok(())
+}
+
 fn handle_mkdir(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
-    let (path, modes) = match unsafe { decode::<MkdirReq>(payload) } {
-        Some((req, _)) => (req.path.clone(), req.mode),
-        None => panic!("Cannot decode mkdir request!"),
-    };
+    let start = Instant::now();
+    let req = decode_req::<MkdirReq>(payload)?;
+    let (path, modes) = (req.path, req.mode);
 
     let path = std::str::from_utf8(&path).unwrap();
 
     debug!("Mkdir request - path: {:?}, modes: {:?}", path, modes);
 
-    let dir_path = format!("{}{}{}", FS_PATH, path, char::from(0));
-    let res;
-    unsafe {
-        res = mkdir(dir_path.as_ptr() as *const i8, modes.try_into().unwrap());
-    }
+    let res = match server_path(path) {
+        Ok(dir_path) => unsafe { mkdir(dir_path.as_ptr() as *const i8, modes.try_into().unwrap()) },
+        Err(_) => -1,
+    };
 
+    record_service_time(RequestOp::MkDir, start.elapsed());
+    log_request(RequestOp::MkDir, -1, 0, 0, res as i64);
     construct_ret(hdr, payload, res, 0, vec![]);
     Ok(())
 }
 
 fn handle_rmdir(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
-    debug!("Rmdir request - UNIMPLEMENTED");
-    construct_ret(hdr, payload, 0, 0, vec![]);
+    let start = Instant::now();
+    let req = decode_req::<RemoveReq>(payload)?;
+    let path = req.path;
+
+    let path = std::str::from_utf8(&path).unwrap();
+
+    debug!("Rmdir request - path: {:?}", path);
+
+    let res = match server_path(path) {
+        Ok(dir_path) => unsafe { rmdir(dir_path.as_ptr() as *const i8) },
+        Err(_) => -1,
+    };
+
+    record_service_time(RequestOp::RmDir, start.elapsed());
+    log_request(RequestOp::RmDir, -1, 0, 0, res as i64);
+    construct_ret(hdr, payload, res, 0, vec![]);
+    Ok(())
+}
+
+fn handle_fadvise(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    let start = Instant::now();
+    let req = decode_req::<FadviseReq>(payload)?;
+    let (fd, offset, len, advice) = (req.fd, req.offset, req.len, req.advice);
+
+    debug!(
+        "Fadvise request - fd: {:?}, offset: {:?}, len: {:?}, advice: {:?}",
+        fd, offset, len, advice
+    );
+
+    let res = fadvise(fd, offset, len, advice);
+
+    record_service_time(RequestOp::Fadvise, start.elapsed());
+    log_request(RequestOp::Fadvise, fd, len as usize, offset, res as i64);
+    construct_ret(hdr, payload, res, 0, vec![]);
+    Ok(())
+}
+
+fn handle_drop_caches(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    let start = Instant::now();
+    debug!("DropCaches request");
+    note_format(payload);
+
+    let res = drop_caches();
+
+    record_service_time(RequestOp::DropCaches, start.elapsed());
+    log_request(RequestOp::DropCaches, -1, 0, 0, res as i64);
+    construct_ret(hdr, payload, res, 0, vec![]);
+    Ok(())
+}
+
+fn handle_fstatat(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    let start = Instant::now();
+    let req = decode_req::<FstatAtReq>(payload)?;
+    let (dirfd, path, flags) = (req.dirfd, req.path, req.flags);
+
+    let path = std::str::from_utf8(&path).unwrap();
+
+    debug!(
+        "FstatAt request - dirfd: {:?}, path: {:?}, flags: {:?}",
+        dirfd, path, flags
+    );
+
+    let response = match fstatat(dirfd, path, flags) {
+        Ok(stat) => FstatAtResponse {
+            result: 0,
+            errno: 0,
+            size: stat.size,
+            mode: stat.mode,
+        },
+        Err(errno) => FstatAtResponse {
+            result: -1,
+            errno: errno,
+            size: 0,
+            mode: 0,
+        },
+    };
+
+    record_service_time(RequestOp::FstatAt, start.elapsed());
+    log_request(RequestOp::FstatAt, dirfd, 0, 0, response.result as i64);
+
+    let mut bytes = Vec::new();
+    encode_reply(&response, &mut bytes);
+
+    payload[0..bytes.len()].copy_from_slice(&bytes);
+
+    hdr.msg_id = 0;
+    hdr.msg_type = 0;
+    hdr.msg_len = (bytes.len() * std::mem::size_of::<u8>()) as MsgLen;
+    Ok(())
+}
+
+fn handle_sendfile(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    let start = Instant::now();
+    let req = decode_req::<SendfileReq>(payload)?;
+    let (out_fd, in_fd, offset, count) = (req.out_fd, req.in_fd, req.offset, req.count as usize);
+
+    debug!(
+        "Sendfile request - out_fd: {:?}, in_fd: {:?}, offset: {:?}, count: {:?}",
+        out_fd, in_fd, offset, count
+    );
+
+    let res = sendfile(out_fd, in_fd, offset, count);
+
+    record_service_time(RequestOp::Sendfile, start.elapsed());
+    log_request(RequestOp::Sendfile, in_fd, count, offset, res as i64);
+
+    let response = SendfileResponse { result: res as i64 };
+    let mut bytes = Vec::new();
+    encode_reply(&response, &mut bytes);
+
+    payload[0..bytes.len()].copy_from_slice(&bytes);
+
+    hdr.msg_id = 0;
+    hdr.msg_type = 0;
+    hdr.msg_len = (bytes.len() * std::mem::size_of::<u8>()) as MsgLen;
+    Ok(())
+}
+
+fn handle_lseek(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    let start = Instant::now();
+    let req = decode_req::<LseekReq>(payload)?;
+    let (fd, offset, whence) = (req.fd, req.offset, req.whence);
+
+    debug!(
+        "Lseek request - fd: {:?}, offset: {:?}, whence: {:?}",
+        fd, offset, whence
+    );
+
+    let response = match lseek(fd, offset, whence) {
+        Ok(res) => LseekResponse {
+            result: res,
+            errno: 0,
+        },
+        Err(errno) => LseekResponse {
+            result: -1,
+            errno: errno,
+        },
+    };
+
+    record_service_time(RequestOp::Lseek, start.elapsed());
+    log_request(RequestOp::Lseek, fd, 0, offset, response.result);
+
+    let mut bytes = Vec::new();
+    encode_reply(&response, &mut bytes);
+
+    payload[0..bytes.len()].copy_from_slice(&bytes);
+
+    hdr.msg_id = 0;
+    hdr.msg_type = 0;
+    hdr.msg_len = (bytes.len() * std::mem::size_of::<u8>()) as MsgLen;
+    Ok(())
+}
+
+// Runs one op of a batch through the handler it has on its own and returns
+// its response. `fd` replaces the request's descriptor if set.
+fn run_batch_op(entry: &BatchEntry, fd: Option<i32>) -> Result<Response, RPCError> {
+    let mut sub = entry.payload.clone();
+    let mut reply_len = BYTE_ORDER_MARK_LEN + std::mem::size_of::<Response>();
+    let handler: RPCHandler = match entry.op {
+        op if op == DRPC::Open as u8 => handle_open,
+        op if op == DRPC::Remove as u8 => handle_remove,
+        op if op == DRPC::PRead as u8 => {
+            let req = decode_req::<ReadReq>(&mut sub)?;
+            let req = ReadReq {
+                fd: fd.unwrap_or(req.fd),
+                size: req.size,
+                offset: req.offset,
+            };
+            reply_len += req.size;
+            sub.clear();
+            encode_msg(&req, &mut sub);
+            handle_pread
+        }
+        op if op == DRPC::PWrite as u8 => {
+            let req = decode_req::<WriteReq>(&mut sub)?;
+            let req = WriteReq {
+                fd: fd.unwrap_or(req.fd),
+                page: req.page,
+                size: req.size,
+                offset: req.offset,
+            };
+            sub.clear();
+            encode_msg(&req, &mut sub);
+            handle_pwrite
+        }
+        op if op == DRPC::Fsync as u8 => {
+            let req = decode_req::<FsyncReq>(&mut sub)?;
+            let req = FsyncReq {
+                fd: fd.unwrap_or(req.fd),
+            };
+            sub.clear();
+            encode_msg(&req, &mut sub);
+            handle_fsync
+        }
+        op if op == DRPC::Close as u8 => {
+            let req = decode_req::<CloseReq>(&mut sub)?;
+            let req = CloseReq {
+                fd: fd.unwrap_or(req.fd),
+            };
+            sub.clear();
+            encode_msg(&req, &mut sub);
+            handle_close
+        }
+        op => {
+            debug!("Batch op {} not supported", op);
+            return Ok(Response {
+                result: -1,
+                size: 0,
+                page: vec![],
+            });
+        }
+    };
+
+    if sub.len() < reply_len {
+        sub.resize(reply_len, 0);
+    }
+    let mut hdr = RPCHeader::default();
+    handler(&mut hdr, &mut sub)?;
+    match decode_msg::<Response>(&mut sub) {
+        Some(res) => Ok(res),
+        None => panic!("Cannot decode batched response!"),
+    }
+}
+
+fn handle_batch(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    // The batched ops answer in their own format, the batch in the one it came in
+    let format = wire_format_of(payload);
+    let ops = decode_req::<BatchReq>(payload)?.ops;
+
+    debug!("Batch request - {} ops", ops.len());
+
+    let mut responses: Vec<Response> = Vec::with_capacity(ops.len());
+    for entry in &ops {
+        // A descriptor that an earlier op failed to open stays invalid
+        let fd = if entry.fd_from >= 0 {
+            Some(
+                responses
+                    .get(entry.fd_from as usize)
+                    .map_or(-1, |r| r.result),
+            )
+        } else {
+            None
+        };
+        responses.push(run_batch_op(entry, fd)?);
+    }
+
+    let response = BatchResponse {
+        responses: responses,
+    };
+    let mut bytes = Vec::new();
+    encode_as(format, &response, &mut bytes);
+
+    payload[0..bytes.len()].copy_from_slice(&bytes);
+
+    hdr.msg_id = 0;
+    hdr.msg_type = 0;
+    hdr.msg_len = (bytes.len() * std::mem::size_of::<u8>()) as MsgLen;
+    Ok(())
+}
+
+fn handle_dump_log(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    debug!("DumpLog request");
+    note_format(payload);
+
+    // Keep the newest records that fit into the payload buffer
+    let mut records = dump_request_log();
+    let max_records = (payload.len() - BYTE_ORDER_MARK_LEN - std::mem::size_of::<LogResponse>())
+        / std::mem::size_of::<LogRecord>();
+    if records.len() > max_records {
+        records.drain(0..records.len() - max_records);
+    }
+    let response = LogResponse { records: records };
+
+    let mut bytes = Vec::new();
+    encode_reply(&response, &mut bytes);
+
+    payload[0..bytes.len()].copy_from_slice(&bytes);
+
+    hdr.msg_id = 0;
+    hdr.msg_type = 0;
+    hdr.msg_len = (bytes.len() * std::mem::size_of::<u8>()) as MsgLen;
+    Ok(())
+}
+
+fn handle_dump_stats(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    debug!("DumpStats request");
+    note_format(payload);
+
+    let response = StatsResponse {
+        ops: server_stats(),
+    };
+
+    let mut bytes = Vec::new();
+    encode_reply(&response, &mut bytes);
+
+    payload[0..bytes.len()].copy_from_slice(&bytes);
+
+    hdr.msg_id = 0;
+    hdr.msg_type = 0;
+    hdr.msg_len = (bytes.len() * std::mem::size_of::<u8>()) as MsgLen;
+    Ok(())
+}
+
+fn handle_reset(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    debug!("Reset request");
+    note_format(payload);
+
+    let closed = close_all_of(connection_client());
+    construct_ret(hdr, payload, closed, 0, vec![]);
+    Ok(())
+}
+
+fn handle_capabilities(hdr: &mut RPCHeader, payload: &mut [u8]) -> Result<(), RPCError> {
+    debug!("Capabilities request");
+    note_format(payload);
+
+    // Not rpc's RPCType, the op codes
+    let caps = Capabilities::of_server(crate::fxrpc::RPCType::DRPC);
+    let response = CapabilitiesResponse {
+        protocol_version: caps.protocol_version,
+        rpc_types: caps.rpc_types.iter().map(|t| t.to_string()).collect(),
+        ops: caps.ops,
+    };
+
+    let mut bytes = Vec::new();
+    encode_reply(&response, &mut bytes);
+
+    payload[0..bytes.len()].copy_from_slice(&bytes);
+
+    hdr.msg_id = 0;
+    hdr.msg_type = 0;
+    hdr.msg_len = (bytes.len() * std::mem::size_of::<u8>()) as MsgLen;
     Ok(())
 }
 
@@ -207,9 +658,22 @@ const PREAD_HANDLER: RPCHandler = handle_pread;
 const WRITE_HANDLER: RPCHandler = handle_write;
 const PWRITE_HANDLER: RPCHandler = handle_pwrite;
 const CLOSE_HANDLER: RPCHandler = handle_close;
+const FSYNC_HANDLER: RPCHandler = handle_fsync;
+const FTRUNCATE_HANDLER: RPCHandler = handle_ftruncate;
 const REMOVE_HANDLER: RPCHandler = handle_remove;
+const RENAME_HANDLER: RPCHandler = handle_rename;
 const MKDIR_HANDLER: RPCHandler = handle_mkdir;
 const RMDIR_HANDLER: RPCHandler = handle_rmdir;
+const DUMP_LOG_HANDLER: RPCHandler = handle_dump_log;
+const FADVISE_HANDLER: RPCHandler = handle_fadvise;
+const DUMP_STATS_HANDLER: RPCHandler = handle_dump_stats;
+const DROP_CACHES_HANDLER: RPCHandler = handle_drop_caches;
+const FSTATAT_HANDLER: RPCHandler = handle_fstatat;
+const SENDFILE_HANDLER: RPCHandler = handle_sendfile;
+const LSEEK_HANDLER: RPCHandler = handle_lseek;
+const BATCH_HANDLER: RPCHandler = handle_batch;
+const CAPABILITIES_HANDLER: RPCHandler = handle_capabilities;
+const RESET_HANDLER: RPCHandler = handle_reset;
 
 fn register_rpcs(server: &mut Server) {
     server
@@ -230,15 +694,54 @@ fn register_rpcs(server: &mut Server) {
     server
         .register(DRPC::Close as RPCType, &CLOSE_HANDLER)
         .unwrap();
+    server
+        .register(DRPC::Fsync as RPCType, &FSYNC_HANDLER)
+        .unwrap();
+    server
+        .register(DRPC::Ftruncate as RPCType, &FTRUNCATE_HANDLER)
+        .unwrap();
     server
         .register(DRPC::Remove as RPCType, &REMOVE_HANDLER)
         .unwrap();
+    server
+        .register(DRPC::Rename as RPCType, &RENAME_HANDLER)
+        .unwrap();
     server
         .register(DRPC::MkDir as RPCType, &MKDIR_HANDLER)
         .unwrap();
     server
         .register(DRPC::RmDir as RPCType, &RMDIR_HANDLER)
         .unwrap();
+    server
+        .register(DRPC::DumpLog as RPCType, &DUMP_LOG_HANDLER)
+        .unwrap();
+    server
+        .register(DRPC::Fadvise as RPCType, &FADVISE_HANDLER)
+        .unwrap();
+    server
+        .register(DRPC::DumpStats as RPCType, &DUMP_STATS_HANDLER)
+        .unwrap();
+    server
+        .register(DRPC::DropCaches as RPCType, &DROP_CACHES_HANDLER)
+        .unwrap();
+    server
+        .register(DRPC::FstatAt as RPCType, &FSTATAT_HANDLER)
+        .unwrap();
+    server
+        .register(DRPC::Sendfile as RPCType, &SENDFILE_HANDLER)
+        .unwrap();
+    server
+        .register(DRPC::Lseek as RPCType, &LSEEK_HANDLER)
+        .unwrap();
+    server
+        .register(DRPC::Batch as RPCType, &BATCH_HANDLER)
+        .unwrap();
+    server
+        .register(DRPC::Capabilities as RPCType, &CAPABILITIES_HANDLER)
+        .unwrap();
+    server
+        .register(DRPC::Reset as RPCType, &RESET_HANDLER)
+        .unwrap();
 }
 
 fn server_from_stream(stream: TcpStream) {
@@ -250,16 +753,25 @@ fn server_from_stream(stream: TcpStream) {
     let _ = server.run_server();
 }
 
-pub fn start_drpc_server_tcp(bind_addr: &str, port: u16) {
+pub fn start_drpc_server_tcp(bind_addr: &str, port: u16, sockets: &SocketOptions) {
     let addr = format!("{}:{}", bind_addr, port);
     let listener = TcpListener::bind(addr).expect("Failed to create TCP listener");
+    serve_drpc_tcp(listener, sockets);
+}
 
+/// Serves DRPC on the connections of `listener`, which tests bind to any free
+/// port.
+pub(crate) fn serve_drpc_tcp(listener: TcpListener, sockets: &SocketOptions) {
     for stream in listener.incoming() {
-        std::thread::spawn(move || server_from_stream(stream.unwrap()));
+        let stream = stream.unwrap();
+        sockets
+            .apply(stream.as_raw_fd(), true)
+            .expect("Failed to set socket options");
+        spawn_server_thread(move || server_from_stream(stream));
     }
 }
 
-fn server_from_unix(stream: UnixStream) {
+pub(crate) fn server_from_unix(stream: UnixStream) {
     let transport = UDS {
         stream: Arc::new(Mutex::new(stream)),
     };
@@ -268,7 +780,7 @@ fn server_from_unix(stream: UnixStream) {
     let _ = server.run_server();
 }
 
-pub fn start_drpc_server_uds(path: &str) {
+pub fn start_drpc_server_uds(path: &str, sockets: &SocketOptions) {
     // Remove existing UDS dir
     let _ = std::fs::remove_dir_all(Path::new(path).parent().unwrap());
 
@@ -278,6 +790,25 @@ pub fn start_drpc_server_uds(path: &str) {
     let listener = UnixListener::bind(path).expect("Failed to create UDS listener");
 
     for stream in listener.incoming() {
-        std::thread::spawn(move || server_from_unix(stream.unwrap()));
+        let stream = stream.unwrap();
+        sockets
+            .apply(stream.as_raw_fd(), false)
+            .expect("Failed to set socket options");
+        spawn_server_thread(move || server_from_unix(stream));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undecodable_requests_end_the_connection() {
+        let mut hdr = RPCHeader::default();
+        let mut payload = Vec::new();
+        encode_msg(&CloseReq { fd: 42 }, &mut payload);
+        payload[0..BYTE_ORDER_MARK_LEN].reverse();
+        assert!(handle_close(&mut hdr, &mut payload).is_err());
+        assert!(handle_close(&mut hdr, &mut [1, 2, 3]).is_err());
     }
 }
@@ -0,0 +1,646 @@
+/*
+    Descriptor bookkeeping on both ends of a connection.
+    The server only closes descriptors it opened for a client, and only for
+    that client, clients turn a repeated close of the same
+    descriptor into a no-op. Under an open limit the server also makes opens
+    wait for a close instead of failing with EMFILE.
+*/
+
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+
+use crate::fxrpc::{
+    batch_fd_index, BatchOp, BatchResult, Capabilities, FileStat, FxRPC, LogRecord, OpHistogram,
+};
+
+/// Identifies the client a descriptor was opened for. 0 is every client that
+/// didn't say who it is.
+pub type ClientId = u64;
+
+lazy_static! {
+    static ref OPEN_FDS: Mutex<HashMap<i32, ClientId>> = Mutex::new(HashMap::new());
+    static ref OPEN_SLOTS: Mutex<OpenSlots> = Mutex::new(OpenSlots {
+        limit: None,
+        pending: 0,
+    });
+    static ref SLOT_FREED: Condvar = Condvar::new();
+}
+
+// How many descriptors the server may have open for clients, counting the
+// opens that got a slot but haven't been tracked yet
+struct OpenSlots {
+    limit: Option<usize>,
+    pending: usize,
+}
+
+impl OpenSlots {
+    // Locks OPEN_FDS, so callers must not hold it
+    fn free(&self) -> bool {
+        match self.limit {
+            Some(limit) => OPEN_FDS.lock().unwrap().len() + self.pending < limit,
+            None => true,
+        }
+    }
+}
+
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static CONNECTION: ClientId = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The client of the calling thread, for servers that serve every connection
+/// on a thread of its own.
+pub fn connection_client() -> ClientId {
+    CONNECTION.with(|id| *id)
+}
+
+/// Lets the server keep at most `limit` descriptors open for clients at
+/// once, opens past it wait for a close. None lifts the limit.
+pub fn set_open_limit(limit: Option<usize>) {
+    OPEN_SLOTS.lock().unwrap().limit = limit;
+    SLOT_FREED.notify_all();
+}
+
+/// The limit `set_open_limit()` set.
+pub fn open_limit() -> Option<usize> {
+    OPEN_SLOTS.lock().unwrap().limit
+}
+
+/// Raises the soft `RLIMIT_NOFILE` of the process to its hard limit. Returns
+/// the soft limit in effect afterwards, None if there's none.
+pub fn raise_nofile_limit() -> Option<usize> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    let raised = libc::rlimit {
+        rlim_cur: limit.rlim_max,
+        rlim_max: limit.rlim_max,
+    };
+    // The kernel may cap it below the hard limit (e.g. macOS' OPEN_MAX), the
+    // old soft limit stays then
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raised) } == 0 {
+        limit = raised;
+    }
+    if limit.rlim_cur == libc::RLIM_INFINITY {
+        None
+    } else {
+        Some(limit.rlim_cur as usize)
+    }
+}
+
+/// The soft `RLIMIT_NOFILE` of the process, None if there's none.
+pub fn nofile_limit() -> Option<usize> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0
+        || limit.rlim_cur == libc::RLIM_INFINITY
+    {
+        None
+    } else {
+        Some(limit.rlim_cur as usize)
+    }
+}
+
+/// Sets the open limit to `fraction` of the soft `RLIMIT_NOFILE`, leaving
+/// the rest for the server's sockets and its own files, and returns it.
+pub fn limit_open_fds(fraction: f64) -> Option<usize> {
+    let open_limit = nofile_limit().map(|soft| ((soft as f64 * fraction) as usize).max(1));
+    set_open_limit(open_limit);
+    open_limit
+}
+
+/// Takes a slot for an open if one is free right away. Every slot taken must
+/// be handed back with `track_open()`, whether the open succeeded or not.
+pub fn try_reserve_open() -> bool {
+    let mut slots = OPEN_SLOTS.lock().unwrap();
+    if slots.free() {
+        slots.pending += 1;
+        true
+    } else {
+        false
+    }
+}
+
+/// Waits until a close frees a slot for an open and takes it, see
+/// `try_reserve_open()`.
+pub fn reserve_open() {
+    let mut slots = OPEN_SLOTS.lock().unwrap();
+    while !slots.free() {
+        slots = SLOT_FREED.wait(slots).unwrap();
+    }
+    slots.pending += 1;
+}
+
+// Wakes the opens waiting for a slot
+fn slots_freed() {
+    let _slots = OPEN_SLOTS.lock().unwrap();
+    SLOT_FREED.notify_all();
+}
+
+/// Records a descriptor the server opened on behalf of `client`, or -1 if
+/// the open failed, and hands back the slot the open took.
+pub fn track_open(fd: i32, client: ClientId) {
+    if fd >= 0 {
+        OPEN_FDS.lock().unwrap().insert(fd, client);
+    }
+    let mut slots = OPEN_SLOTS.lock().unwrap();
+    slots.pending = slots.pending.saturating_sub(1);
+    if fd < 0 {
+        SLOT_FREED.notify_all();
+    }
+}
+
+/// Descriptors the server currently has open on behalf of clients.
+pub fn open_fds() -> Vec<i32> {
+    OPEN_FDS.lock().unwrap().keys().copied().collect()
+}
+
+/// Forgets a descriptor `client` is about to close. Returns false if the server
+/// never opened it (or it was closed already), in which case it must not be
+/// closed: the number may belong to one of the server's own sockets. Nor may
+/// a descriptor the server opened for another client.
+pub fn untrack_close(fd: i32, client: ClientId) -> bool {
+    let tracked = {
+        let mut open_fds = OPEN_FDS.lock().unwrap();
+        match open_fds.get(&fd) {
+            Some(owner) if *owner == client => open_fds.remove(&fd).is_some(),
+            _ => false,
+        }
+    };
+    if tracked {
+        slots_freed();
+    }
+    tracked
+}
+
+/// Closes every descriptor the server has open on behalf of `client`, and
+/// only those. Returns how many it closed.
+pub fn close_all_of(client: ClientId) -> i32 {
+    let fds: Vec<i32> = {
+        let mut open_fds = OPEN_FDS.lock().unwrap();
+        let fds = open_fds
+            .iter()
+            .filter(|(_, owner)| **owner == client)
+            .map(|(fd, _)| *fd)
+            .collect();
+        open_fds.retain(|_, owner| *owner != client);
+        fds
+    };
+    for fd in &fds {
+        unsafe { libc::close(*fd) };
+    }
+    if !fds.is_empty() {
+        slots_freed();
+    }
+    fds.len() as i32
+}
+
+/// Wraps a client and remembers the descriptors it closed. Closing one of them
+/// again succeeds without reaching the server, so a stale descriptor can't close
+/// a file another thread opened under the same number in the meantime.
+pub struct TrackedClient {
+    inner: Box<dyn FxRPC>,
+    closed: HashSet<i32>,
+}
+
+impl TrackedClient {
+    pub fn new(inner: Box<dyn FxRPC>) -> TrackedClient {
+        TrackedClient {
+            inner: inner,
+            closed: HashSet::new(),
+        }
+    }
+}
+
+impl FxRPC for TrackedClient {
+    fn rpc_open(
+        &mut self,
+        path: &str,
+        flags: i32,
+        mode: u32,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        let fd = self.inner.rpc_open(path, flags, mode)?;
+        // The number is live again, a close of it is a real close
+        self.closed.remove(&fd);
+        Ok(fd)
+    }
+
+    fn rpc_read(
+        &mut self,
+        fd: i32,
+        page: &mut Vec<u8>,
+        size: usize,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        self.inner.rpc_read(fd, page, size)
+    }
+
+    fn rpc_pread(
+        &mut self,
+        fd: i32,
+        page: &mut Vec<u8>,
+        size: usize,
+        offset: i64,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        self.inner.rpc_pread(fd, page, size, offset)
+    }
+
+    fn rpc_write(
+        &mut self,
+        fd: i32,
+        page: &[u8],
+        size: usize,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        self.inner.rpc_write(fd, page, size)
+    }
+
+    fn rpc_pwrite(
+        &mut self,
+        fd: i32,
+        page: &[u8],
+        size: usize,
+        offset: i64,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        self.inner.rpc_pwrite(fd, page, size, offset)
+    }
+
+    fn rpc_close(&mut self, fd: i32) -> Result<i32, Box<dyn std::error::Error>> {
+        if self.closed.contains(&fd) {
+            return Ok(0);
+        }
+        let res = self.inner.rpc_close(fd)?;
+        if res == 0 {
+            self.closed.insert(fd);
+        }
+        Ok(res)
+    }
+
+    fn rpc_fsync(&mut self, fd: i32) -> Result<i32, Box<dyn std::error::Error>> {
+        self.inner.rpc_fsync(fd)
+    }
+
+    fn rpc_ftruncate(&mut self, fd: i32, length: i64) -> Result<i32, Box<dyn std::error::Error>> {
+        self.inner.rpc_ftruncate(fd, length)
+    }
+
+    fn rpc_remove(&mut self, path: &str) -> Result<i32, Box<dyn std::error::Error>> {
+        self.inner.rpc_remove(path)
+    }
+
+    fn rpc_rename(
+        &mut self,
+        old_path: &str,
+        new_path: &str,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        self.inner.rpc_rename(old_path, new_path)
+    }
+
+    fn rpc_mkdir(&mut self, path: &str, mode: u32) -> Result<i32, Box<dyn std::error::Error>> {
+        self.inner.rpc_mkdir(path, mode)
+    }
+
+    fn rpc_rmdir(&mut self, path: &str) -> Result<i32, Box<dyn std::error::Error>> {
+        self.inner.rpc_rmdir(path)
+    }
+
+    fn rpc_fadvise(
+        &mut self,
+        fd: i32,
+        offset: i64,
+        len: i64,
+        advice: i32,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        self.inner.rpc_fadvise(fd, offset, len, advice)
+    }
+
+    fn rpc_drop_caches(&mut self) -> Result<i32, Box<dyn std::error::Error>> {
+        self.inner.rpc_drop_caches()
+    }
+
+    fn rpc_fstatat(
+        &mut self,
+        dirfd: i32,
+        path: &str,
+        flags: i32,
+    ) -> Result<FileStat, Box<dyn std::error::Error>> {
+        self.inner.rpc_fstatat(dirfd, path, flags)
+    }
+
+    fn rpc_sendfile(
+        &mut self,
+        out_fd: i32,
+        in_fd: i32,
+        offset: i64,
+        count: usize,
+    ) -> Result<isize, Box<dyn std::error::Error>> {
+        self.inner.rpc_sendfile(out_fd, in_fd, offset, count)
+    }
+
+    fn rpc_lseek(
+        &mut self,
+        fd: i32,
+        offset: i64,
+        whence: i32,
+    ) -> Result<i64, Box<dyn std::error::Error>> {
+        self.inner.rpc_lseek(fd, offset, whence)
+    }
+
+    fn rpc_batch(
+        &mut self,
+        ops: Vec<BatchOp>,
+    ) -> Result<Vec<BatchResult>, Box<dyn std::error::Error>> {
+        // Same bookkeeping as for ops sent on their own. Closes of descriptors
+        // closed before still reach the server, which ignores them.
+        let results = self.inner.rpc_batch(ops.clone())?;
+        for (op, res) in ops.iter().zip(&results) {
+            match op {
+                BatchOp::Open { .. } => {
+                    self.closed.remove(&res.result);
+                }
+                BatchOp::Close { fd } if res.result == 0 => {
+                    let fd = match batch_fd_index(*fd) {
+                        Some(index) => results[index].result,
+                        None => *fd,
+                    };
+                    self.closed.insert(fd);
+                }
+                _ => {}
+            }
+        }
+        Ok(results)
+    }
+
+    fn rpc_dump_server_log(&mut self) -> Result<Vec<LogRecord>, Box<dyn std::error::Error>> {
+        self.inner.rpc_dump_server_log()
+    }
+
+    fn rpc_server_stats(&mut self) -> Result<Vec<OpHistogram>, Box<dyn std::error::Error>> {
+        self.inner.rpc_server_stats()
+    }
+
+    fn rpc_capabilities(&mut self) -> Result<Capabilities, Box<dyn std::error::Error>> {
+        self.inner.rpc_capabilities()
+    }
+
+    fn rpc_reset(&mut self) -> Result<i32, Box<dyn std::error::Error>> {
+        self.inner.rpc_reset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fxrpc::{init_client, start_test_server, RPCType, ReadError, PAGE_SIZE};
+    use libc::{O_CREAT, O_RDWR, O_TRUNC, S_IRWXU};
+    use std::sync::mpsc::{channel, RecvTimeoutError};
+    use std::time::Duration;
+
+    #[test]
+    fn server_closes_each_fd_once() {
+        // Far above anything the test process has open
+        let fd = 100_000;
+        assert!(!untrack_close(fd, 1));
+        track_open(fd, 1);
+        assert!(untrack_close(fd, 1));
+        assert!(!untrack_close(fd, 1));
+    }
+
+    #[test]
+    fn only_the_owner_closes_an_fd() {
+        let fd = 100_001;
+        track_open(fd, 1);
+        assert!(!untrack_close(fd, 2));
+        assert!(untrack_close(fd, 1));
+    }
+
+    #[test]
+    fn reset_closes_only_the_clients_fds() {
+        let open = || unsafe { libc::dup(0) };
+        let (mine, theirs) = (u64::MAX, u64::MAX - 1);
+        let my_fds = [open(), open()];
+        let their_fd = open();
+        for fd in my_fds {
+            track_open(fd, mine);
+        }
+        track_open(their_fd, theirs);
+
+        assert_eq!(close_all_of(mine), 2);
+        assert_eq!(close_all_of(mine), 0);
+        assert!(!untrack_close(my_fds[0], mine));
+        assert!(untrack_close(their_fd, theirs));
+        assert_eq!(unsafe { libc::close(their_fd) }, 0);
+    }
+
+    #[test]
+    fn double_close_test() -> Result<(), Box<dyn std::error::Error>> {
+        let endpoint =
+            start_test_server("/dev/shm/fxmark_double_close_test/fx.sock", RPCType::GRPC);
+        let mut client = init_client(&endpoint, RPCType::GRPC);
+
+        let filename = "double_close_test.txt";
+        let fd = client
+            .rpc_open(filename, O_CREAT | O_RDWR, S_IRWXU)
+            .unwrap();
+        assert!(fd != -1, "DoubleCloseTest: Open Failed");
+
+        let result = client.rpc_close(fd).unwrap();
+        assert!(result == 0, "DoubleCloseTest: Close Failed");
+
+        // Another connection may get the same fd number now, the stale close must not hit it
+        let mut other = init_client(&endpoint, RPCType::GRPC);
+        let other_fd = other.rpc_open(filename, O_CREAT | O_RDWR, S_IRWXU).unwrap();
+        assert!(other_fd != -1, "DoubleCloseTest: Reopen Failed");
+
+        let result = client.rpc_close(fd).unwrap();
+        assert!(result == 0, "DoubleCloseTest: Second close wasn't a no-op");
+
+        let mut page: Vec<u8> = vec![0; PAGE_SIZE];
+        let result = other.rpc_pread(other_fd, &mut page, PAGE_SIZE, 0).unwrap();
+        assert!(result != -1, "DoubleCloseTest: Reopened file was closed");
+
+        let result = other.rpc_close(other_fd).unwrap();
+        assert!(result == 0, "DoubleCloseTest: Close Failed");
+
+        let result = client.rpc_remove(filename).unwrap();
+        assert!(result != -1, "DoubleCloseTest: Remove Failed");
+
+        Ok(())
+    }
+
+    fn close_owner_test_base(
+        rpc_type: RPCType,
+        path: &'static str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let endpoint = start_test_server(path, rpc_type);
+        let mut client = init_client(&endpoint, rpc_type);
+        let mut other = init_client(&endpoint, rpc_type);
+
+        let filename = "close_owner_test.txt";
+        let fd = client.rpc_open(filename, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)?;
+        assert!(fd != -1, "CloseOwnerTest: Open Failed");
+
+        // Not the other client's to close
+        let result = other.rpc_close(fd)?;
+        assert!(
+            result == -libc::EBADF,
+            "CloseOwnerTest: Other Client's Close Returned {}",
+            result
+        );
+        let mut page = vec![0; PAGE_SIZE];
+        let result = client.rpc_pread(fd, &mut page, PAGE_SIZE, 0)?;
+        assert!(result == 0, "CloseOwnerTest: File Was Closed");
+
+        let result = client.rpc_close(fd)?;
+        assert!(result == 0, "CloseOwnerTest: Close Failed");
+        client.rpc_remove(filename)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn close_owner_test() -> Result<(), Box<dyn std::error::Error>> {
+        close_owner_test_base(
+            RPCType::GRPC,
+            "/dev/shm/fxmark_close_owner_grpc_test/fx.sock",
+        )?;
+        close_owner_test_base(
+            RPCType::DRPC,
+            "/dev/shm/fxmark_close_owner_drpc_test/fx.sock",
+        )
+    }
+
+    fn reset_test_base(
+        rpc_type: RPCType,
+        path: &'static str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let endpoint = start_test_server(path, rpc_type);
+
+        let mut client = init_client(&endpoint, rpc_type);
+        let mut other = init_client(&endpoint, rpc_type);
+        let filenames = ["reset_test0.txt", "reset_test1.txt", "reset_test2.txt"];
+        let mut fds = Vec::new();
+        for filename in filenames {
+            let fd = client.rpc_open(filename, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)?;
+            assert!(fd != -1, "ResetTest: Open Failed");
+            fds.push(fd);
+        }
+        let other_fd = other.rpc_open(filenames[0], O_RDWR, 0)?;
+        assert!(other_fd != -1, "ResetTest: Other Open Failed");
+
+        let closed = client.rpc_reset()?;
+        assert!(closed == 3, "ResetTest: Closed {} Files", closed);
+        let mut page = vec![0; PAGE_SIZE];
+        for fd in &fds {
+            let err = client.rpc_pread(*fd, &mut page, PAGE_SIZE, 0).unwrap_err();
+            assert!(
+                err.downcast_ref::<ReadError>() == Some(&ReadError::Failed(libc::EBADF)),
+                "ResetTest: Read After Reset Didn't Fail With EBADF"
+            );
+        }
+        // Nothing left to close
+        assert!(
+            client.rpc_reset()? == 0,
+            "ResetTest: Second Reset Closed Files"
+        );
+
+        // The other client's file stays open
+        let result = other.rpc_pread(other_fd, &mut page, PAGE_SIZE, 0)?;
+        assert!(result == 0, "ResetTest: Other Client's File Was Closed");
+        other.rpc_close(other_fd)?;
+        for filename in filenames {
+            client.rpc_remove(filename)?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn reset_test() -> Result<(), Box<dyn std::error::Error>> {
+        reset_test_base(RPCType::GRPC, "/dev/shm/fxmark_reset_grpc_test/fx.sock")?;
+        reset_test_base(RPCType::DRPC, "/dev/shm/fxmark_reset_drpc_test/fx.sock")
+    }
+
+    #[test]
+    fn opens_past_the_limit_wait_for_a_close() -> Result<(), Box<dyn std::error::Error>> {
+        // Lowering RLIMIT_NOFILE and the server's open limit would hold up the
+        // servers of other tests, so check it in a fresh process
+        if std::env::var("FXMARK_OPEN_LIMIT_TEST").is_err() {
+            let status = std::process::Command::new(std::env::current_exe()?)
+                .args([
+                    "--exact",
+                    "fxrpc::fdtable::tests::opens_past_the_limit_wait_for_a_close",
+                ])
+                .env("FXMARK_OPEN_LIMIT_TEST", "1")
+                .status()?;
+            assert!(status.success());
+            return Ok(());
+        }
+
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        assert_eq!(
+            unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) },
+            0
+        );
+        limit.rlim_cur = 128;
+        assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) }, 0);
+        assert_eq!(limit_open_fds(0.125), Some(16));
+
+        let endpoint = start_test_server("/dev/shm/fxmark_open_limit_test/fx.sock", RPCType::DRPC);
+
+        let mut client = init_client(&endpoint, RPCType::DRPC);
+        let mut fds = Vec::new();
+        for n in 0..16 {
+            let fd = client.rpc_open(
+                &format!("open_limit_test{}.txt", n),
+                O_CREAT | O_RDWR,
+                S_IRWXU,
+            )?;
+            assert!(fd != -1, "OpenLimitTest: Open {} Failed", n);
+            fds.push(fd);
+        }
+
+        // One open past the limit, from another client
+        let (done, opened) = channel();
+        let waiting_endpoint = endpoint.clone();
+        std::thread::spawn(move || {
+            let mut client = init_client(&waiting_endpoint, RPCType::DRPC);
+            let fd = client
+                .rpc_open("open_limit_test16.txt", O_CREAT | O_RDWR, S_IRWXU)
+                .unwrap();
+            if fd != -1 {
+                client.rpc_close(fd).unwrap();
+                client.rpc_remove("open_limit_test16.txt").unwrap();
+            }
+            done.send(fd).unwrap();
+        });
+        assert_eq!(
+            opened.recv_timeout(Duration::from_millis(300)),
+            Err(RecvTimeoutError::Timeout),
+            "OpenLimitTest: Open Past The Limit Didn't Wait"
+        );
+
+        // A close lets it through, and it succeeds rather than failing with EMFILE
+        assert_eq!(client.rpc_close(fds.remove(0))?, 0);
+        let fd = opened.recv_timeout(Duration::from_secs(5))?;
+        assert!(fd != -1, "OpenLimitTest: Queued Open Failed");
+
+        for fd in fds {
+            assert_eq!(client.rpc_close(fd)?, 0);
+        }
+        for n in 0..16 {
+            assert_eq!(client.rpc_remove(&format!("open_limit_test{}.txt", n))?, 0);
+        }
+        Ok(())
+    }
+}
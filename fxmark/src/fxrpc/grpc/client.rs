@@ -3,22 +3,36 @@
     Zack McKevitt - 2023
 */
 
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::os::unix::io::AsRawFd;
 use syscalls::{
-    syscall_client::SyscallClient, CloseRequest, DirRequest, OpenRequest, ReadRequest,
-    RemoveRequest, WriteRequest,
+    syscall_client::SyscallClient, CapabilitiesRequest, CloseRequest, DirRequest,
+    DropCachesRequest, DumpLogRequest, DumpStatsRequest, FadviseRequest, FstatAtRequest,
+    FsyncRequest, FtruncateRequest, LseekRequest, OpenRequest, ReadRequest, RemoveRequest,
+    RenameRequest, ResetRequest, SendfileRequest, WriteRequest,
 };
-use tokio::net::UnixStream;
+use tokio::net::{TcpStream, UnixStream};
 use tokio::runtime::Builder;
 use tokio::runtime::Runtime;
-use tonic::{transport::Endpoint, transport::Uri};
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Uri};
 use tower::service_fn;
 
+use crate::fxrpc::grpc::CLIENT_ID_METADATA;
 use crate::fxrpc::*;
 
 //////////////////////////////////////// CLIENT ////////////////////////////////////////
 pub struct BlockingClient {
     client: SyscallClient<tonic::transport::Channel>,
     rt: Option<Runtime>,
+    /// Random, tells this client's descriptors apart from those of other
+    /// clients of the server.
+    id: u64,
+}
+
+// A random client id
+fn new_client_id() -> u64 {
+    RandomState::new().build_hasher().finish()
 }
 
 impl BlockingClient {
@@ -33,14 +47,63 @@ impl BlockingClient {
         Ok(Self {
             client,
             rt: Some(rt),
+            id: new_client_id(),
+        })
+    }
+
+    /// Connects to the server at `addr`, `HOST:PORT`, with `sockets` set on
+    /// the connection.
+    pub fn connect_tcp_with(
+        addr: &str,
+        sockets: &SocketOptions,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let rt = Builder::new_multi_thread().enable_all().build().unwrap();
+        let endpoint = Endpoint::from_shared(format!("http://{}", addr))?;
+        let (addr, sockets) = (addr.to_string(), *sockets);
+        let channel = rt.block_on(endpoint.connect_with_connector(service_fn(move |_: Uri| {
+            let addr = addr.clone();
+            async move {
+                let stream = TcpStream::connect(addr).await?;
+                sockets.apply(stream.as_raw_fd(), true)?;
+                Ok::<_, std::io::Error>(stream)
+            }
+        })))?;
+
+        Ok(Self {
+            client: SyscallClient::new(channel),
+            rt: Some(rt),
+            id: new_client_id(),
         })
     }
 
     pub fn connect_uds() -> Result<Self, tonic::transport::Error> {
-        async fn connect_uds_async() -> tonic::transport::Channel {
+        Self::connect_uds_path(UDS_PATH)
+    }
+
+    pub fn connect_uds_path(path: &str) -> Result<Self, tonic::transport::Error> {
+        Self::connect_uds_with(path, &SocketOptions::default())
+    }
+
+    /// Connects to the server at `path` with the buffer sizes of `sockets`.
+    pub fn connect_uds_with(
+        path: &str,
+        sockets: &SocketOptions,
+    ) -> Result<Self, tonic::transport::Error> {
+        async fn connect_uds_async(
+            path: String,
+            sockets: SocketOptions,
+        ) -> tonic::transport::Channel {
+            // The URI is ignored, the connector always dials the socket at `path`
             Endpoint::try_from("http://[::]:8080")
                 .unwrap()
-                .connect_with_connector(service_fn(|_: Uri| UnixStream::connect(UDS_PATH)))
+                .connect_with_connector(service_fn(move |_: Uri| {
+                    let path = path.clone();
+                    async move {
+                        let stream = UnixStream::connect(path).await?;
+                        sockets.apply(stream.as_raw_fd(), false)?;
+                        Ok::<_, std::io::Error>(stream)
+                    }
+                }))
                 .await
                 .unwrap()
         }
@@ -49,14 +112,56 @@ impl BlockingClient {
             .enable_all()
             .build()
             .unwrap();
-        let channel = rt.block_on(connect_uds_async());
+        let channel = rt.block_on(connect_uds_async(path.to_string(), *sockets));
         let client = SyscallClient::new(channel);
 
         Ok(Self {
             client,
             rt: Some(rt),
+            id: new_client_id(),
         })
     }
+
+    /// Connects to `endpoint` over TLS, checking the server's certificate
+    /// against `tls.ca`.
+    pub fn connect_tls(
+        endpoint: &RPCEndpoint,
+        tls: &TlsConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let ca = std::fs::read(&tls.ca)?;
+        let config = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(ca));
+        let rt = Builder::new_multi_thread().enable_all().build().unwrap();
+        let channel = match endpoint {
+            RPCEndpoint::Tcp { .. } => {
+                let uri = format!("https://{}", endpoint.socket_addr().unwrap());
+                let endpoint = Endpoint::from_shared(uri)?.tls_config(config)?;
+                rt.block_on(endpoint.connect())?
+            }
+            RPCEndpoint::Unix { path } => {
+                // The connector ignores the URI, but the server's certificate
+                // is checked against its host
+                let path = path.clone();
+                let endpoint = Endpoint::from_static("https://localhost").tls_config(config)?;
+                rt.block_on(endpoint.connect_with_connector(service_fn(move |_: Uri| {
+                    UnixStream::connect(path.clone())
+                })))?
+            }
+            RPCEndpoint::Quic { .. } => return Err(Box::from("gRPC doesn't run over QUIC")),
+        };
+
+        Ok(Self {
+            client: SyscallClient::new(channel),
+            rt: Some(rt),
+            id: new_client_id(),
+        })
+    }
+
+    // Tells the server which client `request` comes from
+    fn tag<T>(&self, request: &mut tonic::Request<T>) {
+        request
+            .metadata_mut()
+            .insert(CLIENT_ID_METADATA, self.id.into());
+    }
 }
 
 impl FxRPC for BlockingClient {
@@ -66,11 +171,12 @@ impl FxRPC for BlockingClient {
         flags: i32,
         mode: u32,
     ) -> Result<i32, Box<dyn std::error::Error>> {
-        let request = tonic::Request::new(OpenRequest {
+        let mut request = tonic::Request::new(OpenRequest {
             path: path.to_string(),
             flags: flags,
             mode: mode,
         });
+        self.tag(&mut request);
         let response = self
             .rt
             .as_ref()
@@ -99,8 +205,8 @@ impl FxRPC for BlockingClient {
             .unwrap()
             .block_on(self.client.read(request))?
             .into_inner();
-        *page = response.page;
-        Ok(response.result)
+        copy_read(page, &response.page, response.result);
+        read_result(response.result)
     }
 
     fn rpc_pread(
@@ -123,20 +229,20 @@ impl FxRPC for BlockingClient {
             .unwrap()
             .block_on(self.client.read(request))?
             .into_inner();
-        *page = response.page;
-        Ok(response.result)
+        copy_read(page, &response.page, response.result);
+        read_result(response.result)
     }
 
     fn rpc_write(
         &mut self,
         fd: i32,
-        page: &Vec<u8>,
+        page: &[u8],
         size: usize,
     ) -> Result<i32, Box<dyn std::error::Error>> {
         let request = tonic::Request::new(WriteRequest {
             pwrite: false,
             fd: fd,
-            page: page.to_vec(),
+            page: page[..write_len(page, size)].to_vec(),
             len: size as u32,
             offset: 0,
         });
@@ -153,14 +259,14 @@ impl FxRPC for BlockingClient {
     fn rpc_pwrite(
         &mut self,
         fd: i32,
-        page: &Vec<u8>,
+        page: &[u8],
         size: usize,
         offset: i64,
     ) -> Result<i32, Box<dyn std::error::Error>> {
         let request = tonic::Request::new(WriteRequest {
             pwrite: true,
             fd: fd,
-            page: page.to_vec(),
+            page: page[..write_len(page, size)].to_vec(),
             len: size as u32,
             offset: offset,
         });
@@ -175,7 +281,8 @@ impl FxRPC for BlockingClient {
     }
 
     fn rpc_close(&mut self, fd: i32) -> Result<i32, Box<dyn std::error::Error>> {
-        let request = tonic::Request::new(CloseRequest { fd: fd });
+        let mut request = tonic::Request::new(CloseRequest { fd: fd });
+        self.tag(&mut request);
 
         let response = self
             .rt
@@ -186,6 +293,31 @@ impl FxRPC for BlockingClient {
         Ok(response.result)
     }
 
+    fn rpc_fsync(&mut self, fd: i32) -> Result<i32, Box<dyn std::error::Error>> {
+        let request = tonic::Request::new(FsyncRequest { fd: fd });
+        let response = self
+            .rt
+            .as_ref()
+            .unwrap()
+            .block_on(self.client.fsync(request))?
+            .into_inner();
+        Ok(response.result)
+    }
+
+    fn rpc_ftruncate(&mut self, fd: i32, length: i64) -> Result<i32, Box<dyn std::error::Error>> {
+        let request = tonic::Request::new(FtruncateRequest {
+            fd: fd,
+            length: length,
+        });
+        let response = self
+            .rt
+            .as_ref()
+            .unwrap()
+            .block_on(self.client.ftruncate(request))?
+            .into_inner();
+        Ok(response.result)
+    }
+
     fn rpc_remove(&mut self, path: &str) -> Result<i32, Box<dyn std::error::Error>> {
         let request = tonic::Request::new(RemoveRequest {
             path: path.to_string(),
@@ -199,6 +331,24 @@ impl FxRPC for BlockingClient {
         Ok(response.result)
     }
 
+    fn rpc_rename(
+        &mut self,
+        old_path: &str,
+        new_path: &str,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        let request = tonic::Request::new(RenameRequest {
+            old_path: old_path.to_string(),
+            new_path: new_path.to_string(),
+        });
+        let response = self
+            .rt
+            .as_ref()
+            .unwrap()
+            .block_on(self.client.rename(request))?
+            .into_inner();
+        Ok(response.result)
+    }
+
     fn rpc_mkdir(&mut self, path: &str, mode: u32) -> Result<i32, Box<dyn std::error::Error>> {
         let request = tonic::Request::new(DirRequest {
             path: path.to_string(),
@@ -226,4 +376,184 @@ impl FxRPC for BlockingClient {
             .into_inner();
         Ok(response.result)
     }
+
+    fn rpc_fadvise(
+        &mut self,
+        fd: i32,
+        offset: i64,
+        len: i64,
+        advice: i32,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        let request = tonic::Request::new(FadviseRequest {
+            fd: fd,
+            offset: offset,
+            len: len,
+            advice: advice,
+        });
+        let response = self
+            .rt
+            .as_ref()
+            .unwrap()
+            .block_on(self.client.fadvise(request))?
+            .into_inner();
+        Ok(response.result)
+    }
+
+    fn rpc_drop_caches(&mut self) -> Result<i32, Box<dyn std::error::Error>> {
+        let request = tonic::Request::new(DropCachesRequest {});
+        let response = self
+            .rt
+            .as_ref()
+            .unwrap()
+            .block_on(self.client.drop_caches(request))?
+            .into_inner();
+        Ok(response.result)
+    }
+
+    fn rpc_fstatat(
+        &mut self,
+        dirfd: i32,
+        path: &str,
+        flags: i32,
+    ) -> Result<FileStat, Box<dyn std::error::Error>> {
+        let request = tonic::Request::new(FstatAtRequest {
+            dirfd: dirfd,
+            path: path.to_string(),
+            flags: flags,
+        });
+        let response = self
+            .rt
+            .as_ref()
+            .unwrap()
+            .block_on(self.client.fstat_at(request))?
+            .into_inner();
+        fstatat_result(
+            response.result,
+            response.errno,
+            FileStat {
+                size: response.size,
+                mode: response.mode,
+            },
+        )
+    }
+
+    fn rpc_sendfile(
+        &mut self,
+        out_fd: i32,
+        in_fd: i32,
+        offset: i64,
+        count: usize,
+    ) -> Result<isize, Box<dyn std::error::Error>> {
+        let request = tonic::Request::new(SendfileRequest {
+            out_fd: out_fd,
+            in_fd: in_fd,
+            offset: offset,
+            count: count as u64,
+        });
+        let response = self
+            .rt
+            .as_ref()
+            .unwrap()
+            .block_on(self.client.sendfile(request))?
+            .into_inner();
+        Ok(response.result as isize)
+    }
+
+    fn rpc_lseek(
+        &mut self,
+        fd: i32,
+        offset: i64,
+        whence: i32,
+    ) -> Result<i64, Box<dyn std::error::Error>> {
+        let request = tonic::Request::new(LseekRequest {
+            fd: fd,
+            offset: offset,
+            whence: whence,
+        });
+        let response = self
+            .rt
+            .as_ref()
+            .unwrap()
+            .block_on(self.client.lseek(request))?
+            .into_inner();
+        lseek_result(response.result, response.errno)
+    }
+
+    // gRPC has no batch op, every op is a round trip of its own
+    fn rpc_batch(
+        &mut self,
+        ops: Vec<BatchOp>,
+    ) -> Result<Vec<BatchResult>, Box<dyn std::error::Error>> {
+        run_batch(self, ops)
+    }
+
+    fn rpc_dump_server_log(&mut self) -> Result<Vec<LogRecord>, Box<dyn std::error::Error>> {
+        let request = tonic::Request::new(DumpLogRequest {});
+        let response = self
+            .rt
+            .as_ref()
+            .unwrap()
+            .block_on(self.client.dump_log(request))?
+            .into_inner();
+        Ok(response
+            .records
+            .into_iter()
+            .map(|r| LogRecord {
+                op: r.op as u8,
+                fd: r.fd,
+                size: r.size,
+                offset: r.offset,
+                result: r.result,
+            })
+            .collect())
+    }
+
+    fn rpc_server_stats(&mut self) -> Result<Vec<OpHistogram>, Box<dyn std::error::Error>> {
+        let request = tonic::Request::new(DumpStatsRequest {});
+        let response = self
+            .rt
+            .as_ref()
+            .unwrap()
+            .block_on(self.client.dump_stats(request))?
+            .into_inner();
+        Ok(response
+            .ops
+            .into_iter()
+            .map(|h| OpHistogram {
+                op: h.op as u8,
+                buckets: h.buckets,
+            })
+            .collect())
+    }
+
+    fn rpc_capabilities(&mut self) -> Result<Capabilities, Box<dyn std::error::Error>> {
+        let request = tonic::Request::new(CapabilitiesRequest {});
+        let response = self
+            .rt
+            .as_ref()
+            .unwrap()
+            .block_on(self.client.capabilities(request))?
+            .into_inner();
+        Ok(Capabilities {
+            protocol_version: response.protocol_version,
+            rpc_types: response
+                .rpc_types
+                .iter()
+                .map(|t| t.parse())
+                .collect::<Result<_, String>>()?,
+            ops: response.ops,
+        })
+    }
+
+    fn rpc_reset(&mut self) -> Result<i32, Box<dyn std::error::Error>> {
+        let mut request = tonic::Request::new(ResetRequest {});
+        self.tag(&mut request);
+        let response = self
+            .rt
+            .as_ref()
+            .unwrap()
+            .block_on(self.client.reset(request))?
+            .into_inner();
+        Ok(response.result)
+    }
 }
@@ -0,0 +1,131 @@
+/*
+    Fault injection for the gRPC syscall server.
+    Only compiled for tests, production servers never take this path.
+*/
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+use tonic::Status;
+use x86::random::rdrand32;
+
+use crate::fxrpc::grpc::ERRNO_METADATA;
+
+// Errnos handed out when a request is failed on purpose
+const INJECTED_ERRNOS: [i32; 3] = [libc::EINTR, libc::EAGAIN, libc::EIO];
+
+#[derive(Debug, Clone, Default)]
+pub struct FaultConfig {
+    /// Drop every Nth request.
+    pub drop_every: Option<u32>,
+    /// Delay each request by this amount before processing it.
+    pub delay: Option<Duration>,
+    /// Fraction (0.0 - 1.0) of requests that fail with a random errno.
+    pub error_rate: f64,
+    /// Fail every Nth read request with the given errno.
+    pub read_errno_every: Option<(u32, i32)>,
+}
+
+// A failed request, with its errno where the client can find it
+fn errno_status(errno: i32) -> Status {
+    let mut status = Status::internal(format!("fault injection: errno {}", errno));
+    status.metadata_mut().insert(ERRNO_METADATA, errno.into());
+    status
+}
+
+#[derive(Debug, Default)]
+pub struct FaultState {
+    config: FaultConfig,
+    requests: AtomicU32,
+    reads: AtomicU32,
+    drops: AtomicU64,
+}
+
+impl FaultState {
+    pub fn new(config: FaultConfig) -> FaultState {
+        FaultState {
+            config: config,
+            requests: AtomicU32::new(0),
+            reads: AtomicU32::new(0),
+            drops: AtomicU64::new(0),
+        }
+    }
+
+    /// Number of requests dropped so far.
+    pub fn drops(&self) -> u64 {
+        self.drops.load(Ordering::Acquire)
+    }
+
+    pub fn inject(&self) -> Result<(), Status> {
+        if let Some(delay) = self.config.delay {
+            std::thread::sleep(delay);
+        }
+
+        let n = self.requests.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(every) = self.config.drop_every {
+            if n % every == 0 {
+                self.drops.fetch_add(1, Ordering::Release);
+                return Err(Status::unavailable("fault injection: dropped request"));
+            }
+        }
+
+        if self.config.error_rate > 0.0 {
+            let mut random_num: u32 = 0;
+            unsafe { rdrand32(&mut random_num) };
+            if (random_num as f64 / u32::MAX as f64) < self.config.error_rate {
+                let errno = INJECTED_ERRNOS[random_num as usize % INJECTED_ERRNOS.len()];
+                return Err(errno_status(errno));
+            }
+        }
+        Ok(())
+    }
+
+    /// Faults that only read requests see, on top of `inject()`.
+    pub fn inject_read(&self) -> Result<(), Status> {
+        let n = self.reads.fetch_add(1, Ordering::Relaxed) + 1;
+        match self.config.read_errno_every {
+            Some((every, errno)) if n % every == 0 => Err(errno_status(errno)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fxrpc::grpc::syscalls::{syscall_server::Syscall, ReadRequest};
+    use crate::fxrpc::grpc::SyscallService;
+    use tokio::runtime::Runtime;
+    use tonic::Request;
+
+    #[test]
+    fn drop_every_third_request() {
+        let service = SyscallService::with_faults(FaultConfig {
+            drop_every: Some(3),
+            delay: None,
+            error_rate: 0.0,
+            read_errno_every: None,
+        });
+        let rt = Runtime::new().unwrap();
+
+        let mut attempts = 0;
+        for _ in 0..9 {
+            // Retry each read until it makes it through
+            loop {
+                attempts += 1;
+                let request = Request::new(ReadRequest {
+                    pread: true,
+                    fd: -1,
+                    size: 16,
+                    offset: 0,
+                });
+                if rt.block_on(service.read(request)).is_ok() {
+                    break;
+                }
+            }
+        }
+
+        // Attempts 3, 6, 9 and 12 are dropped
+        assert_eq!(attempts, 13);
+        assert_eq!(service.fault.as_ref().unwrap().drops(), 4);
+    }
+}
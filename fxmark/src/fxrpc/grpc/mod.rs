@@ -8,5 +8,54 @@ pub mod syscalls {
     tonic::include_proto!("syscalls");
 }
 
+#[cfg(test)]
+pub mod fault;
+
+/// Metadata key of the errno a failed request's status carries.
+pub const ERRNO_METADATA: &str = "errno";
+
+/// Metadata key of the id clients tag their opens and resets with, so the
+/// server knows whose descriptors to close on a reset.
+pub const CLIENT_ID_METADATA: &str = "client-id";
+
 #[derive(Debug, Default)]
-pub struct SyscallService {}
+pub struct SyscallService {
+    #[cfg(test)]
+    fault: Option<fault::FaultState>,
+}
+
+impl SyscallService {
+    // Fault injection is only reachable from tests so production paths are unaffected
+    #[cfg(test)]
+    pub fn with_faults(config: fault::FaultConfig) -> SyscallService {
+        SyscallService {
+            fault: Some(fault::FaultState::new(config)),
+        }
+    }
+
+    #[cfg(test)]
+    fn inject_faults(&self) -> Result<(), tonic::Status> {
+        match &self.fault {
+            Some(fault) => fault.inject(),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(test)]
+    fn inject_read_faults(&self) -> Result<(), tonic::Status> {
+        match &self.fault {
+            Some(fault) => fault.inject_read(),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(test))]
+    fn inject_faults(&self) -> Result<(), tonic::Status> {
+        Ok(())
+    }
+
+    #[cfg(not(test))]
+    fn inject_read_faults(&self) -> Result<(), tonic::Status> {
+        Ok(())
+    }
+}
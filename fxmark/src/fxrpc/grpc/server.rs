@@ -6,27 +6,66 @@
 use libc::*;
 use syscalls::{
     syscall_server::{Syscall, SyscallServer},
-    CloseRequest, DirRequest, FstatRequest, FstatResponse, FsyncRequest, OpenRequest, ReadRequest,
-    RemoveRequest, SyscallResponse, WriteRequest,
+    CapabilitiesRequest, CapabilitiesResponse, CloseRequest, DirRequest, DropCachesRequest,
+    DumpLogRequest, DumpLogResponse, DumpStatsRequest, DumpStatsResponse, FadviseRequest,
+    FstatAtRequest, FstatAtResponse, FstatRequest, FstatResponse, FsyncRequest, FtruncateRequest,
+    LseekRequest, LseekResponse, OpenRequest, ReadRequest, RemoveRequest, RenameRequest,
+    ResetRequest, SendfileRequest, SendfileResponse, SyscallResponse, WriteRequest,
 };
-use tokio::net::UnixListener;
-use tokio::runtime::Runtime;
-use tokio_stream::wrappers::UnixListenerStream;
-use tonic::{transport::Server, Request, Response, Status};
+use tokio::net::{TcpListener, UnixListener};
+use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
+use tokio_stream::StreamExt;
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tonic::{Request, Response, Status};
 
+use std::os::unix::io::AsRawFd;
 use std::os::unix::net::UnixListener as StdUnixListener;
 use std::path::Path;
+use std::time::Instant;
 
+use crate::fxrpc::fdtable::{
+    close_all_of, reserve_open, track_open, try_reserve_open, untrack_close, ClientId,
+};
+use crate::fxrpc::grpc::CLIENT_ID_METADATA;
+use crate::fxrpc::reqlog::{dump_request_log, log_request, RequestOp};
+use crate::fxrpc::stats::{record_service_time, server_stats};
 pub use crate::fxrpc::*;
+// Not libc's
+use crate::fxrpc::{fstatat, lseek, rename, sendfile};
 
 //////////////////////////////////////// SERVER ////////////////////////////////////////
 
-fn libc_open(filename: &str, flags: i32, mode: u32) -> Response<syscalls::SyscallResponse> {
-    let file_path = format!("{}{}{}", FS_PATH, filename, char::from(0));
-    let fd;
-    unsafe {
-        fd = open(file_path.as_ptr() as *const i8, flags, mode);
+// The client that sent `request`, 0 if it didn't say
+fn client_of<T>(request: &Request<T>) -> ClientId {
+    request
+        .metadata()
+        .get(CLIENT_ID_METADATA)
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse().ok())
+        .unwrap_or(0)
+}
+
+fn libc_open(
+    filename: &str,
+    flags: i32,
+    mode: u32,
+    client: ClientId,
+) -> Response<syscalls::SyscallResponse> {
+    // Waiting for a slot mustn't keep the runtime from serving the closes
+    // that free one
+    if !try_reserve_open() {
+        tokio::task::block_in_place(reserve_open);
     }
+    let start = Instant::now();
+    let fd = match server_path(filename) {
+        Ok(file_path) => unsafe {
+            retry_eintr(|| open(file_path.as_ptr() as *const i8, flags, mode))
+        },
+        Err(_) => -1,
+    };
+    track_open(fd, client);
+    record_service_time(RequestOp::Open, start.elapsed());
+    log_request(RequestOp::Open, -1, 0, 0, fd as i64);
     Response::new(syscalls::SyscallResponse {
         result: fd,
         page: vec![0],
@@ -34,36 +73,43 @@ fn libc_open(filename: &str, flags: i32, mode: u32) -> Response<syscalls::Syscal
 }
 
 fn libc_read(fd: i32, size: usize) -> Response<syscalls::SyscallResponse> {
+    let start = Instant::now();
     let res;
-    //let page: &mut [u8; size] = &mut [0; size];
-    let page: Vec<u8> = vec![0; size];
+    let mut page: Vec<u8> = vec![0; size];
     unsafe {
-        res = read(fd, page.as_ptr() as *mut c_void, size);
+        res = retry_eintr(|| read(fd, page.as_mut_ptr() as *mut c_void, size));
     }
-    Response::new(syscalls::SyscallResponse {
-        result: res as i32,
-        page: page.to_vec(),
-    })
+    let result = read_reply(res);
+    // Only what was read goes back
+    page.truncate(read_len(result));
+    record_service_time(RequestOp::Read, start.elapsed());
+    log_request(RequestOp::Read, fd, size, 0, res as i64);
+    Response::new(syscalls::SyscallResponse { result, page: page })
 }
 
 fn libc_pread(fd: i32, size: usize, offset: i64) -> Response<syscalls::SyscallResponse> {
+    let start = Instant::now();
     let res;
-    //let page: &mut [u8; size] = &mut [0; size];
-    let page: Vec<u8> = vec![0; size];
+    let mut page: Vec<u8> = vec![0; size];
     unsafe {
-        res = pread(fd, page.as_ptr() as *mut c_void, size, offset);
+        res = retry_eintr(|| pread(fd, page.as_mut_ptr() as *mut c_void, size, offset));
     }
-    Response::new(syscalls::SyscallResponse {
-        result: res as i32,
-        page: page.to_vec(),
-    })
+    let result = read_reply(res);
+    page.truncate(read_len(result));
+    record_service_time(RequestOp::PRead, start.elapsed());
+    log_request(RequestOp::PRead, fd, size, offset, res as i64);
+    Response::new(syscalls::SyscallResponse { result, page: page })
 }
 
 fn libc_write(fd: i32, page: Vec<u8>, len: usize) -> Response<syscalls::SyscallResponse> {
+    let start = Instant::now();
+    let len = write_len(&page, len);
     let res;
     unsafe {
-        res = write(fd, page.as_ptr() as *const c_void, len);
+        res = retry_eintr(|| write(fd, page.as_ptr() as *const c_void, len));
     }
+    record_service_time(RequestOp::Write, start.elapsed());
+    log_request(RequestOp::Write, fd, len, 0, res as i64);
     Response::new(syscalls::SyscallResponse {
         result: res as i32,
         page: vec![0],
@@ -76,21 +122,33 @@ fn libc_pwrite(
     len: usize,
     offset: i64,
 ) -> Response<syscalls::SyscallResponse> {
+    let start = Instant::now();
+    let len = write_len(&page, len);
     let res;
     unsafe {
-        res = pwrite(fd, page.as_ptr() as *const c_void, len, offset);
+        res = retry_eintr(|| pwrite(fd, page.as_ptr() as *const c_void, len, offset));
     }
+    record_service_time(RequestOp::PWrite, start.elapsed());
+    log_request(RequestOp::PWrite, fd, len, offset, res as i64);
     Response::new(syscalls::SyscallResponse {
         result: res as i32,
         page: vec![0],
     })
 }
 
-fn libc_close(fd: i32) -> Response<syscalls::SyscallResponse> {
+fn libc_close(fd: i32, client: ClientId) -> Response<syscalls::SyscallResponse> {
+    let start = Instant::now();
     let res;
-    unsafe {
-        res = close(fd);
+    if untrack_close(fd, client) {
+        unsafe {
+            res = close(fd);
+        }
+    } else {
+        // Not this client's to close, or closed already
+        res = -libc::EBADF;
     }
+    record_service_time(RequestOp::Close, start.elapsed());
+    log_request(RequestOp::Close, fd, 0, 0, res as i64);
     Response::new(syscalls::SyscallResponse {
         result: res,
         page: vec![0],
@@ -98,34 +156,93 @@ fn libc_close(fd: i32) -> Response<syscalls::SyscallResponse> {
 }
 
 fn libc_remove(filename: &str) -> Response<syscalls::SyscallResponse> {
-    let file_path = format!("{}{}{}", FS_PATH, filename, char::from(0));
-    let fd;
-    unsafe {
-        fd = remove(file_path.as_ptr() as *const i8);
-    }
+    let start = Instant::now();
+    let fd = match server_path(filename) {
+        Ok(file_path) => unsafe { remove(file_path.as_ptr() as *const i8) },
+        Err(_) => -1,
+    };
+    record_service_time(RequestOp::Remove, start.elapsed());
+    log_request(RequestOp::Remove, -1, 0, 0, fd as i64);
     Response::new(syscalls::SyscallResponse {
         result: fd,
         page: vec![0],
     })
 }
 
+fn libc_rename(old_path: &str, new_path: &str) -> Response<syscalls::SyscallResponse> {
+    let start = Instant::now();
+    let res = rename(old_path, new_path);
+    record_service_time(RequestOp::Rename, start.elapsed());
+    log_request(RequestOp::Rename, -1, 0, 0, res as i64);
+    Response::new(syscalls::SyscallResponse {
+        result: res,
+        page: vec![0],
+    })
+}
+
 fn libc_fsync(fd: i32) -> Response<syscalls::SyscallResponse> {
+    let start = Instant::now();
     let res;
     unsafe {
-        res = fsync(fd);
+        res = retry_eintr(|| fsync(fd));
     }
+    record_service_time(RequestOp::Fsync, start.elapsed());
+    log_request(RequestOp::Fsync, fd, 0, 0, res as i64);
     Response::new(syscalls::SyscallResponse {
         result: res,
         page: vec![0],
     })
 }
 
-fn libc_mkdir(dirname: &str, mode: u32) -> Response<syscalls::SyscallResponse> {
-    let dir_path = format!("{}{}{}", FS_PATH, dirname, char::from(0));
+fn libc_ftruncate(fd: i32, length: i64) -> Response<syscalls::SyscallResponse> {
+    let start = Instant::now();
     let res;
     unsafe {
-        res = mkdir(dir_path.as_ptr() as *const i8, mode.try_into().unwrap());
+        res = retry_eintr(|| ftruncate(fd, length));
     }
+    record_service_time(RequestOp::Ftruncate, start.elapsed());
+    log_request(RequestOp::Ftruncate, fd, 0, length, res as i64);
+    Response::new(syscalls::SyscallResponse {
+        result: res,
+        page: vec![0],
+    })
+}
+
+fn libc_fadvise(
+    fd: i32,
+    offset: i64,
+    len: i64,
+    advice: i32,
+) -> Response<syscalls::SyscallResponse> {
+    let start = Instant::now();
+    let res = fadvise(fd, offset, len, advice);
+    record_service_time(RequestOp::Fadvise, start.elapsed());
+    log_request(RequestOp::Fadvise, fd, len as usize, offset, res as i64);
+    Response::new(syscalls::SyscallResponse {
+        result: res,
+        page: vec![0],
+    })
+}
+
+fn libc_drop_caches() -> Response<syscalls::SyscallResponse> {
+    let start = Instant::now();
+    let res = drop_caches();
+    record_service_time(RequestOp::DropCaches, start.elapsed());
+    log_request(RequestOp::DropCaches, -1, 0, 0, res as i64);
+    Response::new(syscalls::SyscallResponse {
+        result: res,
+        page: vec![0],
+    })
+}
+
+fn libc_mkdir(dirname: &str, mode: u32) -> Response<syscalls::SyscallResponse> {
+    let start = Instant::now();
+    let res = match server_path(dirname) {
+        Ok(dir_path) => unsafe { mkdir(dir_path.as_ptr() as *const i8, mode.try_into().unwrap()) },
+        Err(_) => -1,
+    };
+    record_service_time(RequestOp::MkDir, start.elapsed());
+    log_request(RequestOp::MkDir, -1, 0, 0, res as i64);
     Response::new(syscalls::SyscallResponse {
         result: res,
         page: vec![0],
@@ -133,11 +250,13 @@ fn libc_mkdir(dirname: &str, mode: u32) -> Response<syscalls::SyscallResponse> {
 }
 
 fn libc_rmdir(dirname: &str) -> Response<syscalls::SyscallResponse> {
-    let dir_path = format!("{}{}{}", FS_PATH, dirname, char::from(0));
-    let res;
-    unsafe {
-        res = rmdir(dir_path.as_ptr() as *const i8);
-    }
+    let start = Instant::now();
+    let res = match server_path(dirname) {
+        Ok(dir_path) => unsafe { rmdir(dir_path.as_ptr() as *const i8) },
+        Err(_) => -1,
+    };
+    record_service_time(RequestOp::RmDir, start.elapsed());
+    log_request(RequestOp::RmDir, -1, 0, 0, res as i64);
     Response::new(syscalls::SyscallResponse {
         result: res,
         page: vec![0],
@@ -148,6 +267,7 @@ fn libc_rmdir(dirname: &str) -> Response<syscalls::SyscallResponse> {
 // Not yet clear how to conver MaybeUninit<stat> to Vec<u8>
 // Mix only needs file size anyways
 fn libc_fstat_size(fd: i32) -> Response<syscalls::FstatResponse> {
+    let start = Instant::now();
     let res;
     let fsize;
     let mut info = std::mem::MaybeUninit::uninit();
@@ -155,12 +275,67 @@ fn libc_fstat_size(fd: i32) -> Response<syscalls::FstatResponse> {
         res = fstat(fd, info.as_mut_ptr());
         fsize = info.assume_init().st_size;
     }
+    record_service_time(RequestOp::Fstat, start.elapsed());
+    log_request(RequestOp::Fstat, fd, 0, 0, res as i64);
     Response::new(syscalls::FstatResponse {
         result: res,
         size: fsize,
     })
 }
 
+fn libc_fstatat(dirfd: i32, path: &str, flags: i32) -> Response<syscalls::FstatAtResponse> {
+    let start = Instant::now();
+    let res = fstatat(dirfd, path, flags);
+    record_service_time(RequestOp::FstatAt, start.elapsed());
+    let response = match res {
+        Ok(stat) => syscalls::FstatAtResponse {
+            result: 0,
+            errno: 0,
+            size: stat.size,
+            mode: stat.mode,
+        },
+        Err(errno) => syscalls::FstatAtResponse {
+            result: -1,
+            errno: errno,
+            size: 0,
+            mode: 0,
+        },
+    };
+    log_request(RequestOp::FstatAt, dirfd, 0, 0, response.result as i64);
+    Response::new(response)
+}
+
+fn libc_sendfile(
+    out_fd: i32,
+    in_fd: i32,
+    offset: i64,
+    count: usize,
+) -> Response<syscalls::SendfileResponse> {
+    let start = Instant::now();
+    let res = sendfile(out_fd, in_fd, offset, count);
+    record_service_time(RequestOp::Sendfile, start.elapsed());
+    log_request(RequestOp::Sendfile, in_fd, count, offset, res as i64);
+    Response::new(syscalls::SendfileResponse { result: res as i64 })
+}
+
+fn libc_lseek(fd: i32, offset: i64, whence: i32) -> Response<syscalls::LseekResponse> {
+    let start = Instant::now();
+    let res = lseek(fd, offset, whence);
+    record_service_time(RequestOp::Lseek, start.elapsed());
+    let response = match res {
+        Ok(res) => syscalls::LseekResponse {
+            result: res,
+            errno: 0,
+        },
+        Err(errno) => syscalls::LseekResponse {
+            result: -1,
+            errno: errno,
+        },
+    };
+    log_request(RequestOp::Lseek, fd, 0, offset, response.result);
+    Response::new(response)
+}
+
 // TODO: Do error handling
 #[tonic::async_trait]
 impl Syscall for SyscallService {
@@ -168,13 +343,17 @@ impl Syscall for SyscallService {
         &self,
         request: Request<OpenRequest>,
     ) -> Result<Response<SyscallResponse>, Status> {
+        self.inject_faults()?;
+        let client = client_of(&request);
         let r = request.into_inner();
-        Ok(libc_open(&r.path, r.flags, r.mode))
+        Ok(libc_open(&r.path, r.flags, r.mode, client))
     }
     async fn read(
         &self,
         request: Request<ReadRequest>,
     ) -> Result<Response<SyscallResponse>, Status> {
+        self.inject_faults()?;
+        self.inject_read_faults()?;
         let r = request.into_inner();
         match r.pread {
             true => Ok(libc_pread(r.fd, r.size as usize, r.offset)),
@@ -185,6 +364,7 @@ impl Syscall for SyscallService {
         &self,
         request: Request<WriteRequest>,
     ) -> Result<Response<SyscallResponse>, Status> {
+        self.inject_faults()?;
         let r = request.into_inner();
         match r.pwrite {
             true => Ok(libc_pwrite(r.fd, r.page, r.len as usize, r.offset)),
@@ -195,27 +375,56 @@ impl Syscall for SyscallService {
         &self,
         request: Request<CloseRequest>,
     ) -> Result<Response<SyscallResponse>, Status> {
+        self.inject_faults()?;
+        let client = client_of(&request);
         let r = request.into_inner();
-        Ok(libc_close(r.fd))
+        Ok(libc_close(r.fd, client))
     }
     async fn remove(
         &self,
         request: Request<RemoveRequest>,
     ) -> Result<Response<SyscallResponse>, Status> {
+        self.inject_faults()?;
         let r = request.into_inner();
         Ok(libc_remove(&r.path))
     }
+    async fn rename(
+        &self,
+        request: Request<RenameRequest>,
+    ) -> Result<Response<SyscallResponse>, Status> {
+        self.inject_faults()?;
+        let r = request.into_inner();
+        Ok(libc_rename(&r.old_path, &r.new_path))
+    }
     async fn fsync(
         &self,
         request: Request<FsyncRequest>,
     ) -> Result<Response<SyscallResponse>, Status> {
+        self.inject_faults()?;
         let r = request.into_inner();
         Ok(libc_fsync(r.fd))
     }
+    async fn ftruncate(
+        &self,
+        request: Request<FtruncateRequest>,
+    ) -> Result<Response<SyscallResponse>, Status> {
+        self.inject_faults()?;
+        let r = request.into_inner();
+        Ok(libc_ftruncate(r.fd, r.length))
+    }
+    async fn fadvise(
+        &self,
+        request: Request<FadviseRequest>,
+    ) -> Result<Response<SyscallResponse>, Status> {
+        self.inject_faults()?;
+        let r = request.into_inner();
+        Ok(libc_fadvise(r.fd, r.offset, r.len, r.advice))
+    }
     async fn mkdir(
         &self,
         request: Request<DirRequest>,
     ) -> Result<Response<SyscallResponse>, Status> {
+        self.inject_faults()?;
         let r = request.into_inner();
         Ok(libc_mkdir(&r.path, r.mode))
     }
@@ -223,6 +432,7 @@ impl Syscall for SyscallService {
         &self,
         request: Request<DirRequest>,
     ) -> Result<Response<SyscallResponse>, Status> {
+        self.inject_faults()?;
         let r = request.into_inner();
         Ok(libc_rmdir(&r.path))
     }
@@ -230,26 +440,154 @@ impl Syscall for SyscallService {
         &self,
         request: Request<FstatRequest>,
     ) -> Result<Response<FstatResponse>, Status> {
+        self.inject_faults()?;
         let r = request.into_inner();
         Ok(libc_fstat_size(r.fd))
     }
+    async fn fstat_at(
+        &self,
+        request: Request<FstatAtRequest>,
+    ) -> Result<Response<FstatAtResponse>, Status> {
+        self.inject_faults()?;
+        let r = request.into_inner();
+        Ok(libc_fstatat(r.dirfd, &r.path, r.flags))
+    }
+    async fn sendfile(
+        &self,
+        request: Request<SendfileRequest>,
+    ) -> Result<Response<SendfileResponse>, Status> {
+        self.inject_faults()?;
+        let r = request.into_inner();
+        Ok(libc_sendfile(r.out_fd, r.in_fd, r.offset, r.count as usize))
+    }
+    async fn lseek(
+        &self,
+        request: Request<LseekRequest>,
+    ) -> Result<Response<LseekResponse>, Status> {
+        self.inject_faults()?;
+        let r = request.into_inner();
+        Ok(libc_lseek(r.fd, r.offset, r.whence))
+    }
+    async fn drop_caches(
+        &self,
+        _request: Request<DropCachesRequest>,
+    ) -> Result<Response<SyscallResponse>, Status> {
+        self.inject_faults()?;
+        Ok(libc_drop_caches())
+    }
+    async fn dump_log(
+        &self,
+        _request: Request<DumpLogRequest>,
+    ) -> Result<Response<DumpLogResponse>, Status> {
+        self.inject_faults()?;
+        let records = dump_request_log()
+            .into_iter()
+            .map(|r| syscalls::LogRecord {
+                op: r.op as u32,
+                fd: r.fd,
+                size: r.size,
+                offset: r.offset,
+                result: r.result,
+            })
+            .collect();
+        Ok(Response::new(DumpLogResponse { records: records }))
+    }
+    async fn dump_stats(
+        &self,
+        _request: Request<DumpStatsRequest>,
+    ) -> Result<Response<DumpStatsResponse>, Status> {
+        self.inject_faults()?;
+        let ops = server_stats()
+            .into_iter()
+            .map(|h| syscalls::OpHistogram {
+                op: h.op as u32,
+                buckets: h.buckets,
+            })
+            .collect();
+        Ok(Response::new(DumpStatsResponse { ops: ops }))
+    }
+    async fn capabilities(
+        &self,
+        _request: Request<CapabilitiesRequest>,
+    ) -> Result<Response<CapabilitiesResponse>, Status> {
+        let caps = Capabilities::of_server(RPCType::GRPC);
+        Ok(Response::new(CapabilitiesResponse {
+            protocol_version: caps.protocol_version,
+            rpc_types: caps.rpc_types.iter().map(|t| t.to_string()).collect(),
+            ops: caps.ops,
+        }))
+    }
+    async fn reset(
+        &self,
+        request: Request<ResetRequest>,
+    ) -> Result<Response<SyscallResponse>, Status> {
+        self.inject_faults()?;
+        Ok(Response::new(syscalls::SyscallResponse {
+            result: close_all_of(client_of(&request)),
+            page: vec![0],
+        }))
+    }
 }
 
-pub fn start_rpc_server_tcp(bind_addr: &str, port: u16) {
+// A server builder, serving over TLS if `tls` is set
+fn server_builder(tls: Option<&TlsConfig>) -> Result<Server, Box<dyn std::error::Error>> {
+    let tls = match tls {
+        Some(tls) => tls,
+        None => return Ok(Server::builder()),
+    };
+    let cert = std::fs::read(&tls.cert)?;
+    let key = std::fs::read(&tls.key)?;
+    let config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+    Ok(Server::builder().tls_config(config)?)
+}
+
+pub fn start_rpc_server_tcp(
+    bind_addr: &str,
+    port: u16,
+    tls: Option<&TlsConfig>,
+    sockets: &SocketOptions,
+) {
     // Create Syscall server
-    let address = format!("{}:{}", bind_addr, port).parse().unwrap();
+    let address: std::net::SocketAddr = format!("{}:{}", bind_addr, port).parse().unwrap();
     let syscalls_service = SyscallService::default();
 
-    let rt = Runtime::new().expect("Failed to obtain runtime object.");
-    let server_future = Server::builder()
+    let rt = server_runtime();
+    let listener = rt
+        .block_on(TcpListener::bind(address))
+        .expect("Failed to create TCP listener");
+    let sockets = *sockets;
+    let incoming = TcpListenerStream::new(listener).map(move |stream| {
+        if let Ok(stream) = &stream {
+            sockets
+                .apply(stream.as_raw_fd(), true)
+                .expect("Failed to set socket options");
+        }
+        stream
+    });
+    let server_future = server_builder(tls)
+        .expect("Failed to set up TLS")
         .add_service(SyscallServer::new(syscalls_service))
-        .serve(address);
+        .serve_with_incoming(incoming);
     rt.block_on(server_future)
         .expect("Failed to successfully run the future on RunTime.");
 }
 
-#[tokio::main]
-pub async fn start_rpc_server_uds(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+pub fn start_rpc_server_uds(
+    path: &str,
+    tls: Option<&TlsConfig>,
+    sockets: &SocketOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    server_runtime().block_on(serve_uds(path, SyscallService::default(), tls, sockets))
+}
+
+/// Serves `syscalls_service` on a Unix domain socket at `path`, over TLS if
+/// `tls` is set, with the buffer sizes of `sockets`.
+pub async fn serve_uds(
+    path: &str,
+    syscalls_service: SyscallService,
+    tls: Option<&TlsConfig>,
+    sockets: &SocketOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
     println!("UDS path: {}", path);
 
     // Remove existing UDS dir
@@ -258,15 +596,21 @@ pub async fn start_rpc_server_uds(path: &str) -> Result<(), Box<dyn std::error::
     // Create dir for UDS
     let _ = std::fs::create_dir_all(Path::new(path).parent().unwrap());
 
-    let syscalls_service = SyscallService::default();
-
     // Create standard, blocking UDS
     let std_uds = StdUnixListener::bind(path).unwrap();
 
     // Create tokio UDS
     let uds = UnixListener::from_std(std_uds).unwrap();
-    let uds_stream = UnixListenerStream::new(uds);
-    Server::builder()
+    let sockets = *sockets;
+    let uds_stream = UnixListenerStream::new(uds).map(move |stream| {
+        if let Ok(stream) = &stream {
+            sockets
+                .apply(stream.as_raw_fd(), false)
+                .expect("Failed to set socket options");
+        }
+        stream
+    });
+    server_builder(tls)?
         .add_service(SyscallServer::new(syscalls_service))
         .serve_with_incoming(uds_stream)
         .await?;
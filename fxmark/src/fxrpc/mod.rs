@@ -1,9 +1,27 @@
 pub mod drpc;
+pub mod fdtable;
 pub mod grpc;
+pub mod pipeline;
+pub mod reqlog;
+pub mod selfcheck;
+pub mod stats;
+pub mod striped;
 use crate::fxrpc::drpc::*;
+use crate::fxrpc::fdtable::TrackedClient;
 use crate::fxrpc::grpc::*;
+use crate::fxrpc::striped::StripedClient;
+use serde::Serialize;
+use std::os::unix::io::{AsRawFd, RawFd};
 
+pub use crate::fxmark::fill::{FillPattern, VerifyMode};
+pub use crate::fxmark::names::{file_name, validate_name_len, Charset};
+pub use crate::fxmark::sizes::SizeDistribution;
+pub use crate::fxmark::utils::topology::ThreadMapping;
+pub use crate::fxmark::Recipe;
+pub use crate::fxmark::RenameMode;
 pub use crate::fxmark::PAGE_SIZE;
+pub use crate::fxrpc::reqlog::LogRecord;
+pub use crate::fxrpc::stats::OpHistogram;
 
 type StdError = Box<dyn std::error::Error + Send + Sync + 'static>;
 type Result<T, E = StdError> = ::std::result::Result<T, E>;
@@ -12,19 +30,727 @@ type Result<T, E = StdError> = ::std::result::Result<T, E>;
 pub const FS_PATH: &str = "/dev/shm/";
 pub const UDS_PATH: &str = "/dev/shm/uds";
 
-#[derive(Clone, Copy, PartialEq)]
+lazy_static::lazy_static! {
+    // The directory servers resolve client paths in, ends with a `/`
+    static ref SERVER_ROOT: std::sync::RwLock<String> = std::sync::RwLock::new(FS_PATH.to_string());
+}
+
+thread_local! {
+    // The directory servers resolve client paths in on the calling thread,
+    // if not SERVER_ROOT
+    static THREAD_ROOT: std::cell::RefCell<Option<String>> = std::cell::RefCell::new(None);
+}
+
+// `root` as it's prepended to paths
+fn with_slash(root: &str) -> String {
+    let mut root = root.to_string();
+    if !root.ends_with('/') {
+        root.push('/');
+    }
+    root
+}
+
+/// Makes servers in this process resolve client paths under `root` instead
+/// of `FS_PATH`.
+pub fn set_server_root(root: &str) {
+    *SERVER_ROOT.write().unwrap() = with_slash(root);
+}
+
+/// Makes the servers the calling thread starts from now on resolve client
+/// paths under `root` instead of the root of the process, so that servers
+/// in one process, like those of the tests, each get their own.
+pub fn set_thread_server_root(root: &str) {
+    THREAD_ROOT.with(|thread_root| *thread_root.borrow_mut() = Some(with_slash(root)));
+}
+
+/// Where the servers the calling thread starts resolve client paths, ends
+/// with a `/`.
+pub fn server_root() -> String {
+    THREAD_ROOT
+        .with(|root| root.borrow().clone())
+        .unwrap_or_else(|| SERVER_ROOT.read().unwrap().clone())
+}
+
+/// Runs `serve` on a thread of its own that resolves client paths where the
+/// calling thread does, for servers that serve each client on a thread.
+pub fn spawn_server_thread<F>(serve: F) -> std::thread::JoinHandle<()>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let root = server_root();
+    std::thread::spawn(move || {
+        set_thread_server_root(&root);
+        serve()
+    })
+}
+
+/// A runtime for a server, whose threads resolve client paths where the
+/// calling thread does.
+pub fn server_runtime() -> tokio::runtime::Runtime {
+    let root = server_root();
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .on_thread_start(move || set_thread_server_root(&root))
+        .build()
+        .expect("Failed to obtain runtime object.")
+}
+
+/// `path` with its `.` and `..` components resolved and without a leading
+/// `/`, so it names something below where it's resolved. EACCES if a `..`
+/// climbs above that. Symlinks aren't followed.
+pub fn confine_path(path: &str) -> Result<String, i32> {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                if parts.pop().is_none() {
+                    return Err(libc::EACCES);
+                }
+            }
+            part => parts.push(part),
+        }
+    }
+    Ok(parts.join("/"))
+}
+
+/// Where the server keeps a client's `path`, NUL terminated for libc, or
+/// EACCES if it would escape the server root, see `confine_path()`.
+pub fn server_path(path: &str) -> Result<String, i32> {
+    let path = confine_path(path)?;
+    THREAD_ROOT.with(|root| match &*root.borrow() {
+        Some(root) => Ok(format!("{}{}{}", root, path, char::from(0))),
+        None => {
+            let root = SERVER_ROOT.read().unwrap();
+            Ok(format!("{}{}{}", root, path, char::from(0)))
+        }
+    })
+}
+
+/// Retries a libc call for as long as it fails with EINTR.
+pub fn retry_eintr<T, F>(mut syscall: F) -> T
+where
+    T: PartialEq + From<i8>,
+    F: FnMut() -> T,
+{
+    loop {
+        let res = syscall();
+        if res != T::from(-1) || std::io::Error::last_os_error().raw_os_error() != Some(libc::EINTR)
+        {
+            return res;
+        }
+    }
+}
+
+/// Passes an access hint for a file range to the kernel. Returns 0 or an errno,
+/// like posix_fadvise itself. Only the SEQUENTIAL, RANDOM, DONTNEED and WILLNEED
+/// hints are accepted.
+#[cfg(target_os = "linux")]
+pub fn fadvise(fd: i32, offset: i64, len: i64, advice: i32) -> i32 {
+    match advice {
+        libc::POSIX_FADV_SEQUENTIAL
+        | libc::POSIX_FADV_RANDOM
+        | libc::POSIX_FADV_DONTNEED
+        | libc::POSIX_FADV_WILLNEED => unsafe { libc::posix_fadvise(fd, offset, len, advice) },
+        _ => libc::EINVAL,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn fadvise(_fd: i32, _offset: i64, _len: i64, _advice: i32) -> i32 {
+    libc::ENOSYS
+}
+
+/// Drops the server's page cache so the reads that follow start cold. Without
+/// root only the pages of the files clients have open are dropped. Returns 0.
+pub fn drop_caches() -> i32 {
+    if crate::fxmark::utils::drop_caches() {
+        return 0;
+    }
+    for fd in fdtable::open_fds() {
+        // DONTNEED skips dirty pages, write them back first
+        retry_eintr(|| unsafe { libc::fsync(fd) });
+        fadvise(fd, 0, 0, libc::POSIX_FADV_DONTNEED);
+    }
+    0
+}
+
+/// Copies `count` bytes of `in_fd` starting at `offset` to `out_fd` without
+/// passing them through userspace, looping over short transfers. Returns the
+/// number of bytes copied, less than `count` if `in_fd` ends first, or -1 if
+/// nothing could be copied.
+#[cfg(target_os = "linux")]
+pub fn sendfile(out_fd: i32, in_fd: i32, offset: i64, count: usize) -> isize {
+    let mut offset: libc::off_t = offset;
+    let mut copied = 0;
+    while copied < count {
+        let res =
+            retry_eintr(|| unsafe { libc::sendfile(out_fd, in_fd, &mut offset, count - copied) });
+        if res == -1 && copied == 0 {
+            return -1;
+        }
+        if res <= 0 {
+            break;
+        }
+        copied += res as usize;
+    }
+    copied as isize
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn sendfile(_out_fd: i32, _in_fd: i32, _offset: i64, _count: usize) -> isize {
+    -1
+}
+
+/// Renames the client path `old_path` to `new_path`, replacing what was
+/// there, as rename(2) does. Returns 0, or -1 if the rename failed or either
+/// path would escape the server root.
+pub fn rename(old_path: &str, new_path: &str) -> i32 {
+    match (server_path(old_path), server_path(new_path)) {
+        (Ok(old_path), Ok(new_path)) => retry_eintr(|| unsafe {
+            libc::rename(
+                old_path.as_ptr() as *const i8,
+                new_path.as_ptr() as *const i8,
+            )
+        }),
+        _ => -1,
+    }
+}
+
+/// Moves the offset of `fd` as lseek(2) does and returns the new offset.
+/// Besides `SEEK_SET`, `SEEK_CUR` and `SEEK_END`, Linux takes `SEEK_DATA` and
+/// `SEEK_HOLE` to find the next data or hole at or after `offset`, failing
+/// with ENXIO if there is none because `offset` is at or past EOF.
+/// Filesystems without holes report all of a file as data. Returns the
+/// errno on failure.
+pub fn lseek(fd: i32, offset: i64, whence: i32) -> Result<i64, i32> {
+    let res = unsafe { libc::lseek(fd, offset as libc::off_t, whence) };
+    if res == -1 {
+        return Err(std::io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or(libc::EIO));
+    }
+    Ok(res as i64)
+}
+
+// Turns the server's reply to an lseek into the client's result, failures
+// carry the server's errno
+fn lseek_result(result: i64, errno: i32) -> Result<i64, Box<dyn std::error::Error>> {
+    if result == -1 {
+        Err(Box::new(std::io::Error::from_raw_os_error(errno)))
+    } else {
+        Ok(result)
+    }
+}
+
+/// What `rpc_fstatat` found out about a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileStat {
+    pub size: i64,
+    /// File type and permission bits, as in `st_mode`.
+    pub mode: u32,
+}
+
+impl FileStat {
+    pub fn is_symlink(&self) -> bool {
+        self.mode & libc::S_IFMT as u32 == libc::S_IFLNK as u32
+    }
+
+    pub fn is_block_device(&self) -> bool {
+        self.mode & libc::S_IFMT as u32 == libc::S_IFBLK as u32
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & libc::S_IFMT as u32 == libc::S_IFDIR as u32
+    }
+}
+
+// _IOR(0x12, 114, size_t) of linux/fs.h
+#[cfg(target_os = "linux")]
+const BLKGETSIZE64: libc::c_ulong = 0x8008_1272;
+
+/// Size in bytes of the block device `path`, NUL terminated and relative to
+/// `dirfd`. stat reports 0 for devices. Returns the errno on failure.
+#[cfg(target_os = "linux")]
+pub fn block_device_size(dirfd: i32, path: &str) -> Result<i64, i32> {
+    let errno = || {
+        std::io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or(libc::EIO)
+    };
+    let fd = retry_eintr(|| unsafe {
+        libc::openat(
+            dirfd,
+            path.as_ptr() as *const i8,
+            libc::O_RDONLY | libc::O_CLOEXEC,
+        )
+    });
+    if fd == -1 {
+        return Err(errno());
+    }
+    let mut size: u64 = 0;
+    let res = unsafe { libc::ioctl(fd, BLKGETSIZE64 as _, &mut size) };
+    let err = errno();
+    unsafe { libc::close(fd) };
+    if res == -1 {
+        Err(err)
+    } else {
+        Ok(size as i64)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn block_device_size(_dirfd: i32, _path: &str) -> Result<i64, i32> {
+    Err(libc::ENOTTY)
+}
+
+/// Stats `path` relative to the directory `dirfd`, or to the server directory
+/// if `dirfd` is `AT_FDCWD`. Only `AT_SYMLINK_NOFOLLOW` is accepted in `flags`.
+/// The size of a block device is the device's. Returns the errno on failure.
+pub fn fstatat(dirfd: i32, path: &str, flags: i32) -> Result<FileStat, i32> {
+    if flags & !libc::AT_SYMLINK_NOFOLLOW != 0 {
+        return Err(libc::EINVAL);
+    }
+    let path = if dirfd == libc::AT_FDCWD {
+        server_path(path)?
+    } else {
+        format!("{}{}", confine_path(path)?, char::from(0))
+    };
+
+    let mut info = std::mem::MaybeUninit::uninit();
+    let res = unsafe { libc::fstatat(dirfd, path.as_ptr() as *const i8, info.as_mut_ptr(), flags) };
+    if res == -1 {
+        return Err(std::io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or(libc::EIO));
+    }
+    let info = unsafe { info.assume_init() };
+    let mut stat = FileStat {
+        size: info.st_size,
+        mode: info.st_mode as u32,
+    };
+    if stat.is_block_device() {
+        stat.size = block_device_size(dirfd, &path)?;
+    }
+    Ok(stat)
+}
+
+// Turns the server's reply to an fstatat into the client's result, failures
+// carry the server's errno
+fn fstatat_result(
+    result: i32,
+    errno: i32,
+    stat: FileStat,
+) -> Result<FileStat, Box<dyn std::error::Error>> {
+    if result == -1 {
+        Err(Box::new(std::io::Error::from_raw_os_error(errno)))
+    } else {
+        Ok(stat)
+    }
+}
+
+/// One op of an `rpc_batch`. Ops on a file the batch opens itself take the
+/// descriptor `batch_fd()` returns for the index of the `Open`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BatchOp {
+    Open { path: String, flags: i32, mode: u32 },
+    PRead { fd: i32, size: usize, offset: i64 },
+    PWrite { fd: i32, page: Vec<u8>, offset: i64 },
+    Fsync { fd: i32 },
+    Close { fd: i32 },
+    Remove { path: String },
+}
+
+/// Stands for the descriptor that op `index` of the same batch opened.
+pub fn batch_fd(index: usize) -> i32 {
+    -2 - index as i32
+}
+
+/// The index of the op a descriptor from `batch_fd()` refers to, None for
+/// ordinary descriptors.
+pub fn batch_fd_index(fd: i32) -> Option<usize> {
+    if fd <= -2 {
+        Some((-2 - fd) as usize)
+    } else {
+        None
+    }
+}
+
+/// What one op of a batch returned.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchResult {
+    /// The op's result as if it had been sent on its own, e.g. the descriptor
+    /// of an open or the bytes written. A failed read has -errno, as in
+    /// `read_reply()`.
+    pub result: i32,
+    /// The bytes a read returned, empty for other ops.
+    pub page: Vec<u8>,
+}
+
+/// Runs a batch one op (and round trip) at a time, for transports without a
+/// batch op of their own.
+pub fn run_batch(
+    client: &mut dyn FxRPC,
+    ops: Vec<BatchOp>,
+) -> Result<Vec<BatchResult>, Box<dyn std::error::Error>> {
+    let mut results: Vec<BatchResult> = Vec::with_capacity(ops.len());
+    for op in ops {
+        let fd = |fd: i32| match batch_fd_index(fd) {
+            Some(index) => results.get(index).map_or(-1, |r| r.result),
+            None => fd,
+        };
+        let mut page = Vec::new();
+        let result = match op {
+            BatchOp::Open { path, flags, mode } => client.rpc_open(&path, flags, mode)?,
+            BatchOp::PRead {
+                fd: f,
+                size,
+                offset,
+            } => {
+                page = vec![0; size];
+                // A failed read doesn't fail the batch, like on the server
+                let result = match client.rpc_pread(fd(f), &mut page, size, offset) {
+                    Ok(n) => n,
+                    Err(e) => match e.downcast_ref::<ReadError>() {
+                        Some(e) => -e.errno(),
+                        None => return Err(e),
+                    },
+                };
+                page.truncate(read_len(result));
+                result
+            }
+            BatchOp::PWrite {
+                fd: f,
+                page: data,
+                offset,
+            } => client.rpc_pwrite(fd(f), &data, data.len(), offset)?,
+            BatchOp::Fsync { fd: f } => client.rpc_fsync(fd(f))?,
+            BatchOp::Close { fd: f } => client.rpc_close(fd(f))?,
+            BatchOp::Remove { path } => client.rpc_remove(&path)?,
+        };
+        results.push(BatchResult { result, page });
+    }
+    Ok(results)
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
 #[repr(C)]
 pub enum LogMode {
     CSV,
     STDOUT,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[repr(C)]
+pub enum ErrorPolicy {
+    AbortOnError,
+    ContinueOnError,
+}
+
+impl ErrorPolicy {
+    // Panics under AbortOnError, otherwise returns so the caller can count the failure
+    pub fn on_error(&self, msg: &str) {
+        if *self == ErrorPolicy::AbortOnError {
+            panic!("{}", msg);
+        }
+    }
+}
+
+// Errnos known by name on the command line and in the results
+const ERRNO_NAMES: [(&str, i32); 16] = [
+    ("EPERM", libc::EPERM),
+    ("ENOENT", libc::ENOENT),
+    ("EINTR", libc::EINTR),
+    ("EIO", libc::EIO),
+    ("EBADF", libc::EBADF),
+    ("EAGAIN", libc::EAGAIN),
+    ("ENOMEM", libc::ENOMEM),
+    ("EACCES", libc::EACCES),
+    ("EBUSY", libc::EBUSY),
+    ("EEXIST", libc::EEXIST),
+    ("EINVAL", libc::EINVAL),
+    ("ENFILE", libc::ENFILE),
+    ("EMFILE", libc::EMFILE),
+    ("ENOSPC", libc::ENOSPC),
+    ("ETIMEDOUT", libc::ETIMEDOUT),
+    ("ESTALE", libc::ESTALE),
+];
+
+/// Parses an errno given by name (`EAGAIN`) or number (`11`).
+pub fn parse_errno(s: &str) -> Result<i32, String> {
+    if let Some((_, errno)) = ERRNO_NAMES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(s))
+    {
+        return Ok(*errno);
+    }
+    match s.parse::<i32>() {
+        Ok(errno) if errno > 0 => Ok(errno),
+        _ => Err(format!("Unknown errno '{}'", s)),
+    }
+}
+
+/// The name of `errno`, or its number if it has none in `ERRNO_NAMES`.
+pub fn errno_name(errno: i32) -> String {
+    match ERRNO_NAMES.iter().find(|(_, e)| *e == errno) {
+        Some((name, _)) => name.to_string(),
+        None => errno.to_string(),
+    }
+}
+
+// The value of the open(2) flag called `name`
+fn open_flag(name: &str) -> Option<i32> {
+    match name {
+        "O_RDONLY" => Some(libc::O_RDONLY),
+        "O_WRONLY" => Some(libc::O_WRONLY),
+        "O_RDWR" => Some(libc::O_RDWR),
+        "O_CREAT" => Some(libc::O_CREAT),
+        "O_EXCL" => Some(libc::O_EXCL),
+        "O_TRUNC" => Some(libc::O_TRUNC),
+        "O_APPEND" => Some(libc::O_APPEND),
+        "O_NONBLOCK" => Some(libc::O_NONBLOCK),
+        "O_SYNC" => Some(libc::O_SYNC),
+        "O_DSYNC" => Some(libc::O_DSYNC),
+        "O_NOFOLLOW" => Some(libc::O_NOFOLLOW),
+        "O_CLOEXEC" => Some(libc::O_CLOEXEC),
+        #[cfg(target_os = "linux")]
+        "O_DIRECT" => Some(libc::O_DIRECT),
+        #[cfg(target_os = "linux")]
+        "O_NOATIME" => Some(libc::O_NOATIME),
+        _ => None,
+    }
+}
+
+/// Parses a comma separated list of open(2) flags, e.g. `O_CREAT,O_RDWR`,
+/// into their bitwise or.
+pub fn parse_open_flags(s: &str) -> Result<i32, String> {
+    s.split(',').try_fold(0, |flags, name| {
+        let name = name.trim().to_ascii_uppercase();
+        match open_flag(&name) {
+            Some(flag) => Ok(flags | flag),
+            None => Err(format!("Unknown open flag '{}'", name)),
+        }
+    })
+}
+
+/// The errno an op failed with, if the client got to see it: the OS error of
+/// an I/O error, or the errno a gRPC status carries. None if the op succeeded.
+pub fn op_errno<T>(res: &std::result::Result<T, Box<dyn std::error::Error>>) -> Option<i32> {
+    let err = res.as_ref().err()?;
+    if let Some(e) = err.downcast_ref::<std::io::Error>() {
+        return e.raw_os_error();
+    }
+    if let Some(e) = err.downcast_ref::<ReadError>() {
+        return Some(e.errno());
+    }
+    let status = err.downcast_ref::<tonic::Status>()?;
+    status
+        .metadata()
+        .get(grpc::ERRNO_METADATA)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// How a read failed. A read that reached EOF doesn't fail, it returns 0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadError {
+    /// The fd is nonblocking and has no data yet, e.g. an empty pipe whose
+    /// writer is still open. Unlike at EOF, retrying may return data.
+    WouldBlock,
+    /// The read failed with this errno.
+    Failed(i32),
+}
+
+impl ReadError {
+    pub fn errno(&self) -> i32 {
+        match self {
+            ReadError::WouldBlock => libc::EAGAIN,
+            ReadError::Failed(errno) => *errno,
+        }
+    }
+}
+
+impl core::fmt::Display for ReadError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            ReadError::WouldBlock => write!(f, "read would block"),
+            ReadError::Failed(errno) => write!(f, "read failed ({})", errno_name(*errno)),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+/// The `result` servers reply to a read with, for read(2)'s return value
+/// `res`: the bytes read, or -errno if it failed. Call it right after the
+/// read, before anything else can change errno.
+pub fn read_reply(res: isize) -> i32 {
+    if res < 0 {
+        -std::io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or(libc::EIO)
+    } else {
+        res as i32
+    }
+}
+
+/// What clients return for a read whose reply carried `result`, see
+/// `read_reply()`.
+pub fn read_result(result: i32) -> std::result::Result<i32, Box<dyn std::error::Error>> {
+    match result {
+        n if n >= 0 => Ok(n),
+        n if -n == libc::EAGAIN || -n == libc::EWOULDBLOCK => Err(Box::new(ReadError::WouldBlock)),
+        n => Err(Box::new(ReadError::Failed(-n))),
+    }
+}
+
+/// The bytes a read whose reply carried `result` transferred, 0 if it failed.
+pub fn read_len(result: i32) -> usize {
+    result.max(0) as usize
+}
+
+/// Copies the bytes a read returned in `data` to the start of `page`, which
+/// only grows if it's too short to hold them. Bytes past them keep what they
+/// held, so a short read only changes what it read.
+pub fn copy_read(page: &mut Vec<u8>, data: &[u8], result: i32) {
+    let len = read_len(result).min(data.len());
+    if page.len() < len {
+        page.resize(len, 0);
+    }
+    page[..len].copy_from_slice(&data[..len]);
+}
+
+/// The bytes of `page` a write of `size` bytes sends, never more than it has.
+pub fn write_len(page: &[u8], size: usize) -> usize {
+    size.min(page.len())
+}
+
+/// When write benches make their writes durable.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum SyncPolicy {
+    /// Never sync, measures page cache throughput.
+    None,
+    /// fsync after every write.
+    EveryOp,
+    /// fsync after every Nth write.
+    EveryN(usize),
+    /// Open files with O_SYNC, every write is synchronous on the server.
+    OSync,
+}
+
+impl SyncPolicy {
+    /// Flags to add when opening files written under this policy.
+    pub fn open_flags(&self) -> i32 {
+        match self {
+            SyncPolicy::OSync => libc::O_SYNC,
+            _ => 0,
+        }
+    }
+
+    /// Whether the `writes`-th write (counting from 1) has to be followed by an fsync.
+    pub fn sync_after(&self, writes: usize) -> bool {
+        match self {
+            SyncPolicy::EveryOp => true,
+            SyncPolicy::EveryN(n) => writes % n == 0,
+            SyncPolicy::None | SyncPolicy::OSync => false,
+        }
+    }
+}
+
+impl std::str::FromStr for SyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(SyncPolicy::None),
+            "fsync" => Ok(SyncPolicy::EveryOp),
+            "osync" => Ok(SyncPolicy::OSync),
+            _ => match s.strip_prefix("fsync=") {
+                Some(n) => match n.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(SyncPolicy::EveryN(n)),
+                    _ => Err(format!("Invalid sync interval '{}'", n)),
+                },
+                None => Err(format!(
+                    "Unknown sync policy '{}', expected none, fsync, fsync=N or osync",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// Where the write benches put the pages they write.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum WriteMode {
+    /// Overwrite the page at this byte offset in place.
+    OverwriteAt(i64),
+    /// Open files with O_APPEND, every write extends the file.
+    Append,
+    /// Write each page right after the previous one. Once a file would grow
+    /// past its size limit it's truncated and written from the start again.
+    SequentialGrow,
+}
+
+impl WriteMode {
+    /// Flags to add when opening files written in this mode.
+    pub fn open_flags(&self) -> i32 {
+        match self {
+            WriteMode::Append => libc::O_APPEND,
+            _ => 0,
+        }
+    }
+
+    /// Where the next page written to a file of `size` bytes goes, and
+    /// whether the file has to be truncated to 0 bytes before, as
+    /// `SequentialGrow` does once the file would outgrow `limit`.
+    pub fn next_write(&self, size: i64, limit: i64) -> (i64, bool) {
+        match self {
+            WriteMode::OverwriteAt(offset) => (*offset, false),
+            WriteMode::Append => (size, false),
+            WriteMode::SequentialGrow if size + PAGE_SIZE as i64 > limit => (0, true),
+            WriteMode::SequentialGrow => (size, false),
+        }
+    }
+}
+
+impl Default for WriteMode {
+    fn default() -> WriteMode {
+        WriteMode::OverwriteAt(0)
+    }
+}
+
+impl std::str::FromStr for WriteMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "overwrite" => Ok(WriteMode::OverwriteAt(0)),
+            "append" => Ok(WriteMode::Append),
+            "grow" => Ok(WriteMode::SequentialGrow),
+            _ => match s.strip_prefix("overwrite=") {
+                Some(offset) => match offset.parse::<i64>() {
+                    Ok(offset) if offset >= 0 => Ok(WriteMode::OverwriteAt(offset)),
+                    _ => Err(format!("Invalid overwrite offset '{}'", offset)),
+                },
+                None => Err(format!(
+                    "Unknown write mode '{}', expected overwrite, overwrite=OFFSET, append or grow",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
 #[repr(C)]
 pub enum ConnType {
     TcpLocal,
     TcpRemote,
     UDS,
+    /// DRPC over QUIC on the local host.
+    Quic,
 }
 
 impl std::fmt::Display for ConnType {
@@ -33,11 +759,135 @@ impl std::fmt::Display for ConnType {
             ConnType::TcpLocal => write!(f, "tcplocal"),
             ConnType::TcpRemote => write!(f, "tcpremote"),
             ConnType::UDS => write!(f, "uds"),
+            ConnType::Quic => write!(f, "quic"),
+        }
+    }
+}
+
+/// Host parameters that differ across machines and affect results.
+/// Captured once when the client starts.
+#[derive(Clone, Copy, Serialize)]
+pub struct HostInfo {
+    pub page_size: usize,
+    pub sock_sndbuf: usize,
+    pub sock_rcvbuf: usize,
+}
+
+impl HostInfo {
+    /// Measures the host, with the socket buffer sizes of a connection to the
+    /// server at `endpoint` with `sockets` set, as the clients set them. If
+    /// the server can't be reached, those a fresh socket of the connection's
+    /// family and type gets with `sockets` set.
+    pub fn new(endpoint: &RPCEndpoint, sockets: &SocketOptions) -> HostInfo {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+
+        // QUIC connections keep the defaults
+        let sockets = match endpoint {
+            RPCEndpoint::Quic { .. } => SocketOptions::default(),
+            _ => *sockets,
+        };
+        let tcp = matches!(endpoint, RPCEndpoint::Tcp { .. });
+        let (sock_sndbuf, sock_rcvbuf) = match connect_probe(endpoint) {
+            Ok(socket) => {
+                let _ = sockets.apply(socket.as_raw_fd(), tcp);
+                socket_buf_sizes(socket.as_raw_fd())
+            }
+            Err(_) => unsafe {
+                let fd = libc::socket(socket_domain(endpoint), socket_type(endpoint), 0);
+                let _ = sockets.apply(fd, tcp);
+                let sizes = socket_buf_sizes(fd);
+                libc::close(fd);
+                sizes
+            },
+        };
+
+        HostInfo {
+            page_size,
+            sock_sndbuf,
+            sock_rcvbuf,
+        }
+    }
+}
+
+// A connection to the server at `endpoint` of the clients' kind, only to
+// measure it
+fn connect_probe(endpoint: &RPCEndpoint) -> std::io::Result<Box<dyn AsRawFd>> {
+    Ok(match endpoint {
+        RPCEndpoint::Unix { path } => Box::new(std::os::unix::net::UnixStream::connect(path)?),
+        RPCEndpoint::Tcp { .. } => Box::new(std::net::TcpStream::connect(
+            endpoint.socket_addr().unwrap(),
+        )?),
+        RPCEndpoint::Quic { .. } => {
+            let addr = endpoint_addr(endpoint)?;
+            let any = if addr.is_ipv6() {
+                "[::]:0"
+            } else {
+                "0.0.0.0:0"
+            };
+            let socket = std::net::UdpSocket::bind(any)?;
+            socket.connect(addr)?;
+            Box::new(socket)
         }
+    })
+}
+
+// The first address a TCP or QUIC endpoint resolves to
+fn endpoint_addr(endpoint: &RPCEndpoint) -> std::io::Result<std::net::SocketAddr> {
+    use std::net::ToSocketAddrs;
+
+    endpoint
+        .socket_addr()
+        .unwrap()
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| std::io::ErrorKind::AddrNotAvailable.into())
+}
+
+fn socket_domain(endpoint: &RPCEndpoint) -> libc::c_int {
+    match endpoint {
+        RPCEndpoint::Unix { .. } => libc::AF_UNIX,
+        _ => match endpoint_addr(endpoint) {
+            Ok(addr) if addr.is_ipv6() => libc::AF_INET6,
+            _ => libc::AF_INET,
+        },
+    }
+}
+
+fn socket_type(endpoint: &RPCEndpoint) -> libc::c_int {
+    match endpoint {
+        RPCEndpoint::Quic { .. } => libc::SOCK_DGRAM,
+        _ => libc::SOCK_STREAM,
+    }
+}
+
+// SO_SNDBUF and SO_RCVBUF of `fd`
+fn socket_buf_sizes(fd: libc::c_int) -> (usize, usize) {
+    (
+        socket_buf_size(fd, libc::SO_SNDBUF),
+        socket_buf_size(fd, libc::SO_RCVBUF),
+    )
+}
+
+fn socket_buf_size(fd: libc::c_int, opt: libc::c_int) -> usize {
+    let mut val: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            opt,
+            &mut val as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res == 0 {
+        val as usize
+    } else {
+        0
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
 #[repr(C)]
 pub enum RPCType {
     DRPC,
@@ -53,87 +903,1798 @@ impl std::fmt::Display for RPCType {
     }
 }
 
-#[derive(Clone)]
-#[repr(C)]
-pub struct ClientParams {
-    pub cid: usize,
-    pub nclients: usize,
-    pub ccores: usize,
-    pub log_mode: LogMode,
-    pub conn_type: ConnType,
-    pub rpc_type: RPCType,
+impl std::str::FromStr for RPCType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drpc" => Ok(RPCType::DRPC),
+            "grpc" => Ok(RPCType::GRPC),
+            _ => Err(format!("Unknown RPC type '{}'", s)),
+        }
+    }
 }
 
-pub trait FxRPC {
-    fn rpc_open(
-        &mut self,
-        path: &str,
-        flags: i32,
-        mode: u32,
-    ) -> Result<i32, Box<dyn std::error::Error>>;
-    fn rpc_read(
-        &mut self,
-        fd: i32,
-        page: &mut Vec<u8>,
-        size: usize,
-    ) -> Result<i32, Box<dyn std::error::Error>>;
-    fn rpc_pread(
-        &mut self,
-        fd: i32,
-        page: &mut Vec<u8>,
-        size: usize,
-        offset: i64,
-    ) -> Result<i32, Box<dyn std::error::Error>>;
-    fn rpc_write(
-        &mut self,
-        fd: i32,
-        page: &Vec<u8>,
-        size: usize,
-    ) -> Result<i32, Box<dyn std::error::Error>>;
-    fn rpc_pwrite(
-        &mut self,
-        fd: i32,
-        page: &Vec<u8>,
-        size: usize,
-        offset: i64,
-    ) -> Result<i32, Box<dyn std::error::Error>>;
-    fn rpc_close(&mut self, fd: i32) -> Result<i32, Box<dyn std::error::Error>>;
-    fn rpc_remove(&mut self, path: &str) -> Result<i32, Box<dyn std::error::Error>>;
-    fn rpc_mkdir(&mut self, path: &str, mode: u32) -> Result<i32, Box<dyn std::error::Error>>;
-    fn rpc_rmdir(&mut self, path: &str) -> Result<i32, Box<dyn std::error::Error>>;
+/// How DRPC messages are serialized. Abomonation copies the in-memory
+/// layout and needs peers of the same build and byte order, bincode is
+/// portable but copies every field. DRPC servers answer in the format of
+/// each request, so only the client picks one.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum WireFormat {
+    Abomonation,
+    Bincode,
 }
 
-pub fn init_client(conn_type: ConnType, rpc_type: RPCType) -> Box<dyn FxRPC> {
-    match rpc_type {
-        RPCType::GRPC => match conn_type {
-            ConnType::TcpLocal => {
-                Box::new(BlockingClient::connect_tcp("http://[::1]:8080").unwrap())
-            }
-            ConnType::TcpRemote => {
-                Box::new(BlockingClient::connect_tcp("http://172.31.0.1:8080").unwrap())
+impl Default for WireFormat {
+    fn default() -> WireFormat {
+        WireFormat::Abomonation
+    }
+}
+
+impl std::fmt::Display for WireFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WireFormat::Abomonation => write!(f, "abomonation"),
+            WireFormat::Bincode => write!(f, "bincode"),
+        }
+    }
+}
+
+impl std::str::FromStr for WireFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abomonation" => Ok(WireFormat::Abomonation),
+            "bincode" => Ok(WireFormat::Bincode),
+            _ => Err(format!("Unknown wire format '{}'", s)),
+        }
+    }
+}
+
+/// Version of the protocol between clients and servers. Raise it whenever an
+/// op is added or changes its messages.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+// Ops every server handles, the DRPC server handles batches on top
+const SERVER_OPS: [&str; 21] = [
+    "open",
+    "read",
+    "pread",
+    "write",
+    "pwrite",
+    "close",
+    "fsync",
+    "ftruncate",
+    "remove",
+    "rename",
+    "mkdir",
+    "rmdir",
+    "fadvise",
+    "drop_caches",
+    "fstatat",
+    "sendfile",
+    "lseek",
+    "dump_log",
+    "server_stats",
+    "capabilities",
+    "reset",
+];
+
+/// What a server supports, as it answers `rpc_capabilities()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capabilities {
+    pub protocol_version: u32,
+    /// The RPC types the server answers. A server only runs one, but older
+    /// or newer servers may differ in what they can run.
+    pub rpc_types: Vec<RPCType>,
+    /// Names of the ops the server handles, e.g. `pread` or `batch`.
+    pub ops: Vec<String>,
+}
+
+impl Capabilities {
+    /// What this build's server of type `rpc_type` supports.
+    pub fn of_server(rpc_type: RPCType) -> Capabilities {
+        let mut ops: Vec<String> = SERVER_OPS.iter().map(|op| op.to_string()).collect();
+        if rpc_type == RPCType::DRPC {
+            ops.push("batch".to_string());
+        }
+        Capabilities {
+            protocol_version: PROTOCOL_VERSION,
+            rpc_types: vec![rpc_type],
+            ops,
+        }
+    }
+}
+
+/// PEM files to run gRPC over TLS with. The server presents `cert` and `key`,
+/// clients check them against `ca`, which is `cert` itself if it's
+/// self-signed. Certificates for Unix domain sockets must be issued to
+/// localhost.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TlsConfig {
+    pub cert: std::path::PathBuf,
+    pub key: std::path::PathBuf,
+    pub ca: std::path::PathBuf,
+}
+
+/// Options of the stream sockets between clients and servers, set on both
+/// ends. Small requests wait for Nagle's algorithm to batch them unless
+/// `tcp_nodelay` is on, which is why it is by default. The buffer sizes
+/// are kernel defaults if unset, and the kernel may round them up or cap
+/// them at `net.core.wmem_max` and `net.core.rmem_max`. QUIC and TLS
+/// connections keep the defaults.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct SocketOptions {
+    pub tcp_nodelay: bool,
+    /// `SO_SNDBUF` in bytes.
+    pub send_buf: Option<usize>,
+    /// `SO_RCVBUF` in bytes.
+    pub recv_buf: Option<usize>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> SocketOptions {
+        SocketOptions {
+            tcp_nodelay: true,
+            send_buf: None,
+            recv_buf: None,
+        }
+    }
+}
+
+impl SocketOptions {
+    /// Sets the options on the connected socket `fd`, `TCP_NODELAY` only if
+    /// it's a TCP socket.
+    pub fn apply(&self, fd: RawFd, tcp: bool) -> std::io::Result<()> {
+        if tcp {
+            set_socket_opt(
+                fd,
+                libc::IPPROTO_TCP,
+                libc::TCP_NODELAY,
+                self.tcp_nodelay as usize,
+            )?;
+        }
+        if let Some(size) = self.send_buf {
+            set_socket_opt(fd, libc::SOL_SOCKET, libc::SO_SNDBUF, size)?;
+        }
+        if let Some(size) = self.recv_buf {
+            set_socket_opt(fd, libc::SOL_SOCKET, libc::SO_RCVBUF, size)?;
+        }
+        Ok(())
+    }
+}
+
+fn set_socket_opt(
+    fd: RawFd,
+    level: libc::c_int,
+    opt: libc::c_int,
+    val: usize,
+) -> std::io::Result<()> {
+    let val = val.min(libc::c_int::MAX as usize) as libc::c_int;
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            opt,
+            &val as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// Address a server listens on and its clients connect to, given as
+/// `tcp://HOST:PORT` (HOST is an IPv4 address or a bracketed IPv6 address),
+/// `quic://HOST:PORT` (UDP, DRPC only) or `unix:///path/to.sock`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum RPCEndpoint {
+    Tcp { host: String, port: u16 },
+    Unix { path: String },
+    Quic { host: String, port: u16 },
+}
+
+impl RPCEndpoint {
+    /// The endpoint a transport used before endpoints could be given explicitly.
+    pub fn from_conn_type(conn_type: ConnType, rpc_type: RPCType, port: u16) -> RPCEndpoint {
+        let host = match (conn_type, rpc_type) {
+            (ConnType::UDS, _) => {
+                return RPCEndpoint::Unix {
+                    path: UDS_PATH.to_string(),
+                }
             }
-            ConnType::UDS => Box::new(BlockingClient::connect_uds().unwrap()),
-        },
-        RPCType::DRPC => match conn_type {
-            ConnType::TcpLocal => Box::new(init_client_drpc_tcp("127.0.0.1:8080")),
-            ConnType::TcpRemote => Box::new(init_client_drpc_tcp("172.31.0.1:8080")),
-            ConnType::UDS => Box::new(init_client_drpc_uds(UDS_PATH)),
-        },
+            (ConnType::Quic, _) => {
+                return RPCEndpoint::Quic {
+                    host: "127.0.0.1".to_string(),
+                    port: port,
+                }
+            }
+            (ConnType::TcpLocal, RPCType::GRPC) => "[::1]",
+            (ConnType::TcpLocal, RPCType::DRPC) => "127.0.0.1",
+            (ConnType::TcpRemote, _) => "172.31.0.1",
+        };
+        RPCEndpoint::Tcp {
+            host: host.to_string(),
+            port: port,
+        }
+    }
+
+    /// `HOST:PORT` of a TCP or QUIC endpoint.
+    pub fn socket_addr(&self) -> Option<String> {
+        match self {
+            RPCEndpoint::Tcp { host, port } | RPCEndpoint::Quic { host, port } => {
+                Some(format!("{}:{}", host, port))
+            }
+            RPCEndpoint::Unix { .. } => None,
+        }
     }
 }
 
-pub fn run_server(conn_type: ConnType, rpc_type: RPCType, port: u16) {
-    println!("Starting {} {} server", rpc_type, conn_type);
-    match rpc_type {
-        RPCType::GRPC => match conn_type {
-            ConnType::TcpLocal => start_rpc_server_tcp("[::1]", port),
-            ConnType::TcpRemote => start_rpc_server_tcp("172.31.0.1", port),
-            ConnType::UDS => start_rpc_server_uds(UDS_PATH).unwrap(),
-        },
-        RPCType::DRPC => match conn_type {
-            ConnType::TcpLocal => start_drpc_server_tcp("127.0.0.1", port),
-            ConnType::TcpRemote => start_drpc_server_tcp("172.31.0.1", port),
-            ConnType::UDS => start_drpc_server_uds(UDS_PATH),
-        },
+impl std::str::FromStr for RPCEndpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix://") {
+            if !path.starts_with('/') {
+                return Err(format!("Unix socket path in '{}' must be absolute", s));
+            }
+            return Ok(RPCEndpoint::Unix {
+                path: path.to_string(),
+            });
+        }
+
+        let quic = s.starts_with("quic://");
+        let addr = s
+            .strip_prefix("tcp://")
+            .or(s.strip_prefix("quic://"))
+            .ok_or(format!(
+                "Endpoint '{}' must start with tcp://, quic:// or unix://",
+                s
+            ))?;
+        let (host, port) = addr
+            .rsplit_once(':')
+            .ok_or(format!("Endpoint '{}' has no port", s))?;
+        let port = port
+            .parse::<u16>()
+            .map_err(|e| format!("Invalid port in '{}': {}", s, e))?;
+        if host.is_empty() || (host.contains(':') && !host.starts_with('[')) {
+            return Err(format!("Invalid host in '{}', IPv6 hosts need brackets", s));
+        }
+        if quic {
+            return Ok(RPCEndpoint::Quic {
+                host: host.to_string(),
+                port: port,
+            });
+        }
+        Ok(RPCEndpoint::Tcp {
+            host: host.to_string(),
+            port: port,
+        })
+    }
+}
+
+/// Reads the servers of a cluster run from `path`, one `host:port` per line.
+/// Blank lines and lines starting with `#` are skipped.
+pub fn read_servers_file(path: &std::path::Path) -> Result<Vec<RPCEndpoint>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Can't read servers file {}: {}", path.display(), e))?;
+    let mut servers = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let server = format!("tcp://{}", line)
+            .parse::<RPCEndpoint>()
+            .map_err(|e| format!("{}:{}: {}", path.display(), n + 1, e))?;
+        servers.push(server);
+    }
+    Ok(servers)
+}
+
+impl std::fmt::Display for RPCEndpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RPCEndpoint::Tcp { host, port } => write!(f, "tcp://{}:{}", host, port),
+            RPCEndpoint::Unix { path } => write!(f, "unix://{}", path),
+            RPCEndpoint::Quic { host, port } => write!(f, "quic://{}:{}", host, port),
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[repr(C)]
+pub struct ClientParams {
+    pub cid: usize,
+    pub nclients: usize,
+    pub ccores: usize,
+    pub log_mode: LogMode,
+    pub conn_type: ConnType,
+    pub endpoint: RPCEndpoint,
+    /// Servers the bench threads are spread over, round-robin. Empty if all
+    /// of them connect to `endpoint`.
+    pub servers: Vec<RPCEndpoint>,
+    pub rpc_type: RPCType,
+    /// Serialization of DRPC messages.
+    pub wire_format: WireFormat,
+    /// Connections each bench thread opens to the server.
+    pub conns_per_core: usize,
+    /// Ops each bench thread keeps in flight, on connections of their own
+    /// once it's more than 1. Only drbr pipelines its reads.
+    pub queue_depth: usize,
+    /// Connect over TLS, gRPC only.
+    pub tls: Option<TlsConfig>,
+    pub sockets: SocketOptions,
+    pub error_policy: ErrorPolicy,
+    /// Errnos of failed ops that are counted even under `AbortOnError`.
+    pub tolerated_errnos: Vec<i32>,
+    /// Failed ops a run tolerates over all its threads, whatever the
+    /// `error_policy`. Once more fail, the run stops and keeps what it
+    /// measured so far. Ops failing with a tolerated errno don't count.
+    pub error_budget: Option<u64>,
+    pub sync_policy: SyncPolicy,
+    pub write_mode: WriteMode,
+    /// Flags benches open their files with, on top of what `sync_policy`
+    /// and `write_mode` add.
+    pub open_flags: i32,
+    pub files_per_dir: usize,
+    pub spread_files: bool,
+    pub drop_caches: bool,
+    /// Leave the benchmark files on the server after the run.
+    pub keep_files: bool,
+    /// Times each benchmark point is run.
+    pub repeat: usize,
+    /// Keep the files between repeats and truncate them back to their
+    /// initial size, instead of removing and recreating them.
+    pub reuse_files: bool,
+    /// Write the files of the write benches out to their full size before
+    /// the timed phase, so the timed writes don't allocate blocks.
+    pub preallocate: bool,
+    pub numa_local_alloc: bool,
+    /// mlock the buffers each bench thread reads into and writes from.
+    pub lock_buffers: bool,
+    /// Warn if a benchmark point leaves more descriptors open than before it.
+    pub check_fd_leaks: bool,
+    pub thread_mapping: ThreadMapping,
+    /// Pin each bench thread to its core. Off, the threads float wherever
+    /// the scheduler puts them and their results are flagged unpinned.
+    pub pin: bool,
+    /// Fail a run if a bench thread can't be pinned to its core, instead of
+    /// flagging its results unpinned.
+    pub require_pinning: bool,
+    /// Run the cores of a run one after the other on the calling thread,
+    /// without spawning threads or waiting for each other, for debugging.
+    pub single_threaded: bool,
+    pub min_samples: usize,
+    pub rerun_short: usize,
+    /// Ops each thread runs before the barrier, unmeasured.
+    pub warmup_ops: Option<u64>,
+    /// Ops per second each bench thread starts, as many as it can if unset.
+    pub target_iops: Option<u64>,
+    /// Microseconds each bench thread waits between two ops.
+    pub think_time_us: u64,
+    /// Spin through the think time instead of sleeping.
+    pub busy_think: bool,
+    /// Start the first per-second bucket at a multiple of this many ms since
+    /// the epoch, see `align_samples()`.
+    pub align_samples_ms: Option<u64>,
+    pub name_len: usize,
+    pub name_charset: Charset,
+    pub file_size: usize,
+    /// Block device below the server root that drbr and dwom work on in
+    /// place of their files. It's neither created, filled nor removed.
+    pub device: Option<String>,
+    pub working_set_bytes: usize,
+    /// How many cores read each file of the mrpl benchmark. The other
+    /// benchmarks ignore it.
+    pub share_factor: usize,
+    /// Share of the cores that read in the mrph benchmark, the rest write.
+    pub reader_fraction: f64,
+    /// Whether mrename renames within one directory or between two.
+    pub rename_mode: RenameMode,
+    pub seed: u64,
+    /// What benches fill their files with.
+    pub fill_pattern: FillPattern,
+    /// How drbr, mrpl and mrpm check the pages they read back.
+    pub verify: VerifyMode,
+    /// Bytes each op of drbr and ococ reads or writes, the default of the
+    /// benchmark if unset. See `fxmark::io_size()`.
+    pub io_size: Option<usize>,
+    /// Draw the bytes of each drbr read and ococ write from this instead,
+    /// see `fxmark::size_dist()`.
+    pub size_dist: Option<SizeDistribution>,
+    pub compress_output: bool,
+    /// Digits after the decimal point of the fractional numbers in results.
+    pub precision: usize,
+    /// fsync the output file once the results of a benchmark point are
+    /// logged, so they survive a crash of the machine.
+    pub sync_results: bool,
+    /// Print a table of the median throughput per benchmark and core count
+    /// once a sweep is done.
+    pub summary_table: bool,
+    pub host_info: HostInfo,
+    pub recipe: Recipe,
+    /// `key=value` pairs each result row ends with, one column per key.
+    pub tags: Vec<(String, String)>,
+}
+
+impl ClientParams {
+    /// The server bench thread `thread` connects to.
+    pub fn server_of(&self, thread: usize) -> &RPCEndpoint {
+        if self.servers.is_empty() {
+            &self.endpoint
+        } else {
+            &self.servers[thread % self.servers.len()]
+        }
+    }
+
+    /// Whether the benches work on `device` instead of their files.
+    pub fn target_is_device(&self) -> bool {
+        self.device.is_some()
+    }
+}
+
+pub trait FxRPC {
+    fn rpc_open(
+        &mut self,
+        path: &str,
+        flags: i32,
+        mode: u32,
+    ) -> Result<i32, Box<dyn std::error::Error>>;
+    /// Reads up to `size` bytes into `page` and returns how many it read,
+    /// which can be fewer, e.g. from a pipe. Only that many bytes of `page`
+    /// are valid. 0 means EOF. Failed reads return a `ReadError`, with
+    /// `ReadError::WouldBlock` for a nonblocking fd without data.
+    fn rpc_read(
+        &mut self,
+        fd: i32,
+        page: &mut Vec<u8>,
+        size: usize,
+    ) -> Result<i32, Box<dyn std::error::Error>>;
+    /// `rpc_read()` at `offset`.
+    fn rpc_pread(
+        &mut self,
+        fd: i32,
+        page: &mut Vec<u8>,
+        size: usize,
+        offset: i64,
+    ) -> Result<i32, Box<dyn std::error::Error>>;
+    fn rpc_write(
+        &mut self,
+        fd: i32,
+        page: &[u8],
+        size: usize,
+    ) -> Result<i32, Box<dyn std::error::Error>>;
+    fn rpc_pwrite(
+        &mut self,
+        fd: i32,
+        page: &[u8],
+        size: usize,
+        offset: i64,
+    ) -> Result<i32, Box<dyn std::error::Error>>;
+    fn rpc_close(&mut self, fd: i32) -> Result<i32, Box<dyn std::error::Error>>;
+    fn rpc_fsync(&mut self, fd: i32) -> Result<i32, Box<dyn std::error::Error>>;
+    fn rpc_ftruncate(&mut self, fd: i32, length: i64) -> Result<i32, Box<dyn std::error::Error>>;
+    fn rpc_remove(&mut self, path: &str) -> Result<i32, Box<dyn std::error::Error>>;
+    /// Renames `old_path` to `new_path` on the server, see `rename()`.
+    fn rpc_rename(
+        &mut self,
+        old_path: &str,
+        new_path: &str,
+    ) -> Result<i32, Box<dyn std::error::Error>>;
+    fn rpc_mkdir(&mut self, path: &str, mode: u32) -> Result<i32, Box<dyn std::error::Error>>;
+    fn rpc_rmdir(&mut self, path: &str) -> Result<i32, Box<dyn std::error::Error>>;
+    fn rpc_fadvise(
+        &mut self,
+        fd: i32,
+        offset: i64,
+        len: i64,
+        advice: i32,
+    ) -> Result<i32, Box<dyn std::error::Error>>;
+    fn rpc_drop_caches(&mut self) -> Result<i32, Box<dyn std::error::Error>>;
+    fn rpc_fstatat(
+        &mut self,
+        dirfd: i32,
+        path: &str,
+        flags: i32,
+    ) -> Result<FileStat, Box<dyn std::error::Error>>;
+    fn rpc_sendfile(
+        &mut self,
+        out_fd: i32,
+        in_fd: i32,
+        offset: i64,
+        count: usize,
+    ) -> Result<isize, Box<dyn std::error::Error>>;
+    /// Moves the offset of `fd` on the server and returns the new offset,
+    /// see `lseek()`. Failures carry the server's errno, e.g. ENXIO for a
+    /// `SEEK_DATA` or `SEEK_HOLE` past EOF.
+    fn rpc_lseek(
+        &mut self,
+        fd: i32,
+        offset: i64,
+        whence: i32,
+    ) -> Result<i64, Box<dyn std::error::Error>>;
+    /// Runs `ops` in order on the server and returns their results.
+    fn rpc_batch(
+        &mut self,
+        ops: Vec<BatchOp>,
+    ) -> Result<Vec<BatchResult>, Box<dyn std::error::Error>>;
+    fn rpc_dump_server_log(&mut self) -> Result<Vec<LogRecord>, Box<dyn std::error::Error>>;
+    fn rpc_server_stats(&mut self) -> Result<Vec<OpHistogram>, Box<dyn std::error::Error>>;
+    /// Asks the server which RPC types, protocol version and ops it supports.
+    fn rpc_capabilities(&mut self) -> Result<Capabilities, Box<dyn std::error::Error>>;
+    /// Closes every descriptor the server has open on behalf of this client,
+    /// e.g. left over from a test that panicked, and returns how many.
+    /// Descriptors of other clients stay open.
+    fn rpc_reset(&mut self) -> Result<i32, Box<dyn std::error::Error>>;
+}
+
+pub fn init_client(endpoint: &RPCEndpoint, rpc_type: RPCType) -> Box<dyn FxRPC> {
+    init_client_with(endpoint, rpc_type, WireFormat::default())
+}
+
+/// Like `init_client()`, with DRPC messages serialized as `wire_format`.
+/// gRPC clients ignore it.
+pub fn init_client_with(
+    endpoint: &RPCEndpoint,
+    rpc_type: RPCType,
+    wire_format: WireFormat,
+) -> Box<dyn FxRPC> {
+    init_client_with_sockets(endpoint, rpc_type, wire_format, &SocketOptions::default())
+}
+
+/// Like `init_client_with()`, with `sockets` set on the client's socket.
+pub fn init_client_with_sockets(
+    endpoint: &RPCEndpoint,
+    rpc_type: RPCType,
+    wire_format: WireFormat,
+    sockets: &SocketOptions,
+) -> Box<dyn FxRPC> {
+    let client: Box<dyn FxRPC> = match (rpc_type, endpoint) {
+        (RPCType::GRPC, RPCEndpoint::Tcp { .. }) => Box::new(
+            BlockingClient::connect_tcp_with(&endpoint.socket_addr().unwrap(), sockets).unwrap(),
+        ),
+        (RPCType::GRPC, RPCEndpoint::Unix { path }) => {
+            Box::new(BlockingClient::connect_uds_with(path, sockets).unwrap())
+        }
+        (RPCType::DRPC, RPCEndpoint::Tcp { .. }) => Box::new(init_client_drpc_tcp(
+            &endpoint.socket_addr().unwrap(),
+            wire_format,
+            sockets,
+        )),
+        (RPCType::DRPC, RPCEndpoint::Unix { path }) => {
+            Box::new(init_client_drpc_uds(path, wire_format, sockets))
+        }
+        (RPCType::DRPC, RPCEndpoint::Quic { .. }) => Box::new(init_client_drpc_quic(
+            &endpoint.socket_addr().unwrap(),
+            wire_format,
+        )),
+        (RPCType::GRPC, RPCEndpoint::Quic { .. }) => panic!("gRPC doesn't run over QUIC"),
+    };
+    Box::new(TrackedClient::new(client))
+}
+
+/// Connects to the gRPC server at `endpoint` over TLS.
+pub fn init_tls_client(endpoint: &RPCEndpoint, tls: &TlsConfig) -> Box<dyn FxRPC> {
+    let client = BlockingClient::connect_tls(endpoint, tls).unwrap();
+    Box::new(TrackedClient::new(Box::new(client)))
+}
+
+/// Connects to `endpoint` as `client_params` asks, over TLS if it has a
+/// `TlsConfig`.
+pub fn init_client_for(endpoint: &RPCEndpoint, client_params: &ClientParams) -> Box<dyn FxRPC> {
+    match &client_params.tls {
+        Some(tls) => init_tls_client(endpoint, tls),
+        None => init_client_with_sockets(
+            endpoint,
+            client_params.rpc_type,
+            client_params.wire_format,
+            &client_params.sockets,
+        ),
+    }
+}
+
+/// Connects a bench thread to the server. With more than one connection per
+/// core the ops are striped over them.
+pub fn init_bench_client(client_params: &ClientParams) -> Box<dyn FxRPC> {
+    if client_params.conns_per_core <= 1 {
+        return init_client_for(&client_params.endpoint, client_params);
+    }
+    let conns = (0..client_params.conns_per_core)
+        .map(|_| init_client_for(&client_params.endpoint, client_params))
+        .collect();
+    Box::new(StripedClient::new(conns))
+}
+
+pub fn run_server(endpoint: &RPCEndpoint, rpc_type: RPCType) {
+    run_server_with_sockets(endpoint, rpc_type, &SocketOptions::default())
+}
+
+/// Like `run_server()`, with `sockets` set on every connection it accepts.
+pub fn run_server_with_sockets(endpoint: &RPCEndpoint, rpc_type: RPCType, sockets: &SocketOptions) {
+    println!("Starting {} server on {}", rpc_type, endpoint);
+    match (rpc_type, endpoint) {
+        (RPCType::GRPC, RPCEndpoint::Tcp { host, port }) => {
+            start_rpc_server_tcp(host, *port, None, sockets)
+        }
+        (RPCType::GRPC, RPCEndpoint::Unix { path }) => {
+            start_rpc_server_uds(path, None, sockets).unwrap()
+        }
+        (RPCType::DRPC, RPCEndpoint::Tcp { host, port }) => {
+            start_drpc_server_tcp(host, *port, sockets)
+        }
+        (RPCType::DRPC, RPCEndpoint::Unix { path }) => start_drpc_server_uds(path, sockets),
+        (RPCType::DRPC, RPCEndpoint::Quic { host, port }) => start_drpc_server_quic(host, *port),
+        (RPCType::GRPC, RPCEndpoint::Quic { .. }) => panic!("gRPC doesn't run over QUIC"),
     };
 }
+
+/// Runs a gRPC server on `endpoint` that only takes TLS connections.
+pub fn run_tls_server(endpoint: &RPCEndpoint, tls: &TlsConfig) {
+    println!("Starting {} server with TLS on {}", RPCType::GRPC, endpoint);
+    match endpoint {
+        RPCEndpoint::Tcp { host, port } => {
+            start_rpc_server_tcp(host, *port, Some(tls), &SocketOptions::default())
+        }
+        RPCEndpoint::Unix { path } => {
+            start_rpc_server_uds(path, Some(tls), &SocketOptions::default()).unwrap()
+        }
+        RPCEndpoint::Quic { .. } => panic!("gRPC doesn't run over QUIC"),
+    };
+}
+
+/// Starts a server on the Unix socket at `path` that keeps its files in the
+/// socket's directory, apart from those of other tests, and returns its
+/// endpoint once it's up.
+#[cfg(test)]
+pub(crate) fn start_test_server(path: &'static str, rpc_type: RPCType) -> RPCEndpoint {
+    start_test_server_with(path, move |endpoint| run_server(endpoint, rpc_type))
+}
+
+/// Like `start_test_server()`, with the server run by `serve`.
+#[cfg(test)]
+pub(crate) fn start_test_server_with<F>(path: &'static str, serve: F) -> RPCEndpoint
+where
+    F: FnOnce(&RPCEndpoint) + Send + 'static,
+{
+    let endpoint: RPCEndpoint = format!("unix://{}", path).parse().unwrap();
+    let server = endpoint.clone();
+    std::thread::spawn(move || {
+        set_thread_server_root(
+            std::path::Path::new(path)
+                .parent()
+                .unwrap()
+                .to_str()
+                .unwrap(),
+        );
+        serve(&server)
+    });
+    while !std::path::Path::new(path).exists() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    endpoint
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        batch_fd, confine_path, errno_name, file_name, init_client, init_client_with,
+        init_client_with_sockets, init_tls_client, op_errno, parse_errno, parse_open_flags,
+        read_reply, read_result, read_servers_file, retry_eintr, run_tls_server, socket_buf_size,
+        start_test_server, start_test_server_with, BatchOp, Charset, FxRPC, HostInfo, RPCEndpoint,
+        RPCType, ReadError, SocketOptions, SyncPolicy, TlsConfig, WireFormat, WriteMode, PAGE_SIZE,
+        PROTOCOL_VERSION,
+    };
+    use crate::fxrpc::drpc::server::serve_drpc_tcp;
+    use libc::{
+        c_void, AT_SYMLINK_NOFOLLOW, ENOENT, O_CREAT, O_DIRECTORY, O_NONBLOCK, O_RDONLY, O_RDWR,
+        O_TRUNC, S_IRWXU,
+    };
+    use std::time::Duration;
+
+    // Connects to a gRPC server of its own on the socket at `path`
+    fn test_client(path: &'static str) -> Box<dyn FxRPC> {
+        init_client(&start_test_server(path, RPCType::GRPC), RPCType::GRPC)
+    }
+
+    extern "C" fn noop_handler(_: libc::c_int) {}
+
+    #[test]
+    fn read_survives_signal() {
+        // Install a handler without SA_RESTART so a blocked read fails with EINTR
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = noop_handler as usize;
+            libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut());
+        }
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let (rfd, wfd) = (fds[0], fds[1]);
+        let reader = unsafe { libc::pthread_self() };
+
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            unsafe { libc::pthread_kill(reader, libc::SIGUSR1) };
+            std::thread::sleep(Duration::from_millis(100));
+            unsafe { libc::write(wfd, b"x".as_ptr() as *const c_void, 1) };
+        });
+
+        let mut buf = [0u8; 1];
+        let res = retry_eintr(|| unsafe { libc::read(rfd, buf.as_mut_ptr() as *mut c_void, 1) });
+        writer.join().unwrap();
+        assert_eq!(res, 1);
+        assert_eq!(buf[0], b'x');
+
+        unsafe {
+            libc::close(rfd);
+            libc::close(wfd);
+        }
+    }
+
+    #[test]
+    fn parse_endpoints() {
+        let v4: RPCEndpoint = "tcp://0.0.0.0:8080".parse().unwrap();
+        assert_eq!(v4.socket_addr().unwrap(), "0.0.0.0:8080");
+
+        let v6: RPCEndpoint = "tcp://[::1]:8080".parse().unwrap();
+        assert_eq!(v6.socket_addr().unwrap(), "[::1]:8080");
+
+        let uds: RPCEndpoint = "unix:///dev/shm/uds/fx.sock".parse().unwrap();
+        assert_eq!(
+            uds,
+            RPCEndpoint::Unix {
+                path: "/dev/shm/uds/fx.sock".to_string()
+            }
+        );
+        assert_eq!(uds.to_string(), "unix:///dev/shm/uds/fx.sock");
+
+        let quic: RPCEndpoint = "quic://127.0.0.1:8080".parse().unwrap();
+        assert_eq!(
+            quic,
+            RPCEndpoint::Quic {
+                host: "127.0.0.1".to_string(),
+                port: 8080
+            }
+        );
+        assert_eq!(quic.socket_addr().unwrap(), "127.0.0.1:8080");
+        assert_eq!(quic.to_string(), "quic://127.0.0.1:8080");
+
+        assert!("tcp://::1:8080".parse::<RPCEndpoint>().is_err());
+        assert!("tcp://127.0.0.1".parse::<RPCEndpoint>().is_err());
+        assert!("udp://127.0.0.1:8080".parse::<RPCEndpoint>().is_err());
+    }
+
+    #[test]
+    fn servers_file_keeps_order() {
+        let dir = "/dev/shm/fxmark_servers_file_test";
+        std::fs::create_dir_all(dir).unwrap();
+        let path = std::path::Path::new(dir).join("servers");
+        std::fs::write(&path, "# rack 1\n10.0.0.1:8080\n\n10.0.0.2:8081\n").unwrap();
+
+        let servers = read_servers_file(&path).unwrap();
+        assert_eq!(
+            servers,
+            vec![
+                RPCEndpoint::Tcp {
+                    host: "10.0.0.1".to_string(),
+                    port: 8080
+                },
+                RPCEndpoint::Tcp {
+                    host: "10.0.0.2".to_string(),
+                    port: 8081
+                },
+            ]
+        );
+
+        std::fs::write(&path, "10.0.0.1:8080\n10.0.0.2\n").unwrap();
+        let err = read_servers_file(&path).unwrap_err();
+        assert!(err.contains(":2:"), "{}", err);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn errnos_by_name_and_number() {
+        assert_eq!(parse_errno("EAGAIN"), Ok(libc::EAGAIN));
+        assert_eq!(parse_errno("eintr"), Ok(libc::EINTR));
+        assert_eq!(parse_errno("5"), Ok(5));
+        assert!(parse_errno("EWHATEVER").is_err());
+        assert!(parse_errno("0").is_err());
+        assert_eq!(errno_name(libc::ENOSPC), "ENOSPC");
+        assert_eq!(errno_name(200), "200");
+
+        let io: Result<i32, Box<dyn std::error::Error>> =
+            Err(Box::new(std::io::Error::from_raw_os_error(libc::EAGAIN)));
+        assert_eq!(op_errno(&io), Some(libc::EAGAIN));
+        let mut status = tonic::Status::internal("failed");
+        status.metadata_mut().insert("errno", libc::EIO.into());
+        let grpc: Result<i32, Box<dyn std::error::Error>> = Err(Box::new(status));
+        assert_eq!(op_errno(&grpc), Some(libc::EIO));
+        assert_eq!(op_errno(&Ok::<i32, Box<dyn std::error::Error>>(-1)), None);
+    }
+
+    #[test]
+    fn open_flags_by_name() {
+        assert_eq!(
+            parse_open_flags("O_CREAT,O_RDWR,O_APPEND"),
+            Ok(libc::O_CREAT | libc::O_RDWR | libc::O_APPEND)
+        );
+        assert_eq!(parse_open_flags("o_rdonly"), Ok(libc::O_RDONLY));
+        let err = parse_open_flags("O_CREAT,O_FAST").unwrap_err();
+        assert!(err.contains("O_FAST"), "{}", err);
+        assert!(parse_open_flags("").is_err());
+    }
+
+    #[test]
+    fn paths_stay_under_the_root() {
+        assert_eq!(
+            confine_path("data/file.txt"),
+            Ok("data/file.txt".to_string())
+        );
+        assert_eq!(
+            confine_path("/data/./x/../file.txt"),
+            Ok("data/file.txt".to_string())
+        );
+        assert_eq!(confine_path("data/.."), Ok(String::new()));
+        assert_eq!(confine_path("../etc/passwd"), Err(libc::EACCES));
+        assert_eq!(confine_path("data/../../etc/passwd"), Err(libc::EACCES));
+        // Absolute paths are taken relative to the root as well
+        assert_eq!(confine_path("/etc/passwd"), Ok("etc/passwd".to_string()));
+    }
+
+    #[test]
+    fn reads_tell_would_block_from_eof() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let (rfd, wfd) = (fds[0], fds[1]);
+        unsafe { libc::fcntl(rfd, libc::F_SETFL, libc::O_NONBLOCK) };
+        let mut buf = [0u8; 8];
+        let mut read = || {
+            let res = unsafe { libc::read(rfd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+            read_result(read_reply(res))
+        };
+
+        let err = read().unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ReadError>(),
+            Some(&ReadError::WouldBlock)
+        );
+        assert_eq!(op_errno(&Err::<i32, _>(err)), Some(libc::EAGAIN));
+
+        unsafe { libc::write(wfd, b"xy".as_ptr() as *const c_void, 2) };
+        assert_eq!(read().unwrap(), 2);
+        unsafe { libc::close(wfd) };
+        assert_eq!(read().unwrap(), 0);
+        unsafe { libc::close(rfd) };
+
+        let err = read_result(-libc::EBADF).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ReadError>(),
+            Some(&ReadError::Failed(libc::EBADF))
+        );
+    }
+
+    #[test]
+    fn sync_policies() {
+        let none: SyncPolicy = "none".parse().unwrap();
+        let every_op: SyncPolicy = "fsync".parse().unwrap();
+        let every_n: SyncPolicy = "fsync=4".parse().unwrap();
+        let osync: SyncPolicy = "osync".parse().unwrap();
+
+        let synced = |policy: SyncPolicy| (1..=8).filter(|w| policy.sync_after(*w)).count();
+        assert_eq!(synced(none), 0);
+        assert_eq!(synced(every_op), 8);
+        assert_eq!(synced(every_n), 2);
+        assert_eq!(synced(osync), 0);
+
+        assert_eq!(osync.open_flags(), libc::O_SYNC);
+        assert_eq!(every_op.open_flags(), 0);
+        assert!("fsync=0".parse::<SyncPolicy>().is_err());
+        assert!("fdatasync".parse::<SyncPolicy>().is_err());
+    }
+
+    #[test]
+    fn write_modes() {
+        let page = PAGE_SIZE as i64;
+        // Offsets and truncations of 6 writes to a one page file
+        let writes = |mode: WriteMode| {
+            let mut size = page;
+            let mut writes = Vec::new();
+            for _ in 0..6 {
+                let (offset, truncate) = mode.next_write(size, 4 * page);
+                if truncate {
+                    size = 0;
+                }
+                size = size.max(offset + page);
+                writes.push((offset / page, truncate, size / page));
+            }
+            writes
+        };
+
+        let overwrite: WriteMode = "overwrite=2048".parse().unwrap();
+        assert_eq!(overwrite, WriteMode::OverwriteAt(2048));
+        assert!(writes(overwrite).iter().all(|w| *w == (2, false, 3)));
+
+        let append: WriteMode = "append".parse().unwrap();
+        let sizes: Vec<i64> = writes(append).iter().map(|w| w.2).collect();
+        assert_eq!(sizes, [2, 3, 4, 5, 6, 7]);
+        assert_eq!(append.open_flags(), libc::O_APPEND);
+
+        let grow: WriteMode = "grow".parse().unwrap();
+        assert_eq!(
+            writes(grow),
+            [
+                (1, false, 2),
+                (2, false, 3),
+                (3, false, 4),
+                (0, true, 1),
+                (1, false, 2),
+                (2, false, 3)
+            ]
+        );
+        assert_eq!(grow.open_flags(), 0);
+
+        assert_eq!("overwrite".parse(), Ok(WriteMode::OverwriteAt(0)));
+        assert!("overwrite=-1".parse::<WriteMode>().is_err());
+        assert!("truncate".parse::<WriteMode>().is_err());
+    }
+
+    #[test]
+    fn socket_options_are_set() {
+        use std::net::{TcpListener, TcpStream};
+        use std::os::unix::io::AsRawFd;
+
+        let nodelay = |fd| {
+            let mut val: libc::c_int = 0;
+            let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+            let res = unsafe {
+                libc::getsockopt(
+                    fd,
+                    libc::IPPROTO_TCP,
+                    libc::TCP_NODELAY,
+                    &mut val as *mut libc::c_int as *mut libc::c_void,
+                    &mut len,
+                )
+            };
+            assert_eq!(res, 0);
+            val != 0
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let fd = stream.as_raw_fd();
+
+        let sockets = SocketOptions {
+            tcp_nodelay: true,
+            send_buf: Some(64 * 1024),
+            recv_buf: Some(32 * 1024),
+        };
+        sockets.apply(fd, true).unwrap();
+        assert!(nodelay(fd));
+        // Linux doubles the sizes for its bookkeeping
+        assert!(socket_buf_size(fd, libc::SO_SNDBUF) >= 64 * 1024);
+        assert!(socket_buf_size(fd, libc::SO_RCVBUF) >= 32 * 1024);
+
+        let nagle = SocketOptions {
+            tcp_nodelay: false,
+            ..SocketOptions::default()
+        };
+        nagle.apply(fd, true).unwrap();
+        assert!(!nodelay(fd));
+
+        // Not a TCP socket, so it only takes the buffer sizes
+        let (unix, _) = std::os::unix::net::UnixStream::pair().unwrap();
+        sockets.apply(unix.as_raw_fd(), false).unwrap();
+        assert!(sockets.apply(unix.as_raw_fd(), true).is_err());
+    }
+
+    #[test]
+    fn endpoint_write_read_test() -> Result<(), Box<dyn std::error::Error>> {
+        let endpoint = start_test_server("/dev/shm/fxmark_endpoint_test/fx.sock", RPCType::GRPC);
+        let mut client = init_client(&endpoint, RPCType::GRPC);
+
+        let filename = "endpoint_write_read_test.txt";
+        let fd = client
+            .rpc_open(filename, O_CREAT | O_RDWR, S_IRWXU)
+            .unwrap();
+        assert!(fd != -1, "EndpointWriteReadTest: Open Failed");
+
+        let page = "EndpointWriteReadTest".as_bytes();
+        let result = client.rpc_pwrite(fd, page, page.len(), 0).unwrap();
+        assert!(result != -1, "EndpointWriteReadTest: Write Failed");
+
+        let mut page: Vec<u8> = vec![0; PAGE_SIZE];
+        let result = client.rpc_pread(fd, &mut page, PAGE_SIZE, 0).unwrap();
+        assert!(result != -1, "EndpointWriteReadTest: Read Failed");
+
+        let binding = String::from_utf8(page).unwrap();
+        let page_str = binding.trim_matches(char::from(0));
+        assert!(
+            page_str == "EndpointWriteReadTest",
+            "EndpointWriteReadTest: read request returned the following data: {:?}",
+            page_str
+        );
+
+        let result = client.rpc_close(fd).unwrap();
+        assert!(result != -1, "EndpointWriteReadTest: Close Failed");
+
+        let result = client.rpc_remove(filename).unwrap();
+        assert!(result != -1, "EndpointWriteReadTest: Remove Failed");
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn fadvise_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = test_client("/dev/shm/fxmark_fadvise_test/fx.sock");
+
+        let filename = "fadvise_test.txt";
+        let fd = client
+            .rpc_open(filename, O_CREAT | O_RDWR, S_IRWXU)
+            .unwrap();
+        assert!(fd != -1, "FadviseTest: Open Failed");
+
+        let page = vec![0xb; PAGE_SIZE];
+        let result = client.rpc_pwrite(fd, &page, PAGE_SIZE, 0).unwrap();
+        assert!(result != -1, "FadviseTest: Write Failed");
+
+        let result = client
+            .rpc_fadvise(fd, 0, 0, libc::POSIX_FADV_DONTNEED)
+            .unwrap();
+        assert!(result == 0, "FadviseTest: Fadvise returned {}", result);
+
+        let result = client.rpc_close(fd).unwrap();
+        assert!(result != -1, "FadviseTest: Close Failed");
+
+        let result = client.rpc_remove(filename).unwrap();
+        assert!(result != -1, "FadviseTest: Remove Failed");
+
+        Ok(())
+    }
+
+    // Every sync regime must work end to end. Their relative cost only shows on a
+    // disk-backed FS_PATH, the default /dev/shm makes fsync nearly free.
+    #[test]
+    fn sync_policy_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = test_client("/dev/shm/fxmark_sync_policy_test/fx.sock");
+
+        for policy in ["none", "fsync", "fsync=4", "osync"] {
+            let policy: SyncPolicy = policy.parse()?;
+            let filename = "sync_policy_test.txt";
+            let fd = client
+                .rpc_open(filename, O_CREAT | O_RDWR | policy.open_flags(), S_IRWXU)
+                .unwrap();
+            assert!(fd != -1, "SyncPolicyTest({:?}): Open Failed", policy);
+
+            let page = vec![0xb; PAGE_SIZE];
+            for writes in 1..=16 {
+                let offset = (writes * PAGE_SIZE) as i64;
+                let result = client.rpc_pwrite(fd, &page, PAGE_SIZE, offset).unwrap();
+                assert!(result != -1, "SyncPolicyTest({:?}): Write Failed", policy);
+                if policy.sync_after(writes) {
+                    let result = client.rpc_fsync(fd).unwrap();
+                    assert!(result == 0, "SyncPolicyTest({:?}): Fsync Failed", policy);
+                }
+            }
+
+            let result = client.rpc_close(fd).unwrap();
+            assert!(result != -1, "SyncPolicyTest({:?}): Close Failed", policy);
+            let result = client.rpc_remove(filename).unwrap();
+            assert!(result != -1, "SyncPolicyTest({:?}): Remove Failed", policy);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn long_names_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = test_client("/dev/shm/fxmark_long_names_test/fx.sock");
+
+        let names: Vec<String> = (0..16)
+            .map(|n| file_name("longNamesTest", 0, n, 200, Charset::Utf8))
+            .collect();
+        for name in &names {
+            let fd = client.rpc_open(name, O_CREAT | O_RDWR, S_IRWXU).unwrap();
+            assert!(fd != -1, "LongNamesTest: Create Failed");
+            let result = client.rpc_close(fd).unwrap();
+            assert!(result == 0, "LongNamesTest: Close Failed");
+        }
+
+        for name in &names {
+            let result = client.rpc_remove(name).unwrap();
+            assert!(result != -1, "LongNamesTest: Remove Failed");
+            let fd = client.rpc_open(name, O_RDWR, S_IRWXU).unwrap();
+            assert!(fd == -1, "LongNamesTest: File still exists after remove");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn slice_write_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = test_client("/dev/shm/fxmark_slice_write_test/fx.sock");
+
+        let filename = "slice_write_test.txt";
+        let fd = client
+            .rpc_open(filename, O_CREAT | O_RDWR, S_IRWXU)
+            .unwrap();
+        assert!(fd != -1, "SliceWriteTest: Open Failed");
+
+        // Write the middle of a buffer straight from a borrowed slice
+        let buf = *b"xxSliceWriteTestxx";
+        let page = &buf[2..16];
+        let result = client.rpc_pwrite(fd, page, page.len(), 0).unwrap();
+        assert!(result == page.len() as i32, "SliceWriteTest: Write Failed");
+
+        let mut read: Vec<u8> = vec![0; PAGE_SIZE];
+        let result = client.rpc_pread(fd, &mut read, PAGE_SIZE, 0).unwrap();
+        assert!(result == page.len() as i32, "SliceWriteTest: Read Failed");
+        assert_eq!(&read[..page.len()], page);
+
+        let result = client.rpc_close(fd).unwrap();
+        assert!(result == 0, "SliceWriteTest: Close Failed");
+
+        let result = client.rpc_remove(filename).unwrap();
+        assert!(result != -1, "SliceWriteTest: Remove Failed");
+
+        Ok(())
+    }
+
+    // Succeeds with or without root, the server falls back to dropping open files
+    #[test]
+    fn drop_caches_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = test_client("/dev/shm/fxmark_drop_caches_test/fx.sock");
+
+        let filename = "drop_caches_test.txt";
+        let fd = client
+            .rpc_open(filename, O_CREAT | O_RDWR, S_IRWXU)
+            .unwrap();
+        assert!(fd != -1, "DropCachesTest: Open Failed");
+
+        let page = vec![0xb; PAGE_SIZE];
+        let result = client.rpc_pwrite(fd, &page, PAGE_SIZE, 0).unwrap();
+        assert!(result != -1, "DropCachesTest: Write Failed");
+
+        let result = client.rpc_drop_caches().unwrap();
+        assert!(
+            result == 0,
+            "DropCachesTest: DropCaches returned {}",
+            result
+        );
+
+        let mut read: Vec<u8> = vec![0; PAGE_SIZE];
+        let result = client.rpc_pread(fd, &mut read, PAGE_SIZE, 0).unwrap();
+        assert!(result == PAGE_SIZE as i32, "DropCachesTest: Read Failed");
+        assert!(read == page, "DropCachesTest: Read returned wrong data");
+
+        let result = client.rpc_close(fd).unwrap();
+        assert!(result != -1, "DropCachesTest: Close Failed");
+
+        let result = client.rpc_remove(filename).unwrap();
+        assert!(result != -1, "DropCachesTest: Remove Failed");
+
+        Ok(())
+    }
+
+    #[test]
+    fn fstatat_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = test_client("/dev/shm/fxmark_fstatat_test/fx.sock");
+
+        let result = client.rpc_mkdir("fstatat_test", 0o700).unwrap();
+        assert!(result != -1, "FstatAtTest: Mkdir Failed");
+        let dirfd = client
+            .rpc_open("fstatat_test", O_RDONLY | O_DIRECTORY, 0)
+            .unwrap();
+        assert!(dirfd != -1, "FstatAtTest: Open Dir Failed");
+
+        let fd = client
+            .rpc_open("fstatat_test/file.txt", O_CREAT | O_RDWR, S_IRWXU)
+            .unwrap();
+        assert!(fd != -1, "FstatAtTest: Open Failed");
+        let page = vec![0xb; PAGE_SIZE];
+        let result = client.rpc_write(fd, &page, PAGE_SIZE).unwrap();
+        assert!(result == PAGE_SIZE as i32, "FstatAtTest: Write Failed");
+
+        let stat = client.rpc_fstatat(dirfd, "file.txt", 0).unwrap();
+        assert!(
+            stat.size == PAGE_SIZE as i64,
+            "FstatAtTest: Wrong size {}",
+            stat.size
+        );
+
+        let err = client.rpc_fstatat(dirfd, "missing.txt", 0).unwrap_err();
+        let errno = err.downcast_ref::<std::io::Error>().unwrap().raw_os_error();
+        assert!(errno == Some(ENOENT), "FstatAtTest: Expected ENOENT");
+
+        // The server keeps its files next to its socket
+        let link = "/dev/shm/fxmark_fstatat_test/fstatat_test/link.txt";
+        std::os::unix::fs::symlink("file.txt", link)?;
+        let stat = client.rpc_fstatat(dirfd, "link.txt", 0).unwrap();
+        assert!(
+            !stat.is_symlink() && stat.size == PAGE_SIZE as i64,
+            "FstatAtTest: Symlink Not Followed"
+        );
+        let stat = client
+            .rpc_fstatat(dirfd, "link.txt", AT_SYMLINK_NOFOLLOW)
+            .unwrap();
+        assert!(stat.is_symlink(), "FstatAtTest: Symlink Followed");
+
+        client.rpc_close(fd).unwrap();
+        client.rpc_close(dirfd).unwrap();
+        client.rpc_remove("fstatat_test/link.txt").unwrap();
+        client.rpc_remove("fstatat_test/file.txt").unwrap();
+        let result = client.rpc_rmdir("fstatat_test").unwrap();
+        assert!(result != -1, "FstatAtTest: Rmdir Failed");
+
+        Ok(())
+    }
+
+    #[test]
+    fn sendfile_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = test_client("/dev/shm/fxmark_sendfile_test/fx.sock");
+
+        let src = client
+            .rpc_open("sendfile_src.txt", O_CREAT | O_RDWR, S_IRWXU)
+            .unwrap();
+        assert!(src != -1, "SendfileTest: Open Src Failed");
+        let dst = client
+            .rpc_open("sendfile_dst.txt", O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)
+            .unwrap();
+        assert!(dst != -1, "SendfileTest: Open Dst Failed");
+
+        for n in 0..3 {
+            let page = vec![n as u8 + 1; PAGE_SIZE];
+            let result = client
+                .rpc_pwrite(src, &page, PAGE_SIZE, (n * PAGE_SIZE) as i64)
+                .unwrap();
+            assert!(result == PAGE_SIZE as i32, "SendfileTest: Write Failed");
+        }
+
+        // Pages 1 and 2 of the source, then past its end
+        let result = client
+            .rpc_sendfile(dst, src, PAGE_SIZE as i64, 2 * PAGE_SIZE)
+            .unwrap();
+        assert!(
+            result == 2 * PAGE_SIZE as isize,
+            "SendfileTest: Sendfile Failed"
+        );
+        let result = client
+            .rpc_sendfile(dst, src, 2 * PAGE_SIZE as i64, 2 * PAGE_SIZE)
+            .unwrap();
+        assert!(
+            result == PAGE_SIZE as isize,
+            "SendfileTest: Short Sendfile Failed"
+        );
+
+        for (n, expected) in [2u8, 3, 3].iter().enumerate() {
+            let mut page = vec![0; PAGE_SIZE];
+            let result = client
+                .rpc_pread(dst, &mut page, PAGE_SIZE, (n * PAGE_SIZE) as i64)
+                .unwrap();
+            assert!(result == PAGE_SIZE as i32, "SendfileTest: Read Failed");
+            assert!(
+                page.iter().all(|b| b == expected),
+                "SendfileTest: Wrong Data In Page {}",
+                n
+            );
+        }
+
+        client.rpc_close(src).unwrap();
+        client.rpc_close(dst).unwrap();
+        client.rpc_remove("sendfile_src.txt").unwrap();
+        client.rpc_remove("sendfile_dst.txt").unwrap();
+
+        Ok(())
+    }
+
+    // Batches are a DRPC op
+    #[test]
+    fn batch_test() -> Result<(), Box<dyn std::error::Error>> {
+        let endpoint = start_test_server("/dev/shm/fxmark_batch_test/fx.sock", RPCType::DRPC);
+
+        let mut client = init_client(&endpoint, RPCType::DRPC);
+
+        let filename = "batch_test.txt";
+        let results = client.rpc_batch(vec![
+            BatchOp::Open {
+                path: filename.to_string(),
+                flags: O_CREAT | O_RDWR | O_TRUNC,
+                mode: S_IRWXU,
+            },
+            BatchOp::PWrite {
+                fd: batch_fd(0),
+                page: vec![0xa; PAGE_SIZE],
+                offset: 0,
+            },
+            BatchOp::PWrite {
+                fd: batch_fd(0),
+                page: vec![0xb; PAGE_SIZE],
+                offset: PAGE_SIZE as i64,
+            },
+            BatchOp::Close { fd: batch_fd(0) },
+        ])?;
+        assert!(results.len() == 4, "BatchTest: Wrong Number Of Responses");
+        assert!(results[0].result != -1, "BatchTest: Open Failed");
+        assert!(
+            results[1].result == PAGE_SIZE as i32 && results[2].result == PAGE_SIZE as i32,
+            "BatchTest: Write Failed"
+        );
+        assert!(results[3].result == 0, "BatchTest: Close Failed");
+
+        let fd = client.rpc_open(filename, O_RDWR, S_IRWXU).unwrap();
+        assert!(fd != -1, "BatchTest: Reopen Failed");
+        for (n, expected) in [0xau8, 0xb].iter().enumerate() {
+            let mut page = vec![0; PAGE_SIZE];
+            let result = client
+                .rpc_pread(fd, &mut page, PAGE_SIZE, (n * PAGE_SIZE) as i64)
+                .unwrap();
+            assert!(result == PAGE_SIZE as i32, "BatchTest: Read Failed");
+            assert!(
+                page.iter().all(|b| b == expected),
+                "BatchTest: Wrong Data In Page {}",
+                n
+            );
+        }
+
+        client.rpc_close(fd).unwrap();
+        client.rpc_remove(filename).unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn ftruncate_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = test_client("/dev/shm/fxmark_ftruncate_test/fx.sock");
+
+        let fd = client
+            .rpc_open("ftruncate_test.txt", O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)
+            .unwrap();
+        assert!(fd != -1, "FtruncateTest: Open Failed");
+        let page = vec![0xb; 2 * PAGE_SIZE];
+        let result = client.rpc_write(fd, &page, 2 * PAGE_SIZE).unwrap();
+        assert!(
+            result == 2 * PAGE_SIZE as i32,
+            "FtruncateTest: Write Failed"
+        );
+
+        let result = client.rpc_ftruncate(fd, PAGE_SIZE as i64).unwrap();
+        assert!(result == 0, "FtruncateTest: Shrink Failed");
+        let mut page = vec![0; PAGE_SIZE];
+        let result = client
+            .rpc_pread(fd, &mut page, PAGE_SIZE, PAGE_SIZE as i64)
+            .unwrap();
+        assert!(result == 0, "FtruncateTest: Read Past End");
+
+        // Extending reads back zeroes
+        let result = client.rpc_ftruncate(fd, 2 * PAGE_SIZE as i64).unwrap();
+        assert!(result == 0, "FtruncateTest: Extend Failed");
+        let result = client
+            .rpc_pread(fd, &mut page, PAGE_SIZE, PAGE_SIZE as i64)
+            .unwrap();
+        assert!(result == PAGE_SIZE as i32, "FtruncateTest: Read Failed");
+        assert!(page.iter().all(|b| *b == 0), "FtruncateTest: Stale Data");
+
+        client.rpc_close(fd).unwrap();
+        client.rpc_remove("ftruncate_test.txt").unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn nonblocking_read_test() -> Result<(), Box<dyn std::error::Error>> {
+        use std::io::Write;
+
+        let mut client = test_client("/dev/shm/fxmark_nonblocking_read_test/fx.sock");
+
+        // The server keeps its files next to its socket
+        let path = "/dev/shm/fxmark_nonblocking_read_test/nonblocking_read_test";
+        let cpath = std::ffi::CString::new(path)?;
+        let result = unsafe { libc::mkfifo(cpath.as_ptr(), 0o600) };
+        assert!(result == 0, "NonblockingReadTest: Mkfifo Failed");
+
+        let fd = client
+            .rpc_open("nonblocking_read_test", O_RDONLY | O_NONBLOCK, 0)
+            .unwrap();
+        assert!(fd != -1, "NonblockingReadTest: Open Failed");
+        // With a writer the empty pipe would block, without one it's at EOF
+        let mut writer = std::fs::OpenOptions::new().write(true).open(path)?;
+
+        let mut page = vec![0; PAGE_SIZE];
+        let err = client.rpc_read(fd, &mut page, PAGE_SIZE).unwrap_err();
+        assert!(
+            err.downcast_ref::<ReadError>() == Some(&ReadError::WouldBlock),
+            "NonblockingReadTest: Empty Pipe Didn't Block"
+        );
+
+        writer.write_all(b"partial")?;
+        let result = client.rpc_read(fd, &mut page, PAGE_SIZE).unwrap();
+        assert!(result == 7, "NonblockingReadTest: Short Read Failed");
+        assert!(&page[..7] == b"partial", "NonblockingReadTest: Wrong Data");
+
+        drop(writer);
+        let result = client.rpc_read(fd, &mut page, PAGE_SIZE).unwrap();
+        assert!(result == 0, "NonblockingReadTest: No EOF");
+
+        client.rpc_close(fd).unwrap();
+        client.rpc_remove("nonblocking_read_test").unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn capabilities_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = test_client("/dev/shm/fxmark_capabilities_grpc_test/fx.sock");
+        let caps = client.rpc_capabilities()?;
+        assert!(
+            caps.protocol_version == PROTOCOL_VERSION,
+            "CapabilitiesTest: Wrong Protocol Version"
+        );
+        assert!(
+            caps.rpc_types == vec![RPCType::GRPC],
+            "CapabilitiesTest: Wrong RPC Types"
+        );
+        for op in [
+            "open", "read", "pread", "write", "pwrite", "close", "fsync", "remove",
+        ] {
+            assert!(
+                caps.ops.iter().any(|o| o == op),
+                "CapabilitiesTest: Missing Op {}",
+                op
+            );
+        }
+
+        // The DRPC server also runs batches
+        let endpoint = start_test_server(
+            "/dev/shm/fxmark_capabilities_drpc_test/fx.sock",
+            RPCType::DRPC,
+        );
+        let caps = init_client(&endpoint, RPCType::DRPC).rpc_capabilities()?;
+        assert!(
+            caps.protocol_version == PROTOCOL_VERSION,
+            "CapabilitiesTest: Wrong DRPC Protocol Version"
+        );
+        assert!(
+            caps.rpc_types == vec![RPCType::DRPC],
+            "CapabilitiesTest: Wrong DRPC RPC Types"
+        );
+        assert!(
+            caps.ops.iter().any(|o| o == "batch"),
+            "CapabilitiesTest: Missing Batch"
+        );
+
+        Ok(())
+    }
+
+    // The gRPC server runs with a self-signed certificate
+    #[test]
+    fn tls_test() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join("fxmark_tls_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        let tls = TlsConfig {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+            ca: dir.join("cert.pem"),
+        };
+        // Issued to localhost, which clients check certificates of sockets against
+        let generated = std::process::Command::new("openssl")
+            .args([
+                "req",
+                "-x509",
+                "-newkey",
+                "ec",
+                "-pkeyopt",
+                "ec_paramgen_curve:prime256v1",
+            ])
+            .args(["-nodes", "-days", "1", "-subj", "/CN=localhost"])
+            .args(["-addext", "subjectAltName=DNS:localhost"])
+            .args(["-addext", "basicConstraints=critical,CA:FALSE"])
+            .arg("-keyout")
+            .arg(&tls.key)
+            .arg("-out")
+            .arg(&tls.cert)
+            .output();
+        if !generated.map_or(false, |o| o.status.success()) {
+            eprintln!("Skipping tls_test, can't generate a certificate with openssl");
+            return Ok(());
+        }
+
+        let server_tls = tls.clone();
+        let endpoint =
+            start_test_server_with("/dev/shm/fxmark_tls_test/fx.sock", move |endpoint| {
+                run_tls_server(endpoint, &server_tls)
+            });
+
+        let mut client = init_tls_client(&endpoint, &tls);
+        let filename = "tls_test.txt";
+        let fd = client.rpc_open(filename, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)?;
+        assert!(fd != -1, "TlsTest: Open Failed");
+        let page = vec![0xc; PAGE_SIZE];
+        let result = client.rpc_pwrite(fd, &page, PAGE_SIZE, 0)?;
+        assert!(result == PAGE_SIZE as i32, "TlsTest: Write Failed");
+        let mut read = vec![0; PAGE_SIZE];
+        let result = client.rpc_pread(fd, &mut read, PAGE_SIZE, 0)?;
+        assert!(result == PAGE_SIZE as i32, "TlsTest: Read Failed");
+        assert!(read == page, "TlsTest: Wrong Data");
+        client.rpc_close(fd)?;
+        client.rpc_remove(filename)?;
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn partial_page_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = test_client("/dev/shm/fxmark_partial_page_test/fx.sock");
+
+        let filename = "partial_page_test.txt";
+        let fd = client
+            .rpc_open(filename, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)
+            .unwrap();
+        assert!(fd != -1, "PartialPageTest: Open Failed");
+        let result = client.rpc_pwrite(fd, &vec![0xa; PAGE_SIZE], PAGE_SIZE, 0)?;
+        assert!(result == PAGE_SIZE as i32, "PartialPageTest: Fill Failed");
+
+        // Only the first 100 bytes of the buffer are written
+        let result = client.rpc_pwrite(fd, &vec![0xb; PAGE_SIZE], 100, 0)?;
+        assert!(result == 100, "PartialPageTest: Partial Write Failed");
+
+        let mut page = vec![0xff; PAGE_SIZE];
+        let result = client.rpc_pread(fd, &mut page, 100, 0)?;
+        assert!(result == 100, "PartialPageTest: Partial Read Failed");
+        assert!(
+            page[..100].iter().all(|b| *b == 0xb),
+            "PartialPageTest: Wrong Data"
+        );
+        assert!(
+            page.len() == PAGE_SIZE && page[100..].iter().all(|b| *b == 0xff),
+            "PartialPageTest: Read Past Its Size"
+        );
+
+        // A read running past the end of the file comes back short
+        let result = client.rpc_pread(fd, &mut page, PAGE_SIZE, 100)?;
+        assert!(
+            result == (PAGE_SIZE - 100) as i32,
+            "PartialPageTest: Short Read Failed"
+        );
+        assert!(
+            page[..PAGE_SIZE - 100].iter().all(|b| *b == 0xa),
+            "PartialPageTest: Wrong Data In Short Read"
+        );
+
+        client.rpc_close(fd).unwrap();
+        client.rpc_remove(filename).unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn root_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = test_client("/dev/shm/fxmark_root_test/fx.sock");
+
+        // Exists, but outside the server root
+        let fd = client.rpc_open("../../../etc/passwd", O_RDONLY, 0).unwrap();
+        assert!(fd == -1, "RootTest: Opened A File Outside The Root");
+        let err = client
+            .rpc_fstatat(libc::AT_FDCWD, "../../../etc/passwd", 0)
+            .unwrap_err();
+        let errno = err.downcast_ref::<std::io::Error>().unwrap().raw_os_error();
+        assert!(errno == Some(libc::EACCES), "RootTest: Expected EACCES");
+
+        // Climbs back down into the root
+        let fd = client
+            .rpc_open("root_test/../root_test.txt", O_CREAT | O_RDWR, S_IRWXU)
+            .unwrap();
+        assert!(fd != -1, "RootTest: Open Failed");
+        assert!(
+            std::path::Path::new("/dev/shm/fxmark_root_test/root_test.txt").exists(),
+            "RootTest: File Not Under The Root"
+        );
+        client.rpc_close(fd).unwrap();
+        client.rpc_remove("root_test.txt").unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn wire_format_test() -> Result<(), Box<dyn std::error::Error>> {
+        let endpoint = start_test_server("/dev/shm/fxmark_wire_format_test/fx.sock", RPCType::DRPC);
+
+        // Both formats on the same server, it answers each in its own
+        let mut bincode = init_client_with(&endpoint, RPCType::DRPC, WireFormat::Bincode);
+        let mut abomonation = init_client_with(&endpoint, RPCType::DRPC, WireFormat::Abomonation);
+
+        let filename = "wire_format_test.txt";
+        let results = bincode.rpc_batch(vec![
+            BatchOp::Open {
+                path: filename.to_string(),
+                flags: O_CREAT | O_RDWR | O_TRUNC,
+                mode: S_IRWXU,
+            },
+            BatchOp::PWrite {
+                fd: batch_fd(0),
+                page: vec![0xa; PAGE_SIZE],
+                offset: 0,
+            },
+            BatchOp::Close { fd: batch_fd(0) },
+        ])?;
+        assert!(
+            results.len() == 3,
+            "WireFormatTest: Wrong Number Of Responses"
+        );
+        assert!(
+            results[0].result != -1
+                && results[1].result == PAGE_SIZE as i32
+                && results[2].result == 0,
+            "WireFormatTest: Batch Failed"
+        );
+
+        let fd = bincode.rpc_open(filename, O_RDWR, S_IRWXU)?;
+        assert!(fd != -1, "WireFormatTest: Open Failed");
+        let result = bincode.rpc_pwrite(fd, &vec![0xb; PAGE_SIZE], PAGE_SIZE, PAGE_SIZE as i64)?;
+        assert!(result == PAGE_SIZE as i32, "WireFormatTest: Write Failed");
+        let stat = bincode.rpc_fstatat(libc::AT_FDCWD, filename, 0)?;
+        assert!(
+            stat.size == 2 * PAGE_SIZE as i64,
+            "WireFormatTest: Wrong Size"
+        );
+
+        // What one format wrote the other reads
+        let other_fd = abomonation.rpc_open(filename, O_RDWR, 0)?;
+        assert!(other_fd != -1, "WireFormatTest: Other Open Failed");
+        for (client, fd) in [(&mut bincode, fd), (&mut abomonation, other_fd)] {
+            for (n, expected) in [0xau8, 0xb].iter().enumerate() {
+                let mut page = vec![0; PAGE_SIZE];
+                let result = client.rpc_pread(fd, &mut page, PAGE_SIZE, (n * PAGE_SIZE) as i64)?;
+                assert!(result == PAGE_SIZE as i32, "WireFormatTest: Read Failed");
+                assert!(
+                    page.iter().all(|b| b == expected),
+                    "WireFormatTest: Wrong Data In Page {}",
+                    n
+                );
+            }
+        }
+
+        let caps = bincode.rpc_capabilities()?;
+        assert!(
+            caps.protocol_version == PROTOCOL_VERSION && caps.ops.iter().any(|op| op == "batch"),
+            "WireFormatTest: Wrong Capabilities"
+        );
+        assert!(
+            !bincode.rpc_server_stats()?.is_empty(),
+            "WireFormatTest: No Server Stats"
+        );
+
+        abomonation.rpc_close(other_fd)?;
+        bincode.rpc_close(fd)?;
+        bincode.rpc_remove(filename)?;
+
+        Ok(())
+    }
+
+    // SEEK_DATA and SEEK_HOLE are Linux's
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn seek_hole_test() -> Result<(), Box<dyn std::error::Error>> {
+        use libc::{ENXIO, SEEK_DATA, SEEK_HOLE};
+
+        let mut client = test_client("/dev/shm/fxmark_seek_hole_test/fx.sock");
+
+        let fd = client
+            .rpc_open("seek_hole_test.txt", O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)
+            .unwrap();
+        assert!(fd != -1, "SeekHoleTest: Open Failed");
+
+        // Larger than the blocks of any file system, so it stays a hole
+        let hole = 1 << 20;
+        let page = vec![0xc; PAGE_SIZE];
+        let result = client.rpc_pwrite(fd, &page, PAGE_SIZE, hole).unwrap();
+        assert!(result == PAGE_SIZE as i32, "SeekHoleTest: Write Failed");
+        let end = hole + PAGE_SIZE as i64;
+
+        let result = client.rpc_lseek(fd, 0, SEEK_HOLE).unwrap();
+        assert!(result == 0, "SeekHoleTest: Hole Not At Start");
+        let result = client.rpc_lseek(fd, 0, SEEK_DATA).unwrap();
+        assert!(result == hole, "SeekHoleTest: Data Not After Hole");
+        // The end of a file counts as a hole
+        let result = client.rpc_lseek(fd, hole, SEEK_HOLE).unwrap();
+        assert!(result == end, "SeekHoleTest: Hole Not At End");
+
+        for whence in [SEEK_DATA, SEEK_HOLE] {
+            let err = client.rpc_lseek(fd, end, whence).unwrap_err();
+            let errno = err.downcast_ref::<std::io::Error>().unwrap().raw_os_error();
+            assert!(errno == Some(ENXIO), "SeekHoleTest: Expected ENXIO");
+        }
+
+        client.rpc_close(fd).unwrap();
+        client.rpc_remove("seek_hole_test.txt").unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn rename_test() -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = test_client("/dev/shm/fxmark_rename_test/fx.sock");
+
+        let result = client.rpc_mkdir("rename_test", S_IRWXU).unwrap();
+        assert!(result == 0, "RenameTest: Mkdir Failed");
+        let fd = client
+            .rpc_open("rename_test/a.txt", O_CREAT | O_RDWR | O_TRUNC, S_IRWXU)
+            .unwrap();
+        assert!(fd != -1, "RenameTest: Open Failed");
+        let page = vec![0xd; PAGE_SIZE];
+        let result = client.rpc_write(fd, &page, PAGE_SIZE).unwrap();
+        assert!(result == PAGE_SIZE as i32, "RenameTest: Write Failed");
+        client.rpc_close(fd).unwrap();
+
+        // Within the directory, then out of it
+        let result = client
+            .rpc_rename("rename_test/a.txt", "rename_test/b.txt")
+            .unwrap();
+        assert!(result == 0, "RenameTest: Rename Failed");
+        let result = client
+            .rpc_rename("rename_test/b.txt", "rename_test.txt")
+            .unwrap();
+        assert!(result == 0, "RenameTest: Rename Across Dirs Failed");
+        let result = client
+            .rpc_rename("rename_test/a.txt", "rename_test/c.txt")
+            .unwrap();
+        assert!(result == -1, "RenameTest: Renamed A Missing File");
+        let result = client
+            .rpc_rename("rename_test.txt", "../rename_test.txt")
+            .unwrap();
+        assert!(result == -1, "RenameTest: Renamed Out Of The Root");
+
+        let fd = client.rpc_open("rename_test.txt", O_RDONLY, 0).unwrap();
+        assert!(fd != -1, "RenameTest: Open Renamed Failed");
+        let mut read = vec![0; PAGE_SIZE];
+        let result = client.rpc_pread(fd, &mut read, PAGE_SIZE, 0).unwrap();
+        assert!(result == PAGE_SIZE as i32, "RenameTest: Read Failed");
+        assert!(read == page, "RenameTest: Wrong Data");
+        client.rpc_close(fd).unwrap();
+
+        client.rpc_remove("rename_test.txt").unwrap();
+        client.rpc_rmdir("rename_test").unwrap();
+
+        Ok(())
+    }
+
+    #[test]
+    fn host_info_reports_applied_socket_options() {
+        use std::os::unix::io::AsRawFd;
+
+        let sockets = SocketOptions {
+            tcp_nodelay: false,
+            send_buf: Some(96 * 1024),
+            recv_buf: Some(48 * 1024),
+        };
+        // Any free port, connections queue on the listener until it serves them
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let endpoint: RPCEndpoint = format!("tcp://{}", addr).parse().unwrap();
+        std::thread::spawn(move || serve_drpc_tcp(listener, &sockets));
+
+        // Ops run over connections with the options set on both ends
+        let mut client =
+            init_client_with_sockets(&endpoint, RPCType::DRPC, WireFormat::default(), &sockets);
+        assert!(client.rpc_capabilities().is_ok());
+
+        // What the kernel makes of the sizes, which it may double or cap
+        let stream = std::net::TcpStream::connect(&addr).unwrap();
+        sockets.apply(stream.as_raw_fd(), true).unwrap();
+        let host_info = HostInfo::new(&endpoint, &sockets);
+        assert_eq!(
+            host_info.sock_sndbuf,
+            socket_buf_size(stream.as_raw_fd(), libc::SO_SNDBUF)
+        );
+        assert_eq!(
+            host_info.sock_rcvbuf,
+            socket_buf_size(stream.as_raw_fd(), libc::SO_RCVBUF)
+        );
+    }
+}
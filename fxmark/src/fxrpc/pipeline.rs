@@ -0,0 +1,213 @@
+/*
+    Several ops of one core in flight at once.
+    A core that waits for every reply before sending its next op keeps the
+    server's storage at a queue depth of one. A pipeline sends each op over
+    one of its own connections, each served by a thread, and hands out the
+    replies as they complete, so storage can be measured at deeper queues.
+*/
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::fxrpc::{op_errno, run_batch, BatchOp, BatchResult, FxRPC};
+
+/// How a pipelined op ended: its result as in a batch, or the errno the
+/// RPC failed with, if known.
+pub type Completion = Result<BatchResult, Option<i32>>;
+
+/// Keeps up to `depth` ops in flight, one per connection.
+pub struct Pipeline {
+    depth: usize,
+    in_flight: usize,
+    ops: Option<Sender<BatchOp>>,
+    completions: Receiver<Completion>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Pipeline {
+    /// Opens `depth` connections, each with `connect` on the thread that
+    /// serves it.
+    pub fn new<F>(depth: usize, connect: F) -> Pipeline
+    where
+        F: FnOnce() -> Box<dyn FxRPC> + Clone + Send + 'static,
+    {
+        assert!(depth > 0, "Pipeline needs a queue depth of at least 1");
+        let (ops, queue) = channel::<BatchOp>();
+        let queue = Arc::new(Mutex::new(queue));
+        let (done, completions) = channel();
+        let workers = (0..depth)
+            .map(|_| {
+                let connect = connect.clone();
+                let queue = Arc::clone(&queue);
+                let done = done.clone();
+                thread::spawn(move || {
+                    let mut client = connect();
+                    loop {
+                        // Idle workers take turns waiting for the next op
+                        let op = match queue.lock().unwrap().recv() {
+                            Ok(op) => op,
+                            Err(_) => break,
+                        };
+                        let res = match run_batch(&mut *client, vec![op]) {
+                            Ok(mut results) => Ok(results.remove(0)),
+                            res => Err(op_errno(&res)),
+                        };
+                        if done.send(res).is_err() {
+                            break;
+                        }
+                    }
+                })
+            })
+            .collect();
+        Pipeline {
+            depth: depth,
+            in_flight: 0,
+            ops: Some(ops),
+            completions: completions,
+            workers: workers,
+        }
+    }
+
+    /// Most ops in flight at once.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Ops sent whose completion wasn't handed out yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Sends `op`. Once that makes `depth` ops in flight, waits for one of
+    /// them to complete and returns it. At depth 1 every op completes before
+    /// `submit()` returns, as without a pipeline.
+    pub fn submit(&mut self, op: BatchOp) -> Option<Completion> {
+        self.ops
+            .as_ref()
+            .unwrap()
+            .send(op)
+            .expect("Pipeline workers are gone");
+        self.in_flight += 1;
+        if self.in_flight == self.depth {
+            Some(self.complete())
+        } else {
+            None
+        }
+    }
+
+    /// Waits for the next of the ops in flight to complete.
+    pub fn complete(&mut self) -> Completion {
+        assert!(self.in_flight > 0, "No op in flight");
+        let res = self.completions.recv().expect("Pipeline workers are gone");
+        self.in_flight -= 1;
+        res
+    }
+
+    /// Waits for all ops in flight to complete.
+    pub fn drain(&mut self) -> Vec<Completion> {
+        (0..self.in_flight).map(|_| self.complete()).collect()
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        // Hanging up the queue stops the workers once their ops are done
+        self.ops.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    // A transport whose reads take a while, counting how many of them all
+    // its connections have outstanding at once
+    #[derive(Clone)]
+    struct Counting {
+        outstanding: Arc<AtomicUsize>,
+        most: Arc<AtomicUsize>,
+    }
+
+    impl FxRPC for Counting {
+        fn rpc_pread(
+            &mut self,
+            _fd: i32,
+            _page: &mut Vec<u8>,
+            size: usize,
+            _offset: i64,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            let now = self.outstanding.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            self.outstanding.fetch_sub(1, Ordering::SeqCst);
+            Ok(size as i32)
+        }
+
+        unimplemented_ops!(
+            rpc_open,
+            rpc_read,
+            rpc_write,
+            rpc_pwrite,
+            rpc_close,
+            rpc_fsync,
+            rpc_ftruncate,
+            rpc_remove,
+            rpc_rename,
+            rpc_mkdir,
+            rpc_rmdir,
+            rpc_fadvise,
+            rpc_drop_caches,
+            rpc_fstatat,
+            rpc_sendfile,
+            rpc_lseek,
+            rpc_batch,
+            rpc_dump_server_log,
+            rpc_server_stats,
+            rpc_capabilities,
+            rpc_reset
+        );
+    }
+
+    // Most reads outstanding at once while reading 32 pages at `depth`
+    fn most_outstanding(depth: usize) -> usize {
+        let transport = Counting {
+            outstanding: Arc::new(AtomicUsize::new(0)),
+            most: Arc::new(AtomicUsize::new(0)),
+        };
+        let most = Arc::clone(&transport.most);
+        let mut pipeline = Pipeline::new(depth, move || Box::new(transport) as Box<dyn FxRPC>);
+
+        let read = |n: usize| BatchOp::PRead {
+            fd: 3,
+            size: 16,
+            offset: n as i64 * 16,
+        };
+        let mut completions = Vec::new();
+        for n in 0..32 {
+            match pipeline.submit(read(n)) {
+                Some(completion) => completions.push(completion),
+                // Only while the queue fills up
+                None => assert!(n + 1 < depth),
+            }
+            assert!(pipeline.in_flight() < depth);
+        }
+        completions.extend(pipeline.drain());
+        assert_eq!(pipeline.in_flight(), 0);
+        assert_eq!(completions.len(), 32);
+        assert!(completions.iter().all(|c| c.as_ref().unwrap().result == 16));
+        most.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn depth_bounds_ops_in_flight() {
+        assert_eq!(most_outstanding(8), 8);
+        // Every op completes before the next is sent
+        assert_eq!(most_outstanding(1), 1);
+    }
+}
@@ -0,0 +1,156 @@
+/*
+    In-memory ring buffer of recent server requests.
+    Used to debug benchmark anomalies from the server side.
+*/
+
+use abomonation::Abomonation;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// Number of requests retained by the server
+pub const REQUEST_LOG_SIZE: usize = 1024;
+
+static LOG_ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref REQUEST_LOG: RequestLog = RequestLog::new(REQUEST_LOG_SIZE);
+}
+
+/// Operation type of a logged request.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[repr(u8)]
+pub enum RequestOp {
+    Open = 1,
+    Read = 2,
+    PRead = 3,
+    Write = 4,
+    PWrite = 5,
+    Close = 6,
+    Remove = 7,
+    MkDir = 8,
+    RmDir = 9,
+    Fsync = 10,
+    Fstat = 11,
+    Fadvise = 12,
+    DropCaches = 13,
+    FstatAt = 14,
+    Sendfile = 15,
+    Ftruncate = 16,
+    Lseek = 17,
+    Rename = 18,
+}
+
+/// A single logged request and its result.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LogRecord {
+    pub op: u8,
+    pub fd: i32,
+    pub size: u64,
+    pub offset: i64,
+    pub result: i64,
+}
+
+unsafe_abomonate!(LogRecord : op, fd, size, offset, result);
+
+pub struct RequestLog {
+    head: AtomicUsize,
+    slots: Vec<Mutex<Option<LogRecord>>>,
+}
+
+impl RequestLog {
+    pub fn new(capacity: usize) -> RequestLog {
+        RequestLog {
+            head: AtomicUsize::new(0),
+            slots: (0..capacity).map(|_| Mutex::new(None)).collect(),
+        }
+    }
+
+    pub fn record(&self, record: LogRecord) {
+        // Each writer claims its own slot, so slot locks are only contended on wrap-around
+        let idx = self.head.fetch_add(1, Ordering::Relaxed);
+        *self.slots[idx % self.slots.len()].lock().unwrap() = Some(record);
+    }
+
+    /// Returns the retained records, oldest first.
+    pub fn dump(&self) -> Vec<LogRecord> {
+        let head = self.head.load(Ordering::Acquire);
+        let len = self.slots.len();
+        (head.saturating_sub(len)..head)
+            .filter_map(|i| *self.slots[i % len].lock().unwrap())
+            .collect()
+    }
+}
+
+pub fn enable_request_log() {
+    LOG_ENABLED.store(true, Ordering::Release);
+}
+
+pub fn log_request(op: RequestOp, fd: i32, size: usize, offset: i64, result: i64) {
+    if !LOG_ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    REQUEST_LOG.record(LogRecord {
+        op: op as u8,
+        fd: fd,
+        size: size as u64,
+        offset: offset,
+        result: result,
+    });
+}
+
+pub fn dump_request_log() -> Vec<LogRecord> {
+    REQUEST_LOG.dump()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fxrpc::{init_client, start_test_server, RPCType};
+    use libc::{O_CREAT, O_RDWR, S_IRWXU};
+
+    #[test]
+    fn dump_log_test() -> Result<(), Box<dyn std::error::Error>> {
+        enable_request_log();
+        let endpoint = start_test_server("/dev/shm/fxmark_dump_log_test/fx.sock", RPCType::GRPC);
+        let mut client = init_client(&endpoint, RPCType::GRPC);
+
+        let filename = "dump_log_test.txt";
+        let fd = client
+            .rpc_open(filename, O_CREAT | O_RDWR, S_IRWXU)
+            .unwrap();
+        assert!(fd != -1, "DumpLogTest: Open Failed");
+
+        let page = "DumpLogTest".as_bytes();
+        let result = client.rpc_pwrite(fd, page, page.len(), 0).unwrap();
+        assert!(result != -1, "DumpLogTest: Write Failed");
+
+        let result = client.rpc_close(fd).unwrap();
+        assert!(result != -1, "DumpLogTest: Close Failed");
+
+        // The log is the process's, other tests' servers log to it concurrently,
+        // so only look at requests for our fd
+        let log = client.rpc_dump_server_log().unwrap();
+        let ops: Vec<u8> = log
+            .iter()
+            .filter(|r| r.fd == fd || (r.op == RequestOp::Open as u8 && r.result == fd as i64))
+            .map(|r| r.op)
+            .collect();
+        let expected = vec![
+            RequestOp::Open as u8,
+            RequestOp::PWrite as u8,
+            RequestOp::Close as u8,
+        ];
+        assert!(
+            ops.ends_with(&expected),
+            "DumpLogTest: log contained the following ops: {:?}",
+            ops
+        );
+
+        let result = client.rpc_remove(filename).unwrap();
+        assert!(result != -1, "DumpLogTest: Remove Failed");
+
+        Ok(())
+    }
+}
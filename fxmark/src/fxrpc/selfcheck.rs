@@ -0,0 +1,307 @@
+/*
+    A one-shot pass over the ops of a live server.
+    Checks that they round trip correctly before trusting benchmark numbers
+    taken over it.
+*/
+
+use libc::{AT_FDCWD, ENOENT, O_CREAT, O_RDWR, O_TRUNC, POSIX_FADV_SEQUENTIAL, SEEK_END, S_IRWXU};
+
+use crate::fxrpc::{batch_fd, BatchOp, FxRPC, PAGE_SIZE, PROTOCOL_VERSION};
+
+const DIR: &str = "fxmark_self_check";
+
+/// The ops `self_check()` covers, in the order it runs them. Dropping the
+/// server's caches, dumping its request log and resetting the client's
+/// descriptors change or need server state, so they're left out.
+pub const CHECKED_OPS: [&str; 19] = [
+    "mkdir",
+    "open",
+    "write",
+    "pwrite",
+    "fsync",
+    "pread",
+    "read",
+    "lseek",
+    "fstatat",
+    "fadvise",
+    "sendfile",
+    "ftruncate",
+    "rename",
+    "batch",
+    "server_stats",
+    "capabilities",
+    "close",
+    "remove",
+    "rmdir",
+];
+
+/// How one op fared.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpCheck {
+    pub op: &'static str,
+    /// Why the op failed, None if it passed.
+    pub error: Option<String>,
+}
+
+/// The outcome of `self_check()`, one entry per op of `CHECKED_OPS`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SelfCheckReport {
+    pub checks: Vec<OpCheck>,
+}
+
+impl SelfCheckReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.error.is_none())
+    }
+
+    fn check(&mut self, op: &'static str, res: Result<(), String>) {
+        self.checks.push(OpCheck {
+            op,
+            error: res.err(),
+        });
+    }
+}
+
+impl core::fmt::Display for SelfCheckReport {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for check in &self.checks {
+            match &check.error {
+                None => writeln!(f, "{:<12} ok", check.op)?,
+                Some(e) => writeln!(f, "{:<12} FAILED: {}", check.op, e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Fails unless the op returned `expected`
+fn expect<T: PartialEq + core::fmt::Debug>(
+    res: Result<T, Box<dyn std::error::Error>>,
+    expected: T,
+) -> Result<(), String> {
+    match res {
+        Ok(v) if v == expected => Ok(()),
+        Ok(v) => Err(format!("returned {:?}, expected {:?}", v, expected)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// Fails unless a read returned all of `expected`
+fn expect_data(
+    res: Result<i32, Box<dyn std::error::Error>>,
+    page: &[u8],
+    expected: &[u8],
+) -> Result<(), String> {
+    expect(res, expected.len() as i32)?;
+    if &page[..expected.len()] != expected {
+        return Err("read back different data than was written".to_string());
+    }
+    Ok(())
+}
+
+// A page that differs from the pages made with other `seed`s
+fn pattern(seed: u8) -> Vec<u8> {
+    (0..PAGE_SIZE).map(|i| (i % 251) as u8 ^ seed).collect()
+}
+
+/// Runs every op once against the server `client` is connected to, in a
+/// scratch directory it removes again, and checks that the ops succeed and
+/// read back what was written. Ops that can't run because the file couldn't
+/// be opened are reported as failed.
+pub fn self_check(client: &mut dyn FxRPC) -> SelfCheckReport {
+    let mut report = SelfCheckReport::default();
+    let file = format!("{}/data.txt", DIR);
+    let copy = format!("{}/copy.txt", DIR);
+    // Left over from an interrupted check
+    let _ = client.rpc_remove(&file);
+    let _ = client.rpc_remove(&copy);
+    let _ = client.rpc_rmdir(DIR);
+
+    report.check("mkdir", expect(client.rpc_mkdir(DIR, S_IRWXU), 0));
+    let fd = match client.rpc_open(&file, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU) {
+        Ok(fd) if fd >= 0 => {
+            report.check("open", Ok(()));
+            fd
+        }
+        res => {
+            report.check("open", expect(res, 0).map_err(|e| format!("no fd, {}", e)));
+            for op in &CHECKED_OPS[report.checks.len()..] {
+                report.check(*op, Err("not run, open failed".to_string()));
+            }
+            return report;
+        }
+    };
+
+    let first = pattern(0);
+    let second = pattern(0x5a);
+    let mut page = vec![0; PAGE_SIZE];
+    report.check(
+        "write",
+        expect(client.rpc_write(fd, &first, PAGE_SIZE), PAGE_SIZE as i32),
+    );
+    report.check(
+        "pwrite",
+        expect(
+            client.rpc_pwrite(fd, &second, PAGE_SIZE, PAGE_SIZE as i64),
+            PAGE_SIZE as i32,
+        ),
+    );
+    report.check("fsync", expect(client.rpc_fsync(fd), 0));
+    report.check(
+        "pread",
+        expect_data(client.rpc_pread(fd, &mut page, PAGE_SIZE, 0), &page, &first),
+    );
+    // The write moved the offset past the first page, the pwrite didn't
+    report.check(
+        "read",
+        expect_data(client.rpc_read(fd, &mut page, PAGE_SIZE), &page, &second),
+    );
+    report.check(
+        "lseek",
+        expect(client.rpc_lseek(fd, 0, SEEK_END), 2 * PAGE_SIZE as i64),
+    );
+    report.check(
+        "fstatat",
+        expect(
+            client.rpc_fstatat(AT_FDCWD, &file, 0).map(|s| s.size),
+            2 * PAGE_SIZE as i64,
+        ),
+    );
+    report.check(
+        "fadvise",
+        expect(client.rpc_fadvise(fd, 0, 0, POSIX_FADV_SEQUENTIAL), 0),
+    );
+
+    let sendfile = match client.rpc_open(&copy, O_CREAT | O_RDWR | O_TRUNC, S_IRWXU) {
+        Ok(copy_fd) if copy_fd >= 0 => {
+            let res = expect(
+                client.rpc_sendfile(copy_fd, fd, 0, 2 * PAGE_SIZE),
+                2 * PAGE_SIZE as isize,
+            )
+            .and_then(|_| {
+                let res = client.rpc_pread(copy_fd, &mut page, PAGE_SIZE, PAGE_SIZE as i64);
+                expect_data(res, &page, &second)
+            });
+            let _ = client.rpc_close(copy_fd);
+            let _ = client.rpc_remove(&copy);
+            res
+        }
+        _ => Err("not run, can't open the copy".to_string()),
+    };
+    report.check("sendfile", sendfile);
+
+    let ftruncate = expect(client.rpc_ftruncate(fd, PAGE_SIZE as i64), 0).and_then(|_| {
+        expect(
+            client.rpc_pread(fd, &mut page, PAGE_SIZE, PAGE_SIZE as i64),
+            0,
+        )
+        .map_err(|e| format!("reading past the new end: {}", e))
+    });
+    report.check("ftruncate", ftruncate);
+
+    // There and back, the batch opens the file by its name
+    let rename = expect(client.rpc_rename(&file, &copy), 0)
+        .and_then(|_| expect(client.rpc_rename(&copy, &file), 0));
+    report.check("rename", rename);
+
+    let batch = client.rpc_batch(vec![
+        BatchOp::Open {
+            path: file.clone(),
+            flags: O_RDWR,
+            mode: 0,
+        },
+        BatchOp::PRead {
+            fd: batch_fd(0),
+            size: PAGE_SIZE,
+            offset: 0,
+        },
+        BatchOp::Close { fd: batch_fd(0) },
+    ]);
+    let batch = match batch {
+        Ok(results) if results.len() == 3 => {
+            let read = &results[1];
+            expect_data(Ok(read.result), &read.page, &first)
+                .and_then(|_| expect(Ok(results[2].result), 0))
+        }
+        Ok(results) => Err(format!("{} results for 3 ops", results.len())),
+        Err(e) => Err(e.to_string()),
+    };
+    report.check("batch", batch);
+
+    report.check(
+        "server_stats",
+        client
+            .rpc_server_stats()
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+    );
+    report.check(
+        "capabilities",
+        expect(
+            client.rpc_capabilities().map(|c| c.protocol_version),
+            PROTOCOL_VERSION,
+        ),
+    );
+    report.check("close", expect(client.rpc_close(fd), 0));
+    report.check("remove", expect(client.rpc_remove(&file), 0));
+    let rmdir =
+        expect(client.rpc_rmdir(DIR), 0).and_then(|_| match client.rpc_fstatat(AT_FDCWD, DIR, 0) {
+            Ok(_) => Err("the directory is still there".to_string()),
+            Err(e) => match e
+                .downcast_ref::<std::io::Error>()
+                .and_then(|e| e.raw_os_error())
+            {
+                Some(ENOENT) => Ok(()),
+                _ => Err(format!("checking for the directory: {}", e)),
+            },
+        });
+    report.check("rmdir", rmdir);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fxrpc::{init_client, start_test_server, RPCType};
+
+    fn check_server(path: &'static str, rpc_type: RPCType) -> SelfCheckReport {
+        let endpoint = start_test_server(path, rpc_type);
+        let mut client = init_client(&endpoint, rpc_type);
+        self_check(client.as_mut())
+    }
+
+    #[test]
+    fn all_ops_pass_against_a_live_server() {
+        for (path, rpc_type) in [
+            ("/dev/shm/fxmark_self_check_grpc/fx.sock", RPCType::GRPC),
+            ("/dev/shm/fxmark_self_check_drpc/fx.sock", RPCType::DRPC),
+        ] {
+            let report = check_server(path, rpc_type);
+            let ops: Vec<&str> = report.checks.iter().map(|c| c.op).collect();
+            assert_eq!(ops, CHECKED_OPS);
+            assert!(report.passed(), "{}", report);
+        }
+    }
+
+    #[test]
+    fn check_can_run_again() {
+        for (path, rpc_type) in [
+            (
+                "/dev/shm/fxmark_self_check_again_grpc/fx.sock",
+                RPCType::GRPC,
+            ),
+            (
+                "/dev/shm/fxmark_self_check_again_drpc/fx.sock",
+                RPCType::DRPC,
+            ),
+        ] {
+            let endpoint = start_test_server(path, rpc_type);
+            let mut client = init_client(&endpoint, rpc_type);
+            let first = self_check(client.as_mut());
+            assert!(first.passed(), "{}", first);
+            // Nothing of the first check is left to get in the way
+            let second = self_check(client.as_mut());
+            assert!(second.passed(), "{}", second);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+/*
+    Server-side service time histograms, one per operation type.
+    Together with client-side latency this splits a request's latency into
+    network and storage time.
+*/
+
+use abomonation::Abomonation;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::fxrpc::reqlog::RequestOp;
+
+/// Bucket i counts service times in [2^i, 2^(i+1)) ns, bucket 0 also takes 0 ns.
+pub const HISTOGRAM_BUCKETS: usize = 64;
+
+/// Upper bound on the number of histograms, RequestOp values start at 1.
+pub const HISTOGRAM_OPS: usize = RequestOp::Rename as usize + 1;
+
+lazy_static! {
+    static ref SERVER_STATS: Vec<AtomicU64> = (0..HISTOGRAM_OPS * HISTOGRAM_BUCKETS)
+        .map(|_| AtomicU64::new(0))
+        .collect();
+}
+
+/// Service time histogram of one operation type.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpHistogram {
+    pub op: u8,
+    pub buckets: Vec<u64>,
+}
+
+unsafe_abomonate!(OpHistogram : op, buckets);
+
+impl OpHistogram {
+    /// Number of operations recorded.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+}
+
+/// Records how long the server took to handle one request. A single relaxed
+/// atomic increment, so it's fine to call on every request.
+pub fn record_service_time(op: RequestOp, elapsed: Duration) {
+    let nanos = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+    let bucket = (63 - nanos.max(1).leading_zeros()) as usize;
+    SERVER_STATS[op as usize * HISTOGRAM_BUCKETS + bucket].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Histograms of all operation types that were served at least once.
+pub fn server_stats() -> Vec<OpHistogram> {
+    (1..HISTOGRAM_OPS)
+        .map(|op| OpHistogram {
+            op: op as u8,
+            buckets: SERVER_STATS[op * HISTOGRAM_BUCKETS..(op + 1) * HISTOGRAM_BUCKETS]
+                .iter()
+                .map(|b| b.load(Ordering::Relaxed))
+                .collect(),
+        })
+        .filter(|h| h.count() > 0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fxrpc::{init_client, start_test_server, FxRPC, RPCType};
+    use libc::S_IRWXU;
+
+    fn mkdir_histogram() -> Vec<u64> {
+        server_stats()
+            .into_iter()
+            .find(|h| h.op == RequestOp::MkDir as u8)
+            .map_or(vec![0; HISTOGRAM_BUCKETS], |h| h.buckets)
+    }
+
+    #[test]
+    fn histogram_counts_ops() {
+        // The histograms are the process's, other tests' servers add to them
+        // concurrently, so only check that ours were counted
+        let before = mkdir_histogram();
+        for nanos in [0, 1, 700, 1500, 1_000_000] {
+            record_service_time(RequestOp::MkDir, Duration::from_nanos(nanos));
+        }
+
+        let after = mkdir_histogram();
+        let added: Vec<u64> = after.iter().zip(&before).map(|(a, b)| a - b).collect();
+        assert!(added.iter().sum::<u64>() >= 5);
+        assert!(added[0] >= 2);
+        assert!(added[9] >= 1);
+        assert!(added[10] >= 1);
+        assert!(added[19] >= 1);
+    }
+
+    #[test]
+    fn server_stats_test() -> Result<(), Box<dyn std::error::Error>> {
+        let endpoint =
+            start_test_server("/dev/shm/fxmark_server_stats_test/fx.sock", RPCType::GRPC);
+        let mut client = init_client(&endpoint, RPCType::GRPC);
+
+        let mkdirs = |client: &mut dyn FxRPC| -> Result<u64, Box<dyn std::error::Error>> {
+            Ok(client
+                .rpc_server_stats()?
+                .iter()
+                .find(|h| h.op == RequestOp::MkDir as u8)
+                .map_or(0, |h| h.count()))
+        };
+
+        let before = mkdirs(client.as_mut())?;
+        for n in 0..8 {
+            let dirname = format!("serverStatsTest{}", n);
+            let res = client.rpc_mkdir(&dirname, S_IRWXU).unwrap();
+            assert!(res != -1, "ServerStatsTest: Mkdir Failed");
+            let res = client.rpc_rmdir(&dirname).unwrap();
+            assert!(res != -1, "ServerStatsTest: Rmdir Failed");
+        }
+        let after = mkdirs(client.as_mut())?;
+
+        // Other tests may create directories concurrently
+        assert!(
+            after - before >= 8,
+            "ServerStatsTest: server counted {} mkdirs",
+            after - before
+        );
+
+        Ok(())
+    }
+}
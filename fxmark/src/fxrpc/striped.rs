@@ -0,0 +1,296 @@
+/*
+    Several connections to the server behind one client.
+    A single connection per core can leave a fast server idle because of
+    per-connection head-of-line blocking, striping ops over more keeps it busy.
+*/
+
+use std::collections::HashMap;
+
+use crate::fxrpc::{BatchOp, BatchResult, Capabilities, FileStat, FxRPC, LogRecord, OpHistogram};
+
+/// Stripes ops round-robin over its connections. Ops on a descriptor always go
+/// to the connection that opened it, descriptors opened elsewhere stick to a
+/// connection picked by their number.
+pub struct StripedClient {
+    conns: Vec<Box<dyn FxRPC>>,
+    next: usize,
+    owner: HashMap<i32, usize>,
+    ops: Vec<usize>,
+}
+
+impl StripedClient {
+    pub fn new(conns: Vec<Box<dyn FxRPC>>) -> StripedClient {
+        assert!(!conns.is_empty(), "StripedClient needs a connection");
+        let ops = vec![0; conns.len()];
+        StripedClient {
+            conns: conns,
+            next: 0,
+            owner: HashMap::new(),
+            ops: ops,
+        }
+    }
+
+    /// Number of connections to the server.
+    pub fn connections(&self) -> usize {
+        self.conns.len()
+    }
+
+    /// Number of ops sent over each connection so far.
+    pub fn ops_per_connection(&self) -> &[usize] {
+        &self.ops
+    }
+
+    fn next_conn(&mut self) -> usize {
+        let conn = self.next;
+        self.next = (self.next + 1) % self.conns.len();
+        self.ops[conn] += 1;
+        conn
+    }
+
+    // Closed descriptors keep their connection, so a repeated close reaches the
+    // TrackedClient that already closed it
+    fn conn_of(&mut self, fd: i32) -> usize {
+        let conn = match self.owner.get(&fd) {
+            Some(conn) => *conn,
+            None => fd.unsigned_abs() as usize % self.conns.len(),
+        };
+        self.ops[conn] += 1;
+        conn
+    }
+}
+
+impl FxRPC for StripedClient {
+    fn rpc_open(
+        &mut self,
+        path: &str,
+        flags: i32,
+        mode: u32,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        let conn = self.next_conn();
+        let fd = self.conns[conn].rpc_open(path, flags, mode)?;
+        if fd >= 0 {
+            self.owner.insert(fd, conn);
+        }
+        Ok(fd)
+    }
+
+    fn rpc_read(
+        &mut self,
+        fd: i32,
+        page: &mut Vec<u8>,
+        size: usize,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        let conn = self.conn_of(fd);
+        self.conns[conn].rpc_read(fd, page, size)
+    }
+
+    fn rpc_pread(
+        &mut self,
+        fd: i32,
+        page: &mut Vec<u8>,
+        size: usize,
+        offset: i64,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        let conn = self.conn_of(fd);
+        self.conns[conn].rpc_pread(fd, page, size, offset)
+    }
+
+    fn rpc_write(
+        &mut self,
+        fd: i32,
+        page: &[u8],
+        size: usize,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        let conn = self.conn_of(fd);
+        self.conns[conn].rpc_write(fd, page, size)
+    }
+
+    fn rpc_pwrite(
+        &mut self,
+        fd: i32,
+        page: &[u8],
+        size: usize,
+        offset: i64,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        let conn = self.conn_of(fd);
+        self.conns[conn].rpc_pwrite(fd, page, size, offset)
+    }
+
+    fn rpc_close(&mut self, fd: i32) -> Result<i32, Box<dyn std::error::Error>> {
+        let conn = self.conn_of(fd);
+        self.conns[conn].rpc_close(fd)
+    }
+
+    fn rpc_fsync(&mut self, fd: i32) -> Result<i32, Box<dyn std::error::Error>> {
+        let conn = self.conn_of(fd);
+        self.conns[conn].rpc_fsync(fd)
+    }
+
+    fn rpc_ftruncate(&mut self, fd: i32, length: i64) -> Result<i32, Box<dyn std::error::Error>> {
+        let conn = self.conn_of(fd);
+        self.conns[conn].rpc_ftruncate(fd, length)
+    }
+
+    fn rpc_remove(&mut self, path: &str) -> Result<i32, Box<dyn std::error::Error>> {
+        let conn = self.next_conn();
+        self.conns[conn].rpc_remove(path)
+    }
+
+    fn rpc_rename(
+        &mut self,
+        old_path: &str,
+        new_path: &str,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        let conn = self.next_conn();
+        self.conns[conn].rpc_rename(old_path, new_path)
+    }
+
+    fn rpc_mkdir(&mut self, path: &str, mode: u32) -> Result<i32, Box<dyn std::error::Error>> {
+        let conn = self.next_conn();
+        self.conns[conn].rpc_mkdir(path, mode)
+    }
+
+    fn rpc_rmdir(&mut self, path: &str) -> Result<i32, Box<dyn std::error::Error>> {
+        let conn = self.next_conn();
+        self.conns[conn].rpc_rmdir(path)
+    }
+
+    fn rpc_fadvise(
+        &mut self,
+        fd: i32,
+        offset: i64,
+        len: i64,
+        advice: i32,
+    ) -> Result<i32, Box<dyn std::error::Error>> {
+        let conn = self.conn_of(fd);
+        self.conns[conn].rpc_fadvise(fd, offset, len, advice)
+    }
+
+    fn rpc_drop_caches(&mut self) -> Result<i32, Box<dyn std::error::Error>> {
+        self.conns[0].rpc_drop_caches()
+    }
+
+    fn rpc_fstatat(
+        &mut self,
+        dirfd: i32,
+        path: &str,
+        flags: i32,
+    ) -> Result<FileStat, Box<dyn std::error::Error>> {
+        let conn = if dirfd == libc::AT_FDCWD {
+            self.next_conn()
+        } else {
+            self.conn_of(dirfd)
+        };
+        self.conns[conn].rpc_fstatat(dirfd, path, flags)
+    }
+
+    fn rpc_sendfile(
+        &mut self,
+        out_fd: i32,
+        in_fd: i32,
+        offset: i64,
+        count: usize,
+    ) -> Result<isize, Box<dyn std::error::Error>> {
+        let conn = self.conn_of(in_fd);
+        self.conns[conn].rpc_sendfile(out_fd, in_fd, offset, count)
+    }
+
+    fn rpc_lseek(
+        &mut self,
+        fd: i32,
+        offset: i64,
+        whence: i32,
+    ) -> Result<i64, Box<dyn std::error::Error>> {
+        let conn = self.conn_of(fd);
+        self.conns[conn].rpc_lseek(fd, offset, whence)
+    }
+
+    // The whole batch goes over one connection, which owns the files it opens
+    fn rpc_batch(
+        &mut self,
+        ops: Vec<BatchOp>,
+    ) -> Result<Vec<BatchResult>, Box<dyn std::error::Error>> {
+        let conn = self.next_conn();
+        let opens: Vec<bool> = ops
+            .iter()
+            .map(|op| matches!(op, BatchOp::Open { .. }))
+            .collect();
+        let results = self.conns[conn].rpc_batch(ops)?;
+        for (open, res) in opens.iter().zip(&results) {
+            if *open && res.result >= 0 {
+                self.owner.insert(res.result, conn);
+            }
+        }
+        Ok(results)
+    }
+
+    fn rpc_dump_server_log(&mut self) -> Result<Vec<LogRecord>, Box<dyn std::error::Error>> {
+        self.conns[0].rpc_dump_server_log()
+    }
+
+    fn rpc_server_stats(&mut self) -> Result<Vec<OpHistogram>, Box<dyn std::error::Error>> {
+        self.conns[0].rpc_server_stats()
+    }
+
+    fn rpc_capabilities(&mut self) -> Result<Capabilities, Box<dyn std::error::Error>> {
+        self.conns[0].rpc_capabilities()
+    }
+
+    fn rpc_reset(&mut self) -> Result<i32, Box<dyn std::error::Error>> {
+        // Each connection is a client of its own to the server
+        let mut closed = 0;
+        for conn in self.conns.iter_mut() {
+            closed += conn.rpc_reset()?;
+        }
+        Ok(closed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fxrpc::{init_client, start_test_server, RPCType, PAGE_SIZE};
+    use libc::{O_CREAT, O_RDWR, S_IRWXU};
+
+    #[test]
+    fn ops_stripe_over_connections() {
+        let endpoint = start_test_server("/dev/shm/fxmark_striped_test/fx.sock", RPCType::GRPC);
+
+        let conns = (0..4)
+            .map(|_| init_client(&endpoint, RPCType::GRPC))
+            .collect();
+        let mut client = StripedClient::new(conns);
+        assert_eq!(client.connections(), 4);
+
+        let mut fds = Vec::new();
+        for n in 0..8 {
+            let filename = format!("striped_test{}.txt", n);
+            let fd = client
+                .rpc_open(&filename, O_CREAT | O_RDWR, S_IRWXU)
+                .unwrap();
+            assert!(fd != -1);
+            let page = vec![n as u8; PAGE_SIZE];
+            assert_eq!(
+                client.rpc_pwrite(fd, &page, PAGE_SIZE, 0).unwrap(),
+                PAGE_SIZE as i32
+            );
+            fds.push((filename, fd, page));
+        }
+
+        for (filename, fd, page) in &fds {
+            let mut read = vec![0; PAGE_SIZE];
+            assert_eq!(
+                client.rpc_pread(*fd, &mut read, PAGE_SIZE, 0).unwrap(),
+                PAGE_SIZE as i32
+            );
+            assert!(read == *page);
+            assert_eq!(client.rpc_close(*fd).unwrap(), 0);
+            assert_eq!(client.rpc_remove(filename).unwrap(), 0);
+        }
+
+        // Each connection opened two files and served all ops on them
+        let owners: Vec<usize> = fds.iter().map(|(_, fd, _)| client.owner[fd]).collect();
+        assert_eq!(owners, vec![0, 1, 2, 3, 0, 1, 2, 3]);
+        assert_eq!(client.ops_per_connection(), &[10, 10, 10, 10]);
+    }
+}
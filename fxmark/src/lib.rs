@@ -0,0 +1,200 @@
+/*
+    The benchmark and the RPC layer the fxrpc binary is built from, as a
+    library for the benches and integration tests.
+    Zack McKevitt - 2023
+*/
+
+#[macro_use]
+extern crate abomonation;
+
+/// Stubs for the `FxRPC` ops `$op` that a test double leaves out, in the
+/// double's `impl FxRPC`. A test that calls one of them fails.
+#[cfg(test)]
+macro_rules! unimplemented_ops {
+    ($($op:ident),* $(,)?) => {
+        $(unimplemented_ops!(@ $op);)*
+    };
+    (@ rpc_open) => {
+        fn rpc_open(
+            &mut self,
+            _path: &str,
+            _flags: i32,
+            _mode: u32,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_read) => {
+        fn rpc_read(
+            &mut self,
+            _fd: i32,
+            _page: &mut Vec<u8>,
+            _size: usize,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_pread) => {
+        fn rpc_pread(
+            &mut self,
+            _fd: i32,
+            _page: &mut Vec<u8>,
+            _size: usize,
+            _offset: i64,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_write) => {
+        fn rpc_write(
+            &mut self,
+            _fd: i32,
+            _page: &[u8],
+            _size: usize,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_pwrite) => {
+        fn rpc_pwrite(
+            &mut self,
+            _fd: i32,
+            _page: &[u8],
+            _size: usize,
+            _offset: i64,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_close) => {
+        fn rpc_close(&mut self, _fd: i32) -> Result<i32, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_fsync) => {
+        fn rpc_fsync(&mut self, _fd: i32) -> Result<i32, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_ftruncate) => {
+        fn rpc_ftruncate(
+            &mut self,
+            _fd: i32,
+            _length: i64,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_remove) => {
+        fn rpc_remove(&mut self, _path: &str) -> Result<i32, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_rename) => {
+        fn rpc_rename(
+            &mut self,
+            _old_path: &str,
+            _new_path: &str,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_mkdir) => {
+        fn rpc_mkdir(
+            &mut self,
+            _path: &str,
+            _mode: u32,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_rmdir) => {
+        fn rpc_rmdir(&mut self, _path: &str) -> Result<i32, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_fadvise) => {
+        fn rpc_fadvise(
+            &mut self,
+            _fd: i32,
+            _offset: i64,
+            _len: i64,
+            _advice: i32,
+        ) -> Result<i32, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_drop_caches) => {
+        fn rpc_drop_caches(&mut self) -> Result<i32, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_fstatat) => {
+        fn rpc_fstatat(
+            &mut self,
+            _dirfd: i32,
+            _path: &str,
+            _flags: i32,
+        ) -> Result<$crate::fxrpc::FileStat, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_sendfile) => {
+        fn rpc_sendfile(
+            &mut self,
+            _out_fd: i32,
+            _in_fd: i32,
+            _offset: i64,
+            _count: usize,
+        ) -> Result<isize, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_lseek) => {
+        fn rpc_lseek(
+            &mut self,
+            _fd: i32,
+            _offset: i64,
+            _whence: i32,
+        ) -> Result<i64, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_batch) => {
+        fn rpc_batch(
+            &mut self,
+            _ops: Vec<$crate::fxrpc::BatchOp>,
+        ) -> Result<Vec<$crate::fxrpc::BatchResult>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_dump_server_log) => {
+        fn rpc_dump_server_log(
+            &mut self,
+        ) -> Result<Vec<$crate::fxrpc::LogRecord>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_server_stats) => {
+        fn rpc_server_stats(
+            &mut self,
+        ) -> Result<Vec<$crate::fxrpc::OpHistogram>, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_capabilities) => {
+        fn rpc_capabilities(
+            &mut self,
+        ) -> Result<$crate::fxrpc::Capabilities, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+    (@ rpc_reset) => {
+        fn rpc_reset(&mut self) -> Result<i32, Box<dyn std::error::Error>> {
+            unimplemented!()
+        }
+    };
+}
+
+pub mod fxmark;
+pub mod fxrpc;
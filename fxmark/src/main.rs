@@ -2,21 +2,24 @@
     gRPC server to execute system calls.
     Zack McKevitt - 2023
 */
-use clap::{crate_version, value_t, App, Arg};
+use clap::{crate_version, value_t, values_t, App, Arg};
 use std::fs::{remove_file, OpenOptions};
 use std::io::Write;
+use std::path::Path;
 
-#[macro_use]
-extern crate abomonation;
+use fxmark_grpc::fxmark::compare::compare;
+use fxmark_grpc::fxmark::meta::RunMetadata;
+use fxmark_grpc::fxmark::utils::topology::MachineTopology;
+use fxmark_grpc::fxmark::{
+    bench_names, check_base_path, check_error_budget, check_tags, co_run, latency_curve,
+    log_results, parse_tag, parse_workload, probe_servers, sweep, sweep_steps, tagged_header,
+    CSV_HEADER, LATENCY_CURVE_HEADER,
+};
 
-mod fxmark;
-use crate::fxmark::bench;
-use crate::fxmark::utils::topology::MachineTopology;
-
-pub mod fxrpc;
-use crate::fxrpc::ConnType;
-use crate::fxrpc::RPCType;
-use crate::fxrpc::*;
+use fxmark_grpc::fxrpc;
+use fxmark_grpc::fxrpc::ConnType;
+use fxmark_grpc::fxrpc::RPCType;
+use fxmark_grpc::fxrpc::*;
 
 const DEFAULT_OUTFILE: &str = "fxrpc_bench.csv";
 
@@ -29,25 +32,34 @@ fn parseargs(args: std::env::Args) -> clap::ArgMatches<'static> {
             Arg::with_name("mode")
                 .long("mode")
                 .required(true)
-                .help("client or server")
+                .help("client, server, selfcheck or compare")
                 .takes_value(true)
-                .possible_values(&["client", "server", "loc_client_drpc"]),
+                .possible_values(&["client", "server", "loc_client_drpc", "selfcheck", "compare"]),
         )
         .arg(
             Arg::with_name("rpc")
                 .long("rpc")
-                .required(true)
+                .required_unless("baseline")
                 .help("Dinos RPC (drpc) or gRPC (grpc)")
                 .takes_value(true)
                 .possible_values(&["drpc", "grpc"]),
         )
+        .arg(
+            Arg::with_name("wire-format")
+                .long("wire-format")
+                .required(false)
+                .help("Serialization of DRPC messages")
+                .default_value("abomonation")
+                .takes_value(true)
+                .possible_values(&["abomonation", "bincode"]),
+        )
         .arg(
             Arg::with_name("transport")
                 .long("transport")
-                .required(true)
-                .help("TCP Local (tcplocal) TCP Remote (tcpremote) UDS (uds)")
+                .required_unless("baseline")
+                .help("TCP Local (tcplocal) TCP Remote (tcpremote) UDS (uds) QUIC Local (quic)")
                 .takes_value(true)
-                .possible_values(&["tcplocal", "tcpremote", "uds"]),
+                .possible_values(&["tcplocal", "tcpremote", "uds", "quic"]),
         )
         .arg(
             Arg::with_name("port")
@@ -58,6 +70,168 @@ fn parseargs(args: std::env::Args) -> clap::ArgMatches<'static> {
                 .default_value("8080")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("endpoint")
+                .long("endpoint")
+                .required(false)
+                .help("Server address, tcp://HOST:PORT, quic://HOST:PORT or unix:///path/to.sock (overrides --transport and --port)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tls-cert")
+                .long("tls-cert")
+                .required(false)
+                .requires_all(&["tls-key", "tls-ca"])
+                .help("PEM certificate the gRPC server presents, enables TLS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tls-key")
+                .long("tls-key")
+                .required(false)
+                .requires_all(&["tls-cert", "tls-ca"])
+                .help("PEM private key of --tls-cert")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tls-ca")
+                .long("tls-ca")
+                .required(false)
+                .requires_all(&["tls-cert", "tls-key"])
+                .help("PEM CA certificate clients check the server's certificate against")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-tcp-nodelay")
+                .long("no-tcp-nodelay")
+                .required(false)
+                .help("Leave Nagle's algorithm on for TCP connections, it batches small requests")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("send-buf")
+                .long("send-buf")
+                .required(false)
+                .help("SO_SNDBUF in bytes of the client and server sockets, the kernel default if unset")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("recv-buf")
+                .long("recv-buf")
+                .required(false)
+                .help("SO_RCVBUF in bytes of the client and server sockets, the kernel default if unset")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("servers-file")
+                .long("servers-file")
+                .required(false)
+                .help("File of host:port servers, one per line, that the bench threads are spread over")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("bench")
+                .long("bench")
+                .required(false)
+                .help("Benchmark to run, one of those listed in the README")
+                .default_value("mix")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("recipe")
+                .long("recipe")
+                .required(false)
+                .help("Op mix for the recipe benchmark, e.g. read=70,write=20,create=5,unlink=5")
+                .default_value("read=50,write=50")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("file-size")
+                .long("file-size")
+                .required(false)
+                .help("Size in bytes of each core's file in the drbr benchmark, and the limit of files written with --write-mode grow")
+                .default_value("16777216")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("device")
+                .long("device")
+                .required(false)
+                .help("Block device below the server's --root that drbr reads and dwom writes in place of their files")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("working-set-bytes")
+                .long("working-set-bytes")
+                .required(false)
+                .help("Size in bytes of the file the mrpl and mrpm benchmarks read from")
+                .default_value("16777216")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("share-factor")
+                .long("share-factor")
+                .required(false)
+                .help("How many cores read each file of the mrpl benchmark, 1 gives every core its own. Only mrpl takes it, the other benchmarks ignore it")
+                .default_value("1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("reader-fraction")
+                .long("reader-fraction")
+                .required(false)
+                .help("Share of the cores that stat the file in the mrph benchmark, from 0 to 1, the rest write")
+                .default_value("0.5")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rename-mode")
+                .long("rename-mode")
+                .required(false)
+                .help("Whether mrename renames its files within one directory (intra) or between two (inter)")
+                .default_value("intra")
+                .takes_value(true)
+                .possible_values(&["intra", "inter"]),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .required(false)
+                .help("Seed for the random read offsets of the drbr benchmark")
+                .default_value("0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fill")
+                .long("fill")
+                .required(false)
+                .help("What benches fill their files with: zero, ones, random (from --seed) or repeat=HEX")
+                .default_value("repeat=0b")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("verify")
+                .long("verify")
+                .required(false)
+                .help("How drbr, mrpl and mrpm check the pages they read: off, checksum (CRC32) or full")
+                .default_value("off")
+                .takes_value(true)
+                .possible_values(&["off", "checksum", "full"]),
+        )
+        .arg(
+            Arg::with_name("io-size")
+                .long("io-size")
+                .required(false)
+                .help("Bytes each drbr read and each ococ write after an open transfers, defaults to what the benchmark does")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("size-dist")
+                .long("size-dist")
+                .required(false)
+                .help("Distribution the bytes of each drbr read and ococ write are drawn from, overrides --io-size: fixed=N, uniform=MIN-MAX or zipf=MIN-MAX[,EXPONENT]. Other benchmarks ignore it")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("wratio")
                 .long("wratio")
@@ -105,6 +279,334 @@ fn parseargs(args: std::env::Args) -> clap::ArgMatches<'static> {
                 .help("Cores per client")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("sweep")
+                .long("sweep")
+                .required(false)
+                .help("Sweep core counts from 1 up to the client cores in steps of this size")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("deadline")
+                .long("deadline")
+                .required(false)
+                .help("Skip the runs that would start after this many seconds")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("files-per-dir")
+                .long("files-per-dir")
+                .required(false)
+                .help("Number of files to pre-populate the benchmark directory with")
+                .default_value("0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("spread-files")
+                .long("spread-files")
+                .required(false)
+                .help("Spread each core's operations round-robin over all open files")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("numa-local-alloc")
+                .long("numa-local-alloc")
+                .required(false)
+                .help("Allocate bench buffers on each core's local NUMA node (Linux)")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("lock-buffers")
+                .long("lock-buffers")
+                .required(false)
+                .help("mlock bench buffers so the timed loop takes no page faults on them")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("check-fd-leaks")
+                .long("check-fd-leaks")
+                .required(false)
+                .help("Warn if a benchmark point leaves file descriptors open (Linux)")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("thread-mapping")
+                .long("thread-mapping")
+                .required(false)
+                .help("How bench threads are placed on sockets")
+                .default_value("sequential")
+                .possible_values(&["sequential", "compact", "scatter"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-pin")
+                .long("no-pin")
+                .required(false)
+                .help("Let bench threads float wherever the scheduler puts them instead of pinning them")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("single-threaded")
+                .long("single-threaded")
+                .required(false)
+                .help("Run the bench cores one after the other on the main thread, for debugging")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("allow-unpinned")
+                .long("allow-unpinned")
+                .required(false)
+                .help("Run bench threads that can't be pinned to their core unpinned instead of failing")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("conns-per-core")
+                .long("conns-per-core")
+                .required(false)
+                .help("Connections each bench thread opens to the server")
+                .default_value("1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("queue-depth")
+                .long("queue-depth")
+                .required(false)
+                .help("Reads each drbr thread keeps in flight, on connections of their own")
+                .default_value("1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keep-files")
+                .long("keep-files")
+                .required(false)
+                .help("Don't remove the benchmark files after the run, for inspection")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("repeat")
+                .long("repeat")
+                .required(false)
+                .help("Run each benchmark point this many times")
+                .default_value("1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("reuse-files")
+                .long("reuse-files")
+                .required(false)
+                .help("Truncate the benchmark files between repeats instead of recreating them")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("preallocate")
+                .long("preallocate")
+                .required(false)
+                .help("Write the mix and dwom files out to their full size before the timed phase")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("drop-caches")
+                .long("drop-caches")
+                .required(false)
+                .help("Drop the page cache before each run (Linux, requires root)")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("min-samples")
+                .long("min-samples")
+                .required(false)
+                .help("Flag seconds with fewer operations than this as unreliable")
+                .default_value("0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rerun-short")
+                .long("rerun-short")
+                .required(false)
+                .help("Measure an unreliable second again, up to this many times")
+                .default_value("0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("warmup-ops")
+                .long("warmup-ops")
+                .required(false)
+                .help("Ops each thread runs unmeasured before the benchmark starts")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("target-iops")
+                .long("target-iops")
+                .required(false)
+                .help("Ops per second each bench thread starts, instead of running them back to back")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("think-time-us")
+                .long("think-time-us")
+                .required(false)
+                .help("Microseconds each bench thread waits after every op")
+                .default_value("0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("busy-think")
+                .long("busy-think")
+                .required(false)
+                .help("Spin through --think-time-us instead of sleeping")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("align-samples")
+                .long("align-samples")
+                .required(false)
+                .help("Start the first per-second sample of all threads at a multiple of this many ms of wall-clock time, e.g. 1000 for whole seconds")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("co-run")
+                .long("co-run")
+                .required(false)
+                .help("Run this benchmark on these cores (e.g. mix@0-3) at the same time as those of the other --co-run")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("latency-curve")
+                .long("latency-curve")
+                .required(false)
+                .help("Run once per --target-iops in this list (e.g. 1000,2000,4000) and report throughput and p99 latency of each")
+                .multiple(true)
+                .use_delimiter(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sync")
+                .long("sync")
+                .required(false)
+                .help("When writes are synced: none, fsync (every write), fsync=N (every Nth write) or osync (O_SYNC)")
+                .default_value("none")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("write-mode")
+                .long("write-mode")
+                .required(false)
+                .help("Where dwom writes: overwrite, overwrite=OFFSET (in place), append (O_APPEND) or grow (sequential, wraps at --file-size)")
+                .default_value("overwrite")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("open-flags")
+                .long("open-flags")
+                .required(false)
+                .help("Flags benchmarks open their files with, e.g. O_CREAT,O_RDWR,O_DIRECT")
+                .default_value("O_CREAT,O_RDWR")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("name-len")
+                .long("name-len")
+                .required(false)
+                .help("Byte length of file names created by metadata benches, 0 for short default names")
+                .default_value("0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("name-charset")
+                .long("name-charset")
+                .required(false)
+                .help("Characters used to pad generated file names")
+                .default_value("ascii")
+                .possible_values(&["ascii", "utf8"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("baseline")
+                .long("baseline")
+                .required_if("mode", "compare")
+                .help("Result CSV to compare against")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("candidate")
+                .long("candidate")
+                .required_if("mode", "compare")
+                .help("Result CSV to check for regressions")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("threshold")
+                .long("threshold")
+                .required(false)
+                .help("Throughput drop in percent that counts as a regression")
+                .default_value("5")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("abort-on-error")
+                .long("abort-on-error")
+                .required(false)
+                .help("Abort the benchmark on the first failed operation (default)")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("continue-on-error")
+                .long("continue-on-error")
+                .required(false)
+                .help("Count failed operations and keep the benchmark running")
+                .takes_value(false)
+                .conflicts_with("abort-on-error"),
+        )
+        .arg(
+            Arg::with_name("tolerate-errno")
+                .long("tolerate-errno")
+                .required(false)
+                .help("Errnos (e.g. EAGAIN,EINTR) of failed operations to count instead of aborting")
+                .multiple(true)
+                .use_delimiter(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("error-budget")
+                .long("error-budget")
+                .required(false)
+                .help("Failed operations a run rides out before it stops early and keeps its results so far")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tag")
+                .long("tag")
+                .required(false)
+                .help("key=value to add as a column to every result row, can be repeated")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("root")
+                .long("root")
+                .required(false)
+                .help("Directory the server keeps the clients' files in, paths can't leave it; for a client, that of its local server")
+                .default_value(FS_PATH)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-requests")
+                .long("log-requests")
+                .required(false)
+                .help("Keep a server-side log of recent requests")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("open-limit")
+                .long("open-limit")
+                .required(false)
+                .help("Fraction of the soft RLIMIT_NOFILE the server may keep open for clients, later opens wait for a close")
+                .default_value("0.9")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("outfile")
                 .short("o")
@@ -113,21 +615,68 @@ fn parseargs(args: std::env::Args) -> clap::ArgMatches<'static> {
                 .takes_value(true)
                 .default_value(DEFAULT_OUTFILE),
         )
+        .arg(
+            Arg::with_name("compress-output")
+                .long("compress-output")
+                .required(false)
+                .help("Write results zstd-compressed to <output file>.zst")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("precision")
+                .long("precision")
+                .required(false)
+                .help("Digits after the decimal point of throughputs, latencies and CPU utilizations")
+                .default_value("1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sync-results")
+                .long("sync-results")
+                .required(false)
+                .help("fsync the output file after the results of every benchmark point")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("summary-table")
+                .long("summary-table")
+                .required(false)
+                .help("Print the median throughput per benchmark and core count at the end")
+                .takes_value(false),
+        )
         .get_matches_from(args);
     matches
 }
 
+// Exits with 1 if the candidate regressed, so it can gate CI
+fn compare_results(matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let baseline = value_t!(matches, "baseline", String).unwrap_or_else(|e| e.exit());
+    let candidate = value_t!(matches, "candidate", String).unwrap_or_else(|e| e.exit());
+    let threshold = value_t!(matches, "threshold", f64).unwrap_or_else(|e| e.exit());
+
+    let report = compare(Path::new(&baseline), Path::new(&candidate), threshold)?;
+    print!("{}", report);
+    if report.regressions().count() > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = std::env::args();
     let matches = parseargs(args);
 
     let mode = value_t!(matches, "mode", String).unwrap();
+    if mode == "compare" {
+        return compare_results(&matches);
+    }
     let port = value_t!(matches, "port", u16).unwrap();
     let conn_type: ConnType = {
         match value_t!(matches, "transport", String).unwrap().as_str() {
             "tcplocal" => ConnType::TcpLocal,
             "tcpremote" => ConnType::TcpRemote,
             "uds" => ConnType::UDS,
+            "quic" => ConnType::Quic,
             &_ => panic!("Unknown ConnType!"),
         }
     };
@@ -136,14 +685,101 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "drpc" => RPCType::DRPC,
         &_ => panic!("Unknown RPCType!"),
     };
-    let bench_name = String::from("mix");
-    let outfile = value_t!(matches, "outfile", String).unwrap();
+    let endpoint = if matches.is_present("endpoint") {
+        value_t!(matches, "endpoint", RPCEndpoint).unwrap_or_else(|e| e.exit())
+    } else {
+        RPCEndpoint::from_conn_type(conn_type, rpc_type, port)
+    };
+    if let RPCEndpoint::Quic { .. } = endpoint {
+        if rpc_type != RPCType::DRPC {
+            let e = "QUIC is only supported with --rpc drpc".to_string();
+            clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+        }
+    }
+    let wire_format = value_t!(matches, "wire-format", WireFormat).unwrap_or_else(|e| e.exit());
+    if matches.occurrences_of("wire-format") > 0 && rpc_type != RPCType::DRPC {
+        let e = "--wire-format is only supported with --rpc drpc".to_string();
+        clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+    }
+    let tls = if matches.is_present("tls-cert") {
+        if rpc_type != RPCType::GRPC {
+            let e = "TLS is only supported with --rpc grpc".to_string();
+            clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+        }
+        Some(TlsConfig {
+            cert: matches.value_of("tls-cert").unwrap().into(),
+            key: matches.value_of("tls-key").unwrap().into(),
+            ca: matches.value_of("tls-ca").unwrap().into(),
+        })
+    } else {
+        None
+    };
+    let buf_size = |name: &str| {
+        if matches.is_present(name) {
+            Some(value_t!(matches, name, usize).unwrap_or_else(|e| e.exit()))
+        } else {
+            None
+        }
+    };
+    let sockets = SocketOptions {
+        tcp_nodelay: !matches.is_present("no-tcp-nodelay"),
+        send_buf: buf_size("send-buf"),
+        recv_buf: buf_size("recv-buf"),
+    };
+    let bench_name = value_t!(matches, "bench", String).unwrap();
+    if !bench_names().contains(&bench_name.as_str()) {
+        let e = format!(
+            "No benchmark {}, there are {}",
+            bench_name,
+            bench_names().join(", ")
+        );
+        clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+    }
+    let compress_output = matches.is_present("compress-output");
+    let outfile = if compress_output {
+        format!("{}.zst", value_t!(matches, "outfile", String).unwrap())
+    } else {
+        value_t!(matches, "outfile", String).unwrap()
+    };
 
     match mode.as_str() {
         "server" => {
-            run_server(conn_type, rpc_type, port);
+            if matches.is_present("log-requests") {
+                fxrpc::reqlog::enable_request_log();
+            }
+            let root = matches.value_of("root").unwrap();
+            if let Err(e) = check_base_path(Path::new(root)) {
+                clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+            }
+            set_server_root(root);
+            let open_limit = value_t!(matches, "open-limit", f64).unwrap_or_else(|e| e.exit());
+            if !(open_limit > 0.0 && open_limit <= 1.0) {
+                let e = "--open-limit must be above 0 and at most 1".to_string();
+                clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+            }
+            fxrpc::fdtable::raise_nofile_limit();
+            if let Some(limit) = fxrpc::fdtable::limit_open_fds(open_limit) {
+                println!("Keeping at most {} files open for clients", limit);
+            }
+            match &tls {
+                Some(tls) => run_tls_server(&endpoint, tls),
+                None => run_server_with_sockets(&endpoint, rpc_type, &sockets),
+            }
+        }
+        "selfcheck" => {
+            let mut client = match &tls {
+                Some(tls) => init_tls_client(&endpoint, tls),
+                None => init_client_with_sockets(&endpoint, rpc_type, wire_format, &sockets),
+            };
+            let report = fxrpc::selfcheck::self_check(client.as_mut());
+            print!("{}", report);
+            if !report.passed() {
+                std::process::exit(1);
+            }
         }
         "client" => {
+            // The local server's, a remote one may keep its files anywhere
+            let root = matches.value_of("root").unwrap();
             let wratios: Vec<&str> = matches.values_of("wratio").unwrap().collect();
             let wratios: Vec<usize> = wratios
                 .into_iter()
@@ -156,6 +792,148 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .collect();
 
             let duration = value_t!(matches, "duration", u64).unwrap_or_else(|e| e.exit());
+            let recipe = value_t!(matches, "recipe", Recipe).unwrap_or_else(|e| e.exit());
+            let files_per_dir =
+                value_t!(matches, "files-per-dir", usize).unwrap_or_else(|e| e.exit());
+            let sync_policy = value_t!(matches, "sync", SyncPolicy).unwrap_or_else(|e| e.exit());
+            let write_mode =
+                value_t!(matches, "write-mode", WriteMode).unwrap_or_else(|e| e.exit());
+            let open_flags = parse_open_flags(matches.value_of("open-flags").unwrap())
+                .unwrap_or_else(|e| {
+                    clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit()
+                });
+            let min_samples = value_t!(matches, "min-samples", usize).unwrap_or_else(|e| e.exit());
+            let rerun_short = value_t!(matches, "rerun-short", usize).unwrap_or_else(|e| e.exit());
+            let warmup_ops = if matches.is_present("warmup-ops") {
+                Some(value_t!(matches, "warmup-ops", u64).unwrap_or_else(|e| e.exit()))
+            } else {
+                None
+            };
+            let positive_rate = |rate: u64| {
+                if rate == 0 {
+                    let e = "Target rates must be at least one op per second".to_string();
+                    clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+                }
+                rate
+            };
+            let target_iops = if matches.is_present("target-iops") {
+                let rate = value_t!(matches, "target-iops", u64).unwrap_or_else(|e| e.exit());
+                Some(positive_rate(rate))
+            } else {
+                None
+            };
+            let think_time_us =
+                value_t!(matches, "think-time-us", u64).unwrap_or_else(|e| e.exit());
+            let align_samples_ms = if matches.is_present("align-samples") {
+                Some(value_t!(matches, "align-samples", u64).unwrap_or_else(|e| e.exit()))
+            } else {
+                None
+            };
+            let load_points = if matches.is_present("latency-curve") {
+                let rates = values_t!(matches, "latency-curve", u64).unwrap_or_else(|e| e.exit());
+                Some(rates.into_iter().map(positive_rate).collect::<Vec<u64>>())
+            } else {
+                None
+            };
+            let co_run_plan: Option<Vec<(String, Vec<u64>)>> =
+                matches.values_of("co-run").map(|workloads| {
+                    workloads
+                        .map(|workload| {
+                            parse_workload(workload).unwrap_or_else(|e| {
+                                clap::Error::with_description(&e, clap::ErrorKind::InvalidValue)
+                                    .exit()
+                            })
+                        })
+                        .collect()
+                });
+            let name_len = value_t!(matches, "name-len", usize).unwrap_or_else(|e| e.exit());
+            if let Err(e) = validate_name_len(name_len) {
+                clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+            }
+            let file_size = value_t!(matches, "file-size", usize).unwrap_or_else(|e| e.exit());
+            if file_size < PAGE_SIZE {
+                let e = format!("File size must be at least one page ({} bytes)", PAGE_SIZE);
+                clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+            }
+            let device = matches.value_of("device").map(|d| d.to_string());
+            if device.is_some() {
+                if bench_name != "drbr" && bench_name != "dwom" {
+                    let e = "--device only works with --bench drbr or dwom".to_string();
+                    clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+                }
+                if write_mode == WriteMode::Append {
+                    let e = "Devices can't be appended to, use another --write-mode".to_string();
+                    clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+                }
+            }
+            let working_set_bytes =
+                value_t!(matches, "working-set-bytes", usize).unwrap_or_else(|e| e.exit());
+            if working_set_bytes < PAGE_SIZE {
+                let e = format!(
+                    "Working set must be at least one page ({} bytes)",
+                    PAGE_SIZE
+                );
+                clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+            }
+            let share_factor =
+                value_t!(matches, "share-factor", usize).unwrap_or_else(|e| e.exit());
+            if share_factor == 0 {
+                let e = "Each file needs at least one core to share it".to_string();
+                clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+            }
+            let reader_fraction =
+                value_t!(matches, "reader-fraction", f64).unwrap_or_else(|e| e.exit());
+            if !(0.0..=1.0).contains(&reader_fraction) {
+                let e = "Reader fraction must be between 0 and 1".to_string();
+                clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+            }
+            let rename_mode =
+                value_t!(matches, "rename-mode", RenameMode).unwrap_or_else(|e| e.exit());
+            let seed = value_t!(matches, "seed", u64).unwrap_or_else(|e| e.exit());
+            let fill_pattern = value_t!(matches, "fill", FillPattern).unwrap_or_else(|e| e.exit());
+            let verify = value_t!(matches, "verify", VerifyMode).unwrap_or_else(|e| e.exit());
+            let io_size = if matches.is_present("io-size") {
+                Some(value_t!(matches, "io-size", usize).unwrap_or_else(|e| e.exit()))
+            } else {
+                None
+            };
+            let size_dist = if matches.is_present("size-dist") {
+                Some(value_t!(matches, "size-dist", SizeDistribution).unwrap_or_else(|e| e.exit()))
+            } else {
+                None
+            };
+            let repeat = value_t!(matches, "repeat", usize).unwrap_or_else(|e| e.exit());
+            if repeat == 0 {
+                let e = "Each benchmark point needs at least one repeat".to_string();
+                clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+            }
+            let name_charset =
+                value_t!(matches, "name-charset", Charset).unwrap_or_else(|e| e.exit());
+            let conns_per_core =
+                value_t!(matches, "conns-per-core", usize).unwrap_or_else(|e| e.exit());
+            if conns_per_core == 0 {
+                let e = "Each core needs at least one connection".to_string();
+                clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+            }
+            let queue_depth = value_t!(matches, "queue-depth", usize).unwrap_or_else(|e| e.exit());
+            if queue_depth == 0 {
+                let e = "The queue depth must be at least 1".to_string();
+                clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+            }
+            if queue_depth > 1 && bench_name != "drbr" {
+                let e = "--queue-depth only works with --bench drbr".to_string();
+                clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+            }
+            let thread_mapping =
+                value_t!(matches, "thread-mapping", ThreadMapping).unwrap_or_else(|e| e.exit());
+            let servers = if matches.is_present("servers-file") {
+                let path = value_t!(matches, "servers-file", String).unwrap_or_else(|e| e.exit());
+                read_servers_file(Path::new(&path)).unwrap_or_else(|e| {
+                    clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit()
+                })
+            } else {
+                Vec::new()
+            };
 
             let cid = if conn_type != ConnType::TcpLocal {
                 value_t!(matches, "cid", usize).unwrap_or_else(|e| e.exit())
@@ -183,16 +961,128 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 LogMode::STDOUT
             };
 
+            let tolerated_errnos: Vec<i32> = matches
+                .values_of("tolerate-errno")
+                .map(|errnos| errnos.collect())
+                .unwrap_or_else(Vec::new)
+                .into_iter()
+                .map(|errno| {
+                    parse_errno(errno).unwrap_or_else(|e| {
+                        clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit()
+                    })
+                })
+                .collect();
+
+            let tags: Vec<(String, String)> = matches
+                .values_of("tag")
+                .map(|tags| tags.collect())
+                .unwrap_or_else(Vec::new)
+                .into_iter()
+                .map(|tag| {
+                    parse_tag(tag).unwrap_or_else(|e| {
+                        clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit()
+                    })
+                })
+                .collect();
+            if let Err(e) = check_tags(&tags) {
+                clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit();
+            }
+
+            let error_budget = if matches.is_present("error-budget") {
+                Some(value_t!(matches, "error-budget", u64).unwrap_or_else(|e| e.exit()))
+            } else {
+                None
+            };
+
+            let error_policy = if matches.is_present("continue-on-error") {
+                ErrorPolicy::ContinueOnError
+            } else {
+                ErrorPolicy::AbortOnError
+            };
+
             let client_params = ClientParams {
                 cid: cid,
                 nclients: nclients,
                 ccores: ccores,
                 log_mode: log_mode,
                 conn_type: conn_type,
+                endpoint: endpoint.clone(),
+                servers: servers,
                 rpc_type: rpc_type,
+                wire_format: wire_format,
+                conns_per_core: conns_per_core,
+                queue_depth: queue_depth,
+                tls: tls.clone(),
+                sockets: sockets,
+                error_policy: error_policy,
+                tolerated_errnos: tolerated_errnos,
+                error_budget: error_budget,
+                sync_policy: sync_policy,
+                write_mode: write_mode,
+                open_flags: open_flags,
+                files_per_dir: files_per_dir,
+                spread_files: matches.is_present("spread-files"),
+                drop_caches: matches.is_present("drop-caches"),
+                keep_files: matches.is_present("keep-files"),
+                repeat: repeat,
+                reuse_files: matches.is_present("reuse-files"),
+                preallocate: matches.is_present("preallocate"),
+                numa_local_alloc: matches.is_present("numa-local-alloc"),
+                lock_buffers: matches.is_present("lock-buffers"),
+                check_fd_leaks: matches.is_present("check-fd-leaks"),
+                thread_mapping: thread_mapping,
+                pin: !matches.is_present("no-pin"),
+                require_pinning: !matches.is_present("allow-unpinned"),
+                single_threaded: matches.is_present("single-threaded"),
+                min_samples: min_samples,
+                rerun_short: rerun_short,
+                warmup_ops: warmup_ops,
+                target_iops: target_iops,
+                think_time_us: think_time_us,
+                busy_think: matches.is_present("busy-think"),
+                align_samples_ms: align_samples_ms,
+                name_len: name_len,
+                name_charset: name_charset,
+                file_size: file_size,
+                device: device,
+                working_set_bytes: working_set_bytes,
+                share_factor: share_factor,
+                reader_fraction: reader_fraction,
+                rename_mode: rename_mode,
+                seed: seed,
+                fill_pattern: fill_pattern,
+                verify: verify,
+                io_size: io_size,
+                size_dist: size_dist,
+                compress_output: compress_output,
+                precision: value_t!(matches, "precision", usize).unwrap_or_else(|e| e.exit()),
+                sync_results: matches.is_present("sync-results"),
+                summary_table: matches.is_present("summary-table"),
+                // TLS connections keep the default socket options
+                host_info: HostInfo::new(
+                    &endpoint,
+                    &tls.as_ref().map_or(sockets, |_| SocketOptions::default()),
+                ),
+                recipe: recipe,
+                tags: tags,
             };
 
-            let row = "thread_id,benchmark,ncores,write_ratio,open_files,duration_total,duration,operations,client_id,client_cores,nclients,rpctype\n";
+            if client_params.keep_files {
+                eprintln!(
+                    "WARNING: --keep-files leaves the benchmark files in the server directory ({}). \
+                     They are not removed by later runs and may skew their results.",
+                    root
+                );
+            }
+
+            if !probe_servers(&client_params) {
+                return Ok(());
+            }
+
+            let row = match load_points {
+                Some(_) => tagged_header(LATENCY_CURVE_HEADER, &client_params),
+                None => tagged_header(CSV_HEADER, &client_params),
+            };
             match log_mode {
                 LogMode::CSV => {
                     let _ = remove_file(outfile.clone());
@@ -201,8 +1091,29 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .create(true)
                         .open(outfile.clone())
                         .expect("Cant open output file");
-                    let r = csv_file.write(row.as_bytes());
+                    let r = if compress_output {
+                        let frame =
+                            zstd::encode_all(row.as_bytes(), 0).expect("Cant compress header");
+                        csv_file.write_all(&frame)
+                    } else {
+                        csv_file.write(row.as_bytes()).map(|_| ())
+                    };
                     assert!(r.is_ok());
+
+                    // A remote server's directory isn't visible from here
+                    let base_path = if conn_type != ConnType::TcpRemote {
+                        Some(Path::new(root))
+                    } else {
+                        None
+                    };
+                    let meta = RunMetadata::gather(&bench_name, base_path, &client_params);
+                    let meta_dir = match Path::new(&outfile).parent() {
+                        Some(dir) if !dir.as_os_str().is_empty() => dir,
+                        _ => Path::new("."),
+                    };
+                    if let Err(e) = meta.write(meta_dir) {
+                        eprintln!("Can't write run metadata: {}", e);
+                    }
                 }
                 LogMode::STDOUT => {
                     if conn_type != ConnType::UDS {
@@ -211,17 +1122,67 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
 
-            for of in openfs {
-                for wr in &wratios {
-                    bench(
-                        bench_name.clone(),
-                        of,
-                        *wr,
-                        duration,
-                        &client_params,
-                        &outfile,
-                    );
+            let sweep_step = if matches.is_present("sweep") {
+                Some(value_t!(matches, "sweep", usize).unwrap_or_else(|e| e.exit()))
+            } else {
+                None
+            };
+
+            let core_counts = match sweep_step {
+                Some(step) => sweep_steps(client_params.ccores, step),
+                None => vec![client_params.ccores],
+            };
+            let deadline = if matches.is_present("deadline") {
+                let secs = value_t!(matches, "deadline", u64).unwrap_or_else(|e| e.exit());
+                Some(std::time::Duration::from_secs(secs))
+            } else {
+                None
+            };
+
+            if let Some(plan) = co_run_plan {
+                for of in &openfs {
+                    for wr in &wratios {
+                        let results = co_run(plan.clone(), *of, *wr, duration, &client_params)
+                            .unwrap_or_else(|e| {
+                                eprintln!("{}", e);
+                                std::process::exit(1);
+                            });
+                        log_results(&results, &client_params, &outfile);
+                        if let Err(e) = check_error_budget(&results, &client_params) {
+                            eprintln!("{}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            } else if let Some(load_points) = load_points {
+                for of in &openfs {
+                    for wr in &wratios {
+                        if let Err(e) = latency_curve(
+                            bench_name.clone(),
+                            *of,
+                            *wr,
+                            duration,
+                            &client_params,
+                            &load_points,
+                            &outfile,
+                        ) {
+                            eprintln!("{}", e);
+                            std::process::exit(1);
+                        }
+                    }
                 }
+            } else if let Err(e) = sweep(
+                bench_name,
+                &openfs,
+                &wratios,
+                &core_counts,
+                duration,
+                &client_params,
+                &outfile,
+                deadline,
+            ) {
+                eprintln!("{}", e);
+                std::process::exit(1);
             }
         }
         _ => panic!("Unknown mode!"),
@@ -64,11 +64,9 @@ fn write_test_base(pwrite: bool) -> Result<(), Box<dyn std::error::Error>> {
 
     let page = "WriteTest".as_bytes();
     let result = if pwrite {
-        client
-            .grpc_pwrite(fd, &page.to_vec(), page.len(), 0)
-            .unwrap()
+        client.grpc_pwrite(fd, page, page.len(), 0).unwrap()
     } else {
-        client.grpc_write(fd, &page.to_vec(), page.len()).unwrap()
+        client.grpc_write(fd, page, page.len()).unwrap()
     };
 
     // Length of test in files/read_test.txt
@@ -107,7 +105,7 @@ fn write_read_test() -> Result<(), Box<dyn std::error::Error>> {
     assert!(fd != -1, "WriteReadTest: Open Failed");
 
     let page = "WriteReadTest".as_bytes();
-    let result = client.grpc_write(fd, &page.to_vec(), page.len()).unwrap();
+    let result = client.grpc_write(fd, page, page.len()).unwrap();
     assert!(result != -1, "WriteReadTest: Write Failed");
 
     let mut page: Vec<u8> = vec![0; PAGE_SIZE];
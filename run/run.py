@@ -261,7 +261,9 @@ def start_client_tcp(cid, args, node, affinity):
     print("Invoking TCP client in emulated environment with command: " + cmd)
     child.sendline(cmd)
     child.expect_exact("thread_id,benchmark,ncores,write_ratio,open_files,duration_total," \
-                       "duration,operations,client_id,client_cores,nclients,rpctype")
+                       "duration,operations,client_id,client_cores,nclients,rpctype,errors," \
+                       "page_size,sndbuf,rcvbuf,unreliable,cpu_util,thread_mapping,errnos,unpinned,reader_ops,writer_ops," \
+                       "released_us,ended_us,workload")
     child.expect("root@jammy:~# ", timeout=EXP_TIMEOUT)
 
     output = child.before.decode().replace('\r', '')
@@ -101,7 +101,7 @@ fn main() {
         2048 * (((num_cores + 3 - 1) / 3) * 3)
     }
 
-    let row = "thread_id,benchmark,ncores,write_ratio,open_files,duration_total,duration,operations,client_id,client_cores,nclients,rpctype\n";
+    let row = "thread_id,benchmark,ncores,write_ratio,open_files,duration_total,duration,operations,client_id,client_cores,nclients,rpctype,errors,page_size,sndbuf,rcvbuf,unreliable,cpu_util,thread_mapping,errnos,unpinned,reader_ops,writer_ops,released_us,ended_us,workload\n";
     let _ = remove_file(csv.clone());
     let mut csv_file = OpenOptions::new()
         .append(true)